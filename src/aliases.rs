@@ -0,0 +1,152 @@
+//! Old names of gyms, so that renamed or merged venues keep a single identity
+//!
+//! An alias file maps labels, or venue ids, no longer in use to the gym they are now,
+//! one `OLD = "CURRENT"` per line. The current gym is named or given by its venue id,
+//! and may itself be an alias, which is followed:
+//!
+//! ```toml
+//! TOA_PAYOH_WEST = "TOA_PAYOH"
+//! # merged into Toa Payoh
+//! TOA_PAYOH_CC = "TOA_PAYOH_WEST"
+//! "1049" = "TOA_PAYOH"
+//! ```
+//!
+//! Once [install]ed, `Gym::from_str` and the deserialization of [Gym] resolve aliases first,
+//! so config files, flags and archived snapshots with old names all end up with the current
+//! gym. An alias naming a gym that still exists, such as `TOA_PAYOH_CC` above, merges it
+//!
+//! ## Example
+//! ```
+//! use activesg_gym_datamine::{aliases::{self, AliasMap}, models::Gym};
+//!
+//! let map = AliasMap::parse(
+//!     r#"
+//!     TOA_PAYOH_WEST = "TOA_PAYOH"
+//!     TOA_PAYOH_CC = "TOA_PAYOH_WEST"
+//!     "137" = "BISHAN"
+//!     "9999" = "1049"
+//!     "#,
+//! )
+//! .unwrap();
+//! assert_eq!(map.resolve("TOA_PAYOH_CC"), Some(Gym::TOA_PAYOH));
+//! assert_eq!(map.resolve("9999"), Some(Gym::TOA_PAYOH_CC));
+//! assert_eq!(map.resolve("BISHAN"), None);
+//!
+//! let err = AliasMap::parse(r#"OLD = "ATLANTIS""#).unwrap_err().to_string();
+//! assert!(err.contains("OLD is an alias of unknown gym ATLANTIS"), "{}", err);
+//! let err = AliasMap::parse("A = \"B\"\nB = \"C\"\nC = \"A\"").unwrap_err().to_string();
+//! assert!(err.contains("cycle A -> B -> C -> A"), "{}", err);
+//! assert!(AliasMap::parse(r#"BISHAN = "BISHAN""#).is_err());
+//!
+//! assert!("TOA_PAYOH_WEST".parse::<Gym>().is_err());
+//! aliases::install(map);
+//! assert_eq!("TOA_PAYOH_WEST".parse::<Gym>().unwrap(), Gym::TOA_PAYOH);
+//! assert_eq!("TOA_PAYOH_CC".parse::<Gym>().unwrap(), Gym::TOA_PAYOH);
+//! assert_eq!(serde_json::from_str::<Gym>(r#""TOA_PAYOH_WEST""#).unwrap(), Gym::TOA_PAYOH);
+//! assert_eq!("CLEMENTI".parse::<Gym>().unwrap(), Gym::CLEMENTI);
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use lazy_static::lazy_static;
+
+use crate::{errors, models::Gym, DataMResult};
+
+/// Alias file read from the working directory when [ALIASES_ENV] isn't set
+pub const DEFAULT_ALIASES_FILE: &str = "aliases.toml";
+
+/// Environment variable with the path of the alias file
+pub const ALIASES_ENV: &str = "ACTIVESG_ALIASES";
+
+lazy_static! {
+    static ref INSTALLED: RwLock<AliasMap> = RwLock::default();
+}
+
+/// Aliases resolved to the gym they stand for
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AliasMap {
+    aliases: BTreeMap<String, Gym>,
+}
+
+impl AliasMap {
+    /// Parses an alias file, checking that every alias ends at a gym
+    pub fn parse(raw: &str) -> DataMResult<Self> {
+        Self::parse_checked(raw).map_err(errors::Error::InvalidAliases)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> DataMResult<Self> {
+        let path = path.as_ref();
+        let raw = std::fs::read_to_string(path)?;
+        Self::parse_checked(&raw)
+            .map_err(|e| errors::Error::InvalidAliases(format!("{}: {}", path.display(), e)))
+    }
+
+    fn parse_checked(raw: &str) -> Result<Self, String> {
+        let raw = toml::from_str::<BTreeMap<String, String>>(raw).map_err(|e| e.to_string())?;
+
+        let mut aliases = BTreeMap::new();
+        for alias in raw.keys() {
+            let mut chain = vec![alias.as_str()];
+            let gym = loop {
+                let target = raw[*chain.last().unwrap()].as_str();
+                if chain.contains(&target) {
+                    chain.push(target);
+                    return Err(format!("cycle {}", chain.join(" -> ")));
+                }
+                if raw.contains_key(target) {
+                    chain.push(target);
+                    continue;
+                }
+
+                match Gym::from_canonical(target) {
+                    Some(gym) => break gym,
+                    None => {
+                        return Err(format!(
+                            "{} is an alias of unknown gym {}",
+                            chain.last().unwrap(),
+                            target
+                        ))
+                    }
+                }
+            };
+            aliases.insert(alias.clone(), gym);
+        }
+
+        Ok(Self { aliases })
+    }
+
+    /// Gym `alias` stands for, none when it isn't an alias
+    pub fn resolve(&self, alias: &str) -> Option<Gym> {
+        self.aliases.get(alias).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.aliases.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+}
+
+/// Path of the alias file, from [ALIASES_ENV] or [DEFAULT_ALIASES_FILE] when it exists
+pub fn default_path() -> Option<PathBuf> {
+    match std::env::var_os(ALIASES_ENV) {
+        Some(path) => Some(path.into()),
+        None => Some(PathBuf::from(DEFAULT_ALIASES_FILE)).filter(|p| p.exists()),
+    }
+}
+
+/// Makes `map` the aliases resolved by `Gym::from_str` from now on, replacing the previous ones
+pub fn install(map: AliasMap) {
+    *INSTALLED.write().unwrap() = map;
+}
+
+/// Gym `alias` stands for in the installed aliases
+pub fn lookup(alias: &str) -> Option<Gym> {
+    INSTALLED.read().unwrap().resolve(alias)
+}
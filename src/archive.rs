@@ -0,0 +1,374 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{NaiveDate, NaiveDateTime};
+
+use crate::{
+    compress::Compression,
+    models::{Activity, Gym, GymSlotData, GymSlotDataSoA},
+    report::REPORTS_DIR,
+    retention::SnapshotFile,
+    sink::OutputFormat,
+    venue::Venue,
+};
+
+/// Snapshot read back from the output directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchivedSnapshot {
+    pub path: PathBuf,
+
+    /// layout the snapshot was written in
+    pub format: OutputFormat,
+
+    /// the snapshot, converted to [GymSlotData] whatever its format
+    pub data: GymSlotData,
+}
+
+/// Snapshot file that couldn't be read, the walk carries on with the next one
+#[derive(thiserror::Error, Debug)]
+pub enum ArchiveError {
+    #[error("{}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("{}: {message}", path.display())]
+    Corrupt { path: PathBuf, message: String },
+}
+
+impl ArchiveError {
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Io { path, .. } | Self::Corrupt { path, .. } => path,
+        }
+    }
+}
+
+/// Which snapshots a [SnapshotReader] yields, an empty list matches everything
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotFilter {
+    /// first capture date in Singapore time, inclusive
+    pub from: Option<NaiveDate>,
+
+    /// last capture date in Singapore time, inclusive
+    pub to: Option<NaiveDate>,
+
+    pub gyms: Vec<Gym>,
+    pub formats: Vec<OutputFormat>,
+}
+
+impl SnapshotFilter {
+    fn matches_file(&self, file: &SnapshotFile) -> bool {
+        let date = file.captured_at.date();
+        if matches!(self.from, Some(from) if date < from) {
+            return false;
+        }
+        if matches!(self.to, Some(to) if date > to) {
+            return false;
+        }
+
+        // the gym is the last part of `GYM` or `ACTIVITY-GYM`, unknown ones are read to be reported
+        let gym = file.series.rsplit('-').next().and_then(|g| g.parse().ok());
+        match gym {
+            Some(gym) => self.gyms.is_empty() || self.gyms.contains(&gym),
+            None => true,
+        }
+    }
+
+    fn matches(&self, snapshot: &ArchivedSnapshot) -> bool {
+        (self.gyms.is_empty() || self.gyms.iter().any(|g| snapshot.data.gym() == *g))
+            && (self.formats.is_empty() || self.formats.contains(&snapshot.format))
+    }
+}
+
+/// Lazily walks every snapshot under a directory, in path order
+///
+/// Directories are walked recursively so that nested layouts are found, skipping run reports
+/// and diffs. The format is sniffed from the content so that [OutputFormat::Json] and
+/// [OutputFormat::Soa] snapshots can be mixed, compressed or not, and a file that can't be read
+/// is yielded as an [ArchiveError] without stopping the walk
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     archive::{ArchiveError, SnapshotFilter, SnapshotReader},
+///     compress::Compression,
+///     models::Gym,
+///     sink::OutputFormat,
+/// };
+///
+/// let dir = std::env::temp_dir().join(format!("archive-doctest-{}", std::process::id()));
+/// let day = dir.join("2022-01-11");
+/// std::fs::create_dir_all(day.join("nested").join("runs")).unwrap();
+///
+/// let aos = r#"{"gym":"BISHAN","datetime":"2022-01-11T05:57:33",
+///     "data":[{"time":"2022-01-11T10:00:00Z","slots_avail":25}]}"#;
+/// let soa = r#"{"gym":"CLEMENTI","datetime":"2022-01-11T05:57:33",
+///     "time":["2022-01-11T10:00:00Z"],"slots_avail":[3]}"#;
+///
+/// std::fs::write(day.join("BISHAN-2022-01-11 13-57-33.json"), aos).unwrap();
+/// std::fs::write(day.join("nested").join("CLEMENTI-2022-01-11 13-57-33.json"), soa).unwrap();
+/// std::fs::write(day.join("HOUGANG-2022-01-11 13-57-33.json"), "{ not json").unwrap();
+/// let gzip = Compression::Gzip.encode(aos.replace("BISHAN", "TAMPINES").as_bytes(), None).unwrap();
+/// std::fs::write(day.join("TAMPINES-2022-01-11 13-57-33.json.gz"), gzip).unwrap();
+/// std::fs::write(day.join("YISHUN-2022-01-11 13-57-33.json.zst"), [0x28, 0xb5, 0x2f, 0xfd]).unwrap();
+/// std::fs::write(day.join("BISHAN-2022-01-11 13-57-33.diff.json"), "{}").unwrap();
+/// std::fs::write(day.join("nested").join("runs").join("2022-01-11 13-57-33.report.json"), "{}")
+///     .unwrap();
+///
+/// let all = SnapshotReader::new(&dir, SnapshotFilter::default()).collect::<Vec<_>>();
+/// assert_eq!(all.len(), 5);
+///
+/// let (ok, err): (Vec<_>, Vec<_>) = all.into_iter().partition(Result::is_ok);
+/// let formats = ok.into_iter().map(|s| s.unwrap().format).collect::<Vec<_>>();
+/// assert_eq!(formats, vec![OutputFormat::Json, OutputFormat::Json, OutputFormat::Soa]);
+/// assert!(err.iter().any(|e| matches!(e, Err(ArchiveError::Corrupt { .. }))));
+/// assert!(err.iter().all(|e| matches!(e, Err(ArchiveError::Corrupt { .. }))));
+///
+/// let filter = SnapshotFilter {
+///     gyms: vec![Gym::CLEMENTI],
+///     ..Default::default()
+/// };
+/// let clementi = SnapshotReader::new(&dir, filter).collect::<Result<Vec<_>, _>>().unwrap();
+/// assert_eq!(clementi.len(), 1);
+/// assert_eq!(clementi[0].data.data()[0].slots_avail(), 3);
+///
+/// let filter = SnapshotFilter {
+///     from: chrono::NaiveDate::from_ymd_opt(2022, 1, 12),
+///     ..Default::default()
+/// };
+/// assert_eq!(SnapshotReader::new(&dir, filter).count(), 0);
+///
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct SnapshotReader {
+    filter: SnapshotFilter,
+
+    /// directories left to walk
+    dirs: Vec<PathBuf>,
+
+    /// files of the current directory left to read, in reverse order
+    files: Vec<PathBuf>,
+}
+
+impl SnapshotReader {
+    pub fn new<P: Into<PathBuf>>(base: P, filter: SnapshotFilter) -> Self {
+        Self {
+            filter,
+            dirs: vec![base.into()],
+            files: vec![],
+        }
+    }
+
+    /// Lists the next directory, queueing its subdirectories and snapshot files
+    fn read_dir(&mut self, dir: &Path) -> Result<(), ArchiveError> {
+        let io_err = |source| ArchiveError::Io {
+            path: dir.to_path_buf(),
+            source,
+        };
+
+        let mut entries = fs::read_dir(dir)
+            .and_then(|entries| {
+                entries
+                    .map(|e| e.map(|e| e.path()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(io_err)?;
+        entries.sort();
+
+        let mut dirs = vec![];
+        for path in entries {
+            if path.is_dir() {
+                if path.file_name().and_then(|n| n.to_str()) != Some(REPORTS_DIR) {
+                    dirs.push(path);
+                }
+                continue;
+            }
+
+            let file = match Self::snapshot_file(&path) {
+                Some(f) => f,
+                None => continue,
+            };
+            if self.filter.matches_file(&file) {
+                self.files.push(path);
+            }
+        }
+
+        self.files.reverse();
+        self.dirs.extend(dirs.into_iter().rev());
+        Ok(())
+    }
+
+    /// Name of a snapshot, possibly compressed, that isn't a diff or a run report
+    fn snapshot_file(path: &Path) -> Option<SnapshotFile> {
+        let file = SnapshotFile::parse(path)?;
+        match file.series.ends_with(".diff") || file.series == REPORTS_DIR {
+            true => None,
+            false => Some(file),
+        }
+    }
+
+    /// Reads `path`, detecting the format from its content
+    pub fn read(path: &Path) -> Result<ArchivedSnapshot, ArchiveError> {
+        let raw = fs::read(path).map_err(|source| ArchiveError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        let raw = Compression::detect(&raw)
+            .decode(&raw)
+            .map_err(|e| ArchiveError::Corrupt {
+                path: path.to_path_buf(),
+                message: e.to_string(),
+            })?;
+
+        let corrupt = |e: serde_json::Error| ArchiveError::Corrupt {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        };
+        let value = serde_json::from_slice::<serde_json::Value>(&raw).map_err(corrupt)?;
+
+        // struct of array snapshots have the slot times at the top level
+        let (format, data) = match value.get("time").is_some() {
+            true => (
+                OutputFormat::Soa,
+                serde_json::from_value::<GymSlotDataSoA>(value)
+                    .map(GymSlotData::from)
+                    .map_err(corrupt)?,
+            ),
+            false => (
+                OutputFormat::Json,
+                serde_json::from_value(value).map_err(corrupt)?,
+            ),
+        };
+
+        Ok(ArchivedSnapshot {
+            path: path.to_path_buf(),
+            format,
+            data,
+        })
+    }
+}
+
+impl Iterator for SnapshotReader {
+    type Item = Result<ArchivedSnapshot, ArchiveError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(path) = self.files.pop() {
+                match Self::read(&path) {
+                    Ok(s) if !self.filter.matches(&s) => continue,
+                    res => return Some(res),
+                }
+            }
+
+            let dir = self.dirs.pop()?;
+            if let Err(e) = self.read_dir(&dir) {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// Snapshots of an activity at a gym found in the archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeriesSummary {
+    pub snapshots: usize,
+
+    /// capture time of the oldest snapshot, in UTC
+    pub first: NaiveDateTime,
+
+    /// capture time of the newest snapshot, in UTC
+    pub last: NaiveDateTime,
+}
+
+/// Counts the snapshots of every activity and gym yielded by `reader`,
+/// along with the files that couldn't be read
+///
+/// Snapshots are grouped by the gym they were read as, so with [crate::aliases] installed
+/// the snapshots written under an old name are counted with the current gym
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     aliases::{self, AliasMap},
+///     archive::{self, SnapshotFilter, SnapshotReader},
+///     models::{Activity, Gym},
+/// };
+///
+/// let dir = std::env::temp_dir().join(format!("summarize-doctest-{}", std::process::id()));
+/// std::fs::create_dir_all(&dir).unwrap();
+///
+/// let snapshot = |gym: &str, at: &str| {
+///     format!(r#"{{"gym":"{}","datetime":"{}","data":[]}}"#, gym, at)
+/// };
+/// let files = [
+///     ("BISHAN_STADIUM", "2021-12-30T01:00:00"),
+///     ("BISHAN_STADIUM", "2021-12-31T01:00:00"),
+///     ("BISHAN", "2022-01-11T01:00:00"),
+///     ("CLEMENTI", "2022-01-11T01:00:00"),
+/// ];
+/// for (gym, at) in files {
+///     let name = format!("{}-{} 09-00-00.json", gym, &at[..10]);
+///     std::fs::write(dir.join(name), snapshot(gym, at)).unwrap();
+/// }
+///
+/// // the old name can't be read without the alias
+/// let (series, unreadable) = archive::summarize(SnapshotReader::new(&dir, Default::default()));
+/// assert_eq!(unreadable.len(), 2);
+/// assert_eq!(series[&(Activity::GYM, Gym::BISHAN.into())].snapshots, 1);
+///
+/// aliases::install(AliasMap::parse(r#"BISHAN_STADIUM = "BISHAN""#).unwrap());
+/// let filter = SnapshotFilter {
+///     gyms: vec![Gym::BISHAN],
+///     ..Default::default()
+/// };
+/// let (series, unreadable) = archive::summarize(SnapshotReader::new(&dir, filter));
+/// assert!(unreadable.is_empty());
+/// assert_eq!(series.len(), 1);
+///
+/// let bishan = series[&(Activity::GYM, Gym::BISHAN.into())];
+/// assert_eq!(bishan.snapshots, 3);
+/// assert_eq!(bishan.first.to_string(), "2021-12-30 01:00:00");
+/// assert_eq!(bishan.last.to_string(), "2022-01-11 01:00:00");
+///
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub fn summarize<I>(
+    reader: I,
+) -> (
+    BTreeMap<(Activity, Venue), SeriesSummary>,
+    Vec<ArchiveError>,
+)
+where
+    I: IntoIterator<Item = Result<ArchivedSnapshot, ArchiveError>>,
+{
+    let mut series = BTreeMap::<_, SeriesSummary>::new();
+    let mut unreadable = vec![];
+    for res in reader {
+        let data = match res {
+            Ok(s) => s.data,
+            Err(e) => {
+                unreadable.push(e);
+                continue;
+            }
+        };
+
+        let summary = series
+            .entry((data.activity(), data.gym()))
+            .or_insert(SeriesSummary {
+                snapshots: 0,
+                first: data.datetime(),
+                last: data.datetime(),
+            });
+        summary.snapshots += 1;
+        summary.first = summary.first.min(data.datetime());
+        summary.last = summary.last.max(data.datetime());
+    }
+    (series, unreadable)
+}
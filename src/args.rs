@@ -1,15 +1,525 @@
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, argh::FromArgs)]
+use std::{path::PathBuf, time::Duration};
+
+use activesg_gym_datamine::{
+    audit::{DEFAULT_AUDIT_SAMPLES, DEFAULT_DRIFT_TOLERANCE},
+    compress::Compression,
+    config::{parse_day_offsets, parse_days, parse_duration, ActivityVenues},
+    encryption::EncryptionKind,
+    export::{PartFormat, PartitionColumns},
+    gymset::GymSelection,
+    hours::HoursRange,
+    models::{Activity, Gym},
+    ratelimit::RequestRate,
+    schedule::OverlapPolicy,
+    sink::OutputFormat,
+    template::FilenameTemplate,
+    venue::Venue,
+    watch::WatchRule,
+};
+use chrono::NaiveDate;
+
+use crate::logging::LogFormat;
+
+fn parse_gym_selection(s: &str) -> Result<GymSelection, String> {
+    Ok(GymSelection::from(s))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, argh::FromArgs)]
 /// ActiveSG Slot Dataminer
 pub struct Args {
-    /// username
+    /// username, ACTIVESG_USERNAME when not given
     #[argh(option, short = 'u')]
-    pub username: String,
+    pub username: Option<String>,
 
-    /// users password
+    /// users password, ACTIVESG_PASSWORD when not given, else prompted for
     #[argh(option, short = 'p')]
-    pub password: String,
+    pub password: Option<String>,
+
+    /// TOML or YAML (`.yaml`, `.yml`) config file, flags take precedence over it
+    #[argh(option, short = 'c')]
+    pub config: Option<PathBuf>,
 
     /// output data in struct of array
     #[argh(switch, short = 's')]
     pub is_soa: bool,
+
+    /// append every snapshot as a single line to a JSON Lines file per day
+    #[argh(switch)]
+    pub jsonl: bool,
+
+    /// output format, json (default), soa, jsonl, or parquet when built with the parquet feature
+    #[argh(option)]
+    pub format: Option<OutputFormat>,
+
+    /// compression of the written files, none (default), gzip or zstd
+    #[argh(option)]
+    pub compress: Option<Compression>,
+
+    /// compression level, 0 to 9 for gzip (default 6), 1 to 22 for zstd (default 3)
+    #[argh(option)]
+    pub compress_level: Option<u32>,
+
+    /// name of the snapshots in the directory of their day, placeholders {{gym}}, {{gym_id}}, {{date}}, {{time}}, {{queried_date}} and {{ext}}, default "{{gym}}-{{date}} {{time}}.{{ext}}"
+    #[argh(option)]
+    pub filename_template: Option<FilenameTemplate>,
+
+    /// correct the queried dates using the clock skew measured from ActiveSG responses
+    #[argh(switch)]
+    pub trust_server_time: bool,
+
+    /// gyms to query, e.g. "all - (TAMPINES, PASIR_RIS)" or "region:EAST", see the README
+    #[argh(option, from_str_fn(parse_gym_selection))]
+    pub gyms: Option<GymSelection>,
+
+    /// gym never queried, e.g. while under renovation, may be repeated
+    #[argh(option)]
+    pub exclude_gyms: Vec<Gym>,
+
+    /// gym fetched first in every iteration, may be repeated
+    #[argh(option)]
+    pub priority_gym: Vec<Gym>,
+
+    /// venue missing from the known gyms, as ID:NAME, e.g. 1234:MY_GYM, may be repeated
+    #[argh(option)]
+    pub venue_id: Vec<Venue>,
+
+    /// time between the start of each iteration, e.g. 10m, at least 1m, defaults to 20m
+    #[argh(option, from_str_fn(parse_duration))]
+    pub interval: Option<Duration>,
+
+    /// iteration due while the previous one is still running, skip (default) or queue it
+    #[argh(option)]
+    pub overlap_policy: Option<OverlapPolicy>,
+
+    /// number of days queried from today, e.g. 5 for today through +4 days
+    #[argh(option, from_str_fn(parse_days))]
+    pub days: Option<Vec<i64>>,
+
+    /// days relative to today queried, e.g. 0,1,2,3, defaults to 0,2,3
+    #[argh(option, from_str_fn(parse_day_offsets))]
+    pub day_offsets: Option<Vec<i64>>,
+
+    /// run a single iteration and exit, failing when any fetch failed, e.g. from cron
+    #[argh(switch)]
+    pub once: bool,
+
+    /// fetch and parse as usual but only log the snapshots, writing nothing to the output directory
+    #[argh(switch)]
+    pub dry_run: bool,
+
+    /// delay between consecutive fetches, e.g. 500ms or 2s, defaults to 1s
+    #[argh(option, from_str_fn(parse_duration))]
+    pub inter_gym_delay: Option<Duration>,
+
+    /// fetches in flight at once, each paced by the inter gym delay, defaults to 1
+    #[argh(option)]
+    pub concurrency: Option<usize>,
+
+    /// requests per second sent to ActiveSG at most, logins and retries included, e.g. 0.5
+    #[argh(option)]
+    pub rps: Option<RequestRate>,
+
+    /// delay the first iteration by up to this much, e.g. 5m, so that instances started together are spread out
+    #[argh(option, from_str_fn(parse_duration))]
+    pub max_splay: Option<Duration>,
+
+    /// seed of the delay before the first iteration, defaults to the hostname and output directory
+    #[argh(option)]
+    pub splay_seed: Option<String>,
+
+    /// maximum number of fetches per iteration
+    #[argh(option)]
+    pub iteration_budget: Option<usize>,
+
+    /// time of day in Singapore to skip iterations in, catching up as soon as it ends, e.g. 06:00-08:00
+    #[argh(option)]
+    pub blackout: Option<HoursRange>,
+
+    /// maximum number of fetches of the iteration catching up after the blackout, defaults to twice the iteration budget
+    #[argh(option)]
+    pub catch_up_budget: Option<usize>,
+
+    /// pause fetching for the rest of the SGT day once this many MiB were downloaded
+    #[argh(option)]
+    pub max_daily_download_mb: Option<u64>,
+
+    /// remove snapshots older than this many days
+    #[argh(option)]
+    pub retention_days: Option<u32>,
+
+    /// keep at least this many snapshots per gym regardless of age
+    #[argh(option)]
+    pub retention_keep_per_gym: Option<usize>,
+
+    /// log which snapshots retention would remove without removing them
+    #[argh(switch)]
+    pub retention_dry_run: bool,
+
+    /// also query another activity at every gym queried, e.g. SWIMMING, may be repeated
+    #[argh(option)]
+    pub activity: Vec<Activity>,
+
+    /// also query another activity at the given venues, e.g. BADMINTON=BISHAN,CLEMENTI
+    #[argh(option)]
+    pub activity_venues: Vec<ActivityVenues>,
+
+    /// response header to record in the snapshot metadata, replaces the default allow-list
+    #[argh(option)]
+    pub capture_header: Vec<String>,
+
+    /// slots starting sooner than this after capture are marked past_cutoff, defaults to 30m
+    #[argh(option, from_str_fn(parse_duration))]
+    pub booking_cutoff: Option<Duration>,
+
+    /// keep gym slots outside of opening hours, for auditing
+    #[argh(switch)]
+    pub keep_out_of_hours: bool,
+
+    /// add a stable slot_id to every timeslot, for joining snapshots across time
+    #[argh(switch)]
+    pub slot_ids: bool,
+
+    /// add the official name of the gym to every snapshot, next to its identifier
+    #[argh(switch)]
+    pub gym_names: bool,
+
+    /// check at start and weekly that every gym id still leads to the gym, skipping those that don't
+    #[argh(switch)]
+    pub validate_venues: bool,
+
+    /// write usage statistics to stats.json in the output directory every hour, SIGUSR1 resets them
+    #[argh(switch)]
+    pub write_stats: bool,
+
+    /// also write the changes since the previous snapshot of the same gym and date to a .diff.json
+    #[argh(switch)]
+    pub emit_diffs: bool,
+
+    /// append every slot published, filled or reopened to events/<date>.ndjson in the output directory
+    #[argh(switch)]
+    pub event_log: bool,
+
+    /// bundle the files of every past day into <date>.tar.zst in the output directory once the day is over
+    #[argh(switch)]
+    pub compact_days: bool,
+
+    /// fetch without logging in while the credentials keep being rejected, retrying to login every 30 minutes
+    #[argh(switch)]
+    pub anonymous_fallback: bool,
+
+    /// attempts at publishing a snapshot before giving up, retries only go to the sinks that failed, defaults to 3
+    #[argh(option)]
+    pub publish_attempts: Option<u32>,
+
+    /// retries of a fetch failing with a timeout, a connection error or a 5xx, defaults to 2
+    #[argh(option)]
+    pub retries: Option<usize>,
+
+    /// backoff before the first retry in milliseconds, doubled before every following one, defaults to 500
+    #[argh(option)]
+    pub retry_base_ms: Option<u64>,
+
+    /// seconds a request to ActiveSG may take before failing with a timeout, retried like a connection error, defaults to 30
+    #[argh(option)]
+    pub timeout_secs: Option<u64>,
+
+    /// seconds connecting to ActiveSG may take, defaults to 10
+    #[argh(option)]
+    pub connect_timeout_secs: Option<u64>,
+
+    /// password encryption of the login widget, auto (default), pkcs1 or timestamp
+    #[argh(option)]
+    pub password_encryption: Option<EncryptionKind>,
+
+    /// file the session cookies are kept in between runs, reused rather than logging in again
+    #[argh(option)]
+    pub cookie_file: Option<PathBuf>,
+
+    /// command printing the one-time PIN when ActiveSG asks for one, run through the shell, prompted for on a terminal otherwise
+    #[argh(option)]
+    pub otp_command: Option<String>,
+
+    /// don't write a run report to <output>/<date>/runs after every iteration
+    #[argh(switch)]
+    pub no_run_reports: bool,
+
+    /// url sent a HEAD before alerting that ActiveSG is degraded, e.g. https://www.google.com
+    #[argh(option)]
+    pub reference_url: Option<String>,
+
+    /// alert when the p95 delivery latency of a sink stays above this, e.g. 2s
+    #[argh(option, from_str_fn(parse_duration))]
+    pub slow_sink_p95: Option<Duration>,
+
+    /// iterations in a row a sink must be slow, or fast again, before alerting, defaults to 3
+    #[argh(option)]
+    pub slow_sink_iterations: Option<usize>,
+
+    /// stop delivering to a slow sink until restart instead of only alerting
+    #[argh(switch)]
+    pub disable_slow_sinks: bool,
+
+    /// alert when a slot opens up at a gym and time in Singapore, e.g. BISHAN=19:00 or BISHAN=18:00-21:00, repeatable
+    #[argh(option)]
+    pub watch: Vec<WatchRule>,
+
+    /// token of the Telegram bot alerting of the watched slots, with --telegram-chat-id
+    #[argh(option)]
+    pub telegram_token: Option<String>,
+
+    /// chat the Telegram bot sends the alerts to
+    #[argh(option)]
+    pub telegram_chat_id: Option<String>,
+
+    /// webhook of a Discord channel an iteration is reported to when too many fetches failed or the login was rejected
+    #[argh(option)]
+    pub discord_webhook: Option<String>,
+
+    /// failed fetches of an iteration tolerated before reporting it to Discord, defaults to 5
+    #[argh(option)]
+    pub failure_alert_threshold: Option<usize>,
+
+    /// serve the HTTP API on this address, e.g. 127.0.0.1:8080
+    #[cfg(feature = "server")]
+    #[argh(option)]
+    pub http_listen: Option<std::net::SocketAddr>,
+
+    /// same as --http-listen
+    #[cfg(feature = "server")]
+    #[argh(option)]
+    pub serve: Option<std::net::SocketAddr>,
+
+    /// bearer token required to queue refreshes through the HTTP API
+    #[cfg(feature = "server")]
+    #[argh(option)]
+    pub api_token: Option<String>,
+
+    /// store the snapshots in this SQLite database instead of one JSON file each
+    #[cfg(feature = "sqlite")]
+    #[argh(option)]
+    pub sqlite: Option<PathBuf>,
+
+    /// upload the snapshots and diffs to this S3 bucket, with the credentials of the AWS environment
+    #[cfg(feature = "s3")]
+    #[argh(option)]
+    pub s3_bucket: Option<String>,
+
+    /// prefix of the keys uploaded to S3, e.g. miner/
+    #[cfg(feature = "s3")]
+    #[argh(option)]
+    pub s3_prefix: Option<String>,
+
+    /// only upload to S3, without writing to the output directory
+    #[cfg(feature = "s3")]
+    #[argh(switch)]
+    pub no_local: bool,
+
+    /// remove the local copy of a file once uploaded to S3
+    #[cfg(feature = "s3")]
+    #[argh(switch)]
+    pub s3_delete_local: bool,
+
+    /// save booking pages to this directory and replay them while fresh, for parser development
+    #[cfg(feature = "dev")]
+    #[argh(option)]
+    pub dev_artifacts: Option<PathBuf>,
+
+    /// seconds saved booking pages are replayed for, defaults to 3600
+    #[cfg(feature = "dev")]
+    #[argh(option)]
+    pub dev_artifacts_ttl_secs: Option<u64>,
+
+    /// start even when the configuration can exceed the built-in request rate ceiling
+    #[argh(switch)]
+    pub i_know_what_im_doing: bool,
+
+    /// log lines as text or json, one object per line, defaults to text
+    #[argh(option)]
+    pub log_format: Option<LogFormat>,
+
+    /// export the spans over OTLP/HTTP to the OpenTelemetry collector at this url,
+    /// such as http://localhost:4318
+    #[cfg(feature = "otel")]
+    #[argh(option)]
+    pub otlp_endpoint: Option<String>,
+
+    #[argh(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, argh::FromArgs)]
+#[argh(subcommand)]
+pub enum Command {
+    Setup(SetupCommand),
+    SelfTest(SelfTestCommand),
+    EncryptPassword(EncryptPasswordCommand),
+    Health(HealthCommand),
+    Summary(SummaryCommand),
+    Explore(ExploreCommand),
+    ExportDuckdb(ExportDuckdbCommand),
+    Tui(TuiCommand),
+    Audit(AuditCommand),
+    Compact(CompactCommand),
+    ListGyms(ListGymsCommand),
+    Query(QueryCommand),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, argh::FromArgs)]
+/// Interactively create a config file
+#[argh(subcommand, name = "setup")]
+pub struct SetupCommand {
+    /// where to write the config file
+    #[argh(option, short = 'o', default = "PathBuf::from(\"activesg.toml\")")]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, argh::FromArgs)]
+/// Check that the parsers still work against live pages, without writing any data
+#[argh(subcommand, name = "selftest")]
+pub struct SelfTestCommand {
+    /// gym whose booking page is fetched
+    #[argh(option, default = "Gym::BISHAN")]
+    pub gym: Gym,
+
+    /// days from today of the booking page fetched
+    #[argh(option, default = "1")]
+    pub day_offset: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, argh::FromArgs)]
+/// Encrypt a password the same way the login page does, prompting for the password
+#[argh(subcommand, name = "encrypt-password")]
+pub struct EncryptPasswordCommand {
+    /// PEM public key file, reads from stdin when omitted or -
+    #[argh(option, short = 'k')]
+    pub key: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, argh::FromArgs)]
+/// Check the heartbeat of a running miner, for container health checks
+#[argh(subcommand, name = "health")]
+pub struct HealthCommand {
+    /// seconds after which the last iteration is stale, defaults to twice the interval
+    #[argh(option)]
+    pub max_age_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, argh::FromArgs)]
+/// Count the archived snapshots of every gym in the output directory
+#[argh(subcommand, name = "summary")]
+pub struct SummaryCommand {
+    /// first capture date, in Singapore time, e.g. 2022-01-11
+    #[argh(option)]
+    pub from: Option<NaiveDate>,
+
+    /// last capture date, in Singapore time
+    #[argh(option)]
+    pub to: Option<NaiveDate>,
+
+    /// only count these gyms, a gym set such as "region:WEST + BISHAN"
+    #[argh(option, from_str_fn(parse_gym_selection))]
+    pub gyms: Option<GymSelection>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, argh::FromArgs)]
+/// Probe a range of venue ids for gyms missing from the known list, at most 50 ids per run
+#[argh(subcommand, name = "explore")]
+pub struct ExploreCommand {
+    /// first venue id probed
+    #[argh(option)]
+    pub from: u16,
+
+    /// last venue id probed, inclusive
+    #[argh(option)]
+    pub to: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, argh::FromArgs)]
+/// Export the snapshots captured since the last export to a hive-partitioned layout for DuckDB
+#[argh(subcommand, name = "export-duckdb")]
+pub struct ExportDuckdbCommand {
+    /// directory of the export, defaults to duckdb in the output directory
+    #[argh(option, short = 'o')]
+    pub output: Option<PathBuf>,
+
+    /// directory levels of the export, defaults to activity,venue,date
+    #[argh(option, default = "PartitionColumns::default()")]
+    pub partition_by: PartitionColumns,
+
+    /// format of the part files, parquet (default) when built with the parquet feature, or ndjson
+    #[argh(option, default = "PartFormat::default()")]
+    pub format: PartFormat,
+
+    /// size in MiB at which a part file is closed and the next started, defaults to 64
+    #[argh(option, default = "64")]
+    pub target_file_mb: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, argh::FromArgs)]
+/// Run the miner with a live dashboard of the latest availability, driven by keys
+#[argh(subcommand, name = "tui")]
+pub struct TuiCommand {
+    /// columns laid out for with --plain, defaults to $COLUMNS or 100
+    #[argh(option)]
+    pub width: Option<usize>,
+
+    /// print the dashboard again after every line typed instead of taking the whole terminal, e.g. for a dumb terminal
+    #[argh(switch)]
+    pub plain: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, argh::FromArgs)]
+/// Fetch again a sample of the archived snapshots that can still be booked and report what doesn't match
+#[argh(subcommand, name = "audit")]
+pub struct AuditCommand {
+    /// snapshots audited, spread over the archive, defaults to 20
+    #[argh(option, default = "DEFAULT_AUDIT_SAMPLES")]
+    pub samples: usize,
+
+    /// slots a count may have gone up by since capture through cancellations, defaults to 2
+    #[argh(option, default = "DEFAULT_DRIFT_TOLERANCE")]
+    pub tolerance: u16,
+
+    /// only audit this gym, may be repeated
+    #[argh(option)]
+    pub gym: Vec<Gym>,
+
+    /// print the report as JSON
+    #[argh(switch)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, argh::FromArgs)]
+/// List the known gyms with their venue id and official name
+#[argh(subcommand, name = "list-gyms")]
+pub struct ListGymsCommand {
+    /// print the list as JSON
+    #[argh(switch)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, argh::FromArgs)]
+/// Fetch the slots of a single gym and date and print them as JSON, without writing any file
+#[argh(subcommand, name = "query")]
+pub struct QueryCommand {
+    /// gym whose booking page is fetched
+    #[argh(option)]
+    pub gym: Gym,
+
+    /// date of the booking page, e.g. 2022-01-11, defaults to today in Singapore
+    #[argh(option)]
+    pub date: Option<NaiveDate>,
+
+    /// print the slots as a struct of arrays
+    #[argh(switch)]
+    pub soa: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, argh::FromArgs)]
+/// Bundle the files of every past day of the output directory into <date>.tar.zst
+#[argh(subcommand, name = "compact")]
+pub struct CompactCommand {
+    /// only bundle this day, in Singapore time, e.g. 2022-01-11
+    #[argh(option)]
+    pub date: Option<NaiveDate>,
 }
@@ -0,0 +1,522 @@
+//! Spot checks of the archive against live booking pages
+//!
+//! Snapshots whose slots can still be booked are [sample]d from the archive, their booking
+//! pages fetched again, paced by [Pacer::from_config], and parsed with the current parser.
+//! Availability keeps moving after capture, so only what drift can't explain is reported,
+//! see [compare]
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{
+    archive::{ArchivedSnapshot, SnapshotFilter, SnapshotReader},
+    clock::SGT,
+    config::Config,
+    fetch::{self, FetchTask, Fetcher},
+    models::{Activity, Gym, GymSlotData},
+    pacer::Pacer,
+    venue::Venue,
+    DataMResult,
+};
+
+/// Snapshots audited when not told otherwise
+pub const DEFAULT_AUDIT_SAMPLES: usize = 20;
+
+/// Slots a count may go up by between capture and audit, as bookings get cancelled
+pub const DEFAULT_DRIFT_TOLERANCE: u16 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditOptions {
+    /// at most this many snapshots are audited
+    pub samples: usize,
+
+    /// see [DEFAULT_DRIFT_TOLERANCE]
+    pub drift_tolerance: u16,
+
+    /// only audit these gyms, every gym when empty
+    pub gyms: Vec<Gym>,
+}
+
+impl Default for AuditOptions {
+    fn default() -> Self {
+        Self {
+            samples: DEFAULT_AUDIT_SAMPLES,
+            drift_tolerance: DEFAULT_DRIFT_TOLERANCE,
+            gyms: vec![],
+        }
+    }
+}
+
+/// Difference between an archived snapshot and the live page that drift doesn't explain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Discrepancy {
+    /// the archive has a slot the live page doesn't list
+    MissingLive { time: DateTime<Utc> },
+
+    /// the live page lists a slot the archive doesn't have
+    MissingArchived { time: DateTime<Utc> },
+
+    /// more slots left than the archive had, beyond the drift tolerance
+    ImpossibleIncrease {
+        time: DateTime<Utc>,
+        archived: u16,
+        live: u16,
+    },
+}
+
+impl Discrepancy {
+    pub fn time(&self) -> DateTime<Utc> {
+        match self {
+            Self::MissingLive { time }
+            | Self::MissingArchived { time }
+            | Self::ImpossibleIncrease { time, .. } => *time,
+        }
+    }
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let at = self.time().with_timezone(&*SGT).format("%H:%M");
+        match self {
+            Self::MissingLive { .. } => write!(f, "{} archived, missing from the live page", at),
+            Self::MissingArchived { .. } => write!(f, "{} live, missing from the archive", at),
+            Self::ImpossibleIncrease { archived, live, .. } => {
+                write!(f, "{} {} left archived, {} left live", at, archived, live)
+            }
+        }
+    }
+}
+
+/// Outcome of the audit of a single archived snapshot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditedSnapshot {
+    pub path: PathBuf,
+    pub activity: Activity,
+    pub gym: Venue,
+
+    /// of the slots, in Singapore time
+    pub date: NaiveDate,
+
+    /// in UTC
+    pub captured_at: NaiveDateTime,
+
+    /// slots still bookable on both sides
+    pub compared: usize,
+
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+/// Booking page that couldn't be fetched again, its snapshots weren't audited
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FetchFailure {
+    pub activity: Activity,
+    pub gym: Venue,
+    pub date: NaiveDate,
+    pub message: String,
+    pub snapshots: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AuditReport {
+    /// archived snapshots with slots still bookable, which were sampled from
+    pub bookable: usize,
+
+    /// snapshots that couldn't be read
+    pub unreadable: usize,
+
+    pub audited: Vec<AuditedSnapshot>,
+    pub fetch_failures: Vec<FetchFailure>,
+}
+
+impl AuditReport {
+    /// Audited snapshots with at least one discrepancy
+    pub fn suspicious(&self) -> impl Iterator<Item = &AuditedSnapshot> {
+        self.audited.iter().filter(|a| !a.discrepancies.is_empty())
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.suspicious().next().is_none()
+    }
+}
+
+/// One block per suspicious snapshot, then a summary line
+impl fmt::Display for AuditReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for audited in self.suspicious() {
+            writeln!(
+                f,
+                "{:?} {:?} {}, captured {} UTC, {}",
+                audited.activity,
+                audited.gym,
+                audited.date,
+                audited.captured_at,
+                audited.path.display()
+            )?;
+            for discrepancy in &audited.discrepancies {
+                writeln!(f, "  {}", discrepancy)?;
+            }
+        }
+        for failure in &self.fetch_failures {
+            writeln!(
+                f,
+                "{:?} {:?} {}: not audited, {}",
+                failure.activity, failure.gym, failure.date, failure.message
+            )?;
+        }
+        write!(
+            f,
+            "{} of {} bookable snapshots audited, {} with discrepancies, {} fetches failed, {} unreadable",
+            self.audited.len(),
+            self.bookable,
+            self.suspicious().count(),
+            self.fetch_failures.len(),
+            self.unreadable
+        )
+    }
+}
+
+/// Date in Singapore time of the slots of `data`, when at least one of them starts after `now`
+fn bookable_date(data: &GymSlotData, now: DateTime<Utc>) -> Option<NaiveDate> {
+    data.data()
+        .iter()
+        .find(|s| s.time() > now)
+        .map(|s| s.time().with_timezone(&*SGT).date().naive_local())
+}
+
+/// Picks up to `n` snapshots spread evenly over the capture times of `candidates`
+///
+/// Every part of the archive gets its share rather than only the latest captures, so that
+/// a parsing bug of a few days is likely to be sampled. The sample is deterministic,
+/// auditing the same archive twice checks the same snapshots
+///
+/// ## Example
+/// ```
+/// use std::path::PathBuf;
+/// use activesg_gym_datamine::{
+///     archive::ArchivedSnapshot,
+///     audit,
+///     models::{Gym, GymSlotData},
+///     sink::OutputFormat,
+/// };
+/// use chrono::NaiveDate;
+///
+/// let snapshots = (0..10)
+///     .rev()
+///     .map(|hour| ArchivedSnapshot {
+///         path: PathBuf::from(format!("{}.json", hour)),
+///         format: OutputFormat::Json,
+///         data: GymSlotData::new(
+///             Gym::BISHAN,
+///             NaiveDate::from_ymd(2022, 1, 11).and_hms(hour, 0, 0),
+///             vec![],
+///         ),
+///     })
+///     .collect::<Vec<_>>();
+///
+/// let hours = |n| {
+///     audit::sample(snapshots.clone(), n)
+///         .iter()
+///         .map(|s| s.path.to_str().unwrap().to_string())
+///         .collect::<Vec<_>>()
+/// };
+/// assert_eq!(hours(3), ["0.json", "3.json", "6.json"]);
+/// assert_eq!(hours(5), ["0.json", "2.json", "4.json", "6.json", "8.json"]);
+/// assert_eq!(hours(20).len(), 10);
+/// assert!(hours(0).is_empty());
+/// ```
+pub fn sample(mut candidates: Vec<ArchivedSnapshot>, n: usize) -> Vec<ArchivedSnapshot> {
+    candidates.sort_by(|a, b| (a.data.datetime(), &a.path).cmp(&(b.data.datetime(), &b.path)));
+    if n >= candidates.len() {
+        return candidates;
+    }
+
+    let len = candidates.len();
+    let picked = (0..n).map(|i| i * len / n).collect::<Vec<_>>();
+    candidates
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| picked.contains(i))
+        .map(|(_, s)| s)
+        .collect()
+}
+
+/// Differences between `archived` and `live` that drift since capture doesn't explain,
+/// along with the number of slots compared
+///
+/// Only the slots starting after `now` are compared, the live page may not list the others.
+/// Their times must be the same on both sides. A count may go down by any amount as slots
+/// get booked, and up by at most `tolerance` as bookings get cancelled. Counts that are
+/// hidden, or live past the booking cut-off, aren't compared
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     audit::{self, Discrepancy},
+///     models::{Gym, GymSlotData, Timeslot},
+/// };
+/// use chrono::{NaiveDate, TimeZone, Utc};
+///
+/// // 10:00 SGT
+/// let now = Utc.ymd(2022, 1, 11).and_hms(2, 0, 0);
+/// let at = |hour| Utc.ymd(2022, 1, 11).and_hms(hour, 0, 0);
+/// let snapshot = |slots: &[(u32, u16)]| {
+///     let slots = slots.iter().map(|(h, n)| Timeslot::new(at(*h), *n)).collect();
+///     GymSlotData::new(Gym::BISHAN, NaiveDate::from_ymd(2022, 1, 10).and_hms(0, 0, 0), slots)
+/// };
+///
+/// let archived = snapshot(&[(1, 20), (3, 25), (4, 3), (5, 10), (6, 8)]);
+/// // 09:00 is gone from the live page, it already started
+/// let live = snapshot(&[(3, 0), (4, 5), (5, 30), (7, 2)]);
+///
+/// let (compared, discrepancies) = audit::compare(&archived, &live, now, 2);
+/// assert_eq!(compared, 3);
+/// assert_eq!(
+///     discrepancies,
+///     vec![
+///         Discrepancy::ImpossibleIncrease { time: at(5), archived: 10, live: 30 },
+///         Discrepancy::MissingLive { time: at(6) },
+///         Discrepancy::MissingArchived { time: at(7) },
+///     ]
+/// );
+/// assert_eq!(discrepancies[0].to_string(), "13:00 10 left archived, 30 left live");
+/// assert_eq!(discrepancies[1].to_string(), "14:00 archived, missing from the live page");
+///
+/// // 3 left going to 5 is a cancellation too many without tolerance
+/// let (_, discrepancies) = audit::compare(&archived, &live, now, 0);
+/// assert!(discrepancies.contains(&Discrepancy::ImpossibleIncrease {
+///     time: at(4),
+///     archived: 3,
+///     live: 5
+/// }));
+///
+/// let (compared, discrepancies) = audit::compare(&archived, &archived, now, 0);
+/// assert_eq!((compared, discrepancies.len()), (4, 0));
+/// ```
+pub fn compare(
+    archived: &GymSlotData,
+    live: &GymSlotData,
+    now: DateTime<Utc>,
+    tolerance: u16,
+) -> (usize, Vec<Discrepancy>) {
+    let upcoming = |data: &GymSlotData| {
+        data.data()
+            .iter()
+            .filter(|s| s.time() > now)
+            .map(|s| (s.time(), s.clone()))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let (archived, live) = (upcoming(archived), upcoming(live));
+
+    let mut compared = 0;
+    let mut discrepancies = vec![];
+    for (time, slot) in &archived {
+        let fresh = match live.get(time) {
+            Some(fresh) => fresh,
+            None => {
+                discrepancies.push(Discrepancy::MissingLive { time: *time });
+                continue;
+            }
+        };
+        compared += 1;
+
+        if slot.count_hidden() || fresh.count_hidden() || fresh.past_cutoff() {
+            continue;
+        }
+        if fresh.slots_avail() > slot.slots_avail().saturating_add(tolerance) {
+            discrepancies.push(Discrepancy::ImpossibleIncrease {
+                time: *time,
+                archived: slot.slots_avail(),
+                live: fresh.slots_avail(),
+            });
+        }
+    }
+
+    discrepancies.extend(
+        live.keys()
+            .filter(|time| !archived.contains_key(time))
+            .map(|time| Discrepancy::MissingArchived { time: *time }),
+    );
+    discrepancies.sort_by_key(|d| d.time());
+    (compared, discrepancies)
+}
+
+/// Audits a sample of the snapshots under `archive` whose slots can still be booked
+///
+/// Every booking page is fetched once however many of its snapshots were sampled,
+/// with the pacing of the daemon between fetches
+///
+/// ## Example
+/// ```
+/// use std::sync::Arc;
+/// use activesg_gym_datamine::{
+///     audit::{self, AuditOptions, Discrepancy},
+///     clock::FakeClock,
+///     config::Config,
+///     fetch::FakeFetcher,
+///     models::{Gym, User},
+/// };
+/// use chrono::{TimeZone, Utc};
+///
+/// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+/// # async fn main() {
+/// let dir = std::env::temp_dir().join(format!("audit-doctest-{}", std::process::id()));
+/// std::fs::create_dir_all(&dir).unwrap();
+///
+/// // slots at 10:00, 11:00 and 12:00 SGT
+/// let archived = |gym: &str, date: &str, slots: [u16; 3]| {
+///     let data = slots
+///         .iter()
+///         .enumerate()
+///         .map(|(i, n)| {
+///             let start = Utc.ymd(2022, 1, 12).and_hms(2 + i as u32, 0, 0);
+///             let start = start.to_rfc3339().replace("2022-01-12", date);
+///             format!(r#"{{"time":"{}","slots_avail":{}}}"#, start, n)
+///         })
+///         .collect::<Vec<_>>()
+///         .join(",");
+///     let json = format!(
+///         r#"{{"gym":"{}","datetime":"2022-01-11T01:00:00","data":[{}]}}"#,
+///         gym, data
+///     );
+///     std::fs::write(dir.join(format!("{}-{} 09-00-00.json", gym, date)), json).unwrap();
+/// };
+/// archived("BISHAN", "2022-01-12", [25, 3, 10]);
+/// archived("CLEMENTI", "2022-01-12", [5, 5, 5]);
+/// // no longer bookable
+/// archived("HOUGANG", "2022-01-10", [5, 5, 5]);
+///
+/// let fetcher = FakeFetcher::new(|task| {
+///     Ok(match task.gym.gym() {
+///         // 11:00 went from 3 to 30 left and 12:00 turned into 13:00
+///         Some(Gym::BISHAN) => "<label>10:00 AM</label><label>20 Left</label>
+///             <label>11:00 AM</label><label>30 Left</label>
+///             <label>01:00 PM</label><label>5 Left</label>",
+///         _ => "<label>10:00 AM</label><label>4 Left</label>
+///             <label>11:00 AM</label><label>6 Left</label>
+///             <label>12:00 PM</label><label>0 Left</label>",
+///     }
+///     .replace("<label>10", r#"<div class="chkbox-grid"><label>10"#)
+///         + "</div>")
+/// })
+/// // 09:00 SGT on the day before the slots
+/// .with_clock(Arc::new(FakeClock::new(Utc.ymd(2022, 1, 11).and_hms(1, 0, 0))));
+/// let config = Config::new(User::new("user@example.com", "password"));
+///
+/// let report = audit::run(&fetcher, &config, &dir, &AuditOptions::default())
+///     .await
+///     .unwrap();
+/// assert_eq!(report.bookable, 2);
+/// assert_eq!(report.audited.len(), 2);
+///
+/// let suspicious = report.suspicious().collect::<Vec<_>>();
+/// assert_eq!(suspicious.len(), 1);
+/// assert_eq!(suspicious[0].gym, Gym::BISHAN);
+/// assert_eq!(suspicious[0].compared, 2);
+/// let kinds = suspicious[0]
+///     .discrepancies
+///     .iter()
+///     .map(|d| d.to_string())
+///     .collect::<Vec<_>>();
+/// assert_eq!(
+///     kinds,
+///     [
+///         "11:00 3 left archived, 30 left live",
+///         "12:00 archived, missing from the live page",
+///         "13:00 live, missing from the archive",
+///     ]
+/// );
+///
+/// let text = report.to_string();
+/// assert!(text.starts_with("GYM BISHAN 2022-01-12, captured 2022-01-11 01:00:00 UTC"), "{}", text);
+/// assert!(text.ends_with(
+///     "2 of 2 bookable snapshots audited, 1 with discrepancies, 0 fetches failed, 0 unreadable"
+/// ));
+///
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// # }
+/// ```
+pub async fn run<F>(
+    fetcher: &F,
+    config: &Config,
+    archive: &Path,
+    options: &AuditOptions,
+) -> DataMResult<AuditReport>
+where
+    F: Fetcher + Sync,
+{
+    let now = fetcher.now();
+    let filter = SnapshotFilter {
+        gyms: options.gyms.clone(),
+        ..Default::default()
+    };
+
+    let mut report = AuditReport::default();
+    let mut by_task = BTreeMap::<FetchTask, Vec<ArchivedSnapshot>>::new();
+    let mut candidates = vec![];
+    for res in SnapshotReader::new(archive, filter) {
+        match res {
+            Ok(s) if bookable_date(&s.data, now).is_some() => candidates.push(s),
+            Ok(_) => (),
+            Err(e) => {
+                warn!("{}", e);
+                report.unreadable += 1;
+            }
+        }
+    }
+    report.bookable = candidates.len();
+
+    for snapshot in sample(candidates, options.samples) {
+        let date = bookable_date(&snapshot.data, now).unwrap();
+        let task = FetchTask::new(snapshot.data.activity(), snapshot.data.gym(), date);
+        by_task.entry(task).or_default().push(snapshot);
+    }
+
+    let pacer = Pacer::from_config(config);
+    for (i, (task, snapshots)) in by_task.into_iter().enumerate() {
+        if i > 0 {
+            pacer.between_fetches(task).await;
+        }
+
+        let live = match fetch::fetch_slots(fetcher, config, task).await {
+            Ok(live) => live,
+            Err(e) => {
+                report.fetch_failures.push(FetchFailure {
+                    activity: task.activity,
+                    gym: task.gym,
+                    date: task.date,
+                    message: e.to_string(),
+                    snapshots: snapshots.len(),
+                });
+                continue;
+            }
+        };
+
+        for snapshot in snapshots {
+            let (compared, discrepancies) = compare(
+                &snapshot.data,
+                &live,
+                fetcher.now(),
+                options.drift_tolerance,
+            );
+            report.audited.push(AuditedSnapshot {
+                path: snapshot.path,
+                activity: task.activity,
+                gym: task.gym,
+                date: task.date,
+                captured_at: snapshot.data.datetime(),
+                compared,
+                discrepancies,
+            });
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,135 @@
+use std::{sync::Mutex, time::Duration};
+
+use chrono::{DateTime, Utc};
+use tracing::{error, info};
+
+/// How booking pages are fetched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchMode {
+    /// logged in, every slot count is shown
+    Authenticated,
+
+    /// without logging in, some counts may be hidden
+    Anonymous,
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    /// logins rejected in a row
+    failures: u32,
+
+    /// when the breaker last opened, none while closed
+    opened_at: Option<DateTime<Utc>>,
+}
+
+/// Switches to anonymous fetches once the credentials keep being rejected
+///
+/// After [CredentialBreaker::failure_threshold] rejected logins in a row the breaker opens and
+/// pages are fetched anonymously. Once [CredentialBreaker::probe_interval] has passed, the
+/// next fetch tries to login again, half-open: a success closes the breaker, a failure opens
+/// it for another interval
+///
+/// ## Example
+/// ```
+/// use std::time::Duration;
+/// use activesg_gym_datamine::breaker::{CredentialBreaker, FetchMode};
+/// use chrono::{TimeZone, Utc};
+///
+/// let breaker = CredentialBreaker::new(2, Duration::from_secs(30 * 60));
+/// let at = |min| Utc.ymd(2022, 1, 11).and_hms(0, 0, 0) + chrono::Duration::minutes(min);
+///
+/// breaker.record_failure(at(0));
+/// assert_eq!(breaker.mode(at(0)), FetchMode::Authenticated);
+/// breaker.record_failure(at(1));
+/// assert_eq!(breaker.mode(at(1)), FetchMode::Anonymous);
+/// assert_eq!(breaker.mode(at(30)), FetchMode::Anonymous);
+///
+/// // half-open, the probe fails
+/// assert_eq!(breaker.mode(at(31)), FetchMode::Authenticated);
+/// breaker.record_failure(at(31));
+/// assert_eq!(breaker.mode(at(32)), FetchMode::Anonymous);
+///
+/// // half-open again, the probe succeeds
+/// assert_eq!(breaker.mode(at(61)), FetchMode::Authenticated);
+/// breaker.record_success();
+/// assert_eq!(breaker.mode(at(62)), FetchMode::Authenticated);
+///
+/// // a single failure after recovering doesn't open it
+/// breaker.record_failure(at(63));
+/// assert_eq!(breaker.mode(at(63)), FetchMode::Authenticated);
+/// ```
+#[derive(Debug)]
+pub struct CredentialBreaker {
+    failure_threshold: u32,
+    probe_interval: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl Default for CredentialBreaker {
+    fn default() -> Self {
+        Self::new(
+            Self::DEFAULT_FAILURE_THRESHOLD,
+            Self::DEFAULT_PROBE_INTERVAL,
+        )
+    }
+}
+
+impl CredentialBreaker {
+    pub const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+    pub const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+    pub fn new(failure_threshold: u32, probe_interval: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            probe_interval,
+            state: Mutex::default(),
+        }
+    }
+
+    pub fn failure_threshold(&self) -> u32 {
+        self.failure_threshold
+    }
+
+    pub fn probe_interval(&self) -> Duration {
+        self.probe_interval
+    }
+
+    /// How the next page should be fetched at `now`
+    pub fn mode(&self, now: DateTime<Utc>) -> FetchMode {
+        let state = self.state.lock().unwrap();
+        match state.opened_at {
+            Some(opened_at)
+                if (now - opened_at).to_std().unwrap_or_default() < self.probe_interval =>
+            {
+                FetchMode::Anonymous
+            }
+            _ => FetchMode::Authenticated,
+        }
+    }
+
+    /// A login was rejected at `now`
+    pub fn record_failure(&self, now: DateTime<Utc>) {
+        let mut state = self.state.lock().unwrap();
+        state.failures += 1;
+
+        if state.failures >= self.failure_threshold {
+            if state.opened_at.is_none() {
+                error!(
+                    "ALERT: login rejected {} times in a row, fetching anonymously and retrying every {}s",
+                    state.failures,
+                    self.probe_interval.as_secs()
+                );
+            }
+            state.opened_at = Some(now);
+        }
+    }
+
+    /// A login succeeded
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.opened_at.take().is_some() {
+            info!("Logged in again, back to authenticated fetches");
+        }
+        state.failures = 0;
+    }
+}
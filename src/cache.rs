@@ -0,0 +1,122 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+
+use crate::{
+    models::{Activity, Gym, GymSlotData},
+    venue::Venue,
+};
+
+/// Key of a snapshot in the [SnapshotCache]
+pub type SnapshotKey = (Activity, Venue, NaiveDate);
+
+/// Last known good snapshot, served as is during an outage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedSnapshot {
+    pub data: GymSlotData,
+
+    /// seconds since the snapshot was captured
+    pub age_seconds: i64,
+
+    /// the latest fetch of the snapshot failed, so it may not reflect the current availability
+    pub stale: bool,
+}
+
+/// Latest successful snapshot of every activity, gym and queried date
+///
+/// A failed fetch keeps the previous snapshot and only marks it stale,
+/// a failure is no new information about the availability
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     cache::SnapshotCache,
+///     models::{Activity, Gym, GymSlotData, Timeslot},
+/// };
+/// use chrono::{Duration, NaiveDate, Utc};
+///
+/// let cache = SnapshotCache::default();
+/// let date = NaiveDate::from_ymd(2022, 1, 11);
+/// let key = (Activity::GYM, Gym::BISHAN.into(), date);
+/// let captured = Utc::now() - Duration::minutes(20);
+/// let slots = vec![Timeslot::new(Utc::now(), 25)];
+/// cache.insert(date, GymSlotData::new(Gym::BISHAN, captured.naive_utc(), slots));
+///
+/// // outage, the last snapshot is kept
+/// cache.mark_failed(key);
+/// let cached = cache.latest(&key, Utc::now()).unwrap();
+/// assert!(cached.stale);
+/// assert!(cached.age_seconds >= 20 * 60);
+/// assert_eq!(cached.data.data()[0].slots_avail(), 25);
+///
+/// // recovered
+/// cache.insert(date, GymSlotData::new(Gym::BISHAN, Utc::now().naive_utc(), vec![]));
+/// assert!(!cache.latest(&key, Utc::now()).unwrap().stale);
+/// ```
+#[derive(Debug, Default)]
+pub struct SnapshotCache {
+    snapshots: RwLock<HashMap<SnapshotKey, GymSlotData>>,
+
+    /// keys whose latest fetch failed
+    failing: RwLock<HashSet<SnapshotKey>>,
+}
+
+impl SnapshotCache {
+    /// Stores `data` as the latest snapshot of `date`, replacing the previous one
+    pub fn insert(&self, date: NaiveDate, data: GymSlotData) {
+        let key = (data.activity(), data.gym(), date);
+        self.failing.write().unwrap().remove(&key);
+        self.snapshots.write().unwrap().insert(key, data);
+    }
+
+    /// Records that fetching `key` failed, keeping its last snapshot
+    pub fn mark_failed(&self, key: SnapshotKey) {
+        self.failing.write().unwrap().insert(key);
+    }
+
+    pub fn get(&self, key: &SnapshotKey) -> Option<GymSlotData> {
+        self.snapshots.read().unwrap().get(key).cloned()
+    }
+
+    /// Last known good snapshot of `key` with its age at `now` and whether it is stale
+    pub fn latest(&self, key: &SnapshotKey, now: DateTime<Utc>) -> Option<CachedSnapshot> {
+        let data = self.get(key)?;
+        Some(CachedSnapshot {
+            age_seconds: (now.naive_utc() - data.datetime()).num_seconds(),
+            stale: self.failing.read().unwrap().contains(key),
+            data,
+        })
+    }
+
+    /// Queried dates and snapshots of `gym` captured by `iteration`
+    pub fn captured_by(&self, iteration: u64, gym: Gym) -> Vec<(NaiveDate, GymSlotData)> {
+        self.snapshots
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|((activity, g, _), data)| {
+                *activity == Activity::GYM
+                    && *g == gym
+                    && data.metadata().iteration == Some(iteration)
+            })
+            .map(|((_, _, date), data)| (*date, data.clone()))
+            .collect()
+    }
+
+    /// Capture time of the most recent successful snapshot of each known gym, across all dates
+    pub fn last_success(&self) -> HashMap<Gym, NaiveDateTime> {
+        let mut buf = HashMap::<Gym, NaiveDateTime>::new();
+        for ((_, venue, _), data) in self.snapshots.read().unwrap().iter() {
+            let gym = match venue.gym() {
+                Some(gym) => gym,
+                None => continue,
+            };
+            let entry = buf.entry(gym).or_insert_with(|| data.datetime());
+            *entry = (*entry).max(data.datetime());
+        }
+        buf
+    }
+}
@@ -0,0 +1,434 @@
+//! Steps of a login, from the sign in form to the profile, see [LoginStep]
+//!
+//! ActiveSG may ask for a one-time PIN after the password, e.g. when logging in from
+//! a new IP. The PIN is then taken from an [OtpSource] and posted with the form of the
+//! page asking for it
+//!
+//! ## Example
+//! A sign in answered with the OTP page, which only the right PIN gets through
+//! ```
+//! use activesg_gym_datamine::{
+//!     client::{login::OtpSource, DataMiner},
+//!     errors::Error,
+//!     models::User,
+//! };
+//! use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}};
+//!
+//! const LOGIN: &str = r#"<html><body><form id="formSignin" action="/auth/signin" method="post">
+//!     <input type="hidden" name="_csrf" value="WkRfc1BjQ2w0Lmc">
+//!     <input type="hidden" name="rsapublickey" value="-----BEGIN PUBLIC KEY-----
+//! MFwwDQYJKoZIhvcNAQEBBQADSwAwSAJBANtFLKv5ebeCtNwkO9mbMeZCzZS9/YaD
+//! M9vIk9fm74YcqGbYlfqIbn7yfg9ZzKGzIIpU9U35b0jPPwxHQ+CPUYECAwEAAQ==
+//! -----END PUBLIC KEY-----">
+//!     <input type="email" name="email"><input type="password" name="password">
+//! </form></body></html>"#;
+//! const OTP: &str = r#"<html><body><form id="formOtp" action="/auth/otp" method="post">
+//!     <input type="hidden" name="_csrf" value="T3RwQ3NyZg">
+//!     <p>A one-time PIN has been sent to your mobile number ending with 1234</p>
+//!     <input type="text" name="otp" inputmode="numeric" maxlength="6">
+//!     <button type="submit">Verify</button>
+//! </form></body></html>"#;
+//!
+//! # async fn serve(mut socket: TcpStream) {
+//! #     let mut request = vec![];
+//! #     let mut buf = [0; 4096];
+//! #     let (head, body) = loop {
+//! #         let n = socket.read(&mut buf).await.unwrap();
+//! #         request.extend_from_slice(&buf[..n]);
+//! #         let text = String::from_utf8_lossy(&request).to_string();
+//! #         if let Some(end) = text.find("\r\n\r\n") {
+//! #             let len = text[..end]
+//! #                 .lines()
+//! #                 .find_map(|l| {
+//! #                     let l = l.to_ascii_lowercase();
+//! #                     l.strip_prefix("content-length: ").map(|v| v.trim().parse().unwrap())
+//! #                 })
+//! #                 .unwrap_or(0);
+//! #             if request.len() >= end + 4 + len {
+//! #                 break (text[..end].to_string(), text[end + 4..].to_string());
+//! #             }
+//! #         }
+//! #     };
+//! #     let line = head.lines().next().unwrap();
+//! #     let pin_accepted = body.contains("otp=123456") && body.contains("_csrf=T3RwQ3NyZg");
+//! #     let (status, page) = match line.split(' ').take(2).collect::<Vec<_>>()[..] {
+//! #         ["GET", "/auth"] => ("200 OK", LOGIN),
+//! #         ["POST", "/auth/signin"] => ("200 OK", OTP),
+//! #         ["POST", "/auth/otp"] if pin_accepted => ("302 Found\r\nlocation: /profile", ""),
+//! #         ["POST", "/auth/otp"] => ("200 OK", OTP),
+//! #         ["GET", "/profile"] => ("200 OK", "profile"),
+//! #         _ => ("404 Not Found", ""),
+//! #     };
+//! #     let response = format!(
+//! #         "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+//! #         status,
+//! #         page.len(),
+//! #         page
+//! #     );
+//! #     socket.write_all(response.as_bytes()).await.unwrap();
+//! # }
+//! #
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+//! let url = format!("http://{}", listener.local_addr().unwrap());
+//! // answers the sign in with the OTP page, and the PIN 123456 with the profile
+//! tokio::spawn(async move {
+//!     loop {
+//!         let (socket, _) = listener.accept().await.unwrap();
+//!         tokio::spawn(serve(socket));
+//!     }
+//! });
+//!
+//! let login = |otp| {
+//!     let miner = DataMiner::new(User::new("me@example.com", "hunter2"))
+//!         .with_base_url(&url)
+//!         .with_otp(otp);
+//!     async move { miner.check_login().await }
+//! };
+//!
+//! assert!(login(Some(OtpSource::Command("echo 123456".into()))).await.is_ok());
+//!
+//! let rejected = login(Some(OtpSource::Command("echo 654321".into()))).await.unwrap_err();
+//! assert!(matches!(rejected, Error::OtpRejected));
+//!
+//! // nowhere to take the PIN from, e.g. without a terminal
+//! let required = login(None).await.unwrap_err();
+//! assert!(matches!(required, Error::OtpRequired));
+//! assert_eq!(required.class(), "one-time pin required");
+//! # }
+//! ```
+
+use std::{
+    io::{self, BufRead, Write},
+    process::Stdio,
+    time::Duration,
+};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::Url;
+use scraper::{Html, Selector};
+
+use crate::{errors, models::interstitial_parser, DataMResult};
+
+lazy_static! {
+    /// Regex for the name of the input the PIN is typed in
+    ///
+    /// ## Example of PIN inputs
+    /// - otp
+    /// - otp_code
+    /// - pin
+    /// - verificationCode
+    static ref OTP_INPUT_RE: Regex = Regex::new(r"(?i)otp|pin|code").unwrap();
+}
+
+/// Longest an [OtpSource::Command] may take, e.g. waiting for the SMS to come in
+pub const OTP_COMMAND_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Where the one-time PIN comes from when ActiveSG asks for one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OtpSource {
+    /// asks for it on stderr, reading it from stdin
+    Prompt,
+
+    /// runs the command through the shell, the PIN being the first line it prints
+    Command(String),
+}
+
+impl OtpSource {
+    /// The PIN, trimmed
+    ///
+    /// [errors::Error::OtpCommand] when the command fails or prints nothing,
+    /// [errors::Error::OtpRequired] when nothing is typed at the prompt
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::{client::login::OtpSource, errors::Error};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let code = |command: &str| {
+    ///     let source = OtpSource::Command(command.into());
+    ///     async move { source.code().await }
+    /// };
+    ///
+    /// assert_eq!(code("echo 123456").await.unwrap(), "123456");
+    /// assert_eq!(code("echo  042042 ").await.unwrap(), "042042");
+    ///
+    /// let e = code("exit 3").await.unwrap_err();
+    /// assert!(matches!(e, Error::OtpCommand(_)));
+    /// assert_eq!(e.class(), "one-time pin command failed");
+    /// assert!(!e.is_retryable());
+    ///
+    /// assert!(matches!(code("echo").await, Err(Error::OtpCommand(_))));
+    /// # }
+    /// ```
+    pub async fn code(&self) -> DataMResult<String> {
+        match self {
+            Self::Prompt => {
+                let line = tokio::task::spawn_blocking(prompt)
+                    .await
+                    .unwrap_or_else(|e| Err(io::Error::other(e)))?;
+                match line.trim() {
+                    "" => Err(errors::Error::OtpRequired),
+                    code => Ok(code.to_string()),
+                }
+            }
+            Self::Command(command) => {
+                let output = run(command).await?;
+                output
+                    .lines()
+                    .map(str::trim)
+                    .find(|l| !l.is_empty())
+                    .map(|l| l.to_string())
+                    .ok_or_else(|| {
+                        errors::Error::OtpCommand(format!("{} printed no one-time PIN", command))
+                    })
+            }
+        }
+    }
+}
+
+/// Reads a line from stdin after asking for the PIN on stderr
+fn prompt() -> io::Result<String> {
+    eprint!("ActiveSG one-time PIN: ");
+    io::stderr().flush()?;
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(line)
+}
+
+/// Stdout of `command` run through the shell, failing unless it exits successfully
+/// within [OTP_COMMAND_TIMEOUT]
+async fn run(command: &str) -> DataMResult<String> {
+    let failed = |why: String| errors::Error::OtpCommand(format!("{} {}", command, why));
+
+    #[cfg(windows)]
+    let mut cmd = {
+        let mut cmd = tokio::process::Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    };
+    #[cfg(not(windows))]
+    let mut cmd = {
+        let mut cmd = tokio::process::Command::new("sh");
+        cmd.args(["-c", command]);
+        cmd
+    };
+    cmd.stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true);
+
+    let output = tokio::time::timeout(OTP_COMMAND_TIMEOUT, cmd.output())
+        .await
+        .map_err(|_| {
+            failed(format!(
+                "timed out after {}s",
+                OTP_COMMAND_TIMEOUT.as_secs()
+            ))
+        })?
+        .map_err(|e| failed(format!("couldn't be run: {}", e)))?;
+    if !output.status.success() {
+        return Err(failed(format!("failed with {}", output.status)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Form of the page asking for the one-time PIN
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtpForm {
+    /// absolute url the form is posted to
+    pub action: String,
+
+    /// name of the input the PIN goes in
+    pub field: String,
+
+    /// hidden inputs posted along with the PIN, e.g. the csrf token
+    pub hidden: Vec<(String, String)>,
+}
+
+impl OtpForm {
+    /// The form of the page at `url` with a text input named like a PIN, if any
+    ///
+    /// The sign in form has none, its inputs being the email and the password
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::client::login::OtpForm;
+    /// use scraper::Html;
+    ///
+    /// // page the sign in lands on when a PIN is needed, as captured from ActiveSG
+    /// let otp_page = Html::parse_document(
+    ///     r#"<html><head><title>ActiveSG - Verification</title></head><body>
+    ///     <form id="formOtp" action="/auth/otp/verify" method="post">
+    ///         <input type="hidden" name="_csrf" value="T3RwQ3NyZg">
+    ///         <input type="hidden" name="otp_token" value="a1b2c3">
+    ///         <p>A one-time PIN has been sent to your mobile number ending with 1234</p>
+    ///         <input type="text" name="otp" inputmode="numeric" maxlength="6">
+    ///         <button type="submit">Verify</button>
+    ///     </form>
+    ///     </body></html>"#,
+    /// );
+    /// let form = OtpForm::parse("https://members.myactivesg.com/auth/signin", &otp_page).unwrap();
+    /// assert_eq!(form.action, "https://members.myactivesg.com/auth/otp/verify");
+    /// assert_eq!(form.field, "otp");
+    /// assert_eq!(
+    ///     form.submission("123456"),
+    ///     [
+    ///         ("_csrf".to_string(), "T3RwQ3NyZg".to_string()),
+    ///         ("otp_token".to_string(), "a1b2c3".to_string()),
+    ///         ("otp".to_string(), "123456".to_string()),
+    ///     ]
+    /// );
+    ///
+    /// // without an action, the form is posted back to the page
+    /// let no_action = Html::parse_document(
+    ///     r#"<form method="post"><input type="tel" name="verificationCode"></form>"#,
+    /// );
+    /// let form = OtpForm::parse("https://members.myactivesg.com/auth/2fa", &no_action).unwrap();
+    /// assert_eq!(form.action, "https://members.myactivesg.com/auth/2fa");
+    /// assert_eq!(form.field, "verificationCode");
+    ///
+    /// let sign_in = Html::parse_document(
+    ///     r#"<form id="formSignin" action="/auth/signin" method="post">
+    ///     <input type="hidden" name="_csrf" value="WkRfc1BjQ2w0Lmc">
+    ///     <input type="email" name="email"><input type="password" name="password">
+    ///     </form>"#,
+    /// );
+    /// assert_eq!(OtpForm::parse("https://members.myactivesg.com/auth", &sign_in), None);
+    /// ```
+    pub fn parse(url: &str, body: &Html) -> Option<Self> {
+        let form_selector = Selector::parse("form").ok()?;
+        let input_selector = Selector::parse("input[name]").ok()?;
+
+        body.select(&form_selector).find_map(|form| {
+            let inputs = form
+                .select(&input_selector)
+                .map(|i| i.value())
+                .collect::<Vec<_>>();
+            let field = inputs.iter().find(|i| {
+                matches!(
+                    i.attr("type")
+                        .unwrap_or("text")
+                        .to_ascii_lowercase()
+                        .as_str(),
+                    "text" | "tel" | "number" | "password"
+                ) && OTP_INPUT_RE.is_match(i.attr("name").unwrap_or_default())
+            })?;
+            let hidden = inputs
+                .iter()
+                .filter(|i| {
+                    i.attr("type")
+                        .is_some_and(|t| t.eq_ignore_ascii_case("hidden"))
+                })
+                .filter_map(|i| Some((i.attr("name")?.into(), i.attr("value")?.into())))
+                .collect();
+            let action = Url::parse(url)
+                .ok()?
+                .join(form.value().attr("action").unwrap_or_default())
+                .ok()?;
+
+            Some(Self {
+                action: action.to_string(),
+                field: field.attr("name")?.to_string(),
+                hidden,
+            })
+        })
+    }
+
+    /// The fields posted with the PIN `code`
+    pub fn submission(&self, code: &str) -> Vec<(String, String)> {
+        let mut fields = self.hidden.clone();
+        fields.push((self.field.clone(), code.to_string()));
+        fields
+    }
+}
+
+/// Where a login stands, starting at [LoginStep::SignIn]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoginStep {
+    /// the email and the encrypted password are posted with the sign in form
+    SignIn,
+
+    /// ActiveSG asked for the one-time PIN it sent, posted with the form
+    Otp(OtpForm),
+
+    /// the session is logged in
+    LoggedIn,
+}
+
+impl LoginStep {
+    /// The step after posting the form of this one, from the page it landed on at `url`
+    ///
+    /// Landing on the profile logs in. Otherwise the sign in may ask for a PIN, and fails
+    /// with [errors::Error::InvalidCredentialsSessionExpired] when it doesn't. A PIN that
+    /// doesn't log in fails with [errors::Error::OtpRejected]. A captcha or a maintenance
+    /// page fails either, see [interstitial_parser::check]
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::{
+    ///     client::login::{LoginStep, OtpForm},
+    ///     errors::Error,
+    /// };
+    /// use scraper::Html;
+    ///
+    /// let profile = "https://members.myactivesg.com/profile";
+    /// let sign_in_url = "https://members.myactivesg.com/auth/signin";
+    /// let otp_page = Html::parse_document(
+    ///     r#"<html><body><form id="formOtp" action="/auth/otp" method="post">
+    ///     <input type="hidden" name="_csrf" value="T3RwQ3NyZg">
+    ///     <input type="text" name="otp" maxlength="6"><button type="submit">Verify</button>
+    ///     </form></body></html>"#,
+    /// );
+    /// // the sign in form again, with the error of a wrong password or PIN
+    /// let login_page = Html::parse_document(
+    ///     r#"<html><body><div class="alert alert-danger">Invalid email or password</div>
+    ///     <form id="formSignin" action="/auth/signin" method="post">
+    ///     <input type="hidden" name="_csrf" value="WkRfc1BjQ2w0Lmc">
+    ///     <input type="email" name="email"><input type="password" name="password">
+    ///     </form></body></html>"#,
+    /// );
+    /// let captcha = Html::parse_document(
+    ///     r#"<html><body><div class="g-recaptcha" data-sitekey="6Lc"></div></body></html>"#,
+    /// );
+    /// let empty = Html::parse_document("");
+    ///
+    /// // no PIN asked
+    /// assert_eq!(LoginStep::SignIn.next(profile, profile, &empty).unwrap(), LoginStep::LoggedIn);
+    /// assert!(matches!(
+    ///     LoginStep::SignIn.next(sign_in_url, profile, &login_page),
+    ///     Err(Error::InvalidCredentialsSessionExpired)
+    /// ));
+    ///
+    /// // a PIN asked, then accepted or not
+    /// let otp = LoginStep::SignIn.next(sign_in_url, profile, &otp_page).unwrap();
+    /// let form = OtpForm::parse(sign_in_url, &otp_page).unwrap();
+    /// assert_eq!(otp, LoginStep::Otp(form));
+    /// assert_eq!(otp.next(profile, profile, &empty).unwrap(), LoginStep::LoggedIn);
+    /// let otp_url = "https://members.myactivesg.com/auth/otp";
+    /// assert!(matches!(otp.next(otp_url, profile, &otp_page), Err(Error::OtpRejected)));
+    /// assert!(matches!(otp.next(otp_url, profile, &login_page), Err(Error::OtpRejected)));
+    ///
+    /// assert!(matches!(
+    ///     LoginStep::SignIn.next(sign_in_url, profile, &captcha),
+    ///     Err(Error::CaptchaRequired)
+    /// ));
+    /// assert!(matches!(otp.next(otp_url, profile, &captcha), Err(Error::CaptchaRequired)));
+    /// ```
+    pub fn next(&self, url: &str, profile_url: &str, body: &Html) -> DataMResult<Self> {
+        if url == profile_url {
+            return Ok(Self::LoggedIn);
+        }
+        interstitial_parser::check(body)?;
+
+        match self {
+            Self::SignIn => OtpForm::parse(url, body)
+                .map(Self::Otp)
+                .ok_or(errors::Error::InvalidCredentialsSessionExpired),
+            Self::Otp(_) => Err(errors::Error::OtpRejected),
+            Self::LoggedIn => Ok(Self::LoggedIn),
+        }
+    }
+}
@@ -0,0 +1,946 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use reqwest::{
+    header::{HeaderMap, ACCEPT, RETRY_AFTER, USER_AGENT},
+    Client, Response, StatusCode, Url,
+};
+use scraper::Html;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    client::login::{LoginStep, OtpSource},
+    clock::{Clock, SharedClock, SystemClock, SGT},
+    config::Config,
+    cookies::{self, SessionCookies},
+    daemon::Daemon,
+    discovery::ActivityIdCache,
+    encryption::{self, EncryptionKind},
+    errors,
+    explore::VenuePage,
+    fetch::{BookingPage, FetchTask, Fetcher},
+    metrics::DownloadBudget,
+    models::{activity_parser, auth_parser, interstitial_parser, Activity, LoginCredentials, User},
+    ratelimit::RateLimiter,
+    retry,
+    sink::Sink,
+    venue::Venue,
+    DataMResult,
+};
+
+pub mod login;
+
+/// Longest a request to ActiveSG may take by default, see [DataMiner::with_timeouts]
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Longest connecting to ActiveSG may take by default
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `time_from` of the booking page of `date`, the epoch seconds of its midnight in Singapore
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::client;
+/// use chrono::NaiveDate;
+///
+/// assert_eq!(client::time_from(NaiveDate::from_ymd(2022, 1, 11)), 1641830400);
+/// assert_eq!(client::time_from(NaiveDate::from_ymd(2021, 3, 21)), 1616256000);
+///
+/// // 16:00 UTC of the day before
+/// let utc_midnight = NaiveDate::from_ymd(2022, 1, 11).and_hms(0, 0, 0).timestamp();
+/// assert_eq!(utc_midnight - client::time_from(NaiveDate::from_ymd(2022, 1, 11)), 8 * 60 * 60);
+/// ```
+pub fn time_from(date: NaiveDate) -> i64 {
+    SGT.from_local_datetime(&date.and_hms(0, 0, 0))
+        .unwrap()
+        .timestamp()
+}
+
+/// Client of ActiveSG, built once and shared by every fetch of a cycle
+///
+/// Clones share the same [Client], so its pool of connections, its cookies and the session
+/// are reused from one gym and date to the next rather than set up again for every page
+///
+/// ## Example
+/// ```
+/// use std::sync::{
+///     atomic::{AtomicUsize, Ordering},
+///     Arc,
+/// };
+/// use activesg_gym_datamine::{
+///     client::DataMiner,
+///     fetch::{FetchTask, Fetcher},
+///     models::{Activity, Gym, User},
+/// };
+/// use chrono::NaiveDate;
+/// use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+/// let url = format!("http://{}", listener.local_addr().unwrap());
+/// let connections = Arc::new(AtomicUsize::new(0));
+/// let accepted = connections.clone();
+/// tokio::spawn(async move {
+///     loop {
+///         let (mut socket, _) = listener.accept().await.unwrap();
+///         accepted.fetch_add(1, Ordering::SeqCst);
+///         tokio::spawn(async move {
+///             let mut request = vec![];
+///             let mut buf = [0; 1024];
+///             while let Ok(n @ 1..) = socket.read(&mut buf).await {
+///                 request.extend_from_slice(&buf[..n]);
+///                 if request.windows(4).any(|w| w == b"\r\n\r\n") {
+///                     request.clear();
+///                     let response = "HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok";
+///                     socket.write_all(response.as_bytes()).await.unwrap();
+///                 }
+///             }
+///         });
+///     }
+/// });
+///
+/// let miner = DataMiner::new(User::new("", "")).with_anonymous(true).with_base_url(&url);
+/// let day = |d| NaiveDate::from_ymd(2022, 1, d);
+/// let first = miner.fetch_booking_page(FetchTask::new(Activity::GYM, Gym::BISHAN, day(11))).await;
+/// let clone = miner.clone();
+/// let second = clone.fetch_booking_page(FetchTask::new(Activity::GYM, Gym::BISHAN, day(12))).await;
+/// assert_eq!((first.unwrap().body, second.unwrap().body), ("ok".into(), "ok".into()));
+///
+/// // the venue page and both booking pages went through one connection
+/// assert_eq!(connections.load(Ordering::SeqCst), 1);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct DataMiner {
+    internal_client: Client,
+    user: User,
+    activity_ids: Arc<ActivityIdCache>,
+
+    /// browse without logging in, see [crate::breaker::CredentialBreaker]
+    anonymous: bool,
+
+    encryption: EncryptionKind,
+
+    /// referer of the logged in session, shared by the clones of this miner
+    /// along with the cookies of the client, none until the first login
+    session: Arc<tokio::sync::Mutex<Option<String>>>,
+
+    /// timestamps the fetched pages, see [Fetcher::now]
+    clock: SharedClock,
+
+    /// cookies of the client, shared by the clones of this miner
+    cookie_jar: Arc<SessionCookies>,
+
+    /// see [DataMiner::with_cookie_file]
+    cookie_file: Option<PathBuf>,
+
+    /// whether the session saved in [DataMiner::cookie_file] was tried already
+    restore_tried: Arc<AtomicBool>,
+
+    /// every request to ActiveSG takes a token first, see [DataMiner::with_rate_limiter]
+    limiter: Option<Arc<RateLimiter>>,
+
+    /// every response body counts, see [DataMiner::with_download_budget]
+    download_budget: Option<Arc<DownloadBudget>>,
+
+    /// scheme and host of ActiveSG, without the trailing `/`, see [DataMiner::with_base_url]
+    base_url: String,
+
+    /// see [DataMiner::with_otp]
+    otp: Option<OtpSource>,
+}
+
+impl DataMiner {
+    const USER_AGENT: &'static str =
+        "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:95.0) Gecko/20100101 Firefox/95.0";
+
+    const ACCEPT_HEADER: &'static str =
+        "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8";
+
+    /// Creates a miner that logs in as `user`
+    pub fn new(user: User) -> Self {
+        let cookie_jar = Arc::new(SessionCookies::default());
+        Self {
+            internal_client: Self::build_client(
+                cookie_jar.clone(),
+                DEFAULT_TIMEOUT,
+                DEFAULT_CONNECT_TIMEOUT,
+            ),
+            user,
+            activity_ids: Arc::default(),
+            anonymous: false,
+            encryption: EncryptionKind::default(),
+            session: Arc::default(),
+            clock: Arc::new(SystemClock),
+            cookie_jar,
+            cookie_file: None,
+            restore_tried: Arc::default(),
+            limiter: None,
+            download_budget: None,
+            base_url: cookies::ACTIVESG_URL.trim_end_matches('/').to_string(),
+            otp: None,
+        }
+    }
+
+    fn build_client(
+        cookie_jar: Arc<SessionCookies>,
+        timeout: Duration,
+        connect_timeout: Duration,
+    ) -> Client {
+        let mut headers = HeaderMap::new();
+        headers.append(USER_AGENT, Self::USER_AGENT.parse().unwrap());
+        headers.append(ACCEPT, Self::ACCEPT_HEADER.parse().unwrap());
+        Client::builder()
+            .default_headers(headers)
+            .cookie_provider(cookie_jar)
+            .timeout(timeout)
+            .connect_timeout(connect_timeout)
+            .build()
+            .unwrap()
+    }
+
+    /// Creates a miner that logs in as [Config::user] with [Config::password_encryption],
+    /// keeping the session in [Config::cookie_file], sending at most [Config::rps],
+    /// giving up on requests after [Config::timeout] and taking the one-time PIN
+    /// from [Config::otp]
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.user.clone())
+            .with_timeouts(config.timeout, config.connect_timeout)
+            .with_encryption(config.password_encryption)
+            .with_cookie_file(config.cookie_file.clone())
+            .with_otp(config.otp.clone())
+            .with_rate_limiter(config.rps.map(|rps| Arc::new(RateLimiter::new(rps))))
+    }
+
+    /// Fails the requests taking longer than `timeout` in all, or longer than `connect_timeout`
+    /// to connect, with a timeout that [errors::Error::is_retryable]
+    ///
+    /// The client is built again, keeping the cookies
+    ///
+    /// ## Example
+    /// A server that never answers
+    /// ```
+    /// use std::time::{Duration, Instant};
+    /// use activesg_gym_datamine::{
+    ///     client::DataMiner,
+    ///     errors::Error,
+    ///     fetch::{FetchTask, Fetcher},
+    ///     models::{Activity, Gym, User},
+    /// };
+    /// use tokio::net::TcpListener;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    /// let url = format!("http://{}", listener.local_addr().unwrap());
+    /// tokio::spawn(async move {
+    ///     let mut hung = vec![];
+    ///     loop {
+    ///         let (socket, _) = listener.accept().await.unwrap();
+    ///         hung.push(socket);
+    ///     }
+    /// });
+    ///
+    /// let miner = DataMiner::new(User::new("", ""))
+    ///     .with_anonymous(true)
+    ///     .with_base_url(&url)
+    ///     .with_timeouts(Duration::from_millis(200), Duration::from_millis(100));
+    /// let task = FetchTask::new(Activity::GYM, Gym::BISHAN, chrono::NaiveDate::from_ymd(2022, 1, 11));
+    ///
+    /// let started = Instant::now();
+    /// let e = miner.fetch_booking_page(task).await.unwrap_err();
+    /// // the venue page and the booking page, 200ms each
+    /// assert!(started.elapsed() < Duration::from_secs(2));
+    /// assert!(matches!(e.root(), Error::ClientError(e) if e.is_timeout()));
+    /// assert_eq!(e.class(), "timeout fetching booking page");
+    /// assert!(e.is_retryable());
+    /// # }
+    /// ```
+    pub fn with_timeouts(mut self, timeout: Duration, connect_timeout: Duration) -> Self {
+        self.internal_client =
+            Self::build_client(self.cookie_jar.clone(), timeout, connect_timeout);
+        self
+    }
+
+    /// Waits for a token of `limiter` before every request to ActiveSG, logins included
+    ///
+    /// Share the limiter between miners for the cap to hold across them
+    pub fn with_rate_limiter(mut self, limiter: Option<Arc<RateLimiter>>) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// Counts every response body downloaded from ActiveSG against `budget`,
+    /// the failed fetches, login pages and error pages included
+    ///
+    /// Share the budget between miners for the cap to hold across them
+    ///
+    /// ## Example
+    /// ActiveSG answering every page with a 503 error page
+    /// ```
+    /// use std::sync::Arc;
+    /// use activesg_gym_datamine::{
+    ///     client::DataMiner,
+    ///     fetch::{FetchTask, Fetcher},
+    ///     metrics::DownloadBudget,
+    ///     models::{Activity, Gym, User},
+    /// };
+    /// use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    /// let url = format!("http://{}", listener.local_addr().unwrap());
+    /// tokio::spawn(async move {
+    ///     loop {
+    ///         let (mut socket, _) = listener.accept().await.unwrap();
+    ///         let mut request = vec![];
+    ///         let mut buf = [0; 1024];
+    ///         while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+    ///             let n = socket.read(&mut buf).await.unwrap();
+    ///             request.extend_from_slice(&buf[..n]);
+    ///         }
+    ///         let body = "x".repeat(600);
+    ///         let response = format!(
+    ///             "HTTP/1.1 503 Service Unavailable\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+    ///             body.len(),
+    ///             body
+    ///         );
+    ///         socket.write_all(response.as_bytes()).await.unwrap();
+    ///     }
+    /// });
+    ///
+    /// let budget = Arc::new(DownloadBudget::new(1000));
+    /// let miner = DataMiner::new(User::new("", ""))
+    ///     .with_anonymous(true)
+    ///     .with_base_url(&url)
+    ///     .with_download_budget(Some(budget.clone()));
+    /// let task = FetchTask::new(Activity::GYM, Gym::BISHAN, chrono::NaiveDate::from_ymd(2022, 1, 11));
+    /// assert!(miner.fetch_booking_page(task).await.is_err());
+    ///
+    /// // the venue page and the booking page, both error pages
+    /// assert_eq!(budget.used(miner.now()), 1200);
+    /// assert!(budget.is_exhausted(miner.now()));
+    /// # }
+    /// ```
+    pub fn with_download_budget(mut self, budget: Option<Arc<DownloadBudget>>) -> Self {
+        self.download_budget = budget;
+        self
+    }
+
+    /// Counts `body` against the download budget, if any
+    fn record_download(&self, body: &[u8]) {
+        if let Some(budget) = &self.download_budget {
+            budget.record(self.clock.now(), body.len() as u64);
+        }
+    }
+
+    /// Waits for the rate limiter, if any, before a request to ActiveSG
+    async fn throttle(&self) {
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire().await;
+        }
+    }
+
+    /// Keeps the cookies of the session in `path` between runs, see [crate::cookies]
+    ///
+    /// The saved session is tried before the first login, and replaced after every login.
+    /// ActiveSG failing to answer whether it is still logged in fails the fetch, as the login
+    /// would, and the session is tried again by the next one
+    ///
+    /// ## Example
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use activesg_gym_datamine::{
+    ///     client::DataMiner,
+    ///     cookies::{self, SessionCookies},
+    ///     errors::Error,
+    ///     fetch::{FetchTask, Fetcher},
+    ///     models::{Activity, Gym, User},
+    /// };
+    /// use reqwest::{cookie::CookieStore, header::HeaderValue, StatusCode, Url};
+    /// use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    /// let url = format!("http://{}", listener.local_addr().unwrap());
+    /// let requests = Arc::new(Mutex::new(vec![]));
+    /// let received = requests.clone();
+    /// tokio::spawn(async move {
+    ///     let maintenance = "<html><head><title>Scheduled Maintenance</title></head></html>";
+    ///     for (status, body) in [("503 Service Unavailable", ""), ("200 OK", maintenance)] {
+    ///         let (mut socket, _) = listener.accept().await.unwrap();
+    ///         let mut request = vec![];
+    ///         let mut buf = [0; 1024];
+    ///         while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+    ///             let n = socket.read(&mut buf).await.unwrap();
+    ///             request.extend_from_slice(&buf[..n]);
+    ///         }
+    ///         received.lock().unwrap().push(String::from_utf8(request).unwrap().to_lowercase());
+    ///         let response = format!(
+    ///             "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+    ///             status,
+    ///             body.len(),
+    ///             body
+    ///         );
+    ///         socket.write_all(response.as_bytes()).await.unwrap();
+    ///     }
+    /// });
+    ///
+    /// let path = std::env::temp_dir().join(format!("session-doctest-{}.json", std::process::id()));
+    /// let saved = SessionCookies::default();
+    /// let session = HeaderValue::from_static("PHPSESSID=abc123; Path=/");
+    /// saved.set_cookies(&mut std::iter::once(&session), &Url::parse(&url).unwrap());
+    /// cookies::save(&saved, &path, chrono::Utc::now()).unwrap();
+    ///
+    /// let miner = DataMiner::new(User::new("user@example.com", "password"))
+    ///     .with_base_url(&url)
+    ///     .with_cookie_file(Some(path.clone()));
+    /// let task = FetchTask::new(Activity::GYM, Gym::BISHAN, chrono::NaiveDate::from_ymd(2022, 1, 11));
+    /// let e = miner.fetch_booking_page(task).await.unwrap_err();
+    /// assert!(matches!(e.root(), Error::UnexpectedStatus(StatusCode::SERVICE_UNAVAILABLE)), "{}", e);
+    /// let e = miner.fetch_booking_page(task).await.unwrap_err();
+    /// assert!(matches!(e.root(), Error::SiteMaintenance), "{}", e);
+    ///
+    /// // the saved session was checked twice like a browser would, without logging in
+    /// let requests = requests.lock().unwrap();
+    /// assert_eq!(requests.len(), 2);
+    /// for request in requests.iter() {
+    ///     assert!(request.starts_with("get /profile "), "{}", request);
+    ///     assert!(request.contains("cookie: phpsessid=abc123"), "{}", request);
+    ///     assert!(request.contains("user-agent: mozilla/5.0"), "{}", request);
+    /// }
+    /// std::fs::remove_file(&path).unwrap();
+    /// # }
+    /// ```
+    pub fn with_cookie_file(mut self, path: Option<PathBuf>) -> Self {
+        self.cookie_file = path;
+        self
+    }
+
+    /// Takes the PIN from `otp` when ActiveSG asks for one after the password,
+    /// failing with [errors::Error::OtpRequired] without, see [login]
+    pub fn with_otp(mut self, otp: Option<OtpSource>) -> Self {
+        self.otp = otp;
+        self
+    }
+
+    /// Overrides the password encryption detected from the login page
+    pub fn with_encryption(mut self, encryption: EncryptionKind) -> Self {
+        self.encryption = encryption;
+        self
+    }
+
+    /// Fetches booking pages without logging in, the pages are then marked unauthenticated
+    pub fn with_anonymous(mut self, anonymous: bool) -> Self {
+        self.anonymous = anonymous;
+        self
+    }
+
+    /// Timestamps the fetched pages with `clock` instead of the system time
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sends the requests to `url` instead of ActiveSG, such as a local server in tests
+    pub fn with_base_url(mut self, url: &str) -> Self {
+        self.base_url = url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Url of `path` on ActiveSG, or on [DataMiner::with_base_url]
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// Shares the discovered activity ids with other miners, see [DataMiner::activity_id]
+    pub fn with_activity_ids(mut self, activity_ids: Arc<ActivityIdCache>) -> Self {
+        self.activity_ids = activity_ids;
+        self
+    }
+
+    /// Runs the miner forever, or a single iteration with [Config::once]
+    ///
+    /// A single iteration fails when any of its fetches failed, [errors::Error::FetchesFailed]
+    pub async fn exec<S>(config: Config, sink: S) -> DataMResult<()>
+    where
+        S: Sink + Send + Sync + 'static,
+    {
+        let once = config.once;
+        let daemon = Arc::new(Daemon::new(config, sink));
+        if !once {
+            daemon.run().await;
+            return Ok(());
+        }
+
+        let stats = daemon.run_once().await?;
+        match stats.failed {
+            0 => Ok(()),
+            failed => Err(errors::Error::FetchesFailed(
+                failed,
+                stats.fetched + stats.failed,
+            )),
+        }
+    }
+
+    /// Current id of `activity` at `gym`, as linked from the venue page
+    ///
+    /// Discovered on the first fetch of a venue and cached, falling back to the id of [Activity]
+    /// when the venue page can't be loaded or has no link to the activity.
+    /// Returns the id and the bytes downloaded to find it
+    pub async fn activity_id<S>(&self, referer_url: S, activity: Activity, gym: Venue) -> (u16, u64)
+    where
+        S: AsRef<str>,
+    {
+        if let Some(id) = self.activity_ids.get(activity, gym) {
+            return (id, 0);
+        }
+
+        let default = activity as u16;
+        let url = self.url(&format!("/facilities/view/venue/{}", gym.id()));
+        self.throttle().await;
+        let res = self
+            .internal_client
+            .get(&url)
+            .header("Referer", referer_url.as_ref())
+            .send()
+            .await;
+        let body = match res {
+            Ok(r) => {
+                let success = r.status().is_success();
+                let body = r.text().await.ok();
+                if let Some(b) = &body {
+                    self.record_download(b.as_bytes());
+                }
+                body.filter(|_| success)
+            }
+            Err(_) => None,
+        };
+
+        let discovered = body.as_ref().and_then(|b| {
+            activity_parser::find_activity_id(&Html::parse_document(b), activity, gym.id())
+        });
+        let id = match discovered {
+            Some(id) if id != default => {
+                error!(
+                    "ALERT: {:?} at {:?} has activity id {} instead of {}, using {}",
+                    activity, gym, id, default, id
+                );
+                id
+            }
+            Some(id) => id,
+            None => {
+                warn!(
+                    "{:?} at {:?}, activity id not found on the venue page, using {}",
+                    activity, gym, default
+                );
+                default
+            }
+        };
+
+        // cached even when not found so that a broken venue page isn't loaded on every fetch
+        self.activity_ids.insert(activity, gym, id);
+        (id, body.map(|b| b.len() as u64).unwrap_or_default())
+    }
+
+    /// Venue page of the venue id `venue`, whatever the status, see [crate::explore::classify]
+    pub async fn fetch_venue_page(&self, venue: u16) -> DataMResult<VenuePage> {
+        let url = self.url(&format!("/facilities/view/venue/{}", venue));
+        self.throttle().await;
+        let res = self
+            .internal_client
+            .get(&url)
+            .header("Referer", self.url("/facilities"))
+            .send()
+            .await?;
+
+        let status = res.status().as_u16();
+        let url = res.url().to_string();
+        let body = res.text().await?;
+        self.record_download(body.as_bytes());
+
+        Ok(VenuePage { status, url, body })
+    }
+
+    /// Example query
+    /// `https://members.myactivesg.com/facilities/view/activity/1031/venue/154?time_from=1616256000`
+    ///
+    /// Returns the raw booking page, or [errors::Error::SessionExpired] when logged in
+    /// and served the login page instead, errors telling the task and url, see [errors::Error::Fetch]
+    async fn query_timeslots<D, S>(
+        &self,
+        referer_url: S,
+        activity: Activity,
+        facility_type: u16,
+        venue: Venue,
+        date: D,
+    ) -> DataMResult<BookingPage>
+    where
+        D: Into<NaiveDate>,
+        S: AsRef<str>,
+    {
+        let date = date.into();
+
+        // this API does not work when it is 0600 - 0800, see schedule::blackout_left
+        let url = Url::parse(&self.url(&format!(
+            "/facilities/view/activity/{}/venue/{}?time_from={}",
+            facility_type,
+            venue.id(),
+            time_from(date)
+        )))
+        .map_err(|_| errors::Error::FailedToParseUrl)?;
+
+        let task = FetchTask::new(activity, venue, date);
+        let context = |e: errors::Error| e.with_fetch_context(task, Some(url.to_string()));
+
+        self.throttle().await;
+        let res = self
+            .internal_client
+            .get(url.clone())
+            .header("Referer", referer_url.as_ref())
+            .send()
+            .await
+            .map_err(|e| context(e.into()))?;
+
+        let page_url = res.url().to_string();
+        let headers = res.headers().clone();
+        // the activity may have been renumbered since it was discovered
+        if res.status() == StatusCode::NOT_FOUND {
+            self.activity_ids.invalidate(activity, venue);
+        }
+        // an error page has no slots, failing here tells ActiveSG being down or rate limiting
+        // apart from a changed page, once its body is counted
+        let status = self.check_status(&res);
+        let body = res.text().await.map_err(|e| context(e.into()))?;
+        self.record_download(body.as_bytes());
+        status.map_err(context)?;
+
+        let html = Html::parse_document(&body);
+        // an anonymous miner isn't logged in to begin with
+        if !self.anonymous && auth_parser::is_login_page(&page_url, &html) {
+            return Err(context(errors::Error::SessionExpired));
+        }
+        // neither has slots, they would look like a day without any
+        interstitial_parser::check(&html).map_err(context)?;
+
+        Ok(BookingPage::new(page_url, headers, body))
+    }
+
+    /// Fails unless `res` is a success, [errors::Error::RateLimited] with the pause
+    /// it asks for when a 429, [errors::Error::UnexpectedStatus] otherwise
+    fn check_status(&self, res: &Response) -> DataMResult<()> {
+        match res.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::TOO_MANY_REQUESTS => {
+                let retry_after = res
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| retry::parse_retry_after(v, self.clock.now()));
+                Err(errors::Error::RateLimited { retry_after })
+            }
+            status => Err(errors::Error::UnexpectedStatus(status)),
+        }
+    }
+
+    /// Sends a `HEAD` to `url` with the headers and settings of the booking page requests,
+    /// succeeding on any response whatever its status
+    ///
+    /// Used as the reference probe of [crate::outage], the url being somewhere other than ActiveSG
+    pub async fn probe(&self, url: Url) -> DataMResult<()> {
+        self.internal_client.head(url).send().await?;
+        Ok(())
+    }
+
+    /// Logins once, succeeding only if the credentials are accepted
+    ///
+    /// The session is then reused by the following fetches.
+    /// Returns the name of the [crate::encryption::EncryptionStrategy] used
+    pub async fn check_login(&self) -> DataMResult<&'static str> {
+        let mut session = self.session.lock().await;
+        let (referer, _, name) = self.login(&self.user).await?;
+        *session = Some(referer);
+        Ok(name)
+    }
+
+    /// Referer of the session saved in [DataMiner::cookie_file] when it is still logged in,
+    /// tried once, along with the bytes downloaded to check it
+    ///
+    /// Fails when ActiveSG can't answer whether it is, as the login would then
+    async fn restore_session(&self) -> DataMResult<(Option<String>, u64)> {
+        let path = match &self.cookie_file {
+            Some(path) if !self.restore_tried.swap(true, Ordering::SeqCst) => path,
+            _ => return Ok((None, 0)),
+        };
+        match cookies::load(&self.cookie_jar, path) {
+            Ok(0) => return Ok((None, 0)),
+            Ok(_) => (),
+            Err(e) => {
+                warn!("Session not restored: {}", e);
+                return Ok((None, 0));
+            }
+        }
+
+        let res = self.check_session().await;
+        if res.is_err() {
+            // tried again by the next fetch
+            self.restore_tried.store(false, Ordering::SeqCst);
+        }
+        let (logged_in, len) = res?;
+        match logged_in {
+            Some(url) => {
+                info!("Restored the session saved in {}", path.display());
+                Ok((Some(url), len))
+            }
+            None => {
+                info!("Session saved in {} expired, logging in", path.display());
+                Ok((None, len))
+            }
+        }
+    }
+
+    /// Loads the profile with the cookies of the client, returning its url when it wasn't
+    /// redirected to the login page, along with the bytes downloaded
+    async fn check_session(&self) -> DataMResult<(Option<String>, u64)> {
+        let profile = self.url("/profile");
+        self.throttle().await;
+        let res = self
+            .internal_client
+            .get(&profile)
+            .header(USER_AGENT, Self::USER_AGENT)
+            .header(ACCEPT, Self::ACCEPT_HEADER)
+            .send()
+            .await?;
+        let status = self.check_status(&res);
+        let url = res.url().to_string();
+        let body = res.text().await?;
+        self.record_download(body.as_bytes());
+        status?;
+        // a captcha or maintenance page isn't the login page either
+        interstitial_parser::check(&Html::parse_document(&body))?;
+
+        Ok(((url == profile).then_some(url), body.len() as u64))
+    }
+
+    /// Referer of the logged in session, logging in first when there is none
+    ///
+    /// Returns the referer and the bytes downloaded to login, 0 when the session is reused
+    async fn session(&self) -> DataMResult<(String, u64)> {
+        let mut session = self.session.lock().await;
+        if let Some(referer) = &*session {
+            return Ok((referer.clone(), 0));
+        }
+
+        let (restored, check_len) = self.restore_session().await?;
+        if let Some(referer) = restored {
+            *session = Some(referer.clone());
+            return Ok((referer, check_len));
+        }
+
+        let (referer, login_len, _) = self.login(&self.user).await?;
+        *session = Some(referer.clone());
+        Ok((referer, login_len + check_len))
+    }
+
+    /// Forgets the session so that the next fetch logs in again
+    async fn expire_session(&self) {
+        self.session.lock().await.take();
+    }
+
+    /// Returns the login form and the name of the encryption strategy used
+    fn handle_login_credentials(
+        body: String,
+        user: &User,
+        encryption: EncryptionKind,
+    ) -> DataMResult<(LoginCredentials, &'static str)> {
+        let html = Html::parse_document(&body);
+        let form = auth_parser::get_csrf_token(&html).and_then(|csrf_token| {
+            auth_parser::get_rsa_key(&html).map(|rsa_key| (csrf_token, rsa_key))
+        });
+        let (csrf_token, rsa_key) = match form {
+            Ok(form) => form,
+            // a captcha or maintenance page has no login form
+            Err(e) => {
+                interstitial_parser::check(&html)?;
+                return Err(e);
+            }
+        };
+
+        let strategy = encryption::select(encryption, &html)?;
+        debug!(
+            "Encrypting the password with the {} strategy",
+            strategy.name()
+        );
+        let enc_pwd = strategy.encrypt(&rsa_key, &user.password)?;
+
+        Ok((
+            LoginCredentials::new(user.email.clone(), enc_pwd, csrf_token),
+            strategy.name(),
+        ))
+    }
+
+    /// Returns the body of the login page, which contains the csrf token and rsa key
+    pub async fn fetch_login_page(&self) -> DataMResult<String> {
+        let login_url = self.url("/auth");
+
+        let resp_builder = self
+            .internal_client
+            .get(&login_url)
+            .header(USER_AGENT, Self::USER_AGENT)
+            .header(ACCEPT, Self::ACCEPT_HEADER);
+
+        debug!("{:X?}", &resp_builder);
+
+        self.throttle().await;
+        let resp = resp_builder.send().await?;
+        let status = self.check_status(&resp);
+        let body = resp.text().await?;
+        self.record_download(body.as_bytes());
+        status?;
+
+        info!("GET login page successful!");
+
+        Ok(body)
+    }
+
+    /// Logins using user provided, answering the one-time PIN ActiveSG may ask for,
+    /// see [login::LoginStep]
+    ///
+    /// Returns the url of the profile the login landed on, the size of the pages
+    /// downloaded to login and the name of the encryption strategy used
+    async fn login(&self, user: &User) -> DataMResult<(String, u64, &'static str)> {
+        let sign_in = self.url("/auth/signin");
+        let profile = self.url("/profile");
+
+        let body = self.fetch_login_page().await?;
+        let mut login_len = body.len() as u64;
+
+        let (login_creds, encryption) =
+            Self::handle_login_credentials(body, user, self.encryption)?;
+
+        self.throttle().await;
+        let mut res = self
+            .internal_client
+            .post(&sign_in)
+            .header(USER_AGENT, Self::USER_AGENT)
+            .header(ACCEPT, Self::ACCEPT_HEADER)
+            .form(&login_creds)
+            .send()
+            .await?;
+
+        let mut step = LoginStep::SignIn;
+        loop {
+            let status = self.check_status(&res);
+            let url = res.url().to_string();
+            let body = res.text().await.unwrap_or_default();
+            self.record_download(body.as_bytes());
+            status?;
+            login_len += body.len() as u64;
+            step = step.next(&url, &profile, &Html::parse_document(&body))?;
+
+            let form = match &step {
+                LoginStep::Otp(form) => form,
+                _ => {
+                    info!("Logged in successfully!");
+                    if let Some(path) = &self.cookie_file {
+                        if let Err(e) = cookies::save(&self.cookie_jar, path, self.clock.now()) {
+                            warn!("Session not saved to {}: {}", path.display(), e);
+                        }
+                    }
+                    return Ok((url, login_len, encryption));
+                }
+            };
+
+            info!("ActiveSG asked for a one-time PIN");
+            let code = match &self.otp {
+                Some(otp) => otp.code().await?,
+                None => return Err(errors::Error::OtpRequired),
+            };
+
+            self.throttle().await;
+            res = self
+                .internal_client
+                .post(&form.action)
+                .header(USER_AGENT, Self::USER_AGENT)
+                .header(ACCEPT, Self::ACCEPT_HEADER)
+                .header("Referer", &url)
+                .form(&form.submission(&code))
+                .send()
+                .await?;
+        }
+    }
+}
+
+impl Fetcher for DataMiner {
+    async fn fetch_booking_page(&self, task: FetchTask) -> DataMResult<BookingPage> {
+        if self.anonymous {
+            // a fresh client has no session cookies
+            let referer_url = self.url("/facilities");
+            let (facility_type, discovery_len) = self
+                .activity_id(&referer_url, task.activity, task.gym)
+                .await;
+            let mut page = self
+                .query_timeslots(
+                    &referer_url,
+                    task.activity,
+                    facility_type,
+                    task.gym,
+                    task.date,
+                )
+                .await?;
+            page.bytes_downloaded += discovery_len;
+            page.authenticated = false;
+
+            return Ok(page);
+        }
+
+        let (referer_url, mut login_len) = self.session().await?;
+        let (facility_type, discovery_len) = self
+            .activity_id(&referer_url, task.activity, task.gym)
+            .await;
+        let query = |referer_url| {
+            self.query_timeslots(
+                referer_url,
+                task.activity,
+                facility_type,
+                task.gym,
+                task.date,
+            )
+        };
+        let mut page = match query(referer_url).await {
+            // logged out mid-cycle, logging in again once before giving up
+            Err(e) if matches!(e.root(), errors::Error::SessionExpired) => {
+                info!("Session expired, logging in again");
+                self.expire_session().await;
+                let (referer_url, relogin_len) = self.session().await?;
+                login_len += relogin_len;
+
+                match query(referer_url).await {
+                    Err(e) if matches!(e.root(), errors::Error::SessionExpired) => {
+                        self.expire_session().await;
+                        return Err(errors::Error::InvalidCredentialsSessionExpired);
+                    }
+                    res => res?,
+                }
+            }
+            res => res?,
+        };
+        page.bytes_downloaded += login_len + discovery_len;
+
+        Ok(page)
+    }
+
+    fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+}
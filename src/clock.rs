@@ -0,0 +1,100 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, FixedOffset, Utc};
+use lazy_static::lazy_static;
+
+lazy_static! {
+    /// Singapore time, in which ActiveSG lists its slots and the output is dated,
+    /// UTC+8 all year round
+    pub static ref SGT: FixedOffset = FixedOffset::east(8 * 3600);
+}
+
+/// Source of the current time of everything scheduled or timestamped by the miner
+///
+/// [SystemClock] reads the system time, [FakeClock] is moved by hand in tests
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+impl<C> Clock for Arc<C>
+where
+    C: Clock + ?Sized,
+{
+    fn now(&self) -> DateTime<Utc> {
+        (**self).now()
+    }
+}
+
+/// Clock shared by the components of a [crate::daemon::Daemon]
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The system time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock that starts at a given time and moves along with the tokio clock,
+/// plus whatever it is advanced by
+///
+/// With a paused tokio clock it only moves when tokio auto-advances, e.g. over a sleep,
+/// or when told to. Clones share the same time
+///
+/// ## Example
+/// ```
+/// use std::time::Duration;
+/// use activesg_gym_datamine::clock::{Clock, FakeClock};
+/// use chrono::{TimeZone, Utc};
+///
+/// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+/// # async fn main() {
+/// let start = Utc.ymd(2022, 1, 10).and_hms(22, 0, 0);
+/// let clock = FakeClock::new(start);
+/// assert_eq!(clock.now(), start);
+///
+/// clock.advance(chrono::Duration::minutes(5));
+/// assert_eq!(clock.clone().now(), start + chrono::Duration::minutes(5));
+///
+/// tokio::time::sleep(Duration::from_secs(60)).await;
+/// assert_eq!(clock.now(), start + chrono::Duration::minutes(6));
+///
+/// clock.set(start);
+/// assert_eq!(clock.now(), start);
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FakeClock {
+    /// time at `anchor`
+    state: Arc<Mutex<(DateTime<Utc>, tokio::time::Instant)>>,
+}
+
+impl FakeClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new((now, tokio::time::Instant::now()))),
+        }
+    }
+
+    /// Moves the clock forward by `by`, backwards when negative
+    pub fn advance(&self, by: chrono::Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.0 = state.0 + by;
+    }
+
+    /// Moves the clock to `now`
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.state.lock().unwrap() = (now, tokio::time::Instant::now());
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> DateTime<Utc> {
+        let (at, anchor) = *self.state.lock().unwrap();
+        at + chrono::Duration::from_std(anchor.elapsed())
+            .unwrap_or_else(|_| chrono::Duration::zero())
+    }
+}
@@ -0,0 +1,25 @@
+use activesg_gym_datamine::{
+    audit::{self, AuditOptions},
+    client::DataMiner,
+    config::Config,
+    DataMResult,
+};
+
+use crate::args::AuditCommand;
+
+/// Audits a sample of the output directory against live pages and prints the report
+pub async fn run(config: Config, cmd: AuditCommand) -> DataMResult<()> {
+    let data_miner = DataMiner::from_config(&config);
+    let options = AuditOptions {
+        samples: cmd.samples,
+        drift_tolerance: cmd.tolerance,
+        gyms: cmd.gym,
+    };
+
+    let report = audit::run(&data_miner, &config, &config.output_dir, &options).await?;
+    match cmd.json {
+        true => println!("{}", serde_json::to_string_pretty(&report).unwrap()),
+        false => println!("{}", report),
+    }
+    Ok(())
+}
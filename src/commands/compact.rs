@@ -0,0 +1,42 @@
+use activesg_gym_datamine::{
+    clock::SGT,
+    compaction::{self, Compacted},
+    config::ConfigFile,
+    metrics::human_bytes,
+    sink::FileSink,
+    DataMResult,
+};
+use chrono::Utc;
+
+use crate::args::{Args, CompactCommand};
+
+/// Bundles the past days of the output directory, or only the day given, printing each bundle
+pub fn run(args: &Args, cmd: CompactCommand) -> DataMResult<()> {
+    let file = match &args.config {
+        Some(path) => ConfigFile::load(path)?,
+        None => ConfigFile::default(),
+    };
+    let output_dir = file
+        .output_dir
+        .unwrap_or_else(|| FileSink::DEFAULT_OUTPUT_DIR.into());
+
+    let compacted = match cmd.date {
+        Some(date) => vec![compaction::compact_day(&output_dir, date)?],
+        None => {
+            let today = Utc::now().with_timezone(&*SGT).naive_local().date();
+            compaction::compact(&output_dir, today)?
+        }
+    };
+
+    for Compacted { date, files, bytes } in &compacted {
+        println!(
+            "{}: {} files, {}",
+            compaction::bundle_path(&output_dir, *date).display(),
+            files,
+            human_bytes(*bytes)
+        );
+    }
+    println!("{} days compacted", compacted.len());
+
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use std::io::Read;
+
+use activesg_gym_datamine::{models::auth_parser, DataMResult};
+
+use crate::args::EncryptPasswordCommand;
+
+fn read_key(cmd: &EncryptPasswordCommand) -> DataMResult<String> {
+    match &cmd.key {
+        Some(path) if path.as_os_str() != "-" => Ok(std::fs::read_to_string(path)?),
+        _ => {
+            let mut buf = String::new();
+            std::io::stdin().read_to_string(&mut buf)?;
+            Ok(buf)
+        }
+    }
+}
+
+/// Prints the base64 ciphertext and its length, the password itself is never printed
+pub fn run(cmd: EncryptPasswordCommand) -> DataMResult<()> {
+    let key = read_key(&cmd)?;
+    let bits = auth_parser::rsa_key_bits(&key)?;
+    eprintln!("Detected {} bit RSA public key", bits);
+
+    let password = rpassword::prompt_password("Password: ")?;
+    let enc_pwd = auth_parser::generate_enc_pwd(&key, &password)?;
+
+    println!("{}", enc_pwd);
+    eprintln!("{} base64 characters", enc_pwd.len());
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use std::time::Instant;
+
+use activesg_gym_datamine::{
+    client::DataMiner,
+    config::Config,
+    explore::{self, Discoveries, ProbeOutcome, VenueProbe, DISCOVERED_VENUES_FILENAME},
+    DataMResult,
+};
+use chrono::Utc;
+use tracing::warn;
+
+use crate::args::ExploreCommand;
+
+fn describe(probe: &VenueProbe) -> String {
+    let known = match probe.known {
+        Some(gym) => format!(" (known as {:?})", gym),
+        None => String::new(),
+    };
+
+    match &probe.outcome {
+        ProbeOutcome::Gym { name, activity_id } => {
+            format!("gym {:?}, activity {}{}", name, activity_id, known)
+        }
+        ProbeOutcome::NonGym { name, activities } => {
+            format!("no gym at {:?} [{}]{}", name, activities.join(", "), known)
+        }
+        ProbeOutcome::Missing { reason } => format!("no venue, {}{}", reason, known),
+    }
+}
+
+/// Probes every id of the range once, [explore::PROBE_DELAY] apart and within [explore::TIME_BOX],
+/// then writes the discoveries and prints how they differ from the known gyms
+pub async fn run(config: Config, cmd: ExploreCommand) -> DataMResult<()> {
+    let range = explore::probe_range(cmd.from, cmd.to)?;
+
+    let data_miner = DataMiner::from_config(&config);
+    data_miner.check_login().await?;
+
+    let started = Instant::now();
+    let mut venues = vec![];
+    for id in range {
+        if started.elapsed() > explore::TIME_BOX {
+            warn!("Time box reached, stopping before {}", id);
+            break;
+        }
+        if !venues.is_empty() {
+            tokio::time::sleep(explore::PROBE_DELAY).await;
+        }
+
+        let outcome = match data_miner.fetch_venue_page(id).await {
+            Ok(page) => explore::classify(id, &page),
+            Err(e) => {
+                warn!("{}: probing failed, {}", id, e);
+                continue;
+            }
+        };
+
+        let probe = VenueProbe::new(id, outcome);
+        println!("{}: {}", id, describe(&probe));
+        venues.push(probe);
+    }
+
+    let discoveries = Discoveries {
+        probed_at: Utc::now(),
+        venues,
+    };
+    discoveries.write(&config.output_dir)?;
+    println!(
+        "Wrote {}",
+        config.output_dir.join(DISCOVERED_VENUES_FILENAME).display()
+    );
+
+    for probe in discoveries.venues.iter().filter(|p| p.is_new_gym()) {
+        println!("NEW {}: {}", probe.id, describe(probe));
+    }
+    for probe in discoveries.venues.iter().filter(|p| p.is_lost_gym()) {
+        println!("GONE {}: {}", probe.id, describe(probe));
+    }
+
+    Ok(())
+}
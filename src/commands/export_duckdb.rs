@@ -0,0 +1,47 @@
+use activesg_gym_datamine::{
+    config::ConfigFile,
+    export::{self, ExportOptions, PartFormat},
+    sink::FileSink,
+    DataMResult,
+};
+
+use crate::args::{Args, ExportDuckdbCommand};
+
+/// Exports the new snapshots of the output directory and prints what was added
+/// along with the glob to read the export from DuckDB
+pub fn run(args: &Args, cmd: ExportDuckdbCommand) -> DataMResult<()> {
+    let file = match &args.config {
+        Some(path) => ConfigFile::load(path)?,
+        None => ConfigFile::default(),
+    };
+    let archive = file
+        .output_dir
+        .unwrap_or_else(|| FileSink::DEFAULT_OUTPUT_DIR.into());
+    let out = cmd.output.unwrap_or_else(|| archive.join("duckdb"));
+
+    let options = ExportOptions {
+        target_file_bytes: cmd.target_file_mb.max(1) * 1024 * 1024,
+        partition_by: cmd.partition_by,
+        format: cmd.format,
+    };
+    let summary = export::run(&archive, &out, &options)?;
+
+    println!(
+        "{} snapshots exported, {} rows in {} new part files, {} unreadable",
+        summary.snapshots, summary.rows, summary.files, summary.unreadable
+    );
+    let levels = vec!["*"; options.partition_by.0.len()].join("/");
+    let function = match options.format {
+        PartFormat::Ndjson => "read_json_auto",
+        #[cfg(feature = "parquet")]
+        PartFormat::Parquet => "read_parquet",
+    };
+    println!(
+        "{}('{}/{}/*.{}', hive_partitioning = true)",
+        function,
+        out.display(),
+        levels,
+        options.format.extension()
+    );
+    Ok(())
+}
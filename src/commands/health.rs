@@ -0,0 +1,69 @@
+use std::{path::PathBuf, process::ExitCode, time::Duration};
+
+use activesg_gym_datamine::{
+    config::{Config, ConfigFile},
+    heartbeat::{Health, Heartbeat},
+    sink::FileSink,
+    DataMResult,
+};
+use chrono::Utc;
+
+use crate::args::{Args, HealthCommand};
+
+/// Output directory and freshness threshold, from the same config file as the daemon
+fn resolve(args: &Args, cmd: &HealthCommand) -> DataMResult<(PathBuf, Duration)> {
+    let file = match &args.config {
+        Some(path) => ConfigFile::load(path)?,
+        None => ConfigFile::default(),
+    };
+
+    let output_dir = file
+        .output_dir
+        .unwrap_or_else(|| FileSink::DEFAULT_OUTPUT_DIR.into());
+    let interval = args
+        .interval
+        .or_else(|| file.interval_secs.map(Duration::from_secs))
+        .unwrap_or(Config::DEFAULT_INTERVAL);
+    let max_age = cmd
+        .max_age_secs
+        .map(Duration::from_secs)
+        .unwrap_or_else(|| Health::default_max_age(interval));
+
+    Ok((output_dir, max_age))
+}
+
+/// Prints a single status line, exiting with 0 only when healthy
+pub fn run(args: &Args, cmd: HealthCommand) -> ExitCode {
+    let res = resolve(args, &cmd).and_then(|(output_dir, max_age)| {
+        let heartbeat = Heartbeat::read(&output_dir)?;
+        Ok((Health::of(heartbeat.as_ref(), Utc::now(), max_age), max_age))
+    });
+
+    let healthy = match res {
+        Ok((Health::Healthy { age }, _)) => {
+            println!("healthy: last iteration {}s ago", age.as_secs());
+            true
+        }
+        Ok((Health::Stale { age }, max_age)) => {
+            println!(
+                "stale: last iteration {}s ago, more than {}s",
+                age.as_secs(),
+                max_age.as_secs()
+            );
+            false
+        }
+        Ok((Health::Missing, _)) => {
+            println!("missing: no heartbeat written yet");
+            false
+        }
+        Err(e) => {
+            println!("error: {}", e);
+            false
+        }
+    };
+
+    match healthy {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    }
+}
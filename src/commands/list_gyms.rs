@@ -0,0 +1,41 @@
+use activesg_gym_datamine::{errors, models::Gym, DataMResult};
+use serde::Serialize;
+
+use crate::args::ListGymsCommand;
+
+/// A known gym as printed by `list-gyms --json`
+#[derive(Debug, Serialize)]
+struct GymEntry {
+    gym: &'static str,
+    venue_id: u16,
+    name: &'static str,
+}
+
+/// Prints every known gym, its venue id and its official name
+pub fn run(cmd: ListGymsCommand) -> DataMResult<()> {
+    let gyms = Gym::gym_slice()
+        .iter()
+        .map(|gym| GymEntry {
+            gym: gym.name(),
+            venue_id: *gym as u16,
+            name: gym.display(),
+        })
+        .collect::<Vec<_>>();
+
+    if cmd.json {
+        let json = serde_json::to_string_pretty(&gyms).map_err(errors::Error::Serialize)?;
+        println!("{}", json);
+        return Ok(());
+    }
+
+    let width = gyms.iter().map(|g| g.gym.len()).max().unwrap_or_default();
+    for GymEntry {
+        gym,
+        venue_id,
+        name,
+    } in &gyms
+    {
+        println!("{:width$} {:>5}  {}", gym, venue_id, name, width = width);
+    }
+    Ok(())
+}
@@ -0,0 +1,12 @@
+pub mod audit;
+pub mod compact;
+pub mod encrypt_password;
+pub mod explore;
+pub mod export_duckdb;
+pub mod health;
+pub mod list_gyms;
+pub mod query;
+pub mod selftest;
+pub mod setup;
+pub mod summary;
+pub mod tui;
@@ -0,0 +1,29 @@
+use activesg_gym_datamine::{
+    client::DataMiner,
+    config::{self, Config},
+    errors,
+    fetch::{self, FetchTask},
+    models::{Activity, GymSlotDataSoA},
+    DataMResult,
+};
+use chrono::Utc;
+
+use crate::args::QueryCommand;
+
+/// Fetches the booking page of a single gym and date, printing the snapshot as JSON to stdout
+pub async fn run(config: Config, cmd: QueryCommand) -> DataMResult<()> {
+    let date = cmd
+        .date
+        .unwrap_or_else(|| config::target_dates(Utc::now(), &[0])[0]);
+    let data_miner = DataMiner::from_config(&config);
+
+    let task = FetchTask::new(Activity::GYM, cmd.gym, date);
+    let data = fetch::fetch_slots(&data_miner, &config, task).await?;
+
+    let json = match cmd.soa {
+        true => serde_json::to_string_pretty(&GymSlotDataSoA::from(data)),
+        false => serde_json::to_string_pretty(&data),
+    };
+    println!("{}", json.map_err(errors::Error::Serialize)?);
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use activesg_gym_datamine::{
+    client::DataMiner,
+    config::{self, Config},
+    errors,
+    fetch::{FetchTask, Fetcher},
+    models::Activity,
+    selftest::{self, Check},
+    DataMResult,
+};
+use chrono::Utc;
+
+use crate::args::SelfTestCommand;
+
+/// Runs every parser against live pages, writing nothing
+async fn run_checks(config: &Config, cmd: &SelfTestCommand) -> Vec<Check> {
+    let data_miner = DataMiner::from_config(config);
+    let mut checks = selftest::check_login_page(data_miner.fetch_login_page().await);
+
+    let login = data_miner
+        .check_login()
+        .await
+        .map(|encryption| format!("accepted, {} password encryption", encryption));
+    let logged_in = login.is_ok();
+    checks.push(Check::new("login", true, login.map_err(|e| e.to_string())));
+
+    if !logged_in {
+        return checks;
+    }
+
+    let date = config::target_dates(Utc::now(), &[cmd.day_offset])[0];
+    let task = FetchTask::new(Activity::GYM, cmd.gym, date);
+    let page = data_miner.fetch_booking_page(task).await;
+    checks.extend(selftest::check_booking_page(
+        page,
+        cmd.gym,
+        date,
+        Utc::now(),
+    ));
+
+    checks
+}
+
+pub async fn run(config: Config, cmd: SelfTestCommand) -> DataMResult<()> {
+    let checks = run_checks(&config, &cmd).await;
+    for check in &checks {
+        println!("{}", check);
+    }
+
+    match checks.iter().filter(|c| c.is_failure()).count() {
+        0 => Ok(()),
+        n => Err(errors::Error::SelfTestFailed(n)),
+    }
+}
@@ -0,0 +1,68 @@
+use std::{
+    io::{self, IsTerminal},
+    path::PathBuf,
+};
+
+use activesg_gym_datamine::{
+    client::DataMiner,
+    config::ConfigFile,
+    errors,
+    models::User,
+    setup::{self, Wizard},
+    DataMResult,
+};
+
+use crate::args::SetupCommand;
+
+pub async fn run(cmd: SetupCommand) -> DataMResult<()> {
+    if !io::stdin().is_terminal() {
+        return Err(errors::Error::InvalidConfig(
+            "setup must be run from an interactive terminal, \
+             write the config by hand instead, see the Configuration section of the README"
+                .into(),
+        ));
+    }
+
+    let stdin = io::stdin();
+    let mut wizard = Wizard::new(stdin.lock(), io::stdout(), true);
+    let password_path = cmd.output.with_extension("password");
+
+    let existing = [&cmd.output, &password_path]
+        .into_iter()
+        .filter(|p| p.exists())
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>();
+    if !existing.is_empty() {
+        let question = format!("Overwrite {}?", existing.join(" and "));
+        if !wizard.confirm(&question, false)? {
+            println!("Nothing written, pick another file with --output");
+            return Ok(());
+        }
+    }
+
+    let answers = wizard.run()?;
+    let password_name = password_path
+        .file_name()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| password_path.clone());
+
+    setup::write_password_file(&password_path, &answers.password)?;
+    std::fs::write(&cmd.output, setup::render_config(&answers, &password_name))?;
+    println!(
+        "Wrote {} and {}",
+        cmd.output.display(),
+        password_path.display()
+    );
+
+    if wizard.confirm("Validate the credentials by logging in now?", true)? {
+        // read back what was written so the check covers the config as a whole
+        let file = ConfigFile::load(&cmd.output)?;
+        let password = file.resolve_password()?.unwrap_or_default();
+        let user = User::new(answers.username, password);
+
+        let encryption = DataMiner::new(user).check_login().await?;
+        println!("Login successful, {} password encryption", encryption);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use activesg_gym_datamine::{
+    archive::{self, SnapshotFilter, SnapshotReader},
+    config::ConfigFile,
+    distance,
+    sink::FileSink,
+    DataMResult,
+};
+use tracing::warn;
+
+use crate::args::{Args, SummaryCommand};
+
+/// Prints the number of snapshots and the capture range of every gym in the output directory,
+/// nearest gym first when a home location is configured
+pub fn run(args: &Args, cmd: SummaryCommand) -> DataMResult<()> {
+    let file = match &args.config {
+        Some(path) => ConfigFile::load(path)?,
+        None => ConfigFile::default(),
+    };
+    let gyms = match &cmd.gyms {
+        Some(gyms) => file.gym_set(gyms)?,
+        None => vec![],
+    };
+    let home = file.home_location;
+    let output_dir = file
+        .output_dir
+        .unwrap_or_else(|| FileSink::DEFAULT_OUTPUT_DIR.into());
+
+    let filter = SnapshotFilter {
+        from: cmd.from,
+        to: cmd.to,
+        gyms,
+        formats: vec![],
+    };
+
+    let (by_series, unreadable) = archive::summarize(SnapshotReader::new(output_dir, filter));
+    for e in &unreadable {
+        warn!("{}", e);
+    }
+
+    let mut by_series = by_series.into_iter().collect::<Vec<_>>();
+    distance::sort_by_distance(&mut by_series, |((_, gym), _)| *gym, home);
+
+    for ((activity, gym), summary) in &by_series {
+        let away = distance::distance_km(home, *gym)
+            .map(|km| format!(" ({:.1} km)", km))
+            .unwrap_or_default();
+        println!(
+            "{:?} {:?}{}: {} snapshots, {} to {} UTC",
+            activity, gym, away, summary.snapshots, summary.first, summary.last
+        );
+    }
+    println!("{} unreadable", unreadable.len());
+
+    Ok(())
+}
@@ -0,0 +1,278 @@
+use std::{
+    io::{self, IsTerminal, Stdout},
+    sync::Arc,
+};
+
+use activesg_gym_datamine::{
+    config::Config,
+    daemon::Daemon,
+    dashboard::{self, Command, DashboardState, Grid, Input, Key, Prompt},
+    fetch::FetchTask,
+    report::TaskOutcome,
+    sink::Sink,
+    DataMResult,
+};
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{backend::CrosstermBackend, Terminal};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    sync::mpsc,
+};
+
+use crate::args::TuiCommand;
+
+/// Width laid out for when neither `--width` nor `COLUMNS` tells
+const DEFAULT_WIDTH: usize = 100;
+
+const PLAIN_PROMPT: &str = "d: next date, f [text]: filter gyms, r <GYM>: refresh, q: quit";
+
+const PROMPT: &str = "d: next date, f: filter gyms, esc: every gym, r: refresh a gym, q: quit";
+
+fn width(cmd: &TuiCommand) -> usize {
+    cmd.width
+        .or_else(|| std::env::var("COLUMNS").ok()?.parse().ok())
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+/// Grid and sidebar of the date shown, none when nothing is queried
+fn view<S>(
+    daemon: &Daemon<S>,
+    state: &DashboardState,
+    message: Option<&str>,
+) -> Option<(Grid, Vec<String>)>
+where
+    S: Sink + Send + Sync + 'static,
+{
+    let date = state.date()?;
+    let update = daemon.subscribe().borrow().clone();
+    let gyms = state.visible(&daemon.config().gyms);
+    let grid = Grid::build(daemon.cache(), &gyms, date, daemon.clock().now());
+
+    let mut sidebar = dashboard::sidebar(update.as_ref(), &daemon.quarantined());
+    sidebar.extend(state.filter().map(|f| format!("filter: {}", f)));
+    sidebar.extend(message.map(str::to_string));
+    Some((grid, sidebar))
+}
+
+/// Fetches `task` again in the background, sending how it went to `refreshed`
+fn refresh<S>(daemon: &Arc<Daemon<S>>, task: FetchTask, refreshed: &mpsc::UnboundedSender<String>)
+where
+    S: Sink + Send + Sync + 'static,
+{
+    let (daemon, refreshed) = (daemon.clone(), refreshed.clone());
+    tokio::spawn(async move {
+        let outcome = match daemon.refresh(task).await {
+            Ok(TaskOutcome::Ok { slots, .. }) => format!("{} slots", slots),
+            Ok(TaskOutcome::Failed { message, .. }) => message,
+            Ok(TaskOutcome::Skipped { reason }) => reason,
+            Err(e) => e.to_string(),
+        };
+        let _ = refreshed.send(format!(
+            "refreshed {:?} {}: {}",
+            task.gym, task.date, outcome
+        ));
+    });
+}
+
+/// Applies a command typed in the dashboard, returning whether to quit
+fn apply<S>(
+    daemon: &Arc<Daemon<S>>,
+    state: &mut DashboardState,
+    input: DataMResult<Input>,
+    message: &mut Option<String>,
+    refreshed: &mpsc::UnboundedSender<String>,
+) -> bool
+where
+    S: Sink + Send + Sync + 'static,
+{
+    *message = None;
+    match input.map(|input| state.apply(input)) {
+        Ok(Some(Command::Quit)) => return true,
+        Ok(Some(Command::Refresh(task))) => {
+            *message = Some(format!("refreshing {:?} {}", task.gym, task.date));
+            refresh(daemon, task, refreshed);
+        }
+        Ok(None) => (),
+        Err(e) => *message = Some(e.to_string()),
+    }
+    false
+}
+
+/// Runs the miner in the background and shows its latest snapshots, redrawn after every
+/// iteration and command
+///
+/// On a terminal the dashboard takes the whole screen and is driven by keys, see [Prompt],
+/// otherwise or with `--plain` it is printed again after every line typed
+pub async fn run<S>(config: Config, sink: S, cmd: TuiCommand) -> DataMResult<()>
+where
+    S: Sink + Send + Sync + 'static,
+{
+    let daemon = Arc::new(Daemon::new(config, sink));
+    let state = DashboardState::new(daemon.config().target_dates_at(daemon.clock().now()));
+    tokio::spawn(daemon.clone().run());
+
+    match cmd.plain || !io::stdout().is_terminal() {
+        true => run_plain(daemon, state, cmd).await,
+        false => {
+            let mut terminal = Screen::enter()?;
+            run_screen(daemon, state, &mut terminal.0).await
+        }
+    }
+}
+
+/// The terminal taken over by the dashboard, given back when dropped, on errors included
+struct Screen(Terminal<CrosstermBackend<Stdout>>);
+
+impl Screen {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        Terminal::new(CrosstermBackend::new(io::stdout())).map(Self)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen);
+        let _ = self.0.show_cursor();
+    }
+}
+
+/// Key of the dashboard `event` is, none for those it doesn't use
+fn key(event: Event) -> Option<Key> {
+    let key = match event {
+        Event::Key(key) if key.kind != KeyEventKind::Release => key,
+        _ => return None,
+    };
+    match key.code {
+        // raw mode doesn't turn ctrl-c into a signal
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Some(Key::Char('q')),
+        KeyCode::Char(c) => Some(Key::Char(c)),
+        KeyCode::Enter => Some(Key::Enter),
+        KeyCode::Backspace => Some(Key::Backspace),
+        KeyCode::Esc => Some(Key::Esc),
+        _ => None,
+    }
+}
+
+async fn run_screen<S>(
+    daemon: Arc<Daemon<S>>,
+    mut state: DashboardState,
+    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+) -> DataMResult<()>
+where
+    S: Sink + Send + Sync + 'static,
+{
+    let mut updates = daemon.subscribe();
+    let (refreshed, mut outcomes) = mpsc::unbounded_channel::<String>();
+
+    // crossterm only reads events blocking, none means a resize
+    let (pressed, mut keys) = mpsc::unbounded_channel::<Option<Key>>();
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            let key = match event {
+                Event::Resize(..) => None,
+                event => match key(event) {
+                    Some(key) => Some(key),
+                    None => continue,
+                },
+            };
+            if pressed.send(key).is_err() {
+                return;
+            }
+        }
+    });
+
+    let mut prompt = Prompt::default();
+    let mut message = None;
+    loop {
+        let view = view(&daemon, &state, message.as_deref());
+        let line = match prompt.line() {
+            Some(line) => format!("{}_", line),
+            None => PROMPT.to_string(),
+        };
+        terminal.draw(|frame| match &view {
+            Some((grid, sidebar)) => dashboard::draw(frame, grid, sidebar, &line),
+            None => frame.render_widget("no date queried", frame.area()),
+        })?;
+
+        tokio::select! {
+            changed = updates.changed() => {
+                if changed.is_err() {
+                    return Ok(());
+                }
+                let dates = updates.borrow().as_ref().map(|u| u.dates.clone());
+                if let Some(dates) = dates {
+                    state.set_dates(dates);
+                }
+            }
+            Some(outcome) = outcomes.recv() => message = Some(outcome),
+            key = keys.recv() => {
+                let input = match key {
+                    Some(Some(key)) => prompt.key(key),
+                    Some(None) => None,
+                    // no more events from the terminal
+                    None => return Ok(()),
+                };
+                if let Some(input) = input {
+                    if apply(&daemon, &mut state, input, &mut message, &refreshed) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn run_plain<S>(
+    daemon: Arc<Daemon<S>>,
+    mut state: DashboardState,
+    cmd: TuiCommand,
+) -> DataMResult<()>
+where
+    S: Sink + Send + Sync + 'static,
+{
+    let mut updates = daemon.subscribe();
+    // outcomes of the refreshes, fetched in the background so that input isn't blocked
+    let (refreshed, mut outcomes) = mpsc::unbounded_channel::<String>();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut message = None;
+
+    loop {
+        match view(&daemon, &state, message.as_deref()) {
+            Some((grid, sidebar)) => {
+                print!("{}", dashboard::render(&grid, &sidebar, width(&cmd), false))
+            }
+            None => println!("no date queried"),
+        }
+        println!("{}", PLAIN_PROMPT);
+
+        tokio::select! {
+            changed = updates.changed() => {
+                if changed.is_err() {
+                    return Ok(());
+                }
+                let dates = updates.borrow().as_ref().map(|u| u.dates.clone());
+                if let Some(dates) = dates {
+                    state.set_dates(dates);
+                }
+            }
+            Some(outcome) = outcomes.recv() => message = Some(outcome),
+            line = lines.next_line() => {
+                // stdin closed
+                let line = match line? {
+                    Some(line) => line,
+                    None => return Ok(()),
+                };
+                if apply(&daemon, &mut state, line.parse(), &mut message, &refreshed) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! Bundles the files of past days into `<output_dir>/<date>.tar.zst`, see [compact_day]
+//!
+//! A day is compacted once the date in Singapore has rolled over, the dated directory is
+//! removed after its files are in the bundle. Compacting again merges whatever is left in
+//! the directory into the existing bundle, so a miner restarted at any point finishes the job
+//!
+//! ## Example
+//! ```
+//! use std::{collections::BTreeMap, io::Read};
+//! use activesg_gym_datamine::compaction;
+//! use chrono::NaiveDate;
+//!
+//! let dir = std::env::temp_dir().join(format!("compaction-doctest-{}", std::process::id()));
+//! let (day, today) = (NaiveDate::from_ymd(2022, 1, 11), NaiveDate::from_ymd(2022, 1, 12));
+//! let day_dir = dir.join("2022-01-11");
+//! std::fs::create_dir_all(day_dir.join("runs")).unwrap();
+//! std::fs::create_dir_all(dir.join("2022-01-12")).unwrap();
+//! std::fs::write(day_dir.join("BISHAN-2022-01-11 09-30-00.json"), "{}").unwrap();
+//! std::fs::write(day_dir.join("runs").join("2022-01-11 09-30-00.report.json"), "{}").unwrap();
+//!
+//! let bundled = |path| {
+//!     let raw = zstd::decode_all(std::fs::File::open(path).unwrap()).unwrap();
+//!     let mut archive = tar::Archive::new(&raw[..]);
+//!     let mut files = BTreeMap::new();
+//!     for entry in archive.entries().unwrap() {
+//!         let mut entry = entry.unwrap();
+//!         let name = entry.path().unwrap().to_string_lossy().into_owned();
+//!         let mut body = String::new();
+//!         entry.read_to_string(&mut body).unwrap();
+//!         files.insert(name, body);
+//!     }
+//!     files
+//! };
+//!
+//! // today is still being written to
+//! assert_eq!(compaction::pending_days(&dir, today).unwrap(), [day]);
+//! let compacted = compaction::compact(&dir, today).unwrap();
+//! assert_eq!(compacted.len(), 1);
+//! assert_eq!((compacted[0].date, compacted[0].files), (day, 2));
+//! assert!(!day_dir.exists() && dir.join("2022-01-12").exists());
+//!
+//! let bundle = compaction::bundle_path(&dir, day);
+//! assert_eq!(bundle, dir.join("2022-01-11.tar.zst"));
+//! let files = bundled(&bundle);
+//! let names = files.keys().map(String::as_str).collect::<Vec<_>>();
+//! assert_eq!(names, ["BISHAN-2022-01-11 09-30-00.json", "runs/2022-01-11 09-30-00.report.json"]);
+//!
+//! // a restart after the bundle was written but before the files were removed,
+//! // the files left replace their copy in the bundle
+//! std::fs::create_dir_all(&day_dir).unwrap();
+//! std::fs::write(day_dir.join("BISHAN-2022-01-11 09-30-00.json"), "{\"again\":1}").unwrap();
+//! std::fs::write(day_dir.join("CLEMENTI-2022-01-11 09-30-00.json"), "{}").unwrap();
+//! assert_eq!(compaction::compact_day(&dir, day).unwrap().files, 3);
+//! let files = bundled(&bundle);
+//! assert_eq!(files.len(), 3);
+//! assert_eq!(files["BISHAN-2022-01-11 09-30-00.json"], "{\"again\":1}");
+//! assert!(!day_dir.exists());
+//!
+//! // nothing left to do
+//! assert!(compaction::pending_days(&dir, today).unwrap().is_empty());
+//! assert_eq!(compaction::compact_day(&dir, day).unwrap().files, 0);
+//! assert!(bundle.exists());
+//! std::fs::remove_dir_all(&dir).unwrap();
+//! ```
+
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use chrono::NaiveDate;
+use tracing::info;
+
+use crate::DataMResult;
+
+/// Extension of the bundle of a day
+pub const BUNDLE_EXTENSION: &str = ".tar.zst";
+
+/// A day bundled by [compact_day]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compacted {
+    pub date: NaiveDate,
+
+    /// files in the bundle, those of an earlier bundle included, 0 when nothing was left to do
+    pub files: usize,
+
+    /// size of the bundle
+    pub bytes: u64,
+}
+
+/// `<output_dir>/<date>.tar.zst`
+pub fn bundle_path(output_dir: &Path, date: NaiveDate) -> PathBuf {
+    output_dir.join(format!("{}{}", date.format("%Y-%m-%d"), BUNDLE_EXTENSION))
+}
+
+/// Days before `today` whose dated directory is still in `output_dir`, oldest first
+pub fn pending_days(output_dir: &Path, today: NaiveDate) -> DataMResult<Vec<NaiveDate>> {
+    let mut buf = vec![];
+    if !output_dir.exists() {
+        return Ok(buf);
+    }
+
+    for entry in fs::read_dir(output_dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let date = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| NaiveDate::parse_from_str(name, "%Y-%m-%d").ok());
+        match date {
+            Some(date) if date < today => buf.push(date),
+            _ => (),
+        }
+    }
+
+    buf.sort();
+    Ok(buf)
+}
+
+/// Compacts every day of [pending_days]
+pub fn compact(output_dir: &Path, today: NaiveDate) -> DataMResult<Vec<Compacted>> {
+    pending_days(output_dir, today)?
+        .into_iter()
+        .map(|date| compact_day(output_dir, date))
+        .collect()
+}
+
+/// Bundles every file of the directory of `date`, run reports included, into
+/// [bundle_path] with zstd, then removes the directory
+///
+/// The bundle is written next to its final name and renamed into place, and the files of an
+/// existing bundle are carried over unless the directory has a file of the same name.
+/// Appended files of the day, JSON Lines and Parquet, are already a single file and are left alone
+pub fn compact_day(output_dir: &Path, date: NaiveDate) -> DataMResult<Compacted> {
+    let dir = output_dir.join(date.format("%Y-%m-%d").to_string());
+    let bundle = bundle_path(output_dir, date);
+    if !dir.is_dir() {
+        return Ok(Compacted {
+            date,
+            files: 0,
+            bytes: 0,
+        });
+    }
+
+    let mut files = vec![];
+    list_files(&dir, Path::new(""), &mut files)?;
+    files.sort();
+
+    let mut tmp = bundle.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let written = write_bundle(&tmp, &bundle, &dir, &files).and_then(|count| {
+        fs::rename(&tmp, &bundle)?;
+        Ok(count)
+    });
+    let count = match written {
+        Ok(count) => count,
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+    };
+
+    fs::remove_dir_all(&dir)?;
+    let bytes = fs::metadata(&bundle)?.len();
+    info!(
+        "{}, {} files bundled, {} removed",
+        bundle.display(),
+        count,
+        dir.display()
+    );
+    Ok(Compacted {
+        date,
+        files: count,
+        bytes,
+    })
+}
+
+/// Paths of the files under `dir`, relative to the directory being compacted
+fn list_files(dir: &Path, relative: &Path, buf: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let relative = relative.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            list_files(&entry.path(), &relative, buf)?;
+        } else if relative.extension() != Some("tmp".as_ref()) {
+            // a temporary file is what is left of a write that didn't finish
+            buf.push(relative);
+        }
+    }
+    Ok(())
+}
+
+/// Writes the files of an existing `bundle` not in `files`, then `files` from `dir`, to `tmp`,
+/// returning how many were written
+fn write_bundle(tmp: &Path, bundle: &Path, dir: &Path, files: &[PathBuf]) -> io::Result<usize> {
+    let encoder = zstd::Encoder::new(File::create(tmp)?, 0)?;
+    let mut builder = tar::Builder::new(encoder);
+    let mut count = 0;
+
+    if bundle.exists() {
+        let replaced = files.iter().collect::<BTreeSet<_>>();
+        let mut previous = tar::Archive::new(zstd::Decoder::new(File::open(bundle)?)?);
+        for entry in previous.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            if replaced.contains(&path) {
+                continue;
+            }
+            let mut header = entry.header().clone();
+            builder.append_data(&mut header, &path, &mut entry)?;
+            count += 1;
+        }
+    }
+
+    for path in files {
+        builder.append_path_with_name(dir.join(path), path)?;
+        count += 1;
+    }
+
+    let file = builder.into_inner()?.finish()?;
+    file.sync_all()?;
+    Ok(count)
+}
@@ -0,0 +1,168 @@
+//! Compression of the files written by [crate::sink::FileSink], see [Compression]
+//!
+//! Compressed snapshots keep their name with the extension of the compression appended,
+//! e.g. `BISHAN-2022-01-11 09-30-00.json.gz`, and are read back by [crate::archive]
+//!
+//! ## Example
+//! ```
+//! use activesg_gym_datamine::{
+//!     compress::Compression,
+//!     models::{Gym, GymSlotData, Timeslot},
+//!     sink::{FileSink, OutputFormat, Sink},
+//! };
+//! use chrono::{NaiveDate, TimeZone, Utc};
+//! use std::io::Read;
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let dir = std::env::temp_dir().join(format!("gzip-doctest-{}", std::process::id()));
+//! let sink = FileSink::new(&dir, OutputFormat::Json).with_compression(Compression::Gzip, Some(9));
+//!
+//! let slot = Timeslot::new(Utc.ymd(2022, 1, 11).and_hms(13, 0, 0), 7);
+//! let captured = NaiveDate::from_ymd(2022, 1, 11).and_hms(1, 30, 0);
+//! let data = GymSlotData::new(Gym::BISHAN, captured, vec![slot; 100]);
+//! let written = sink.publish(&data).await.unwrap();
+//!
+//! let path = dir.join("2022-01-11").join("BISHAN-2022-01-11 09-30-00.json.gz");
+//! let raw = std::fs::read(&path).unwrap();
+//! assert_eq!(raw.len() as u64, written);
+//!
+//! let mut json = String::new();
+//! flate2::read::GzDecoder::new(&raw[..]).read_to_string(&mut json).unwrap();
+//! assert_eq!(json, serde_json::to_string_pretty(&data).unwrap());
+//! assert_eq!(Compression::Gzip.decode(&raw).unwrap(), json.as_bytes());
+//!
+//! assert!(raw.len() < json.len() / 10);
+//! assert!(Compression::Gzip.check_level(Some(10)).is_err());
+//!
+//! let zstd = Compression::Zstd.encode(json.as_bytes(), Some(19)).unwrap();
+//! assert_eq!(Compression::detect(&zstd), Compression::Zstd);
+//! assert_eq!(Compression::Zstd.decode(&zstd).unwrap(), json.as_bytes());
+//! assert!(Compression::Zstd.check_level(Some(0)).is_err());
+//! assert_eq!("none".parse::<Compression>().unwrap(), Compression::None);
+//! std::fs::remove_dir_all(&dir).unwrap();
+//! # }
+//! ```
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{errors, DataMResult};
+
+/// Compression of the written files, none by default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+
+    /// `.gz`, readable with `zcat`
+    Gzip,
+
+    /// `.zst`, readable with `zstdcat`
+    Zstd,
+}
+
+impl Compression {
+    const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+    const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+    /// Appended to the name of the files, empty when uncompressed
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::None => "",
+            Self::Gzip => ".gz",
+            Self::Zstd => ".zst",
+        }
+    }
+
+    /// Compression used for `raw`, from its magic bytes
+    pub fn detect(raw: &[u8]) -> Self {
+        if raw.starts_with(&Self::GZIP_MAGIC) {
+            Self::Gzip
+        } else if raw.starts_with(&Self::ZSTD_MAGIC) {
+            Self::Zstd
+        } else {
+            Self::None
+        }
+    }
+
+    /// Refuses a `level` out of the range of the compression, none meaning its default
+    pub fn check_level(&self, level: Option<u32>) -> DataMResult<()> {
+        match (self, level) {
+            (_, None) | (Self::Gzip, Some(0..=9)) | (Self::Zstd, Some(1..=22)) => Ok(()),
+            (Self::None, Some(_)) => Err(errors::Error::InvalidConfig(
+                "a compression level needs a compression".into(),
+            )),
+            (Self::Gzip, Some(level)) => Err(errors::Error::InvalidConfig(format!(
+                "invalid gzip level {}, expected 0 to 9",
+                level
+            ))),
+            (Self::Zstd, Some(level)) => Err(errors::Error::InvalidConfig(format!(
+                "invalid zstd level {}, expected 1 to 22",
+                level
+            ))),
+        }
+    }
+
+    /// `data` compressed at `level`, the default level of the compression when none
+    pub fn encode(&self, data: &[u8], level: Option<u32>) -> io::Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Gzip => {
+                let level =
+                    level.map_or_else(flate2::Compression::default, flate2::Compression::new);
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), level);
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            // 0 is the default level of zstd
+            Self::Zstd => zstd::encode_all(data, level.unwrap_or(0) as i32),
+        }
+    }
+
+    /// `raw` decompressed, every member or frame of a file appended to line by line included
+    pub fn decode(&self, raw: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(raw.to_vec()),
+            Self::Gzip => {
+                let mut buf = vec![];
+                flate2::read::MultiGzDecoder::new(raw).read_to_end(&mut buf)?;
+                Ok(buf)
+            }
+            Self::Zstd => zstd::decode_all(raw),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = errors::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "gzip" => Ok(Self::Gzip),
+            "zstd" => Ok(Self::Zstd),
+            _ => Err(errors::Error::InvalidConfig(format!(
+                "unknown compression {}, expected none, gzip or zstd",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::None => "none",
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        };
+        f.write_str(name)
+    }
+}
@@ -0,0 +1,1240 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::{login::OtpSource, DEFAULT_CONNECT_TIMEOUT, DEFAULT_TIMEOUT},
+    clock::SGT,
+    compress::Compression,
+    distance::LatLng,
+    encryption::EncryptionKind,
+    errors,
+    fetch::FetchTask,
+    filter::SinkFilter,
+    gymset::{GymGroups, GymSelection},
+    headers::DEFAULT_CAPTURED_HEADERS,
+    hours::{HoursRange, OpeningHours},
+    models::{Activity, Gym, User},
+    notify::NotifierConfig,
+    ratelimit::RequestRate,
+    retention::RetentionPolicy,
+    retry::RetryPolicy,
+    schedule::{self, IterationKind, OverlapPolicy, RateInputs},
+    sink::{FileSink, OutputFormat},
+    sinkhealth::SlowSinkPolicy,
+    template::FilenameTemplate,
+    venue::Venue,
+    watch::Profile,
+    DataMResult,
+};
+
+/// Environment variable with the username, when not given by a flag
+pub const USERNAME_ENV: &str = "ACTIVESG_USERNAME";
+
+/// Environment variable with the password, when not given by a flag
+pub const PASSWORD_ENV: &str = "ACTIVESG_PASSWORD";
+
+/// Venues to be queried for an activity other than [Activity::GYM]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActivityVenues {
+    pub activity: Activity,
+    pub venues: Vec<Gym>,
+}
+
+impl FromStr for ActivityVenues {
+    type Err = errors::Error;
+
+    /// Parses `ACTIVITY=GYM,GYM`, for example `BADMINTON=BISHAN,CLEMENTI`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (activity, venues) = s
+            .split_once('=')
+            .ok_or_else(|| errors::Error::InvalidActivity(s.into()))?;
+
+        Ok(Self {
+            activity: activity.trim().parse()?,
+            venues: venues
+                .split(',')
+                .map(|v| v.trim().parse())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// Parses a duration such as `1s`, `500ms` or `2m`, a bare number is in seconds
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::config;
+/// use std::time::Duration;
+///
+/// assert_eq!(config::parse_duration("90").unwrap(), Duration::from_secs(90));
+/// assert_eq!(config::parse_duration("1s").unwrap(), Duration::from_secs(1));
+/// assert_eq!(config::parse_duration(" 500ms ").unwrap(), Duration::from_millis(500));
+/// assert_eq!(config::parse_duration("2m").unwrap(), Duration::from_secs(120));
+/// assert_eq!(config::parse_duration("6h").unwrap(), Duration::from_secs(6 * 60 * 60));
+///
+/// for invalid in ["", "s", "-1s", "1.5s", "10d", "5 minutes"] {
+///     assert!(config::parse_duration(invalid).is_err(), "{:?}", invalid);
+/// }
+///
+/// // too long to be counted in seconds rather than wrapping around
+/// let e = config::parse_duration("999999999999999999h").unwrap_err();
+/// assert_eq!(e, "duration 999999999999999999h is too long");
+/// assert!(config::parse_duration("999999999999999999m").is_err());
+/// ```
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value = value
+        .parse::<u64>()
+        .map_err(|_| format!("invalid duration {}", s))?;
+    let secs = |multiplier: u64| {
+        value
+            .checked_mul(multiplier)
+            .map(Duration::from_secs)
+            .ok_or_else(|| format!("duration {} is too long", s))
+    };
+
+    match unit.trim() {
+        "" | "s" => Ok(Duration::from_secs(value)),
+        "ms" => Ok(Duration::from_millis(value)),
+        "m" => secs(60),
+        "h" => secs(60 * 60),
+        _ => Err(format!(
+            "invalid duration unit in {}, expected ms, s, m or h",
+            s
+        )),
+    }
+}
+
+/// Parses a list of day offsets such as `0,1,2,3`, see [check_day_offsets]
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::config;
+///
+/// assert_eq!(config::parse_day_offsets("0, 1,3").unwrap(), [0, 1, 3]);
+/// for invalid in ["", "0,,1", "tomorrow", "-1", "0,1,0", "0,15"] {
+///     assert!(config::parse_day_offsets(invalid).is_err(), "{:?}", invalid);
+/// }
+/// ```
+pub fn parse_day_offsets(s: &str) -> Result<Vec<i64>, String> {
+    let offsets = s
+        .split(',')
+        .map(|d| {
+            d.trim()
+                .parse::<i64>()
+                .map_err(|_| format!("invalid day offset {:?} in {}", d.trim(), s))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    check_day_offsets(&offsets)?;
+    Ok(offsets)
+}
+
+/// Parses a number of days to query from today, `3` being today, +1 and +2 days
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::config;
+///
+/// assert_eq!(config::parse_days("5").unwrap(), [0, 1, 2, 3, 4]);
+/// assert_eq!(config::parse_days("1").unwrap(), [0]);
+/// for invalid in ["0", "16", "-2", "two", "18446744073709551615"] {
+///     assert!(config::parse_days(invalid).is_err(), "{:?}", invalid);
+/// }
+/// ```
+pub fn parse_days(s: &str) -> Result<Vec<i64>, String> {
+    let days = s
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| format!("invalid number of days {}", s))?;
+    first_days(days)
+}
+
+/// Offsets of the first `days` days from today
+pub fn first_days(days: u64) -> Result<Vec<i64>, String> {
+    let max = Config::MAX_DAY_OFFSET as u64 + 1;
+    if !(1..=max).contains(&days) {
+        return Err(format!("{} days can't be queried, from 1 to {}", days, max));
+    }
+    Ok((0..days as i64).collect())
+}
+
+/// Fails unless `offsets` has at least one day, none twice, and none before today
+/// or after [Config::MAX_DAY_OFFSET]
+pub fn check_day_offsets(offsets: &[i64]) -> Result<(), String> {
+    if offsets.is_empty() {
+        return Err("at least one day must be queried".into());
+    }
+    for (i, offset) in offsets.iter().enumerate() {
+        if !(0..=Config::MAX_DAY_OFFSET).contains(offset) {
+            return Err(format!(
+                "day offset {} is out of range, it must be from 0 to {}",
+                offset,
+                Config::MAX_DAY_OFFSET
+            ));
+        }
+        if offsets[..i].contains(offset) {
+            return Err(format!("day offset {} is given twice", offset));
+        }
+    }
+    Ok(())
+}
+
+/// Dates `offsets` days from the date in Singapore at `now_utc`
+///
+/// The day rolls over at 16:00 UTC, so a miner running on a UTC machine between
+/// midnight and 08:00 in Singapore already queries the new day
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::config;
+/// use chrono::{NaiveDate, TimeZone, Utc};
+///
+/// let day = |d| NaiveDate::from_ymd(2022, 1, d);
+/// let offsets = [0, 2, 3];
+///
+/// // 23:59 SGT on the 10th
+/// let now = Utc.ymd(2022, 1, 10).and_hms(15, 59, 59);
+/// assert_eq!(config::target_dates(now, &offsets), [day(10), day(12), day(13)]);
+///
+/// // midnight SGT, still the 10th in UTC
+/// let now = Utc.ymd(2022, 1, 10).and_hms(16, 0, 0);
+/// assert_eq!(config::target_dates(now, &offsets), [day(11), day(13), day(14)]);
+///
+/// // 07:59 SGT, the last minute UTC and Singapore disagree on the date
+/// let now = Utc.ymd(2022, 1, 10).and_hms(23, 59, 0);
+/// assert_eq!(config::target_dates(now, &offsets), [day(11), day(13), day(14)]);
+///
+/// // 08:00 SGT, both on the 11th
+/// let now = Utc.ymd(2022, 1, 11).and_hms(0, 0, 0);
+/// assert_eq!(config::target_dates(now, &offsets), [day(11), day(13), day(14)]);
+///
+/// // across the end of a month and of a year
+/// let now = Utc.ymd(2022, 12, 30).and_hms(17, 0, 0);
+/// let dates = config::target_dates(now, &offsets);
+/// assert_eq!(dates, [(2022, 12, 31), (2023, 1, 2), (2023, 1, 3)].map(|(y, m, d)| NaiveDate::from_ymd(y, m, d)));
+/// ```
+pub fn target_dates(now_utc: DateTime<Utc>, offsets: &[i64]) -> Vec<NaiveDate> {
+    let today = now_utc.with_timezone(&*SGT).naive_local().date();
+    offsets
+        .iter()
+        .map(|d| today + chrono::Duration::days(*d))
+        .collect()
+}
+
+/// Gyms to query, `allow` or else every gym, minus `exclude`
+///
+/// Fails when a gym is both allowed and excluded, or when nothing is left to query
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{config, errors::Error, models::Gym};
+///
+/// // neither, every gym
+/// assert_eq!(config::resolve_gyms(None, &[]).unwrap(), Gym::gym_slice());
+///
+/// // allow only, in the order given
+/// let allow = [Gym::CLEMENTI, Gym::BISHAN];
+/// assert_eq!(config::resolve_gyms(Some(&allow), &[]).unwrap(), allow);
+///
+/// // exclude only, every other gym
+/// let gyms = config::resolve_gyms(None, &[Gym::BISHAN]).unwrap();
+/// assert_eq!(gyms.len(), Gym::gym_slice().len() - 1);
+/// assert!(!gyms.contains(&Gym::BISHAN));
+///
+/// // both
+/// let allow = [Gym::CLEMENTI, Gym::BISHAN, Gym::TAMPINES];
+/// assert_eq!(
+///     config::resolve_gyms(Some(&allow[..2]), &[Gym::TAMPINES]).unwrap(),
+///     [Gym::CLEMENTI, Gym::BISHAN]
+/// );
+///
+/// // conflicting
+/// let e = config::resolve_gyms(Some(&allow), &[Gym::TAMPINES]).unwrap_err();
+/// assert!(matches!(e, Error::InvalidConfig(_)));
+/// assert_eq!(e.to_string(), "Invalid config: TAMPINES is both in gyms and excluded gyms");
+///
+/// // nothing left
+/// let e = config::resolve_gyms(None, Gym::gym_slice()).unwrap_err();
+/// assert_eq!(e.to_string(), "Invalid config: every gym is excluded, nothing to query");
+/// assert!(config::resolve_gyms(Some(&[]), &[]).is_err());
+/// ```
+pub fn resolve_gyms(allow: Option<&[Gym]>, exclude: &[Gym]) -> DataMResult<Vec<Gym>> {
+    if let Some(allow) = allow {
+        if let Some(gym) = allow.iter().find(|g| exclude.contains(g)) {
+            return Err(errors::Error::InvalidConfig(format!(
+                "{:?} is both in gyms and excluded gyms",
+                gym
+            )));
+        }
+    }
+
+    let gyms = allow
+        .unwrap_or_else(|| Gym::gym_slice())
+        .iter()
+        .filter(|g| !exclude.contains(g))
+        .copied()
+        .collect::<Vec<_>>();
+
+    match gyms.is_empty() {
+        true => Err(errors::Error::InvalidConfig(
+            "every gym is excluded, nothing to query".into(),
+        )),
+        false => Ok(gyms),
+    }
+}
+
+/// Everything needed to perform a mining iteration
+#[derive(Clone)]
+pub struct Config {
+    /// credentials used to login to ActiveSG
+    pub user: User,
+
+    /// gyms to query, in iteration order
+    pub gyms: Vec<Gym>,
+
+    /// gyms fetched first in every iteration, the rest are ordered by staleness
+    pub priority_gyms: Vec<Gym>,
+
+    /// venues missing from [Gym], queried for gym slots after [Config::gyms]
+    pub custom_venues: Vec<Venue>,
+
+    /// maximum number of fetches per iteration, the remaining fetches are skipped
+    pub iteration_budget: Option<usize>,
+
+    /// time of day in Singapore when ActiveSG doesn't answer, iterations are skipped
+    /// and caught up once it ends, see [schedule::Scheduler]
+    pub blackout: Option<HoursRange>,
+
+    /// [Config::iteration_budget] of the iteration catching up after the blackout
+    pub catch_up_budget: Option<usize>,
+
+    /// fetching pauses until midnight SGT once this many bytes were downloaded in the day,
+    /// counting every response, the failed fetches, login pages and error pages included
+    pub max_daily_download_bytes: Option<u64>,
+
+    /// additional activities to query, after the gyms
+    pub other_activities: Vec<ActivityVenues>,
+
+    /// root directory of everything written to disk
+    pub output_dir: PathBuf,
+
+    /// pruning of old snapshots in [Config::output_dir], ran after every iteration
+    pub retention: RetentionPolicy,
+
+    /// days relative to today that will be queried for each gym
+    pub day_offsets: Vec<i64>,
+
+    /// time between the start of each iteration
+    pub interval: Duration,
+
+    /// what to do with an iteration due while the previous one is still running,
+    /// see [schedule::Cycles]
+    pub overlap_policy: OverlapPolicy,
+
+    /// run a single iteration and exit rather than one every [Config::interval]
+    pub once: bool,
+
+    /// log the snapshots instead of storing them, and write nothing to [Config::output_dir],
+    /// see [crate::sink::DryRunSink]
+    pub dry_run: bool,
+
+    /// delay between consecutive fetches so that we don't hammer the site,
+    /// see [Config::politeness_delay]
+    pub inter_gym_delay: Duration,
+
+    /// fetches in flight at once, each paced by [Config::inter_gym_delay], sharing the session
+    pub concurrency: usize,
+
+    /// cap on the requests sent to ActiveSG, logins and retries included, see [crate::ratelimit]
+    pub rps: Option<RequestRate>,
+
+    /// upper bound of the delay before the first iteration, see [Config::splay]
+    pub max_splay: Duration,
+
+    /// seed of [Config::splay], defaults to the hostname and [Config::output_dir]
+    pub splay_seed: Option<String>,
+
+    /// correct the date window using the clock skew measured from ActiveSG's `Date` header
+    pub trust_server_time: bool,
+
+    /// response headers copied into the snapshot metadata, see [crate::headers]
+    pub captured_headers: Vec<String>,
+
+    /// overrides of [Gym::opening_hours] for venues with unusual hours
+    pub opening_hours: HashMap<Gym, OpeningHours>,
+
+    /// keep gym slots outside of opening hours instead of dropping them
+    pub keep_out_of_hours: bool,
+
+    /// slots starting sooner than this after capture are marked past the booking cut-off
+    pub booking_cutoff: Duration,
+
+    /// add a stable [crate::models::Timeslot::slot_id_of] to every timeslot
+    pub slot_ids: bool,
+
+    /// add [crate::models::Gym::display] to every snapshot
+    pub gym_names: bool,
+
+    /// write [crate::stats::UsageStats] to [Config::output_dir] every hour
+    pub write_stats: bool,
+
+    /// check at start and weekly that every gym id still leads to the gym,
+    /// quarantining the gyms that don't
+    pub validate_venues: bool,
+
+    /// watch lists evaluated against every new snapshot
+    pub profiles: Vec<Profile>,
+
+    /// write a [crate::report::RunReport] after every iteration
+    pub run_reports: bool,
+
+    /// write a [crate::diff::SnapshotDiff] against the previous snapshot alongside each snapshot
+    pub emit_diffs: bool,
+
+    /// append slots being published, filled and reopened to [crate::events::EVENTS_DIR]
+    pub event_log: bool,
+
+    /// bundle the files of every past day into a `.tar.zst`, see [crate::compaction]
+    pub compact_days: bool,
+
+    /// fetch anonymously while the credentials are rejected, see [crate::breaker::CredentialBreaker]
+    pub anonymous_fallback: bool,
+
+    /// where the user lives, alerts are sorted by distance from it, see [crate::distance]
+    pub home_location: Option<LatLng>,
+
+    /// attempts at publishing a snapshot, retries only go to the sinks that failed,
+    /// see [crate::sink::deliver]
+    pub publish_attempts: u32,
+
+    /// retries of a fetch failing for a transient reason, see [crate::retry]
+    pub retry: RetryPolicy,
+
+    /// longest a request to ActiveSG may take, from connecting to the end of the body,
+    /// failing with a retryable timeout past it
+    pub timeout: Duration,
+
+    /// longest connecting to ActiveSG may take, within [Config::timeout]
+    pub connect_timeout: Duration,
+
+    /// password encryption of the login widget, detected from the login page by default
+    pub password_encryption: EncryptionKind,
+
+    /// cookies of the session kept between runs, see [crate::cookies]
+    pub cookie_file: Option<PathBuf>,
+
+    /// where the one-time PIN ActiveSG may ask for after the password comes from,
+    /// failing the login without, see [crate::client::login]
+    pub otp: Option<OtpSource>,
+
+    /// highly available url probed when fetches keep failing after connecting to ActiveSG,
+    /// ActiveSG is only reported degraded when it answers, see [crate::outage]
+    pub reference_url: Option<reqwest::Url>,
+
+    /// when a sink is too slow, latencies are only measured without one, see [crate::sinkhealth]
+    pub slow_sink: Option<SlowSinkPolicy>,
+
+    /// where an iteration with too many failures is reported, once per iteration,
+    /// see [crate::metrics::IterationStats::failure_alert_due]
+    pub failure_notifier: Option<NotifierConfig>,
+
+    /// failed fetches of an iteration tolerated before reporting it
+    pub failure_alert_threshold: usize,
+
+    /// replay saved booking pages instead of fetching them
+    #[cfg(feature = "dev")]
+    pub dev_artifacts: Option<crate::dev::DevArtifacts>,
+
+    /// serve the [crate::server] API on this address
+    #[cfg(feature = "server")]
+    pub http_listen: Option<std::net::SocketAddr>,
+
+    /// bearer token required by `POST /api/refresh`, refreshes are refused without one
+    #[cfg(feature = "server")]
+    pub api_token: Option<String>,
+
+    /// store the snapshots in this SQLite database instead of JSON files, see [crate::storage]
+    #[cfg(feature = "sqlite")]
+    pub sqlite: Option<PathBuf>,
+
+    /// upload the snapshots and diffs written to this bucket, see [crate::s3]
+    #[cfg(feature = "s3")]
+    pub s3_bucket: Option<String>,
+
+    /// prepended to the keys of the uploaded files
+    #[cfg(feature = "s3")]
+    pub s3_prefix: String,
+
+    /// only upload the files, without writing them to [Config::output_dir]
+    #[cfg(feature = "s3")]
+    pub no_local: bool,
+
+    /// remove the local copy of a file once uploaded
+    #[cfg(feature = "s3")]
+    pub s3_delete_local: bool,
+
+    /// snapshots delivered to each sink, by [crate::sink::Sink::name], see [crate::filter]
+    pub sink_filters: BTreeMap<String, SinkFilter>,
+}
+
+impl Config {
+    /// Default day offsets, which is today, +2 days and +3 days
+    pub const DEFAULT_DAY_OFFSETS: [i64; 3] = [0, 2, 3];
+
+    /// Furthest day that can be queried, 2 weeks from today
+    pub const MAX_DAY_OFFSET: i64 = 14;
+
+    /// 20 min between iterations
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60 * 20);
+
+    /// Shorter intervals are refused whatever the politeness check says
+    pub const MIN_INTERVAL: Duration = Duration::from_secs(60);
+
+    /// 1s between each fetch
+    pub const DEFAULT_INTER_GYM_DELAY: Duration = Duration::from_secs(1);
+
+    /// Up to 5 failed fetches in an iteration go unreported
+    pub const DEFAULT_FAILURE_ALERT_THRESHOLD: usize = 5;
+
+    /// One fetch at a time
+    pub const DEFAULT_CONCURRENCY: usize = 1;
+
+    /// 1 week between venue validations, see [Config::validate_venues]
+    pub const VENUE_VALIDATION_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+
+    /// 30 min before the slot starts
+    pub const DEFAULT_BOOKING_CUTOFF: Duration = Duration::from_secs(60 * 30);
+
+    /// Creates a config for `user` that queries every gym with the default offsets
+    pub fn new(user: User) -> Self {
+        Self {
+            user,
+            gyms: Gym::gym_slice().to_vec(),
+            priority_gyms: vec![],
+            custom_venues: vec![],
+            iteration_budget: None,
+            blackout: None,
+            catch_up_budget: None,
+            max_daily_download_bytes: None,
+            other_activities: vec![],
+            output_dir: FileSink::DEFAULT_OUTPUT_DIR.into(),
+            retention: RetentionPolicy::default(),
+            day_offsets: Self::DEFAULT_DAY_OFFSETS.to_vec(),
+            interval: Self::DEFAULT_INTERVAL,
+            overlap_policy: OverlapPolicy::default(),
+            once: false,
+            dry_run: false,
+            inter_gym_delay: Self::DEFAULT_INTER_GYM_DELAY,
+            concurrency: Self::DEFAULT_CONCURRENCY,
+            rps: None,
+            max_splay: Duration::ZERO,
+            splay_seed: None,
+            trust_server_time: false,
+            captured_headers: DEFAULT_CAPTURED_HEADERS.map(String::from).to_vec(),
+            opening_hours: HashMap::new(),
+            keep_out_of_hours: false,
+            booking_cutoff: Self::DEFAULT_BOOKING_CUTOFF,
+            slot_ids: false,
+            gym_names: false,
+            write_stats: false,
+            validate_venues: false,
+            profiles: vec![],
+            run_reports: true,
+            emit_diffs: false,
+            event_log: false,
+            compact_days: false,
+            anonymous_fallback: false,
+            publish_attempts: 3,
+            retry: RetryPolicy::default(),
+            timeout: DEFAULT_TIMEOUT,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            home_location: None,
+            password_encryption: EncryptionKind::default(),
+            cookie_file: None,
+            otp: None,
+            reference_url: None,
+            slow_sink: None,
+            failure_notifier: None,
+            failure_alert_threshold: Self::DEFAULT_FAILURE_ALERT_THRESHOLD,
+            #[cfg(feature = "dev")]
+            dev_artifacts: None,
+            #[cfg(feature = "server")]
+            http_listen: None,
+            #[cfg(feature = "server")]
+            api_token: None,
+            #[cfg(feature = "sqlite")]
+            sqlite: None,
+            #[cfg(feature = "s3")]
+            s3_bucket: None,
+            #[cfg(feature = "s3")]
+            s3_prefix: String::new(),
+            #[cfg(feature = "s3")]
+            no_local: false,
+            #[cfg(feature = "s3")]
+            s3_delete_local: false,
+            sink_filters: BTreeMap::new(),
+        }
+    }
+
+    /// Requests per minute above which the miner refuses to start without an override
+    pub const MAX_REQUESTS_PER_MINUTE: f64 = 60.0;
+
+    /// login page, login and booking page, when the session has expired
+    pub const REQUESTS_PER_FETCH: usize = 3;
+
+    /// Inputs of [schedule::worst_case_request_rate] for this config
+    pub fn rate_inputs(&self) -> RateInputs {
+        let dates = vec![NaiveDate::from_ymd(1970, 1, 1); self.day_offsets.len()];
+        RateInputs {
+            fetches_per_iteration: self.fetch_tasks(&dates).len(),
+            requests_per_fetch: Self::REQUESTS_PER_FETCH,
+            retries: self.retry.retries,
+            concurrency: self.concurrency,
+            limiter_spacing: self.rps.map(|r| r.spacing()),
+            interval: self.interval,
+            inter_gym_delay: self.politeness_delay(),
+        }
+    }
+
+    /// Fails when the worst case request rate is above [Config::MAX_REQUESTS_PER_MINUTE]
+    ///
+    /// ## Example
+    /// ```
+    /// use std::time::Duration;
+    /// use activesg_gym_datamine::{config::Config, models::User};
+    ///
+    /// let mut config = Config::new(User::new("user@example.com", "password"));
+    /// config.interval = Duration::from_secs(60);
+    /// config.concurrency = 4;
+    /// assert!(config.check_politeness().is_err());
+    ///
+    /// // at most one request per second, whatever the concurrency
+    /// config.rps = "1".parse().ok();
+    /// assert!(config.check_politeness().is_ok());
+    /// // the rate limiter spaces the fetches already
+    /// assert_eq!(config.politeness_delay(), Duration::ZERO);
+    /// ```
+    pub fn check_politeness(&self) -> DataMResult<()> {
+        let inputs = self.rate_inputs();
+        let rate = schedule::worst_case_request_rate(&inputs);
+        if rate <= Self::MAX_REQUESTS_PER_MINUTE {
+            return Ok(());
+        }
+
+        Err(errors::Error::InvalidConfig(format!(
+            "up to {:.0} requests per minute ({} fetches every {}s, {} requests each, \
+             {} retries, concurrency {}, {}ms between fetches) is above the limit of {:.0}, \
+             increase the interval or the delay, or pass --i-know-what-im-doing",
+            rate,
+            inputs.fetches_per_iteration,
+            inputs.interval.as_secs(),
+            inputs.requests_per_fetch,
+            inputs.retries,
+            inputs.concurrency,
+            inputs.inter_gym_delay.as_millis(),
+            Self::MAX_REQUESTS_PER_MINUTE
+        )))
+    }
+
+    /// Fetches allowed in an iteration of `kind`
+    ///
+    /// Catching up after the blackout is allowed [Config::catch_up_budget],
+    /// or else twice the [Config::iteration_budget]
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::{config::Config, models::User, schedule::IterationKind};
+    ///
+    /// let mut config = Config::new(User::new("user@example.com", "password"));
+    /// assert_eq!(config.iteration_budget_for(IterationKind::CatchUp), None);
+    ///
+    /// config.iteration_budget = Some(10);
+    /// assert_eq!(config.iteration_budget_for(IterationKind::Regular), Some(10));
+    /// assert_eq!(config.iteration_budget_for(IterationKind::CatchUp), Some(20));
+    ///
+    /// config.catch_up_budget = Some(50);
+    /// assert_eq!(config.iteration_budget_for(IterationKind::Regular), Some(10));
+    /// assert_eq!(config.iteration_budget_for(IterationKind::CatchUp), Some(50));
+    /// ```
+    pub fn iteration_budget_for(&self, kind: IterationKind) -> Option<usize> {
+        match kind {
+            IterationKind::Regular => self.iteration_budget,
+            IterationKind::CatchUp => self
+                .catch_up_budget
+                .or_else(|| self.iteration_budget.map(|b| b * 2)),
+        }
+    }
+
+    /// Sleep between consecutive fetches, see [schedule::politeness_delay]
+    pub fn politeness_delay(&self) -> Duration {
+        schedule::politeness_delay(self.inter_gym_delay, self.rps.map(|r| r.spacing()))
+    }
+
+    /// Delay before the first iteration, so that installations started together
+    /// don't all login at the same time, see [schedule::splay_offset]
+    pub fn splay(&self) -> Duration {
+        let seed = self.splay_seed.clone().unwrap_or_else(|| {
+            let hostname = std::env::var("HOSTNAME")
+                .ok()
+                .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+                .unwrap_or_default();
+            format!("{}:{}", hostname.trim(), self.output_dir.display())
+        });
+
+        schedule::splay_offset(&seed, self.max_splay)
+    }
+
+    /// Opening hours of `gym`, taking overrides into account
+    pub fn opening_hours_of(&self, gym: Gym) -> OpeningHours {
+        self.opening_hours
+            .get(&gym)
+            .copied()
+            .unwrap_or_else(|| gym.opening_hours())
+    }
+
+    /// Dates to be queried relative to `now`, see [target_dates]
+    pub fn target_dates_at(&self, now: DateTime<Utc>) -> Vec<NaiveDate> {
+        target_dates(now, &self.day_offsets)
+    }
+
+    /// Expands the configured activities and venues into one [FetchTask] per date
+    ///
+    /// Gyms come first, then [Config::custom_venues],
+    /// followed by [Config::other_activities] in the order they were given
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::{
+    ///     config::Config,
+    ///     fetch::FetchTask,
+    ///     models::{Activity, Gym, User},
+    ///     venue::Venue,
+    /// };
+    /// use chrono::NaiveDate;
+    ///
+    /// let mut config = Config::new(User::new("user@example.com", "password"));
+    /// config.gyms = vec![Gym::BISHAN, Gym::CLEMENTI];
+    /// config.custom_venues = vec!["1234:MY_GYM".parse::<Venue>().unwrap()];
+    /// config.other_activities = vec![
+    ///     "BADMINTON=BISHAN".parse().unwrap(),
+    ///     "SWIMMING=CLEMENTI, BISHAN".parse().unwrap(),
+    /// ];
+    ///
+    /// let [d0, d1] = [11, 12].map(|d| NaiveDate::from_ymd(2022, 1, d));
+    /// let my_gym = "1234:MY_GYM".parse::<Venue>().unwrap();
+    /// assert_eq!(
+    ///     config.fetch_tasks(&[d0, d1]),
+    ///     vec![
+    ///         FetchTask::new(Activity::GYM, Gym::BISHAN, d0),
+    ///         FetchTask::new(Activity::GYM, Gym::BISHAN, d1),
+    ///         FetchTask::new(Activity::GYM, Gym::CLEMENTI, d0),
+    ///         FetchTask::new(Activity::GYM, Gym::CLEMENTI, d1),
+    ///         FetchTask::new(Activity::GYM, my_gym, d0),
+    ///         FetchTask::new(Activity::GYM, my_gym, d1),
+    ///         FetchTask::new(Activity::BADMINTON, Gym::BISHAN, d0),
+    ///         FetchTask::new(Activity::BADMINTON, Gym::BISHAN, d1),
+    ///         FetchTask::new(Activity::SWIMMING, Gym::CLEMENTI, d0),
+    ///         FetchTask::new(Activity::SWIMMING, Gym::CLEMENTI, d1),
+    ///         FetchTask::new(Activity::SWIMMING, Gym::BISHAN, d0),
+    ///         FetchTask::new(Activity::SWIMMING, Gym::BISHAN, d1),
+    ///     ]
+    /// );
+    ///
+    /// // only other activities
+    /// config.gyms = vec![];
+    /// config.custom_venues = vec![];
+    /// config.other_activities.truncate(1);
+    /// assert_eq!(
+    ///     config.fetch_tasks(&[d0]),
+    ///     vec![FetchTask::new(Activity::BADMINTON, Gym::BISHAN, d0)]
+    /// );
+    /// ```
+    pub fn fetch_tasks(&self, dates: &[NaiveDate]) -> Vec<FetchTask> {
+        self.fetch_tasks_with(&self.gyms, dates)
+    }
+
+    /// Same as [Config::fetch_tasks] but with the gyms in the order of `gyms`
+    pub fn fetch_tasks_with(&self, gyms: &[Gym], dates: &[NaiveDate]) -> Vec<FetchTask> {
+        let gyms = gyms
+            .iter()
+            .map(|gym| (Activity::GYM, Venue::from(*gym)))
+            .chain(self.custom_venues.iter().map(|v| (Activity::GYM, *v)));
+        let others = self
+            .other_activities
+            .iter()
+            .flat_map(|a| a.venues.iter().map(|gym| (a.activity, Venue::from(*gym))));
+
+        gyms.chain(others)
+            .flat_map(|(activity, venue)| {
+                dates
+                    .iter()
+                    .map(move |date| FetchTask::new(activity, venue, *date))
+            })
+            .collect()
+    }
+}
+
+/// On disk configuration, every field is optional and CLI flags take precedence
+///
+/// ## Example
+/// ```toml
+/// username = "user@example.com"
+/// password_file = "activesg.password"
+/// gyms = "group:near-home + CLEMENTI"
+/// exclude_gyms = ["region:EAST"]
+/// interval_secs = 1200
+/// output_dir = "output"
+/// format = "json"
+///
+/// [opening_hours.CLEMENTI]
+/// weekday = "07:00-22:00"
+/// weekend = "08:00-20:00"
+///
+/// [gym_groups]
+/// near-home = "BISHAN, TOA_PAYOH"
+///
+/// [[profiles]]
+/// name = "alice"
+/// watches = [{ gyms = "group:near-home", min_slots = 5, hours = "18:00-21:00" }]
+/// notifiers = [{ kind = "webhook", url = "https://example.com/hook" }]
+///
+/// [filters.file]
+/// gyms = "group:near-home"
+/// only_when_changed = true
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    /// email address used to login
+    pub username: Option<String>,
+
+    /// password used to login, prefer [ConfigFile::password_file]
+    pub password: Option<String>,
+
+    /// file containing only the password, relative to the config file
+    pub password_file: Option<PathBuf>,
+
+    /// gyms to query as a gym set, see [crate::gymset], defaults to every gym
+    pub gyms: Option<GymSelection>,
+
+    /// gyms never queried, removed from [ConfigFile::gyms]
+    pub exclude_gyms: Option<GymSelection>,
+
+    /// named gym sets, referred to as `group:NAME`
+    pub gym_groups: GymGroups,
+
+    /// gyms fetched first in every iteration
+    pub priority_gyms: Option<Vec<Gym>>,
+
+    /// venues missing from the known gyms, as `ID:NAME`
+    pub venue_ids: Option<Vec<Venue>>,
+
+    /// activities also queried at every gym, e.g. `["SWIMMING"]`
+    pub activities: Option<Vec<Activity>>,
+
+    /// maximum number of fetches per iteration
+    pub iteration_budget: Option<usize>,
+
+    /// time of day when ActiveSG doesn't answer, e.g. `06:00-08:00`
+    pub blackout: Option<HoursRange>,
+
+    /// maximum number of fetches of the iteration catching up after the blackout
+    pub catch_up_budget: Option<usize>,
+
+    /// seconds between iterations
+    pub interval_secs: Option<u64>,
+
+    /// `skip` or `queue` an iteration due while the previous one is still running
+    pub overlap_policy: Option<OverlapPolicy>,
+
+    /// number of days queried from today, exclusive with [ConfigFile::day_offsets]
+    pub days: Option<u64>,
+
+    /// days relative to today queried, [Config::DEFAULT_DAY_OFFSETS] by default
+    pub day_offsets: Option<Vec<i64>>,
+
+    /// milliseconds between consecutive fetches
+    pub inter_gym_delay_ms: Option<u64>,
+
+    /// fetches in flight at once
+    pub concurrency: Option<usize>,
+
+    /// requests per second sent to ActiveSG at most, e.g. `0.5`
+    pub rps: Option<RequestRate>,
+
+    /// seconds the first iteration is delayed by at most
+    pub max_splay_secs: Option<u64>,
+
+    /// seed of the delay before the first iteration, defaults to the hostname and output dir
+    pub splay_seed: Option<String>,
+
+    /// minutes before the start of a slot when booking closes
+    pub booking_cutoff_mins: Option<u64>,
+
+    /// directory the snapshots are written to
+    pub output_dir: Option<PathBuf>,
+
+    /// output format of the snapshots
+    pub format: Option<OutputFormat>,
+
+    /// compression of the written files, `none`, `gzip` or `zstd`
+    pub compress: Option<Compression>,
+
+    /// compression level, 0 to 9 for gzip, 1 to 22 for zstd
+    pub compress_level: Option<u32>,
+
+    /// names of the snapshots and diffs, see [crate::template]
+    pub filename_template: Option<FilenameTemplate>,
+
+    /// daily download cap in MiB
+    pub max_daily_download_mb: Option<u64>,
+
+    /// remove snapshots older than this many days
+    pub retention_days: Option<u32>,
+
+    /// keep at least this many snapshots per gym regardless of age
+    pub retention_keep_per_gym: Option<usize>,
+
+    /// add a stable `slot_id` to every timeslot
+    pub slot_ids: bool,
+
+    /// add the official `gym_name` to every snapshot
+    pub gym_names: bool,
+
+    /// write `stats.json` to the output directory every hour
+    pub write_stats: bool,
+
+    /// check at start and weekly that every gym id still leads to the gym
+    pub validate_venues: bool,
+
+    /// write a run report after every iteration, defaults to true
+    pub run_reports: Option<bool>,
+
+    /// write a `.diff.json` against the previous snapshot alongside each snapshot
+    pub emit_diffs: bool,
+
+    /// append availability events to `events/<date>.ndjson` in the output directory
+    pub event_log: bool,
+
+    /// bundle the files of every past day into `<date>.tar.zst` in the output directory
+    pub compact_days: bool,
+
+    /// fetch anonymously while the credentials are rejected
+    pub anonymous_fallback: bool,
+
+    /// attempts at publishing a snapshot
+    pub publish_attempts: Option<u32>,
+
+    /// retries of a fetch failing for a transient reason
+    pub retries: Option<usize>,
+
+    /// milliseconds of backoff before the first retry, doubled before every following one
+    pub retry_base_ms: Option<u64>,
+
+    /// seconds a request to ActiveSG may take
+    pub timeout_secs: Option<u64>,
+
+    /// seconds connecting to ActiveSG may take
+    pub connect_timeout_secs: Option<u64>,
+
+    /// `[lat, lng]` gyms are sorted by distance from, e.g. `[1.3521, 103.8198]`
+    pub home_location: Option<LatLng>,
+
+    /// `auto`, `pkcs1` or `timestamp`
+    pub password_encryption: Option<EncryptionKind>,
+
+    /// file the session cookies are kept in between runs, relative to the working directory
+    pub cookie_file: Option<PathBuf>,
+
+    /// command printing the one-time PIN ActiveSG may ask for after the password
+    pub otp_command: Option<String>,
+
+    /// url sent a `HEAD` before alerting that ActiveSG is degraded
+    pub reference_url: Option<String>,
+
+    /// milliseconds of p95 delivery latency above which a sink is slow
+    pub slow_sink_p95_ms: Option<u64>,
+
+    /// iterations in a row a sink must be slow, or fast again, before alerting
+    pub slow_sink_iterations: Option<usize>,
+
+    /// stop delivering to a slow sink until restart
+    pub disable_slow_sinks: bool,
+
+    /// Discord webhook the iterations with too many failures are reported to
+    pub discord_webhook: Option<String>,
+
+    /// failed fetches of an iteration tolerated before reporting it
+    pub failure_alert_threshold: Option<usize>,
+
+    /// address the API is served on, e.g. `127.0.0.1:8080`
+    #[cfg(feature = "server")]
+    pub http_listen: Option<std::net::SocketAddr>,
+
+    /// bearer token of `POST /api/refresh`
+    #[cfg(feature = "server")]
+    pub api_token: Option<String>,
+
+    /// SQLite database the snapshots are stored in instead of JSON files,
+    /// relative to the working directory
+    #[cfg(feature = "sqlite")]
+    pub sqlite: Option<PathBuf>,
+
+    /// S3 bucket the snapshots and diffs are uploaded to
+    #[cfg(feature = "s3")]
+    pub s3_bucket: Option<String>,
+
+    /// prefix of the uploaded keys, e.g. `miner/`
+    #[cfg(feature = "s3")]
+    pub s3_prefix: Option<String>,
+
+    /// only upload to S3, without writing to the output directory
+    #[cfg(feature = "s3")]
+    pub no_local: bool,
+
+    /// remove the local copy of a file once uploaded to S3
+    #[cfg(feature = "s3")]
+    pub s3_delete_local: bool,
+
+    /// overrides of [Gym::opening_hours]
+    pub opening_hours: HashMap<Gym, OpeningHours>,
+
+    /// watch lists with their notifiers, see [Profile]
+    pub profiles: Vec<Profile>,
+
+    /// snapshots delivered to each sink, see [SinkFilter]
+    pub filters: BTreeMap<String, SinkFilter>,
+}
+
+impl ConfigFile {
+    /// Reads and parses the config at `path`, YAML for `.yaml` and `.yml` files and TOML otherwise
+    ///
+    /// A relative [ConfigFile::password_file] is resolved against the directory of `path`
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::{config::ConfigFile, models::Gym};
+    /// use std::path::Path;
+    ///
+    /// let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    /// let toml = ConfigFile::load(fixtures.join("config.toml")).unwrap();
+    /// let yaml = ConfigFile::load(fixtures.join("config.yaml")).unwrap();
+    /// assert_eq!(toml, yaml);
+    ///
+    /// assert_eq!(yaml.password_file, Some(fixtures.join("activesg.password")));
+    /// assert_eq!(
+    ///     yaml.gym_set(yaml.gyms.as_ref().unwrap()).unwrap(),
+    ///     [Gym::BISHAN, Gym::CLEMENTI, Gym::TOA_PAYOH]
+    /// );
+    /// assert_eq!(yaml.opening_hours[&Gym::CLEMENTI].weekend.to_string(), "08:00-20:00");
+    /// assert_eq!(yaml.profiles[0].watches[0].min_slots, 5);
+    /// assert!(yaml.filters["file"].admits_gym(Gym::TOA_PAYOH.into()));
+    /// assert!(!yaml.filters["file"].admits_gym(Gym::CLEMENTI.into()));
+    ///
+    /// // the error tells the file along with the line and column
+    /// let dir = std::env::temp_dir().join(format!("config-doctest-{}", std::process::id()));
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// std::fs::write(dir.join("broken.yml"), "username: a\ninterval_secs: soon\n").unwrap();
+    /// let e = ConfigFile::load(dir.join("broken.yml")).unwrap_err().to_string();
+    /// assert!(e.contains("broken.yml: interval_secs: invalid type"), "{}", e);
+    /// assert!(e.contains("line 2 column 16"), "{}", e);
+    /// std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn load<P: AsRef<Path>>(path: P) -> DataMResult<Self> {
+        let path = path.as_ref();
+        let raw = std::fs::read_to_string(path)?;
+        let is_yaml = matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("yaml" | "yml")
+        );
+
+        let parsed = match is_yaml {
+            true => Self::from_yaml(&raw),
+            false => Self::from_toml(&raw),
+        };
+        let mut file = parsed
+            .map_err(|e| errors::Error::InvalidConfig(format!("{}: {}", path.display(), e)))?;
+
+        if let (Some(pwd_file), Some(dir)) = (&file.password_file, path.parent()) {
+            file.password_file = Some(dir.join(pwd_file));
+        }
+
+        for profile in &mut file.profiles {
+            for watch in &mut profile.watches {
+                watch.resolve_gyms(&file.gym_groups).map_err(|e| {
+                    errors::Error::InvalidConfig(format!(
+                        "{}: profile {}, {}",
+                        path.display(),
+                        profile.name,
+                        e
+                    ))
+                })?;
+            }
+        }
+
+        for (sink, filter) in &mut file.filters {
+            filter.resolve_gyms(&file.gym_groups).map_err(|e| {
+                errors::Error::InvalidConfig(format!(
+                    "{}: filter of sink {}, {}",
+                    path.display(),
+                    sink,
+                    e
+                ))
+            })?;
+        }
+
+        Ok(file)
+    }
+
+    /// Parses a TOML config, the error includes the line and column
+    pub fn from_toml(raw: &str) -> Result<Self, String> {
+        toml::from_str(raw).map_err(|e| e.to_string())
+    }
+
+    /// Parses a YAML config with the same schema as the TOML one,
+    /// the error includes the line and column
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::config::ConfigFile;
+    ///
+    /// let yaml = ConfigFile::from_yaml("gyms: BISHAN\ninterval_secs: 600\n").unwrap();
+    /// let toml = ConfigFile::from_toml("gyms = \"BISHAN\"\ninterval_secs = 600\n").unwrap();
+    /// assert_eq!(yaml, toml);
+    /// assert_eq!(ConfigFile::from_yaml("").unwrap(), ConfigFile::default());
+    ///
+    /// let e = ConfigFile::from_yaml("username: a\nintervl_secs: 600\n").unwrap_err();
+    /// assert!(e.starts_with("unknown field `intervl_secs`"), "{}", e);
+    /// assert!(e.contains("at line 2 column 1"), "{}", e);
+    ///
+    /// let e = ConfigFile::from_yaml("gyms: [BISHAN\n").unwrap_err();
+    /// assert!(e.contains("line 2 column 1"), "{}", e);
+    /// ```
+    pub fn from_yaml(raw: &str) -> Result<Self, String> {
+        serde_yaml::from_str(raw).map_err(|e| e.to_string())
+    }
+
+    /// Resolves a gym set of this file against [ConfigFile::gym_groups]
+    pub fn gym_set(&self, selection: &GymSelection) -> DataMResult<Vec<Gym>> {
+        Ok(selection.resolve(&self.gym_groups)?.into_iter().collect())
+    }
+
+    /// Password from [ConfigFile::password] or else the content of [ConfigFile::password_file]
+    pub fn resolve_password(&self) -> DataMResult<Option<String>> {
+        match (&self.password, &self.password_file) {
+            (Some(pwd), _) => Ok(Some(pwd.clone())),
+            (None, Some(f)) => {
+                let pwd = std::fs::read_to_string(f)?;
+                Ok(Some(pwd.trim_end_matches(['\r', '\n']).to_string()))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// Resolves the credentials, each from its flag, else [USERNAME_ENV] and [PASSWORD_ENV]
+/// as looked up by `env`, else the config `file`, else for the password only, `prompt`
+///
+/// The password file is only read when neither the flag nor the environment has a password,
+/// and `prompt` is only called once the username is known and nothing else has a password.
+/// It returns none when it can't ask, e.g. without a terminal
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     config::{self, ConfigFile},
+///     models::User,
+/// };
+///
+/// let env = |vars: &'static [(&'static str, &'static str)]| {
+///     move |key: &str| vars.iter().find(|(k, _)| *k == key).map(|(_, v)| v.to_string())
+/// };
+/// let none = env(&[]);
+/// let both = env(&[("ACTIVESG_USERNAME", "env@example.com"), ("ACTIVESG_PASSWORD", "env")]);
+/// let mut file = ConfigFile::default();
+/// file.username = Some("file@example.com".into());
+/// file.password = Some("file".into());
+///
+/// let creds = |user: User| (user.email, user.password);
+/// let typed = || -> Result<Option<String>, _> { Ok(Some("typed".into())) };
+/// let unused = || -> Result<Option<String>, _> { panic!("prompted for a password") };
+/// let flag = || (Some("flag@example.com".to_string()), Some("flag".to_string()));
+/// let user = config::resolve_user(flag(), &both, &file, unused).unwrap();
+/// assert_eq!(creds(user), ("flag@example.com".into(), "flag".into()));
+///
+/// let user = config::resolve_user((None, None), &both, &file, unused).unwrap();
+/// assert_eq!(creds(user), ("env@example.com".into(), "env".into()));
+///
+/// let user = config::resolve_user((None, None), &none, &file, unused).unwrap();
+/// assert_eq!(creds(user), ("file@example.com".into(), "file".into()));
+///
+/// // each credential falls back on its own
+/// let user = config::resolve_user((None, Some("flag".into())), &both, &file, unused).unwrap();
+/// assert_eq!(creds(user), ("env@example.com".into(), "flag".into()));
+///
+/// // the password is typed when given nowhere else
+/// let flag = || (Some("flag@example.com".to_string()), None);
+/// let user = config::resolve_user(flag(), &none, &ConfigFile::default(), typed).unwrap();
+/// assert_eq!(creds(user), ("flag@example.com".into(), "typed".into()));
+///
+/// // not without a username, whose error comes first
+/// let err = config::resolve_user((None, None), &none, &ConfigFile::default(), unused);
+/// assert!(err.err().unwrap().to_string().contains("ACTIVESG_USERNAME"));
+///
+/// // `User` isn't `Debug`, keeping the password out of logs
+/// let no_terminal = || -> Result<Option<String>, _> { Ok(None) };
+/// let err = config::resolve_user(flag(), &none, &ConfigFile::default(), no_terminal);
+/// let err = err.err().unwrap().to_string();
+/// assert!(err.contains("password"), "{}", err);
+/// assert!(err.contains("ACTIVESG_PASSWORD"), "{}", err);
+/// assert!(!err.contains("ACTIVESG_USERNAME"), "{}", err);
+/// ```
+pub fn resolve_user<E, P>(
+    flags: (Option<String>, Option<String>),
+    env: E,
+    file: &ConfigFile,
+    prompt: P,
+) -> DataMResult<User>
+where
+    E: Fn(&str) -> Option<String>,
+    P: FnOnce() -> DataMResult<Option<String>>,
+{
+    let (username, password) = flags;
+    let username = username
+        .or_else(|| env(USERNAME_ENV))
+        .or_else(|| file.username.clone());
+    let password = match password.or_else(|| env(PASSWORD_ENV)) {
+        Some(pwd) => Some(pwd),
+        None => file.resolve_password()?,
+    };
+    let password = match (&username, password) {
+        (Some(_), None) => prompt()?.filter(|p| !p.is_empty()),
+        (_, password) => password,
+    };
+
+    match (username, password) {
+        (Some(u), Some(p)) => Ok(User::new(u, p)),
+        (username, _) => {
+            let missing = match username {
+                Some(_) => format!(
+                    "password must be typed in a terminal or provided by -p, {}",
+                    PASSWORD_ENV
+                ),
+                None => format!("username must be provided by -u, {}", USERNAME_ENV),
+            };
+            Err(errors::Error::InvalidConfig(format!(
+                "{} or the config file",
+                missing
+            )))
+        }
+    }
+}
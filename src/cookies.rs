@@ -0,0 +1,163 @@
+//! Cookies of the logged in session kept on disk between runs,
+//! see [crate::client::DataMiner::with_cookie_file]
+//!
+//! The cookies are saved whole, with their domain, path, expiry and flags, so that they are
+//! sent back only where ActiveSG set them, a `Secure` one never over plain http, and the
+//! expired ones are dropped when loading. The file is readable only by its owner, it is as
+//! good as the password while the session lasts
+//!
+//! ## Example
+//! ```
+//! use activesg_gym_datamine::cookies::{self, SessionCookies};
+//! use reqwest::{cookie::CookieStore, header::HeaderValue, Url};
+//!
+//! let dir = std::env::temp_dir().join(format!("cookies-doctest-{}", std::process::id()));
+//! let path = dir.join("session.json");
+//! let url = Url::parse(cookies::ACTIVESG_URL).unwrap();
+//!
+//! let store = SessionCookies::default();
+//! let set_cookies = [
+//!     HeaderValue::from_static("PHPSESSID=abc123; Path=/; Secure; HttpOnly"),
+//!     HeaderValue::from_static("remember=1; Path=/booking; Max-Age=3600"),
+//!     HeaderValue::from_static("gone=1; Path=/; Max-Age=0"),
+//! ];
+//! store.set_cookies(&mut set_cookies.iter(), &url);
+//! cookies::save(&store, &path, chrono::Utc::now()).unwrap();
+//!
+//! let restored = SessionCookies::default();
+//! assert_eq!(cookies::load(&restored, &path).unwrap(), 2);
+//! let header = |url: &str| {
+//!     let cookies = restored.cookies(&Url::parse(url).unwrap())?;
+//!     Some(cookies.to_str().unwrap().to_string())
+//! };
+//! assert_eq!(header("https://members.myactivesg.com/profile").unwrap(), "PHPSESSID=abc123");
+//! let booking = header("https://members.myactivesg.com/booking/slots").unwrap();
+//! assert!(booking == "PHPSESSID=abc123; remember=1" || booking == "remember=1; PHPSESSID=abc123");
+//! // secure, and for ActiveSG only
+//! assert_eq!(header("http://members.myactivesg.com/profile"), None);
+//! assert_eq!(header("https://example.com/"), None);
+//!
+//! // nothing saved yet
+//! let missing = dir.join("missing.json");
+//! assert_eq!(cookies::load(&SessionCookies::default(), &missing).unwrap(), 0);
+//! std::fs::write(&path, "{ not json").unwrap();
+//! assert!(cookies::load(&SessionCookies::default(), &path).is_err());
+//! std::fs::remove_dir_all(&dir).unwrap();
+//! ```
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+use chrono::{DateTime, Utc};
+use cookie_store::Cookie;
+use reqwest::{cookie::CookieStore, header::HeaderValue, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::{errors, DataMResult};
+
+/// Site the cookies are saved for and restored to
+pub const ACTIVESG_URL: &str = "https://members.myactivesg.com/";
+
+/// Cookies of a client, kept with everything ActiveSG set them with so that they can be saved
+#[derive(Debug, Default)]
+pub struct SessionCookies(Mutex<cookie_store::CookieStore>);
+
+impl SessionCookies {
+    fn lock(&self) -> MutexGuard<'_, cookie_store::CookieStore> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl CookieStore for SessionCookies {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let mut store = self.lock();
+        for header in cookie_headers {
+            if let Ok(cookie) = header.to_str() {
+                // rejected cookies, e.g. for another domain, aren't kept by browsers either
+                let _ = store.parse(cookie, url);
+            }
+        }
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let cookies = self
+            .lock()
+            .get_request_values(url)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        match cookies.is_empty() {
+            true => None,
+            false => HeaderValue::from_str(&cookies).ok(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedCookies {
+    saved_at: DateTime<Utc>,
+
+    /// session cookies included, the login is one of them
+    cookies: Vec<Cookie<'static>>,
+}
+
+/// Writes the unexpired cookies of `store` to `path`, replacing the file
+pub fn save(store: &SessionCookies, path: &Path, now: DateTime<Utc>) -> DataMResult<()> {
+    let saved = SavedCookies {
+        saved_at: now,
+        cookies: store.lock().iter_unexpired().cloned().collect(),
+    };
+    let data = serde_json::to_string_pretty(&saved).map_err(errors::Error::Serialize)?;
+
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    write_private(Path::new(&tmp), data.as_bytes())?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Writes `data` to a file readable only by its owner
+fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(data)
+}
+
+/// Adds the unexpired cookies saved at `path` to `store`, returning how many there were,
+/// 0 when nothing was saved yet
+pub fn load(store: &SessionCookies, path: &Path) -> DataMResult<usize> {
+    let raw = match fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let saved = serde_json::from_str::<SavedCookies>(&raw).map_err(|e| {
+        errors::Error::InvalidConfig(format!("cookie file {}: {}", path.display(), e))
+    })?;
+
+    let mut store = store.lock();
+    let mut loaded = 0;
+    for cookie in saved.cookies {
+        // as if set again by the domain it was set for
+        let url = match Url::parse(&format!("https://{}/", String::from(&cookie.domain))) {
+            Ok(url) => url,
+            Err(_) => continue,
+        };
+        if store.insert(cookie, &url).is_ok() {
+            loaded += 1;
+        }
+    }
+    Ok(loaded)
+}
@@ -0,0 +1,1121 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use futures_util::stream::{self, StreamExt};
+use tracing::{debug, error, info, warn, Instrument, Span};
+
+use crate::{
+    breaker::{CredentialBreaker, FetchMode},
+    cache::SnapshotCache,
+    client::DataMiner,
+    clock::{Clock, SharedClock, SystemClock, SGT},
+    compaction,
+    config::Config,
+    diff::SnapshotDiff,
+    discovery::ActivityIdCache,
+    errors, events,
+    fetch::{self, FetchTask, VenueCheck},
+    heartbeat::Heartbeat,
+    horizon::{self, HorizonChange, HorizonTracker},
+    iteration::IterationCounter,
+    metrics::{human_bytes, DownloadBudget, IterationStats, Metrics},
+    models::{Activity, Gym, GymSlotData},
+    notify::{AlertDispatcher, AnyNotifier, Notifier},
+    outage::{self, PhaseCounts, Verdict},
+    pacer::{Pacer, WaitReason},
+    ratelimit::RateLimiter,
+    report::{RunReport, TaskOutcome, TaskReport},
+    retention,
+    retry::{self, Retried},
+    schedule::{self, IterationKind},
+    sink::{self, Delivery, PublishKey, Sink},
+    sinkhealth::{SinkMonitor, SinkState},
+    skew::{self, SkewTracker},
+    stats::{self, UsageStats},
+    trace::{self, TraceContext},
+    DataMResult,
+};
+
+/// Long running miner, state in here is shared across iterations
+pub struct Daemon<S> {
+    config: Config,
+    sink: S,
+    skew_tracker: SkewTracker,
+    cache: SnapshotCache,
+    metrics: Arc<Metrics>,
+    download_budget: Option<Arc<DownloadBudget>>,
+    alerts: AlertDispatcher,
+    horizons: HorizonTracker,
+    pacer: Pacer,
+    breaker: CredentialBreaker,
+    iterations: IterationCounter,
+
+    /// logged in once and reused by every fetch until the session expires
+    miner: DataMiner,
+
+    /// a client of its own, without the cookies of the failed logins
+    anonymous_miner: DataMiner,
+
+    /// shared by both miners, see [Config::rps]
+    limiter: Option<Arc<RateLimiter>>,
+
+    /// see [Config::failure_notifier]
+    failure_notifier: Option<AnyNotifier>,
+
+    /// gyms whose booking page refers to another venue, not fetched until validated again
+    quarantine: Mutex<HashSet<Gym>>,
+    last_venue_validation: Mutex<Option<DateTime<Utc>>>,
+
+    /// day in Singapore the past days were last compacted on, see [Config::compact_days]
+    last_compaction: Mutex<Option<NaiveDate>>,
+
+    /// a permit is held for each fetch and the pacing after it, shared by iterations
+    /// and refreshes, see [Config::concurrency]
+    fetching: tokio::sync::Semaphore,
+
+    /// the last iteration judged ActiveSG degraded, alerted once until it recovers
+    degraded: AtomicBool,
+
+    /// every decision depending on the time of day reads it from here
+    clock: SharedClock,
+
+    /// delivery latencies per sink, see [Config::slow_sink]
+    sinks: SinkMonitor,
+
+    /// last finished iteration, see [Daemon::subscribe]
+    updates: tokio::sync::watch::Sender<Option<IterationUpdate>>,
+}
+
+/// A finished iteration, as seen by the subscribers of [Daemon::subscribe]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IterationUpdate {
+    pub finished_at: DateTime<Utc>,
+
+    /// queried dates of the iteration
+    pub dates: Vec<NaiveDate>,
+    pub stats: IterationStats,
+}
+
+impl<S> Daemon<S>
+where
+    S: Sink + Send + Sync + 'static,
+{
+    pub fn new(config: Config, sink: S) -> Self {
+        let download_budget = config
+            .max_daily_download_bytes
+            .map(|limit| Arc::new(DownloadBudget::new(limit)));
+        let slow_sink = config.slow_sink;
+        let pacer = Pacer::from_config(&config);
+        let fetching = tokio::sync::Semaphore::new(config.concurrency);
+        let failure_notifier = config.failure_notifier.as_ref().map(|n| n.build());
+        let iterations = match config.dry_run {
+            true => IterationCounter::new(&config.output_dir).without_persisting(),
+            false => IterationCounter::new(&config.output_dir),
+        };
+        // shared so that an activity id is discovered once, see [DataMiner::activity_id]
+        let activity_ids = Arc::<ActivityIdCache>::default();
+        // one cap over both miners
+        let limiter = config.rps.map(|rps| Arc::new(RateLimiter::new(rps)));
+        let miner = DataMiner::from_config(&config)
+            .with_activity_ids(activity_ids.clone())
+            .with_rate_limiter(limiter.clone())
+            .with_download_budget(download_budget.clone());
+        let anonymous_miner = DataMiner::from_config(&config)
+            .with_activity_ids(activity_ids)
+            .with_anonymous(true)
+            .with_rate_limiter(limiter.clone())
+            .with_download_budget(download_budget.clone());
+
+        let metrics = Arc::new(Metrics::default());
+        let alerts = AlertDispatcher::new(&config.profiles, config.home_location, metrics.clone());
+
+        Self {
+            config,
+            sink,
+            skew_tracker: SkewTracker::default(),
+            cache: SnapshotCache::default(),
+            metrics,
+            download_budget,
+            alerts,
+            horizons: HorizonTracker::default(),
+            pacer,
+            breaker: CredentialBreaker::default(),
+            iterations,
+            miner,
+            anonymous_miner,
+            limiter,
+            failure_notifier,
+            quarantine: Mutex::default(),
+            last_venue_validation: Mutex::default(),
+            last_compaction: Mutex::default(),
+            fetching,
+            degraded: AtomicBool::default(),
+            clock: Arc::new(SystemClock),
+            updates: tokio::sync::watch::channel(None).0,
+            sinks: SinkMonitor::new(slow_sink),
+        }
+    }
+
+    /// Schedules and timestamps with `clock` instead of the system time
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.miner = self.miner.with_clock(clock.clone());
+        self.anonymous_miner = self.anonymous_miner.with_clock(clock.clone());
+        self.metrics.reset(clock.now());
+        self.clock = clock;
+        self
+    }
+
+    /// Sends the requests to `url` instead of ActiveSG, see [DataMiner::with_base_url]
+    pub fn with_base_url(mut self, url: &str) -> Self {
+        self.miner = self.miner.with_base_url(url);
+        self.anonymous_miner = self.anonymous_miner.with_base_url(url);
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub fn cache(&self) -> &SnapshotCache {
+        &self.cache
+    }
+
+    pub fn clock(&self) -> &SharedClock {
+        &self.clock
+    }
+
+    /// Receives every finished iteration, none until the first one
+    pub fn subscribe(&self) -> tokio::sync::watch::Receiver<Option<IterationUpdate>> {
+        self.updates.subscribe()
+    }
+
+    /// Gyms currently not fetched, see [Config::validate_venues]
+    pub fn quarantined(&self) -> Vec<Gym> {
+        let mut buf = self
+            .quarantine
+            .lock()
+            .unwrap()
+            .iter()
+            .copied()
+            .collect::<Vec<_>>();
+        buf.sort();
+        buf
+    }
+
+    /// Runs an iteration every [Config::interval], forever
+    pub async fn run(self: Arc<Self>) {
+        #[cfg(feature = "server")]
+        if let Some(addr) = self.config.http_listen {
+            self.clone().spawn_server(addr);
+        }
+
+        if self.config.write_stats && !self.config.dry_run {
+            tokio::spawn(self.clone().write_stats());
+
+            #[cfg(unix)]
+            tokio::spawn(self.clone().reset_stats_on_signal());
+        }
+
+        // every tick is shifted by the splay since the timer starts after it
+        self.sleep_splay().await;
+
+        let mut scheduler = schedule::Scheduler::new(
+            self.config.interval,
+            self.config.blackout,
+            self.clock.clone(),
+        );
+
+        let mut cycles = schedule::Cycles::new(self.config.overlap_policy);
+
+        loop {
+            let kind = scheduler.next().await;
+
+            let daemon = self.clone();
+            // everything the iteration sends out carries its trace, see [crate::trace]
+            let cycle = trace::scope(TraceContext::new_root(), async move {
+                // a number that can't be persisted could be handed out again after a restart
+                if let Err(e) = daemon.iterate(kind).await {
+                    error!("numbering the iteration failed, skipping it: {}", e);
+                }
+            });
+            cycles.start(cycle).await;
+        }
+    }
+
+    /// Runs a single iteration after the splay, for scheduling by cron or a systemd timer
+    ///
+    /// The API isn't served nor are the usage statistics written, the statistics of the iteration
+    /// are returned instead
+    pub async fn run_once(self: Arc<Self>) -> DataMResult<IterationStats> {
+        self.sleep_splay().await;
+        trace::scope(
+            TraceContext::new_root(),
+            self.iterate(IterationKind::Regular),
+        )
+        .await
+    }
+
+    async fn sleep_splay(&self) {
+        let splay = self.config.splay();
+        if !self.config.max_splay.is_zero() {
+            info!(
+                "Splay of {}ms out of {}ms before the first iteration",
+                splay.as_millis(),
+                self.config.max_splay.as_millis()
+            );
+            tokio::time::sleep(splay).await;
+        }
+    }
+
+    /// Numbers and runs an iteration over the dates targeted now, then checks and reports on it
+    ///
+    /// Within a `cycle` span of the iteration number and trace id, see [trace::iteration_span]
+    async fn iterate(&self, kind: IterationKind) -> DataMResult<IterationStats> {
+        let span = trace::iteration_span();
+        let res = self.iterate_in_span(kind).instrument(span.clone()).await;
+        trace::record_outcome(&span, &res);
+        res
+    }
+
+    async fn iterate_in_span(&self, kind: IterationKind) -> DataMResult<IterationStats> {
+        let now = match self.config.trust_server_time {
+            true => skew::corrected_now(self.clock.now(), self.skew_tracker.last()),
+            false => self.clock.now(),
+        };
+        let dt = self.config.target_dates_at(now);
+
+        let iteration = self.iterations.next()?;
+        Span::current().record("iteration", iteration);
+        self.metrics
+            .last_iteration
+            .store(iteration, Ordering::Relaxed);
+
+        self.validate_venues_if_due().await;
+
+        let started_at = self.clock.now();
+        let stats = self.run_iteration(iteration, &dt, kind).await;
+        info!("Iteration {} done: {}", iteration, stats.summary());
+        self.check_horizons(iteration).await;
+        self.check_outage(&stats.phases).await;
+        self.check_failures(&stats).await;
+        self.check_sinks().await;
+
+        let heartbeat = Heartbeat {
+            at: self.clock.now(),
+            fetched: stats.fetched,
+            failed: stats.failed,
+            iteration,
+        };
+        self.write_heartbeat(heartbeat).await;
+        self.updates.send_replace(Some(IterationUpdate {
+            finished_at: self.clock.now(),
+            dates: dt,
+            stats: stats.clone(),
+        }));
+
+        if self.config.run_reports {
+            self.write_report(RunReport::new(started_at, self.clock.now(), &stats))
+                .await;
+        }
+        self.prune().await;
+        self.compact_if_rolled_over().await;
+        Ok(stats)
+    }
+
+    /// Serves the API on `addr` and fetches the refreshes it queues
+    #[cfg(feature = "server")]
+    fn spawn_server(self: Arc<Self>, addr: std::net::SocketAddr) {
+        use crate::server::{self, Api, RefreshQueue, MAX_PENDING_REFRESHES};
+
+        let (queue, queued) = RefreshQueue::new(MAX_PENDING_REFRESHES);
+        let api = Arc::new(Api::new(self.config.api_token.clone(), queue));
+        tokio::spawn(self.clone().run_refreshes(api.clone(), queued));
+        tokio::spawn(async move {
+            if let Err(e) = server::serve(addr, api, self).await {
+                error!("serving the API failed: {}", e);
+            }
+        });
+    }
+
+    /// Fetches the refreshes queued through the API one by one, forever
+    #[cfg(feature = "server")]
+    async fn run_refreshes(
+        self: Arc<Self>,
+        api: Arc<crate::server::Api>,
+        mut queued: tokio::sync::mpsc::Receiver<crate::server::QueuedRefresh>,
+    ) {
+        use crate::server::RefreshStatus;
+
+        let client = crate::notify::http_client();
+        while let Some(refresh) = queued.recv().await {
+            let context = TraceContext::new_root();
+            trace::scope(context, async {
+                api.queue().set_status(refresh.id, RefreshStatus::Running);
+                let status = match self.refresh(refresh.task).await {
+                    Ok(TaskOutcome::Ok { slots, .. }) => RefreshStatus::Done { slots },
+                    Ok(_) => RefreshStatus::Done { slots: 0 },
+                    Err(e) => RefreshStatus::Failed {
+                        class: e.class().to_string(),
+                        message: e.to_string(),
+                    },
+                };
+                info!(
+                    "Refresh {} of {:?} {}: {:?}, trace {}",
+                    refresh.id, refresh.task.gym, refresh.task.date, status, context
+                );
+                api.queue().set_status(refresh.id, status.clone());
+
+                if let Some(url) = &refresh.callback_url {
+                    let body = serde_json::json!({ "request_id": refresh.id, "result": status });
+                    let res = trace::propagate(client.post(url)).json(&body).send().await;
+                    if let Err(e) = res.and_then(|r| r.error_for_status()) {
+                        warn!("refresh {}, callback to {} failed: {}", refresh.id, url, e);
+                    }
+                }
+            })
+            .await;
+        }
+    }
+
+    /// Fetches and publishes `task` right away, numbered like an iteration
+    ///
+    /// Waits for the fetch in progress and paces like any other fetch,
+    /// nothing is fetched once the daily download budget is exhausted
+    ///
+    /// Within a `refresh` span of the task, see [trace::refresh_span]
+    pub async fn refresh(&self, task: FetchTask) -> DataMResult<TaskOutcome> {
+        let span = trace::refresh_span(task);
+        let res = self.refresh_in_span(task).instrument(span.clone()).await;
+        trace::record_outcome(&span, &res);
+        res
+    }
+
+    async fn refresh_in_span(&self, task: FetchTask) -> DataMResult<TaskOutcome> {
+        if let Some(budget) = &self.download_budget {
+            if budget.is_exhausted(self.clock.now()) {
+                return Err(errors::Error::BudgetExhausted);
+            }
+        }
+        let iteration = self.iterations.next()?;
+
+        let _fetching = self.fetching.acquire().await.unwrap();
+        let mut stats = IterationStats {
+            iteration,
+            ..Default::default()
+        };
+        let retried = self.fetch(task).await;
+        stats.waited += retried.waited;
+        let res = match retried.res {
+            Ok(data) => self.process(task, iteration, data, &mut stats).await,
+            Err(e) => {
+                self.cache.mark_failed((task.activity, task.gym, task.date));
+                Err(e)
+            }
+        };
+        match &res {
+            Ok(_) => {
+                self.metrics.fetches_ok.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => self.metrics.record_failure(e),
+        }
+
+        self.flush_sink().await;
+
+        let waited = self.pacer.between_fetches(task).await;
+        self.metrics.add_waited(WaitReason::InterGymDelay, waited);
+        self.take_limiter_waits();
+        res
+    }
+
+    /// Time the requests waited for the rate limiter since the last call, counted in the metrics
+    fn take_limiter_waits(&self) -> std::time::Duration {
+        let waited = match &self.limiter {
+            Some(limiter) => limiter.take_waited(),
+            None => return std::time::Duration::ZERO,
+        };
+        self.metrics.add_waited(WaitReason::RateLimiter, waited);
+        waited
+    }
+
+    /// Writes the usage statistics every [stats::STATS_INTERVAL], forever
+    async fn write_stats(self: Arc<Self>) {
+        let mut timer = tokio::time::interval(stats::STATS_INTERVAL);
+
+        loop {
+            timer.tick().await;
+
+            let mut usage = UsageStats::from_metrics(&self.metrics, self.clock.now());
+            usage.quarantined = self.quarantined();
+            let output_dir = self.config.output_dir.clone();
+            match tokio::task::spawn_blocking(move || stats::write(&output_dir, &usage)).await {
+                Ok(Err(e)) => error!("writing stats failed: {}", e),
+                Err(e) => error!("stats task panicked: {}", e),
+                Ok(Ok(_)) => (),
+            }
+        }
+    }
+
+    /// Resets the counters every time SIGUSR1 is received
+    #[cfg(unix)]
+    async fn reset_stats_on_signal(self: Arc<Self>) {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut usr1 = match signal(SignalKind::user_defined1()) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("failed to install SIGUSR1 handler: {}", e);
+                return;
+            }
+        };
+
+        while usr1.recv().await.is_some() {
+            info!("SIGUSR1 received, resetting stats");
+            self.metrics.reset(self.clock.now());
+        }
+    }
+
+    /// Checks that the booking page of every gym still refers to the gym,
+    /// at most once every [Config::VENUE_VALIDATION_INTERVAL]
+    ///
+    /// Gyms leading to another venue are quarantined so that no mislabeled snapshot is written,
+    /// and released once a later validation matches
+    async fn validate_venues_if_due(&self) {
+        if !self.config.validate_venues {
+            return;
+        }
+
+        let now = self.clock.now();
+        {
+            let mut last = self.last_venue_validation.lock().unwrap();
+            let since_last = last.map(|l| (now - l).to_std().unwrap_or_default());
+            if matches!(since_last, Some(d) if d < Config::VENUE_VALIDATION_INTERVAL) {
+                return;
+            }
+            *last = Some(now);
+        }
+
+        let today = now.with_timezone(&*SGT).date().naive_local();
+        for gym in &self.config.gyms {
+            match fetch::check_venue(&self.miner, *gym, today).await {
+                Ok(VenueCheck::Match(name)) => {
+                    debug!("{:?} is {:?}", gym, name);
+                    if self.quarantine.lock().unwrap().remove(gym) {
+                        info!("{:?} matches its venue again, releasing it", gym);
+                    }
+                }
+                Ok(VenueCheck::Mismatch(name)) => {
+                    error!(
+                        "ALERT: {:?} booking page is titled {:?}, expected {:?}, quarantining it",
+                        gym,
+                        name,
+                        gym.display()
+                    );
+                    self.quarantine.lock().unwrap().insert(*gym);
+                }
+                Ok(VenueCheck::Unknown) => warn!("{:?} booking page has no venue title", gym),
+                Err(e) => warn!("validating the venue of {:?} failed: {}", gym, e),
+            }
+
+            let task = FetchTask::new(Activity::GYM, *gym, today);
+            let waited = self.pacer.between_fetches(task).await;
+            self.metrics.add_waited(WaitReason::InterGymDelay, waited);
+        }
+    }
+
+    /// Publishes the changes since the previous snapshot, returning the bytes written
+    async fn publish_diff(&self, diff: &SnapshotDiff) -> u64 {
+        match self.sink.publish_diff(diff).await {
+            Ok(written) => {
+                self.metrics.add_bytes_written(self.sink.name(), written);
+                written
+            }
+            Err(e) => {
+                error!("{}, publishing diff failed: {}", self.sink.name(), e);
+                0
+            }
+        }
+    }
+
+    /// Writes out what the sinks buffered, returning the bytes written
+    async fn flush_sink(&self) -> u64 {
+        match self.sink.flush().await {
+            Ok(written) => {
+                self.metrics.add_bytes_written(self.sink.name(), written);
+                written
+            }
+            Err(e) => {
+                error!("{}, flushing failed: {}", self.sink.name(), e);
+                0
+            }
+        }
+    }
+
+    /// Appends the availability events of `diff` to the event log, returning the bytes written
+    ///
+    /// Captures more than two intervals apart are marked as across a gap
+    async fn append_events(&self, diff: &SnapshotDiff) -> u64 {
+        let found = events::detect(diff, self.config.interval * 2);
+        if found.is_empty() || self.config.dry_run {
+            return 0;
+        }
+
+        let output_dir = self.config.output_dir.clone();
+        match tokio::task::spawn_blocking(move || events::append(&output_dir, &found)).await {
+            Ok(Ok(written)) => {
+                self.metrics.add_bytes_written(events::EVENTS_DIR, written);
+                written
+            }
+            Ok(Err(e)) => {
+                error!("appending events failed: {}", e);
+                0
+            }
+            Err(e) => {
+                error!("events task panicked: {}", e);
+                0
+            }
+        }
+    }
+
+    /// Evaluates every profile against `data` and starts delivering the new alerts,
+    /// awaited at the end of the iteration, see [AlertDispatcher]
+    ///
+    /// Only called with freshly fetched snapshots, so an outage never looks like the
+    /// watched slots disappearing
+    fn dispatch_alerts(&self, data: &GymSlotData) {
+        self.alerts.dispatch(data, self.clock.now());
+    }
+
+    /// Records the booking horizon of every gym captured by `iteration`,
+    /// alerting every profile and logging an event when it lastingly changes
+    async fn check_horizons(&self, iteration: u64) {
+        let mut changes = vec![];
+        for gym in &self.config.gyms {
+            let snapshots = self.cache.captured_by(iteration, *gym);
+            let horizon = horizon::horizon_days(snapshots.iter().map(|(d, s)| (*d, s)));
+            let captured_at = snapshots.iter().map(|(_, s)| s.datetime()).max();
+            if let (Some(horizon), Some(at)) = (horizon, captured_at) {
+                changes.extend(self.horizons.record(*gym, horizon, at));
+            }
+        }
+
+        for change in &changes {
+            error!("ALERT: {}", change);
+            self.notify_operational(&change.to_string()).await;
+        }
+
+        if self.config.event_log && !changes.is_empty() {
+            self.append_horizon_changes(changes).await;
+        }
+    }
+
+    /// Judges the failures of an iteration, alerting every profile when ActiveSG looks degraded
+    ///
+    /// The [Config::reference_url] is only probed when the response failures dominate,
+    /// so that a flaky local network isn't blamed on ActiveSG
+    async fn check_outage(&self, phases: &PhaseCounts) {
+        let probe = match (&self.config.reference_url, phases.response_dominates()) {
+            (Some(url), true) => match self.miner.probe(url.clone()).await {
+                Ok(_) => Some(true),
+                Err(e) => {
+                    debug!("reference probe of {} failed: {}", url, e);
+                    Some(false)
+                }
+            },
+            _ => None,
+        };
+
+        match outage::assess(phases, probe) {
+            Verdict::Degraded => {
+                if !self.degraded.swap(true, Ordering::Relaxed) {
+                    let message = format!(
+                        "ActiveSG degraded, {} of {} fetches failed after connecting",
+                        phases.response, phases.attempted
+                    );
+                    error!("ALERT: {}", message);
+                    self.notify_operational(&message).await;
+                }
+            }
+            verdict => {
+                if verdict == Verdict::LocalNetwork {
+                    warn!(
+                        "{} of {} fetches failed to connect, the local network is likely at fault",
+                        phases.connect, phases.attempted
+                    );
+                }
+                if self.degraded.swap(false, Ordering::Relaxed) {
+                    info!("ActiveSG recovered");
+                }
+            }
+        }
+    }
+
+    /// Reports the failures of an iteration when due, see [IterationStats::failure_alert_due]
+    async fn check_failures(&self, stats: &IterationStats) {
+        let notifier = match &self.failure_notifier {
+            Some(notifier) if stats.failure_alert_due(self.config.failure_alert_threshold) => {
+                notifier
+            }
+            _ => return,
+        };
+        if let Err(e) = notifier.notify_operational(&stats.failure_report()).await {
+            warn!(
+                "reporting the failures to {} failed: {}",
+                notifier.name(),
+                e
+            );
+        }
+    }
+
+    /// Judges the delivery latencies of an iteration, alerting every profile when a sink
+    /// turns slow, is disabled or recovers
+    async fn check_sinks(&self) {
+        for event in self.sinks.end_iteration() {
+            match event.state {
+                SinkState::Healthy => info!("{}", event),
+                SinkState::Slow => error!("ALERT: {}", event),
+                SinkState::Disabled => error!(
+                    "ALERT: {}, nothing more is delivered to it, restart once it is fixed",
+                    event
+                ),
+            }
+            self.notify_operational(&event.to_string()).await;
+        }
+        self.metrics.set_sink_statuses(self.sinks.statuses());
+        self.metrics
+            .delivery_queue_peak
+            .store(self.sinks.last_peak_in_flight() as u64, Ordering::Relaxed);
+    }
+
+    /// Sends `message` through the notifiers of every profile
+    async fn notify_operational(&self, message: &str) {
+        self.alerts.notify_operational(message).await;
+    }
+
+    async fn append_horizon_changes(&self, changes: Vec<HorizonChange>) {
+        if self.config.dry_run {
+            return;
+        }
+        let output_dir = self.config.output_dir.clone();
+        match tokio::task::spawn_blocking(move || events::append(&output_dir, &changes)).await {
+            Ok(Ok(written)) => self.metrics.add_bytes_written(events::EVENTS_DIR, written),
+            Ok(Err(e)) => error!("appending horizon changes failed: {}", e),
+            Err(e) => error!("events task panicked: {}", e),
+        }
+    }
+
+    async fn write_heartbeat(&self, heartbeat: Heartbeat) {
+        if self.config.dry_run {
+            return;
+        }
+        let output_dir = self.config.output_dir.clone();
+        match tokio::task::spawn_blocking(move || heartbeat.write(&output_dir)).await {
+            Ok(Ok(_)) => (),
+            Ok(Err(e)) => error!("writing heartbeat failed: {}", e),
+            Err(e) => error!("heartbeat task panicked: {}", e),
+        }
+    }
+
+    async fn write_report(&self, report: RunReport) {
+        if self.config.dry_run {
+            return;
+        }
+        let output_dir = self.config.output_dir.clone();
+        match tokio::task::spawn_blocking(move || report.write(&output_dir)).await {
+            Ok(Ok(path)) => debug!("{}, run report written", path.display()),
+            Ok(Err(e)) => error!("writing run report failed: {}", e),
+            Err(e) => error!("run report task panicked: {}", e),
+        }
+    }
+
+    /// Applies the retention policy to the output directory
+    async fn prune(&self) {
+        let policy = self.config.retention;
+        if !policy.is_enabled() || self.config.dry_run {
+            return;
+        }
+
+        let output_dir = self.config.output_dir.clone();
+        let now = self.clock.now().with_timezone(&*SGT).naive_local();
+        let res =
+            tokio::task::spawn_blocking(move || retention::apply(&output_dir, &policy, now)).await;
+
+        match res {
+            Ok(Err(e)) => error!("retention failed: {}", e),
+            Err(e) => error!("retention task panicked: {}", e),
+            Ok(Ok(_)) => (),
+        }
+    }
+
+    /// Bundles the past days once the date in Singapore rolls over, and after a restart
+    async fn compact_if_rolled_over(&self) {
+        if !self.config.compact_days || self.config.dry_run {
+            return;
+        }
+
+        let today = self.clock.now().with_timezone(&*SGT).naive_local().date();
+        if *self.last_compaction.lock().unwrap() == Some(today) {
+            return;
+        }
+
+        let output_dir = self.config.output_dir.clone();
+        let res =
+            tokio::task::spawn_blocking(move || compaction::compact(&output_dir, today)).await;
+        match res {
+            Ok(Err(e)) => error!(
+                "compaction failed, retrying after the next iteration: {}",
+                e
+            ),
+            Err(e) => error!("compaction task panicked: {}", e),
+            Ok(Ok(_)) => *self.last_compaction.lock().unwrap() = Some(today),
+        }
+    }
+
+    /// Fetches `task`, retrying transient failures as configured in [Config::retry]
+    async fn fetch(&self, task: FetchTask) -> Retried<GymSlotData> {
+        let retried = retry::retry(&self.config.retry, &self.pacer, task, || {
+            self.fetch_once(task)
+        })
+        .await;
+        self.metrics
+            .add_waited(WaitReason::RetryBackoff, retried.waited - retried.throttled);
+        self.metrics
+            .add_waited(WaitReason::TooManyRequests, retried.throttled);
+        retried
+    }
+
+    /// Fetches and parses `task` from ActiveSG, or from the dev artifacts when configured
+    async fn fetch_once(&self, task: FetchTask) -> DataMResult<GymSlotData> {
+        #[cfg(feature = "dev")]
+        if let Some(artifacts) = &self.config.dev_artifacts {
+            return fetch::fetch_slots(&artifacts.fetcher(&self.miner), &self.config, task).await;
+        }
+
+        if !self.config.anonymous_fallback {
+            return fetch::fetch_slots(&self.miner, &self.config, task).await;
+        }
+
+        let anonymous = || fetch::fetch_slots(&self.anonymous_miner, &self.config, task);
+        if self.breaker.mode(self.clock.now()) == FetchMode::Anonymous {
+            return anonymous().await;
+        }
+
+        let res = fetch::fetch_slots(&self.miner, &self.config, task).await;
+        match &res {
+            Ok(_) => self.breaker.record_success(),
+            Err(e)
+                if matches!(
+                    e.root(),
+                    errors::Error::InvalidCredentialsSessionExpired
+                        | errors::Error::OtpRequired
+                        | errors::Error::OtpRejected
+                ) =>
+            {
+                self.breaker.record_failure(self.clock.now());
+                // the fetch that opened the breaker isn't lost
+                if self.breaker.mode(self.clock.now()) == FetchMode::Anonymous {
+                    return anonymous().await;
+                }
+            }
+            Err(_) => (),
+        }
+        res
+    }
+
+    /// Publishes a freshly fetched snapshot of `task`, diffing it against the previous one,
+    /// raising its alerts and caching it
+    async fn process(
+        &self,
+        task: FetchTask,
+        iteration: u64,
+        mut data: GymSlotData,
+        stats: &mut IterationStats,
+    ) -> DataMResult<TaskOutcome> {
+        let config = &self.config;
+        data.mut_metadata().iteration = Some(iteration);
+        let slots = data.data().len();
+        let downloaded = data.metadata().bytes_downloaded.unwrap_or_default();
+        stats.bytes_downloaded += downloaded;
+        // the miners count the budget themselves, failed fetches included
+        self.metrics
+            .bytes_downloaded
+            .fetch_add(downloaded, Ordering::Relaxed);
+        let parse_warnings = data.metadata().parse_warnings.unwrap_or_default();
+        self.metrics
+            .parse_warnings
+            .fetch_add(parse_warnings as u64, Ordering::Relaxed);
+
+        let key = PublishKey::new(task.activity, task.gym, task.date, iteration);
+        let mut delivery = Delivery::without(self.sinks.disabled());
+        let in_flight = self.sinks.start_delivery();
+        let res = sink::deliver(
+            &self.sink,
+            &key,
+            &data,
+            config.publish_attempts,
+            &mut delivery,
+        )
+        .await;
+        drop(in_flight);
+        // the sinks that succeeded count even when another one failed
+        for (sink, written) in delivery.written() {
+            *stats.bytes_written.entry(sink.clone()).or_default() += written;
+            *stats.deliveries.entry(sink.clone()).or_default() += 1;
+            self.metrics.add_bytes_written(sink, *written);
+        }
+        for sink in delivery.filtered() {
+            *stats.filtered.entry(sink.clone()).or_default() += 1;
+        }
+        for (sink, latencies) in delivery.latencies() {
+            for latency in latencies {
+                self.sinks.record(sink, *latency);
+            }
+        }
+
+        // hidden counts would look like slots being filled, anonymous snapshots
+        // are only published
+        let authenticated = data.metadata().is_authenticated();
+        let diff = self
+            .cache
+            .get(&(task.activity, task.gym, task.date))
+            .filter(|_| authenticated)
+            .map(|previous| SnapshotDiff::between(&previous, &data));
+        if let (true, Some(diff)) = (config.emit_diffs, &diff) {
+            let written = self.publish_diff(diff).await;
+            *stats
+                .bytes_written
+                .entry(self.sink.name().to_string())
+                .or_default() += written;
+        }
+        if let (true, Some(diff)) = (config.event_log, &diff) {
+            let written = self.append_events(diff).await;
+            *stats
+                .bytes_written
+                .entry(events::EVENTS_DIR.to_string())
+                .or_default() += written;
+        }
+
+        if authenticated {
+            self.dispatch_alerts(&data);
+            self.cache.insert(task.date, data);
+        }
+        res.map(|_| TaskOutcome::Ok {
+            slots,
+            bytes_downloaded: downloaded,
+        })
+    }
+
+    /// Fetches and publishes `task` of `iteration`, then paces before `next`
+    ///
+    /// Returns the statistics of this task alone, merged into the iteration by the caller.
+    /// Skipped once the daily download budget is exhausted.
+    /// Everything logged meanwhile, the login included, is within a `fetch` span of the task
+    /// (see [trace::fetch_span]) the caller runs it within
+    async fn run_task(
+        &self,
+        iteration: u64,
+        task: FetchTask,
+        next: FetchTask,
+        skew_measured: &AtomicBool,
+        budget_warned: &AtomicBool,
+    ) -> IterationStats {
+        let mut stats = IterationStats::default();
+
+        // one fetch per permit with the refreshes, held until paced so that the delay holds
+        let _fetching = self.fetching.acquire().await.unwrap();
+        if let Some(budget) = &self.download_budget {
+            if budget.is_exhausted(self.clock.now()) {
+                if !budget_warned.swap(true, Ordering::Relaxed) {
+                    warn!(
+                        "ALERT: daily download budget of {} exhausted, pausing fetches until midnight SGT",
+                        human_bytes(budget.limit_bytes())
+                    );
+                }
+                stats.skipped += 1;
+                stats
+                    .tasks
+                    .push(TaskReport::skipped(task, "daily download budget"));
+                trace::record_skipped(&Span::current());
+                return stats;
+            }
+        }
+
+        let started = Instant::now();
+        let retried = self.fetch(task).await;
+        stats.waited += retried.waited;
+        let res = match retried.res {
+            Ok(data) => {
+                // one measurement per iteration is enough
+                if let Some(s) = data.metadata().clock_skew_secs {
+                    if !skew_measured.swap(true, Ordering::Relaxed) {
+                        self.skew_tracker.record(chrono::Duration::seconds(s));
+                    }
+                }
+                self.process(task, iteration, data, &mut stats).await
+            }
+            Err(e) => {
+                // the last snapshot stays as is, a failure says nothing about availability
+                self.cache.mark_failed((task.activity, task.gym, task.date));
+                Err(e)
+            }
+        };
+        let duration_ms = started.elapsed().as_millis() as u64;
+        trace::record_outcome(&Span::current(), &res);
+
+        match res {
+            Ok(outcome) => {
+                stats
+                    .tasks
+                    .push(TaskReport::new(task, outcome, duration_ms));
+                stats.fetched += 1;
+                stats.phases.record_ok();
+                self.metrics.fetches_ok.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                debug!("fetch failed: {}", e);
+                let outcome = TaskOutcome::Failed {
+                    class: e.class().to_string(),
+                    message: e.to_string(),
+                };
+                stats
+                    .tasks
+                    .push(TaskReport::new(task, outcome, duration_ms));
+                stats.errors.record(task, &e);
+                stats.failed += 1;
+                stats.phases.record_failure(&e);
+                self.metrics.record_failure(&e);
+            }
+        }
+
+        // pacing before the next task, or the end of the iteration
+        let waited = self.pacer.between_fetches(next).await;
+        self.metrics.add_waited(WaitReason::InterGymDelay, waited);
+        stats.waited += waited;
+        stats
+    }
+
+    /// Fetches and publishes every task for `dates` once
+    ///
+    /// Catching up after the blackout fetches the stalest gyms first, whatever
+    /// [Config::priority_gyms], within [Config::iteration_budget_for]
+    async fn run_iteration(
+        &self,
+        iteration: u64,
+        dates: &[NaiveDate],
+        kind: IterationKind,
+    ) -> IterationStats {
+        let config = &self.config;
+        let mut stats = IterationStats {
+            iteration,
+            trace_id: trace::current().map(|c| c.trace_id_hex()),
+            ..Default::default()
+        };
+
+        self.metrics.iterations.fetch_add(1, Ordering::Relaxed);
+
+        let priority = match kind {
+            IterationKind::Regular => &config.priority_gyms[..],
+            IterationKind::CatchUp => &[],
+        };
+        let gyms = schedule::prioritize_gyms(&config.gyms, priority, &self.cache.last_success());
+
+        // every activity of a quarantined venue is skipped, not only its gym
+        let quarantined = self.quarantined();
+        let (skipped, mut tasks): (Vec<_>, Vec<_>) = config
+            .fetch_tasks_with(&gyms, dates)
+            .into_iter()
+            .partition(|t| t.gym.gym().is_some_and(|g| quarantined.contains(&g)));
+        for task in skipped {
+            stats.tasks.push(TaskReport::skipped(task, "quarantined"));
+            stats.skipped += 1;
+        }
+
+        if let Some(budget) = config.iteration_budget_for(kind) {
+            if tasks.len() > budget {
+                warn!(
+                    "Iteration budget of {} fetches reached, skipping {} fetches",
+                    budget,
+                    tasks.len() - budget
+                );
+                stats.skipped += tasks.len() - budget;
+                for task in tasks.drain(budget..) {
+                    stats
+                        .tasks
+                        .push(TaskReport::skipped(task, "iteration budget"));
+                }
+            }
+        }
+
+        // up to [Config::concurrency] tasks in flight, a failure doesn't cancel the others
+        let skew_measured = AtomicBool::new(false);
+        let budget_warned = AtomicBool::new(false);
+        let paced = tasks
+            .iter()
+            .enumerate()
+            .map(|(i, task)| (*task, tasks.get(i + 1).copied().unwrap_or(*task)))
+            .collect::<Vec<_>>();
+        let mut running = stream::iter(paced)
+            .map(|(task, next)| {
+                self.run_task(iteration, task, next, &skew_measured, &budget_warned)
+                    .instrument(trace::fetch_span(task))
+            })
+            .buffer_unordered(config.concurrency);
+        while let Some(task_stats) = running.next().await {
+            stats.merge(task_stats);
+        }
+        drop(running);
+        stats.waited += self.take_limiter_waits();
+        // delivered meanwhile, an iteration doesn't end before its alerts
+        self.alerts.wait().await;
+
+        let written = self.flush_sink().await;
+        if written > 0 {
+            *stats
+                .bytes_written
+                .entry(self.sink.name().to_string())
+                .or_default() += written;
+        }
+
+        // one line per kind of error rather than per gym, full detail is at debug level
+        for line in stats.errors.lines() {
+            match &stats.trace_id {
+                Some(trace_id) => warn!("{}, trace {}", line, trace_id),
+                None => warn!("{}", line),
+            }
+        }
+
+        stats
+    }
+}
+
+#[cfg(feature = "server")]
+impl<S> crate::server::ApiState for Daemon<S>
+where
+    S: Sink + Send + Sync + 'static,
+{
+    fn budget_exhausted(&self) -> bool {
+        matches!(&self.download_budget, Some(b) if b.is_exhausted(self.clock.now()))
+    }
+
+    fn snapshot(&self, key: &crate::cache::SnapshotKey) -> Option<crate::cache::CachedSnapshot> {
+        self.cache.latest(key, self.clock.now())
+    }
+
+    fn sinks(&self) -> Vec<crate::sinkhealth::SinkStatus> {
+        self.sinks.statuses()
+    }
+
+    fn today(&self) -> NaiveDate {
+        self.clock.now().with_timezone(&*SGT).date().naive_local()
+    }
+}
@@ -0,0 +1,716 @@
+//! Data and state of the live dashboard, kept apart from the terminal so that they can be
+//! tested without one
+//!
+//! [Grid] is what is shown, built from the [SnapshotCache] of the running miner,
+//! [DashboardState] is what the user selected, changed by an [Input] at a time, typed
+//! through a [Prompt] a [Key] at a time. [draw] lays both out on a ratatui terminal,
+//! and [render] as plain text
+
+use std::{fmt::Write, str::FromStr};
+
+use chrono::{DateTime, NaiveDate, Timelike, Utc};
+
+use crate::{
+    cache::SnapshotCache,
+    clock::SGT,
+    daemon::IterationUpdate,
+    errors,
+    fetch::FetchTask,
+    models::{Activity, Gym},
+};
+
+/// Slots left at or below which a cell is shown as low
+pub const LOW_SLOTS: u16 = 5;
+
+/// Width of an hour column, e.g. ` 25 `
+const CELL_WIDTH: usize = 4;
+
+/// Availability of a gym at an hour
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cell {
+    /// no slot at that hour, or the gym wasn't captured yet
+    Unknown,
+    Full,
+    Low(u16),
+    Open(u16),
+}
+
+impl Cell {
+    pub fn of(slots: Option<u16>) -> Self {
+        match slots {
+            None => Self::Unknown,
+            Some(0) => Self::Full,
+            Some(n) if n <= LOW_SLOTS => Self::Low(n),
+            Some(n) => Self::Open(n),
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Self::Unknown => "·".into(),
+            Self::Full => "x".into(),
+            Self::Low(n) | Self::Open(n) => n.to_string(),
+        }
+    }
+
+    #[cfg(feature = "cli")]
+    fn style(&self) -> ratatui::style::Style {
+        use ratatui::style::{Color, Style};
+        match self {
+            Self::Unknown => Style::default(),
+            Self::Full => Style::default().fg(Color::Red),
+            Self::Low(_) => Style::default().fg(Color::Yellow),
+            Self::Open(_) => Style::default().fg(Color::Green),
+        }
+    }
+
+    /// ANSI color escape of the cell, red when full, yellow when low, green when open
+    fn color(&self) -> &'static str {
+        match self {
+            Self::Unknown => "",
+            Self::Full => "\x1b[31m",
+            Self::Low(_) => "\x1b[33m",
+            Self::Open(_) => "\x1b[32m",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GridRow {
+    pub gym: Gym,
+
+    /// one per [Grid::hours]
+    pub cells: Vec<Cell>,
+
+    /// the latest fetch of the gym failed, see [crate::cache::CachedSnapshot::stale]
+    pub stale: bool,
+}
+
+/// Gyms × upcoming hours of a date
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grid {
+    pub date: NaiveDate,
+
+    /// hours in Singapore time with a slot at any of the gyms, those already started left out
+    pub hours: Vec<u32>,
+    pub rows: Vec<GridRow>,
+}
+
+impl Grid {
+    /// Grid of the latest snapshots of `gyms` on `date` at `now`
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::{
+    ///     cache::SnapshotCache,
+    ///     dashboard::{Cell, Grid},
+    ///     models::{Gym, GymSlotData, Timeslot},
+    /// };
+    /// use chrono::{NaiveDate, TimeZone, Utc};
+    ///
+    /// let date = NaiveDate::from_ymd(2022, 1, 11);
+    /// // 09:00, 10:00 and 11:00 SGT
+    /// let slot = |h, n| Timeslot::new(Utc.ymd(2022, 1, 11).and_hms(h, 0, 0), n);
+    /// let captured = Utc.ymd(2022, 1, 11).and_hms(1, 30, 0);
+    ///
+    /// let cache = SnapshotCache::default();
+    /// let slots = vec![slot(1, 25), slot(2, 3), slot(3, 0)];
+    /// cache.insert(date, GymSlotData::new(Gym::BISHAN, captured.naive_utc(), slots));
+    ///
+    /// // 09:30 SGT, the 09:00 slot has started
+    /// let grid = Grid::build(&cache, &[Gym::BISHAN, Gym::CLEMENTI], date, captured);
+    /// assert_eq!(grid.hours, vec![10, 11]);
+    /// assert_eq!(grid.rows[0].cells, vec![Cell::Low(3), Cell::Full]);
+    /// assert_eq!(grid.rows[1].cells, vec![Cell::Unknown, Cell::Unknown]);
+    ///
+    /// // every hour of another day
+    /// let tomorrow = Grid::build(&cache, &[Gym::BISHAN], date, captured - chrono::Duration::days(1));
+    /// assert_eq!(tomorrow.hours, vec![9, 10, 11]);
+    /// ```
+    pub fn build(cache: &SnapshotCache, gyms: &[Gym], date: NaiveDate, now: DateTime<Utc>) -> Self {
+        let now = now.with_timezone(&*SGT);
+        let snapshots = gyms
+            .iter()
+            .map(|gym| {
+                (
+                    *gym,
+                    cache.latest(&(Activity::GYM, (*gym).into(), date), now.into()),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let slot_hours = |slots: &[crate::models::Timeslot]| {
+            slots
+                .iter()
+                .map(|s| (s.time().with_timezone(&*SGT).hour(), s.slots_avail()))
+                .collect::<Vec<_>>()
+        };
+
+        let mut hours = snapshots
+            .iter()
+            .flat_map(|(_, s)| s.iter().flat_map(|s| slot_hours(s.data.data())))
+            .map(|(hour, _)| hour)
+            .filter(|hour| date != now.date().naive_local() || *hour > now.hour())
+            .collect::<Vec<_>>();
+        hours.sort_unstable();
+        hours.dedup();
+
+        let rows = snapshots
+            .iter()
+            .map(|(gym, snapshot)| {
+                let slots = snapshot
+                    .as_ref()
+                    .map(|s| slot_hours(s.data.data()))
+                    .unwrap_or_default();
+                let cells = hours
+                    .iter()
+                    .map(|h| Cell::of(slots.iter().find(|(hour, _)| hour == h).map(|s| s.1)))
+                    .collect();
+                GridRow {
+                    gym: *gym,
+                    cells,
+                    stale: snapshot.as_ref().is_some_and(|s| s.stale),
+                }
+            })
+            .collect();
+
+        Self { date, hours, rows }
+    }
+}
+
+/// A command typed into the dashboard, one per line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    /// `d`, shows the next queried date
+    NextDate,
+
+    /// `f <text>` keeps the gyms whose name contains the text, `f` alone shows every gym
+    Filter(Option<String>),
+
+    /// `r <GYM>`, fetches the gym again for the date shown
+    Refresh(Gym),
+
+    /// `q`
+    Quit,
+
+    /// an empty line
+    Redraw,
+}
+
+impl FromStr for Input {
+    type Err = errors::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (command, arg) = match s.split_once(char::is_whitespace) {
+            Some((command, arg)) => (command, Some(arg.trim())),
+            None => (s, None),
+        };
+
+        match (command, arg) {
+            ("", _) => Ok(Self::Redraw),
+            ("d", None) => Ok(Self::NextDate),
+            ("f", filter) => Ok(Self::Filter(filter.map(str::to_string))),
+            ("r", Some(gym)) => Ok(Self::Refresh(gym.parse()?)),
+            ("q", None) => Ok(Self::Quit),
+            _ => Err(errors::Error::InvalidConfig(format!(
+                "unknown command {:?}, expected d, f [text], r <GYM> or q",
+                s
+            ))),
+        }
+    }
+}
+
+/// A key pressed in the dashboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Esc,
+}
+
+/// Command line of the dashboard
+///
+/// `d` and `q` act straight away, `f` and `r` start typing the rest of their command,
+/// e.g. `r CLEMENTI`, which Enter runs and Esc cancels. Esc with nothing typed clears
+/// the filter
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     dashboard::{Input, Key, Prompt},
+///     models::Gym,
+/// };
+///
+/// let mut prompt = Prompt::default();
+/// let mut type_in = |keys: &str| {
+///     keys.chars().map(Key::Char).filter_map(|k| prompt.key(k)).collect::<Vec<_>>()
+/// };
+/// assert_eq!(type_in("d").pop().unwrap().unwrap(), Input::NextDate);
+/// assert!(type_in("r CLEMENTX").is_empty());
+///
+/// assert_eq!(prompt.line(), Some("r CLEMENTX"));
+/// assert!(prompt.key(Key::Backspace).is_none());
+/// assert!(prompt.key(Key::Char('I')).is_none());
+/// assert_eq!(prompt.key(Key::Enter).unwrap().unwrap(), Input::Refresh(Gym::CLEMENTI));
+/// assert_eq!(prompt.line(), None);
+///
+/// // typos are reported once entered
+/// "f".chars().chain(" yishun".chars()).for_each(|c| assert!(prompt.key(Key::Char(c)).is_none()));
+/// assert_eq!(prompt.key(Key::Enter).unwrap().unwrap(), Input::Filter(Some("yishun".into())));
+/// "r NOWHERE".chars().for_each(|c| assert!(prompt.key(Key::Char(c)).is_none()));
+/// assert!(prompt.key(Key::Enter).unwrap().is_err());
+///
+/// // cancelled, then the filter cleared
+/// prompt.key(Key::Char('f'));
+/// assert!(prompt.key(Key::Esc).is_none());
+/// assert_eq!(prompt.key(Key::Esc).unwrap().unwrap(), Input::Filter(None));
+/// assert_eq!(prompt.key(Key::Char('q')).unwrap().unwrap(), Input::Quit);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Prompt {
+    /// none unless a command is being typed
+    line: Option<String>,
+}
+
+impl Prompt {
+    /// Command being typed
+    pub fn line(&self) -> Option<&str> {
+        self.line.as_deref()
+    }
+
+    /// Applies `key`, returning the command it completed if any
+    pub fn key(&mut self, key: Key) -> Option<Result<Input, errors::Error>> {
+        let line = match &mut self.line {
+            Some(line) => line,
+            None => {
+                return match key {
+                    Key::Char('d') => Some(Ok(Input::NextDate)),
+                    Key::Char('q') => Some(Ok(Input::Quit)),
+                    Key::Char(c @ ('f' | 'r')) => {
+                        self.line = Some(format!("{} ", c));
+                        None
+                    }
+                    Key::Enter => Some(Ok(Input::Redraw)),
+                    Key::Esc => Some(Ok(Input::Filter(None))),
+                    _ => None,
+                };
+            }
+        };
+
+        match key {
+            // the space after the command is there already
+            Key::Char(' ') if line.ends_with(' ') => (),
+            Key::Char(c) => line.push(c),
+            Key::Backspace => {
+                line.pop();
+            }
+            Key::Esc => self.line = None,
+            Key::Enter => return self.line.take().map(|line| line.parse()),
+        }
+        None
+    }
+}
+
+/// What the dashboard asks the miner to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Refresh(FetchTask),
+    Quit,
+}
+
+/// Selection of the dashboard
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     dashboard::{Command, DashboardState, Input},
+///     fetch::FetchTask,
+///     models::{Activity, Gym},
+/// };
+/// use chrono::NaiveDate;
+///
+/// let day = |d| NaiveDate::from_ymd(2022, 1, d);
+/// let mut state = DashboardState::new(vec![day(11), day(13)]);
+/// let gyms = [Gym::BISHAN, Gym::BUKIT_BATOK, Gym::CLEMENTI];
+///
+/// // input => (command, date shown, gyms shown)
+/// let table = [
+///     ("", None, day(11), 3),
+///     ("d", None, day(13), 3),
+///     ("f bukit", None, day(13), 1),
+///     ("r CLEMENTI", Some(Command::Refresh(FetchTask::new(Activity::GYM, Gym::CLEMENTI, day(13)))), day(13), 1),
+///     ("d", None, day(11), 1),
+///     ("f", None, day(11), 3),
+///     ("q", Some(Command::Quit), day(11), 3),
+/// ];
+/// for (line, command, date, shown) in table {
+///     let input: Input = line.parse().unwrap();
+///     assert_eq!(state.apply(input), command, "{:?}", line);
+///     assert_eq!(state.date(), Some(date), "{:?}", line);
+///     assert_eq!(state.visible(&gyms).len(), shown, "{:?}", line);
+/// }
+///
+/// // the date shown survives the dates moving on, when still queried
+/// state.apply(Input::NextDate);
+/// state.set_dates(vec![day(12), day(13), day(14)]);
+/// assert_eq!(state.date(), Some(day(13)));
+/// state.set_dates(vec![day(15)]);
+/// assert_eq!(state.date(), Some(day(15)));
+///
+/// assert!("r NOWHERE".parse::<Input>().is_err());
+/// assert!("x".parse::<Input>().is_err());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DashboardState {
+    dates: Vec<NaiveDate>,
+    date: usize,
+    filter: Option<String>,
+}
+
+impl DashboardState {
+    pub fn new(dates: Vec<NaiveDate>) -> Self {
+        Self {
+            dates,
+            ..Default::default()
+        }
+    }
+
+    /// Date shown, none when nothing is queried
+    pub fn date(&self) -> Option<NaiveDate> {
+        self.dates.get(self.date).copied()
+    }
+
+    /// Replaces the queried dates, e.g. after midnight, keeping the date shown when possible
+    pub fn set_dates(&mut self, dates: Vec<NaiveDate>) {
+        let shown = self.date();
+        self.date = shown
+            .and_then(|d| dates.iter().position(|x| *x == d))
+            .unwrap_or_default();
+        self.dates = dates;
+    }
+
+    pub fn filter(&self) -> Option<&str> {
+        self.filter.as_deref()
+    }
+
+    /// Gyms of `gyms` matching the filter, by enum or display name, ignoring case
+    pub fn visible(&self, gyms: &[Gym]) -> Vec<Gym> {
+        let filter = match &self.filter {
+            Some(f) => f.to_lowercase(),
+            None => return gyms.to_vec(),
+        };
+        gyms.iter()
+            .copied()
+            .filter(|g| {
+                format!("{:?}", g).to_lowercase().contains(&filter)
+                    || g.display().to_lowercase().contains(&filter)
+            })
+            .collect()
+    }
+
+    pub fn apply(&mut self, input: Input) -> Option<Command> {
+        match input {
+            Input::NextDate => {
+                if !self.dates.is_empty() {
+                    self.date = (self.date + 1) % self.dates.len();
+                }
+                None
+            }
+            Input::Filter(filter) => {
+                self.filter = filter;
+                None
+            }
+            Input::Refresh(gym) => self
+                .date()
+                .map(|date| Command::Refresh(FetchTask::new(Activity::GYM, gym, date))),
+            Input::Quit => Some(Command::Quit),
+            Input::Redraw => None,
+        }
+    }
+}
+
+/// Status lines shown beside the grid: the last iteration, its failures and the quarantined gyms
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     daemon::IterationUpdate, dashboard, metrics::IterationStats, models::Gym,
+/// };
+/// use chrono::{TimeZone, Utc};
+///
+/// assert_eq!(dashboard::sidebar(None, &[]), ["waiting for the first iteration"]);
+///
+/// let update = IterationUpdate {
+///     finished_at: Utc.ymd(2022, 1, 11).and_hms(1, 30, 0),
+///     dates: vec![],
+///     stats: IterationStats { iteration: 42, fetched: 24, failed: 1, ..Default::default() },
+/// };
+/// let lines = dashboard::sidebar(Some(&update), &[Gym::CLEMENTI]);
+/// assert_eq!(lines[0], "iteration 42 at 09:30:00");
+/// assert_eq!(lines[1], "24 fetched, 1 failed");
+/// assert_eq!(lines.last().unwrap(), "quarantined: CLEMENTI");
+/// ```
+pub fn sidebar(update: Option<&IterationUpdate>, quarantined: &[Gym]) -> Vec<String> {
+    let mut lines = match update {
+        Some(update) => vec![
+            format!(
+                "iteration {} at {}",
+                update.stats.iteration,
+                update.finished_at.with_timezone(&*SGT).format("%H:%M:%S")
+            ),
+            format!(
+                "{} fetched, {} failed",
+                update.stats.fetched, update.stats.failed
+            ),
+        ],
+        None => vec!["waiting for the first iteration".to_string()],
+    };
+    if let Some(update) = update {
+        lines.extend(update.stats.errors.lines());
+    }
+    if !quarantined.is_empty() {
+        let gyms = quarantined
+            .iter()
+            .map(|g| format!("{:?}", g))
+            .collect::<Vec<_>>();
+        lines.push(format!("quarantined: {}", gyms.join(", ")));
+    }
+    lines
+}
+
+/// Lays out `grid` with `sidebar` on its right, or below it when `width` is too narrow
+///
+/// When even the grid doesn't fit, each gym is listed on a line with its open hours instead.
+/// Cells are colored with ANSI escapes when `color`
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     dashboard::{self, Cell, Grid, GridRow},
+///     models::Gym,
+/// };
+/// use chrono::NaiveDate;
+///
+/// let grid = Grid {
+///     date: NaiveDate::from_ymd(2022, 1, 11),
+///     hours: vec![10, 11],
+///     rows: vec![GridRow { gym: Gym::BISHAN, cells: vec![Cell::Open(25), Cell::Full], stale: false }],
+/// };
+/// let sidebar = vec!["iteration 42".to_string()];
+///
+/// let wide = dashboard::render(&grid, &sidebar, 80, false);
+/// let mut lines = wide.lines();
+/// assert!(lines.next().unwrap().ends_with("  iteration 42"));
+/// assert_eq!(lines.nth(1).unwrap().split_whitespace().collect::<Vec<_>>(), ["BISHAN", "25", "x"]);
+///
+/// let narrow = dashboard::render(&grid, &sidebar, 15, false);
+/// assert!(narrow.lines().any(|l| l.starts_with("BISHAN") && l.contains("10:00 (25)")));
+/// assert!(narrow.lines().any(|l| l == "iteration 42"));
+/// ```
+pub fn render(grid: &Grid, sidebar: &[String], width: usize, color: bool) -> String {
+    let (name_width, grid_width) = widths(grid);
+    let sidebar_width = sidebar.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+
+    let title = grid.date.to_string();
+    if grid_width > width {
+        let mut lines = vec![title];
+        // too narrow for the grid, one line per gym
+        for row in &grid.rows {
+            let open = grid
+                .hours
+                .iter()
+                .zip(&row.cells)
+                .filter_map(|(h, c)| match c {
+                    Cell::Low(n) | Cell::Open(n) => Some(format!("{:02}:00 ({})", h, n)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            let open = match open.is_empty() {
+                true => "none open".to_string(),
+                false => open.join(", "),
+            };
+            lines.push(format!("{:?}{}: {}", row.gym, stale_mark(row), open));
+        }
+        lines.push(String::new());
+        lines.extend(sidebar.iter().cloned());
+        return lines.join("\n") + "\n";
+    }
+
+    // lines of the grid with their width as displayed, i.e. without color escapes
+    let mut rows = vec![(title.clone(), title.len())];
+    let mut header = " ".repeat(name_width);
+    for hour in &grid.hours {
+        let _ = write!(
+            header,
+            "{:>width$}",
+            format!("{:02}", hour),
+            width = CELL_WIDTH
+        );
+    }
+    rows.push((header, grid_width));
+    for row in &grid.rows {
+        let mut line = format!(
+            "{:<width$}",
+            format!("{:?}{}", row.gym, stale_mark(row)),
+            width = name_width
+        );
+        for cell in &row.cells {
+            let text = format!("{:>width$}", cell.text(), width = CELL_WIDTH);
+            match (color, cell.color()) {
+                (true, escape) if !escape.is_empty() => {
+                    let _ = write!(line, "{}{}\x1b[0m", escape, text);
+                }
+                _ => line.push_str(&text),
+            }
+        }
+        rows.push((line, grid_width));
+    }
+
+    // beside the grid when both fit, below otherwise
+    let mut lines = Vec::with_capacity(rows.len() + sidebar.len() + 1);
+    if grid_width + 2 + sidebar_width <= width {
+        let height = rows.len().max(sidebar.len());
+        for i in 0..height {
+            let (line, shown) = rows.get(i).cloned().unwrap_or_default();
+            match sidebar.get(i) {
+                Some(text) => lines.push(format!(
+                    "{}{}  {}",
+                    line,
+                    " ".repeat(grid_width.saturating_sub(shown)),
+                    text
+                )),
+                None => lines.push(line),
+            }
+        }
+    } else {
+        lines.extend(rows.into_iter().map(|(line, _)| line));
+        lines.push(String::new());
+        lines.extend(sidebar.iter().cloned());
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// Width of the gym names and of the whole grid, the names included
+fn widths(grid: &Grid) -> (usize, usize) {
+    let name_width = grid
+        .rows
+        .iter()
+        .map(|r| format!("{:?}", r.gym).len() + 1)
+        .max()
+        .unwrap_or_default()
+        .max(10);
+    (name_width, name_width + grid.hours.len() * CELL_WIDTH)
+}
+
+/// Draws `grid` with `sidebar` on its right and `prompt` on the last line
+///
+/// When the grid and the sidebar don't fit side by side, or the grid is taller than the
+/// frame, the plain text of [render] is drawn instead
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     dashboard::{self, Cell, Grid, GridRow},
+///     models::Gym,
+/// };
+/// use chrono::NaiveDate;
+/// use ratatui::{backend::TestBackend, style::Color, Terminal};
+///
+/// let grid = Grid {
+///     date: NaiveDate::from_ymd(2022, 1, 11),
+///     hours: vec![10, 11],
+///     rows: vec![GridRow { gym: Gym::BISHAN, cells: vec![Cell::Open(25), Cell::Full], stale: true }],
+/// };
+/// let sidebar = vec!["iteration 42".to_string()];
+/// let draw = |width, height| {
+///     let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+///     terminal.draw(|f| dashboard::draw(f, &grid, &sidebar, "d: next date")).unwrap();
+///     terminal.backend().buffer().clone()
+/// };
+/// let line = |buffer: &ratatui::buffer::Buffer, y| {
+///     (0..buffer.area.width).map(|x| buffer[(x, y)].symbol()).collect::<String>()
+/// };
+///
+/// let wide = draw(60, 6);
+/// assert!(line(&wide, 0).starts_with("2022-01-11"));
+/// assert!(line(&wide, 0).contains("iteration 42"));
+/// assert_eq!(line(&wide, 2).split_whitespace().collect::<Vec<_>>(), ["BISHAN*", "25", "x"]);
+/// let full = line(&wide, 2).find('x').unwrap() as u16;
+/// assert_eq!(wide[(full, 2)].fg, Color::Red);
+/// assert!(line(&wide, 5).starts_with("d: next date"));
+///
+/// // the sidebar below the grid when both don't fit beside each other
+/// let below = draw(20, 6);
+/// assert_eq!(line(&below, 2).split_whitespace().collect::<Vec<_>>(), ["BISHAN*", "25", "x"]);
+/// assert!(line(&below, 4).starts_with("iteration 42"));
+///
+/// // and one line per gym when even the grid doesn't fit
+/// let narrow = draw(15, 6);
+/// assert!(line(&narrow, 1).starts_with("BISHAN*: 10:00"));
+/// assert!(line(&narrow, 5).starts_with("d: next date"));
+/// ```
+#[cfg(feature = "cli")]
+pub fn draw(frame: &mut ratatui::Frame, grid: &Grid, sidebar: &[String], prompt: &str) {
+    use ratatui::{
+        layout::{Constraint, Layout},
+        text::Line,
+        widgets::{Paragraph, Row, Table},
+    };
+
+    let [main, prompt_area] =
+        Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+    frame.render_widget(Line::raw(prompt), prompt_area);
+
+    let (name_width, grid_width) = widths(grid);
+    let sidebar_width = sidebar.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    let fits = grid_width + 2 + sidebar_width <= main.width as usize
+        && grid.rows.len() + 2 <= main.height as usize;
+    if !fits {
+        let text = render(grid, sidebar, main.width as usize, false);
+        frame.render_widget(Paragraph::new(text), main);
+        return;
+    }
+
+    let [grid_area, sidebar_area] = Layout::horizontal([
+        Constraint::Length(grid_width as u16 + 2),
+        Constraint::Min(0),
+    ])
+    .areas(main);
+    let [title_area, table_area] =
+        Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(grid_area);
+    frame.render_widget(Line::raw(grid.date.to_string()), title_area);
+
+    let header = Row::new(
+        std::iter::once(String::new()).chain(grid.hours.iter().map(|h| format!("{:>3}", h))),
+    );
+    let rows = grid.rows.iter().map(|row| {
+        let name = format!("{:?}{}", row.gym, stale_mark(row)).into();
+        let cells = row.cells.iter().map(|c| {
+            let text = format!("{:>3}", c.text());
+            ratatui::widgets::Cell::new(text).style(c.style())
+        });
+        Row::new(std::iter::once(name).chain(cells))
+    });
+    let columns = std::iter::once(Constraint::Length(name_width as u16 - 1)).chain(
+        grid.hours
+            .iter()
+            .map(|_| Constraint::Length(CELL_WIDTH as u16 - 1)),
+    );
+    frame.render_widget(Table::new(rows, columns).header(header), table_area);
+
+    let sidebar = sidebar
+        .iter()
+        .map(|l| Line::raw(l.as_str()))
+        .collect::<Vec<_>>();
+    frame.render_widget(Paragraph::new(sidebar), sidebar_area);
+}
+
+fn stale_mark(row: &GridRow) -> &'static str {
+    match row.stale {
+        true => "*",
+        false => "",
+    }
+}
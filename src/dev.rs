@@ -0,0 +1,158 @@
+//! Development helpers, only built with the `dev` feature
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use reqwest::header::HeaderMap;
+use tracing::info;
+
+use crate::{
+    fetch::{BookingPage, FetchTask, Fetcher},
+    DataMResult,
+};
+
+/// Booking pages saved to disk so that parser changes can be iterated on without the network
+///
+/// Pages younger than `ttl` are replayed instead of fetched, which also skips the login.
+/// Snapshots built from replayed pages are marked [crate::models::SnapshotMetadata::synthetic]
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     config::Config,
+///     dev::DevArtifacts,
+///     fetch::{self, FakeFetcher, FetchTask, Fetcher},
+///     models::{Activity, Gym, User},
+/// };
+/// use chrono::NaiveDate;
+/// use std::{
+///     sync::atomic::{AtomicUsize, Ordering},
+///     time::{Duration, SystemTime},
+/// };
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let dir = std::env::temp_dir().join(format!("dev-doctest-{}", std::process::id()));
+/// let fetched = AtomicUsize::new(0);
+/// let inner = FakeFetcher::new(|_| {
+///     fetched.fetch_add(1, Ordering::SeqCst);
+///     Ok(r#"<div class="chkbox-grid"><label>07:00 AM</label><label>25 Left</label></div>"#.into())
+/// });
+/// let mut config = Config::new(User::new("user@example.com", "password"));
+/// config.keep_out_of_hours = true;
+/// let task = FetchTask::new(Activity::GYM, Gym::BISHAN, NaiveDate::from_ymd(2022, 1, 11));
+///
+/// let artifacts = DevArtifacts::new(&dir, DevArtifacts::DEFAULT_TTL);
+/// let path = artifacts.booking_page_path(task);
+/// assert_eq!(path, dir.join("booking-GYM-BISHAN-2022-01-11.html"));
+/// assert!(!artifacts.is_fresh(&path, SystemTime::now()));
+///
+/// // fetched through `inner` and saved
+/// let fetcher = artifacts.fetcher(&inner);
+/// let page = fetcher.fetch_booking_page(task).await.unwrap();
+/// assert!(page.url.starts_with("fake://"));
+/// assert!(page.bytes_downloaded > 0);
+/// assert_eq!(fetched.load(Ordering::SeqCst), 1);
+/// assert!(artifacts.is_fresh(&path, SystemTime::now()));
+///
+/// // replayed while fresh, marked synthetic and downloading nothing
+/// let replayed = fetcher.fetch_booking_page(task).await.unwrap();
+/// assert!(replayed.url.starts_with("artifact://"));
+/// assert_eq!((replayed.synthetic, replayed.bytes_downloaded), (true, 0));
+/// assert_eq!(replayed.body, page.body);
+/// let data = fetch::fetch_slots(&fetcher, &config, task).await.unwrap();
+/// assert!(data.metadata().synthetic);
+/// assert_eq!(data.metadata().bytes_downloaded, Some(0));
+/// assert_eq!(data.data().len(), 1);
+/// assert_eq!(fetched.load(Ordering::SeqCst), 1);
+///
+/// // stale once the ttl is over, fetched again
+/// let later = SystemTime::now() + DevArtifacts::DEFAULT_TTL;
+/// assert!(!artifacts.is_fresh(&path, later));
+/// let expired = DevArtifacts::new(&dir, Duration::ZERO);
+/// let page = expired.fetcher(&inner).fetch_booking_page(task).await.unwrap();
+/// assert!(page.url.starts_with("fake://"));
+/// assert_eq!(fetched.load(Ordering::SeqCst), 2);
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DevArtifacts {
+    pub dir: PathBuf,
+    pub ttl: Duration,
+}
+
+impl DevArtifacts {
+    /// 1 hour
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+
+    pub fn new<P: Into<PathBuf>>(dir: P, ttl: Duration) -> Self {
+        Self {
+            dir: dir.into(),
+            ttl,
+        }
+    }
+
+    pub fn booking_page_path(&self, task: FetchTask) -> PathBuf {
+        self.dir.join(format!(
+            "booking-{:?}-{:?}-{}.html",
+            task.activity, task.gym, task.date
+        ))
+    }
+
+    /// Whether the artifact at `path` was saved less than [DevArtifacts::ttl] before `now`
+    pub fn is_fresh(&self, path: &Path, now: SystemTime) -> bool {
+        std::fs::metadata(path)
+            .and_then(|m| m.modified())
+            .map(|modified| now.duration_since(modified).unwrap_or_default() < self.ttl)
+            .unwrap_or(false)
+    }
+
+    /// Wraps `inner` so that its pages are saved and replayed
+    pub fn fetcher<'a, F>(&'a self, inner: &'a F) -> ArtifactFetcher<'a, F> {
+        ArtifactFetcher {
+            inner,
+            artifacts: self,
+        }
+    }
+}
+
+/// [Fetcher] replaying fresh [DevArtifacts] and saving the pages fetched through `inner`
+pub struct ArtifactFetcher<'a, F> {
+    inner: &'a F,
+    artifacts: &'a DevArtifacts,
+}
+
+impl<F> Fetcher for ArtifactFetcher<'_, F>
+where
+    F: Fetcher + Sync,
+{
+    async fn fetch_booking_page(&self, task: FetchTask) -> DataMResult<BookingPage> {
+        let path = self.artifacts.booking_page_path(task);
+
+        if self.artifacts.is_fresh(&path, SystemTime::now()) {
+            let body = tokio::fs::read_to_string(&path).await?;
+            let mut page = BookingPage::new(
+                format!("artifact://{}", path.display()),
+                HeaderMap::new(),
+                body,
+            );
+            page.bytes_downloaded = 0;
+            page.synthetic = true;
+            return Ok(page);
+        }
+
+        let page = self.inner.fetch_booking_page(task).await?;
+        tokio::fs::create_dir_all(&self.artifacts.dir).await?;
+        tokio::fs::write(&path, &page.body).await?;
+        info!("{}, artifact saved", path.display());
+
+        Ok(page)
+    }
+
+    fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        self.inner.now()
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    models::{Activity, GymSlotData, Timeslot},
+    venue::Venue,
+};
+
+/// Change of a single timeslot between two snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(tag = "change", rename_all = "lowercase")]
+pub enum SlotDiff {
+    Added {
+        time: DateTime<Utc>,
+        slots_avail: u16,
+    },
+    Removed {
+        time: DateTime<Utc>,
+        slots_avail: u16,
+    },
+    Changed {
+        time: DateTime<Utc>,
+        before: u16,
+        after: u16,
+    },
+}
+
+impl SlotDiff {
+    pub fn time(&self) -> DateTime<Utc> {
+        match self {
+            Self::Added { time, .. } | Self::Removed { time, .. } | Self::Changed { time, .. } => {
+                *time
+            }
+        }
+    }
+}
+
+/// Changes between two consecutive snapshots of the same gym and date
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotDiff {
+    pub activity: Activity,
+    pub gym: Venue,
+
+    /// capture time of the old snapshot
+    pub from: NaiveDateTime,
+
+    /// capture time of the new snapshot
+    pub to: NaiveDateTime,
+
+    /// ordered by slot time
+    pub changes: Vec<SlotDiff>,
+}
+
+impl SnapshotDiff {
+    /// Diff turning `old` into `new`, which must be snapshots of the same gym and date
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::{
+    ///     diff::{SlotDiff, SnapshotDiff},
+    ///     models::{Gym, GymSlotData, Timeslot},
+    /// };
+    /// use chrono::{NaiveDate, TimeZone, Utc};
+    ///
+    /// let at = |h| Utc.ymd(2022, 1, 11).and_hms(h, 0, 0);
+    /// let snapshot = |minute, slots: &[(u32, u16)]| {
+    ///     let captured = NaiveDate::from_ymd(2022, 1, 11).and_hms(1, minute, 0);
+    ///     let slots = slots.iter().map(|(h, n)| Timeslot::new(at(*h), *n)).collect();
+    ///     GymSlotData::new(Gym::BISHAN, captured, slots)
+    /// };
+    /// let old = snapshot(0, &[(1, 25), (2, 12), (3, 7)]);
+    /// let new = snapshot(20, &[(2, 12), (3, 4), (4, 25)]);
+    ///
+    /// let diff = SnapshotDiff::between(&old, &new);
+    /// assert_eq!(
+    ///     diff.changes,
+    ///     [
+    ///         SlotDiff::Removed { time: at(1), slots_avail: 25 },
+    ///         SlotDiff::Changed { time: at(3), before: 7, after: 4 },
+    ///         SlotDiff::Added { time: at(4), slots_avail: 25 },
+    ///     ]
+    /// );
+    /// assert_eq!((diff.from, diff.to), (old.datetime(), new.datetime()));
+    /// assert!(SnapshotDiff::between(&new, &new).is_empty());
+    ///
+    /// let json = serde_json::to_value(&diff).unwrap();
+    /// assert_eq!(json["activity"], "GYM");
+    /// assert_eq!(json["gym"], "BISHAN");
+    /// assert_eq!(
+    ///     json["changes"][1],
+    ///     serde_json::json!({
+    ///         "change": "changed",
+    ///         "time": "2022-01-11T03:00:00Z",
+    ///         "before": 7,
+    ///         "after": 4,
+    ///     })
+    /// );
+    /// assert_eq!(serde_json::from_value::<SnapshotDiff>(json).unwrap(), diff);
+    /// ```
+    pub fn between(old: &GymSlotData, new: &GymSlotData) -> Self {
+        let before = slots_by_time(old.data());
+        let after = slots_by_time(new.data());
+
+        let mut changes = vec![];
+        for (time, slots) in &before {
+            match after.get(time) {
+                None => changes.push(SlotDiff::Removed {
+                    time: *time,
+                    slots_avail: *slots,
+                }),
+                Some(a) if a != slots => changes.push(SlotDiff::Changed {
+                    time: *time,
+                    before: *slots,
+                    after: *a,
+                }),
+                Some(_) => (),
+            }
+        }
+        for (time, slots) in &after {
+            if !before.contains_key(time) {
+                changes.push(SlotDiff::Added {
+                    time: *time,
+                    slots_avail: *slots,
+                });
+            }
+        }
+        changes.sort_by_key(SlotDiff::time);
+
+        Self {
+            activity: new.activity(),
+            gym: new.gym(),
+            from: old.datetime(),
+            to: new.datetime(),
+            changes,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Applies the diff to `old`, giving back the slots and capture time of the new snapshot
+    ///
+    /// The metadata of `old` is kept as it isn't part of the diff
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::{
+    ///     diff::SnapshotDiff,
+    ///     models::{Activity, Gym, GymSlotData, Timeslot},
+    /// };
+    /// use chrono::{NaiveDate, TimeZone, Utc};
+    ///
+    /// let at = |h| Utc.ymd(2022, 1, 11).and_hms(h, 0, 0);
+    /// let snapshot = |minute, slots: &[(u32, u16)]| {
+    ///     let captured = NaiveDate::from_ymd(2022, 1, 11).and_hms(1, minute, 0);
+    ///     let slots = slots.iter().map(|(h, n)| Timeslot::new(at(*h), *n)).collect();
+    ///     GymSlotData::new(Gym::BISHAN, captured, slots).with_activity(Activity::BADMINTON)
+    /// };
+    /// let mut old = snapshot(0, &[(3, 7), (1, 25), (2, 12)]);
+    /// old.mut_metadata().iteration = Some(41);
+    /// let mut new = snapshot(20, &[(2, 12), (3, 4), (4, 25)]);
+    /// new.mut_metadata().iteration = Some(42);
+    ///
+    /// let diff = SnapshotDiff::between(&old, &new);
+    /// let applied = diff.apply(&old);
+    /// assert_eq!(applied.activity(), Activity::BADMINTON);
+    /// assert_eq!(applied.datetime(), new.datetime());
+    /// assert_eq!(applied.data(), new.data());
+    /// // not part of the diff
+    /// assert_eq!(applied.metadata().iteration, Some(41));
+    ///
+    /// // replaying a chain of diffs rebuilds the latest snapshot
+    /// let newest = snapshot(40, &[(4, 20), (5, 25)]);
+    /// let diffs = [diff, SnapshotDiff::between(&new, &newest)];
+    /// let rebuilt = diffs.iter().fold(old.clone(), |data, diff| diff.apply(&data));
+    /// assert_eq!(rebuilt.data(), newest.data());
+    /// assert_eq!(rebuilt.datetime(), newest.datetime());
+    ///
+    /// // an empty diff only moves the capture time, slots come out ordered
+    /// let unchanged = SnapshotDiff::between(&old, &snapshot(20, &[(1, 25), (2, 12), (3, 7)]));
+    /// let applied = unchanged.apply(&old);
+    /// assert_eq!(applied.data().iter().map(|s| s.time()).collect::<Vec<_>>(), [at(1), at(2), at(3)]);
+    /// ```
+    pub fn apply(&self, old: &GymSlotData) -> GymSlotData {
+        let mut slots = slots_by_time(old.data());
+        for change in &self.changes {
+            match *change {
+                SlotDiff::Added { time, slots_avail } => {
+                    slots.insert(time, slots_avail);
+                }
+                SlotDiff::Removed { time, .. } => {
+                    slots.remove(&time);
+                }
+                SlotDiff::Changed { time, after, .. } => {
+                    slots.insert(time, after);
+                }
+            }
+        }
+
+        let data = slots
+            .into_iter()
+            .map(|(time, slots)| Timeslot::new(time, slots))
+            .collect();
+        let mut new = GymSlotData::new(old.gym(), self.to, data)
+            .with_activity(old.activity())
+            .with_gym_name(old.gym_name().is_some())
+            .with_venue_display_name(old.venue_display_name().map(String::from));
+        *new.mut_metadata() = old.metadata().clone();
+        new
+    }
+}
+
+fn slots_by_time(slots: &[Timeslot]) -> BTreeMap<DateTime<Utc>, u16> {
+    slots.iter().map(|s| (s.time(), s.slots_avail())).collect()
+}
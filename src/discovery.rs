@@ -0,0 +1,64 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{models::Activity, venue::Venue};
+
+/// Activity ids found on the venue pages, so that a renumbered activity is followed
+///
+/// Entries expire after [ActivityIdCache::ttl], after which the venue page is loaded again
+#[derive(Debug)]
+pub struct ActivityIdCache {
+    ttl: Duration,
+    ids: Mutex<HashMap<(Activity, Venue), (u16, Instant)>>,
+}
+
+impl Default for ActivityIdCache {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_TTL)
+    }
+}
+
+impl ActivityIdCache {
+    /// 1 day
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60 * 24);
+
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            ids: Mutex::default(),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Id of `activity` at `gym` if discovered less than [ActivityIdCache::ttl] ago
+    pub fn get(&self, activity: Activity, gym: Venue) -> Option<u16> {
+        let mut ids = self.ids.lock().unwrap();
+        match ids.get(&(activity, gym)) {
+            Some((id, at)) if at.elapsed() < self.ttl => Some(*id),
+            Some(_) => {
+                ids.remove(&(activity, gym));
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, activity: Activity, gym: Venue, id: u16) {
+        self.ids
+            .lock()
+            .unwrap()
+            .insert((activity, gym), (id, Instant::now()));
+    }
+
+    /// Forgets the id of `activity` at `gym`, e.g. when its booking page looks wrong,
+    /// so that it is discovered again on the next fetch
+    pub fn invalidate(&self, activity: Activity, gym: Venue) {
+        self.ids.lock().unwrap().remove(&(activity, gym));
+    }
+}
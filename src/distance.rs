@@ -0,0 +1,125 @@
+use std::{cmp::Ordering, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use crate::venue::Venue;
+
+/// Mean radius of the earth in kilometres
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Point on the map in degrees, written `[lat, lng]` in the config file
+///
+/// Always finite and within range, so it can be compared for equality
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "[f64; 2]", into = "[f64; 2]")]
+pub struct LatLng {
+    pub lat: f64,
+    pub lng: f64,
+}
+
+impl Eq for LatLng {}
+
+impl LatLng {
+    /// [None] unless `lat` is within ±90 and `lng` within ±180
+    pub fn new(lat: f64, lng: f64) -> Option<Self> {
+        match (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lng) {
+            true => Some(Self { lat, lng }),
+            false => None,
+        }
+    }
+
+    /// Great-circle distance in kilometres, by the haversine formula
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::distance::LatLng;
+    ///
+    /// let raffles_place = LatLng::new(1.2840, 103.8510).unwrap();
+    /// let changi_airport = LatLng::new(1.3644, 103.9915).unwrap();
+    ///
+    /// let km = raffles_place.distance_km(changi_airport);
+    /// assert!((km - 18.0).abs() < 0.5, "{}", km);
+    /// assert_eq!(changi_airport.distance_km(raffles_place), km);
+    /// assert_eq!(raffles_place.distance_km(raffles_place), 0.0);
+    /// ```
+    pub fn distance_km(&self, other: LatLng) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lng = (other.lng - self.lng).to_radians();
+
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lng / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+}
+
+impl TryFrom<[f64; 2]> for LatLng {
+    type Error = String;
+
+    fn try_from([lat, lng]: [f64; 2]) -> Result<Self, Self::Error> {
+        Self::new(lat, lng).ok_or_else(|| format!("invalid location [{}, {}]", lat, lng))
+    }
+}
+
+impl From<LatLng> for [f64; 2] {
+    fn from(p: LatLng) -> Self {
+        [p.lat, p.lng]
+    }
+}
+
+impl fmt::Display for LatLng {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}, {}]", self.lat, self.lng)
+    }
+}
+
+/// Distance from `home` to `gym` in kilometres,
+/// none without a home location or for a custom venue
+pub fn distance_km<V: Into<Venue>>(home: Option<LatLng>, gym: V) -> Option<f64> {
+    let gym = gym.into().gym()?;
+    home.map(|h| h.distance_km(gym.location()))
+}
+
+/// Sorts `items` by the distance of their gym from `home`, nearest first
+///
+/// Ties, and every item when there is no home location, are sorted alphabetically
+/// by [Venue::display]. Custom venues have no known location and come after the gyms.
+/// The sort is stable so items of the same gym keep their order
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     distance::{self, LatLng},
+///     models::Gym,
+/// };
+///
+/// let gyms = [Gym::TAMPINES, Gym::CLEMENTI, Gym::BISHAN, Gym::PASIR_RIS];
+///
+/// let mut sorted = gyms;
+/// distance::sort_by_distance(&mut sorted, |g| *g, None);
+/// assert_eq!(sorted, [Gym::BISHAN, Gym::CLEMENTI, Gym::PASIR_RIS, Gym::TAMPINES]);
+///
+/// // from Bedok
+/// let home = LatLng::new(1.3240, 103.9300);
+/// distance::sort_by_distance(&mut sorted, |g| *g, home);
+/// assert_eq!(sorted, [Gym::TAMPINES, Gym::PASIR_RIS, Gym::BISHAN, Gym::CLEMENTI]);
+///
+/// let km = distance::distance_km(home, Gym::TAMPINES).unwrap();
+/// assert!(km > 2.0 && km < 5.0, "{}", km);
+/// assert_eq!(distance::distance_km(None, Gym::TAMPINES), None);
+/// ```
+pub fn sort_by_distance<T, F, V>(items: &mut [T], gym_of: F, home: Option<LatLng>)
+where
+    F: Fn(&T) -> V,
+    V: Into<Venue>,
+{
+    items.sort_by(|a, b| {
+        let (a, b): (Venue, Venue) = (gym_of(a).into(), gym_of(b).into());
+        let by_distance = match (distance_km(home, a), distance_km(home, b)) {
+            (Some(da), Some(db)) => da.partial_cmp(&db).unwrap_or(Ordering::Equal),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+        by_distance.then_with(|| a.display().cmp(b.display()))
+    });
+}
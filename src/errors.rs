@@ -1,3 +1,10 @@
+use std::time::Duration;
+
+use reqwest::StatusCode;
+
+use crate::{fetch::FetchTask, retry::MAX_RETRY_AFTER};
+
+#[allow(clippy::enum_variant_names)]
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("ReqwestError: {0}")]
@@ -9,6 +16,9 @@ pub enum Error {
     #[error("Invalid login credentials/session expired!")]
     InvalidCredentialsSessionExpired,
 
+    #[error("Session expired, got the login page!")]
+    SessionExpired,
+
     #[error("Failed to parse PEM!")]
     FailedToParsePEM,
 
@@ -21,9 +31,329 @@ pub enum Error {
     #[error("Failed to parse url!")]
     FailedToParseUrl,
 
-    #[error("Invalid gym!")]
+    #[error("Invalid gym {0}, valid gyms are {}", crate::models::Gym::names())]
     InvalidGym(String),
 
+    #[error("Invalid slot count {0:?}")]
+    InvalidSlotCount(String),
+
+    #[error("Invalid activity!")]
+    InvalidActivity(String),
+
+    #[error("Invalid venue {0}")]
+    InvalidVenue(String),
+
+    #[error("Invalid gym set: {0}")]
+    InvalidGymSet(String),
+
+    #[error("Invalid aliases: {0}")]
+    InvalidAliases(String),
+
     #[error("Tokio file io error: {0}")]
-    Io(#[from] std::io::Error)
+    Io(#[from] std::io::Error),
+
+    #[error("Cancelled!")]
+    Cancelled,
+
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+
+    #[error("{0} critical self-test check(s) failed!")]
+    SelfTestFailed(usize),
+
+    #[error("Iteration counter exhausted!")]
+    IterationCounterExhausted,
+
+    #[error("{0} of {1} fetch(es) failed!")]
+    FetchesFailed(usize, usize),
+
+    #[error("SQLite error: {0}")]
+    Sqlite(String),
+
+    #[error("Parquet error: {0}")]
+    Parquet(String),
+
+    #[error("Notifier error: {0}")]
+    Notifier(String),
+
+    #[error("Failed to serialize: {0}")]
+    Serialize(serde_json::Error),
+
+    /// ActiveSG served a captcha challenge instead of the page
+    #[error("ActiveSG asked for a captcha!")]
+    CaptchaRequired,
+
+    /// ActiveSG served its maintenance page instead of the page
+    #[error("ActiveSG is under maintenance!")]
+    SiteMaintenance,
+
+    /// ActiveSG asked for a one-time PIN with nowhere to take it from,
+    /// see [crate::client::login::OtpSource]
+    #[error("ActiveSG asked for a one-time PIN, see --otp-command!")]
+    OtpRequired,
+
+    /// The one-time PIN didn't log in
+    #[error("ActiveSG rejected the one-time PIN!")]
+    OtpRejected,
+
+    /// The command giving the one-time PIN failed
+    #[error("One-time PIN command failed: {0}")]
+    OtpCommand(String),
+
+    /// Nothing more is fetched until midnight in Singapore,
+    /// see [crate::metrics::DownloadBudget]
+    #[error("Daily download budget exhausted!")]
+    BudgetExhausted,
+
+    /// ActiveSG answered with a 429, asking to wait `retry_after` if it said how long
+    #[error("Rate limited by ActiveSG{}", retry_after_secs(.retry_after))]
+    RateLimited { retry_after: Option<Duration> },
+
+    /// ActiveSG answered with neither a success nor a 429
+    #[error("Unexpected HTTP status {0}")]
+    UnexpectedStatus(StatusCode),
+
+    /// `source` failed while fetching `task`, at `url` when the request was sent,
+    /// see [Error::with_fetch_context]
+    #[error("{task}{}: {source}", in_parens(.url))]
+    Fetch {
+        task: FetchTask,
+        url: Option<String>,
+        source: Box<Error>,
+    },
+}
+
+/// `, retry after 30s`, nothing without a pause
+fn retry_after_secs(retry_after: &Option<Duration>) -> String {
+    retry_after
+        .map(|d| format!(", retry after {}s", d.as_secs()))
+        .unwrap_or_default()
+}
+
+/// ` (url)`, nothing without one
+fn in_parens(url: &Option<String>) -> String {
+    url.as_ref()
+        .map(|u| format!(" ({})", u))
+        .unwrap_or_default()
+}
+
+impl Error {
+    /// Short description of the kind of error, without the details of the specific occurrence
+    ///
+    /// Used to group the same failure across gyms, e.g. during an outage
+    pub fn class(&self) -> &'static str {
+        match self {
+            Self::ClientError(e) if e.is_timeout() => "timeout fetching booking page",
+            Self::ClientError(e) if e.is_connect() => "connection failed",
+            Self::ClientError(e) if e.is_status() => "unexpected http status",
+            Self::ClientError(e) if e.is_decode() => "invalid response body",
+            Self::ClientError(_) => "request failed",
+            Self::CantFindElement(_) => "element missing from page",
+            Self::InvalidCredentialsSessionExpired => "invalid credentials or session expired",
+            Self::SessionExpired => "session expired",
+            Self::FailedToParsePEM | Self::FailedToGenerateKeyFromPEM => "invalid login key",
+            Self::FailedToParseSelector => "invalid selector",
+            Self::FailedToParseUrl => "invalid url",
+            Self::InvalidGym(_) => "invalid gym",
+            Self::InvalidSlotCount(_) => "invalid slot count",
+            Self::InvalidActivity(_) => "invalid activity",
+            Self::InvalidVenue(_) => "invalid venue",
+            Self::InvalidGymSet(_) => "invalid gym set",
+            Self::InvalidAliases(_) => "invalid aliases",
+            Self::Io(_) => "io error",
+            Self::Cancelled => "cancelled",
+            Self::InvalidConfig(_) => "invalid config",
+            Self::SelfTestFailed(_) => "self-test failed",
+            Self::IterationCounterExhausted => "iteration counter exhausted",
+            Self::FetchesFailed(..) => "fetches failed",
+            Self::Sqlite(_) => "sqlite error",
+            Self::Parquet(_) => "parquet error",
+            Self::Notifier(_) => "notifier error",
+            Self::Serialize(_) => "serialization failed",
+            Self::CaptchaRequired => "captcha required",
+            Self::SiteMaintenance => "site under maintenance",
+            Self::OtpRequired => "one-time pin required",
+            Self::OtpRejected => "one-time pin rejected",
+            Self::OtpCommand(_) => "one-time pin command failed",
+            Self::BudgetExhausted => "download budget exhausted",
+            Self::RateLimited { .. } => "rate limited",
+            Self::UnexpectedStatus(_) => "unexpected http status",
+            Self::Fetch { source, .. } => source.class(),
+        }
+    }
+
+    /// Where the request broke down, see [FailurePhase::of_request]
+    pub fn phase(&self) -> FailurePhase {
+        match self {
+            Self::ClientError(e) => FailurePhase::of_request(
+                e.is_connect(),
+                e.is_timeout() || e.is_body(),
+                e.status().map(|s| s.as_u16()),
+            ),
+            Self::UnexpectedStatus(status) => {
+                FailurePhase::of_request(false, false, Some(status.as_u16()))
+            }
+            Self::Fetch { source, .. } => source.phase(),
+            _ => FailurePhase::Other,
+        }
+    }
+
+    /// Tells which fetch failed, unless the error already does
+    ///
+    /// The class, phase and retryability are those of the error itself, see [Error::root]
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::{
+    ///     errors::Error,
+    ///     fetch::FetchTask,
+    ///     models::{Activity, Gym},
+    /// };
+    /// use chrono::NaiveDate;
+    ///
+    /// let date = NaiveDate::from_ymd(2022, 1, 11);
+    /// let task = FetchTask::new(Activity::GYM, Gym::BISHAN, date);
+    /// let url = "https://members.myactivesg.com/facilities/view/activity/1031/venue/137?time_from=1641830400";
+    ///
+    /// let e = Error::CantFindElement("rsapublickey").with_fetch_context(task, Some(url.into()));
+    /// assert_eq!(
+    ///     e.to_string(),
+    ///     "BISHAN on 2022-01-11 (https://members.myactivesg.com/facilities/view/activity/1031/venue/137?time_from=1641830400): ReqwestError: rsapublickey"
+    /// );
+    /// assert_eq!(e.class(), "element missing from page");
+    /// assert!(matches!(e.root(), Error::CantFindElement(_)));
+    ///
+    /// // no request sent, e.g. the login failed
+    /// let e = Error::InvalidCredentialsSessionExpired.with_fetch_context(task, None);
+    /// assert_eq!(
+    ///     e.to_string(),
+    ///     "BISHAN on 2022-01-11: Invalid login credentials/session expired!"
+    /// );
+    ///
+    /// // the innermost context is kept
+    /// let badminton = FetchTask::new(Activity::BADMINTON, Gym::CLEMENTI, date.succ());
+    /// let e = Error::SessionExpired
+    ///     .with_fetch_context(badminton, Some("https://example.com".into()))
+    ///     .with_fetch_context(task, None);
+    /// assert_eq!(
+    ///     e.to_string(),
+    ///     "BADMINTON at CLEMENTI on 2022-01-12 (https://example.com): Session expired, got the login page!"
+    /// );
+    /// assert!(matches!(e.root(), Error::SessionExpired));
+    /// assert!(!e.is_retryable());
+    ///
+    /// let io = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+    /// assert_eq!(Error::from(io).root().to_string(), "Tokio file io error: connection reset");
+    /// ```
+    pub fn with_fetch_context(self, task: FetchTask, url: Option<String>) -> Self {
+        match self {
+            Self::Fetch { .. } => self,
+            e => Self::Fetch {
+                task,
+                url,
+                source: Box::new(e),
+            },
+        }
+    }
+
+    /// The error without the context of [Error::Fetch]
+    pub fn root(&self) -> &Self {
+        match self {
+            Self::Fetch { source, .. } => source.root(),
+            e => e,
+        }
+    }
+
+    /// Whether the same request may succeed when sent again, see [crate::retry]
+    ///
+    /// ActiveSG being unreachable or failing to answer is retryable, and so is a 429 unless
+    /// it asks to wait longer than [MAX_RETRY_AFTER]. Anything about the request or the page
+    /// itself isn't
+    ///
+    /// ## Example
+    /// ```
+    /// use std::time::Duration;
+    /// use activesg_gym_datamine::errors::{Error, FailurePhase};
+    /// use reqwest::StatusCode;
+    ///
+    /// let rate_limited = |secs: Option<u64>| Error::RateLimited { retry_after: secs.map(Duration::from_secs) };
+    /// assert_eq!(rate_limited(Some(30)).to_string(), "Rate limited by ActiveSG, retry after 30s");
+    /// assert_eq!(rate_limited(None).to_string(), "Rate limited by ActiveSG");
+    /// assert!(rate_limited(Some(30)).is_retryable());
+    /// assert!(rate_limited(None).is_retryable());
+    /// assert!(!rate_limited(Some(3600)).is_retryable());
+    /// // ActiveSG is up, just busy
+    /// assert_eq!(rate_limited(None).phase(), FailurePhase::Other);
+    ///
+    /// let unexpected = Error::UnexpectedStatus(StatusCode::BAD_GATEWAY);
+    /// assert_eq!(unexpected.to_string(), "Unexpected HTTP status 502 Bad Gateway");
+    /// assert_eq!(unexpected.class(), "unexpected http status");
+    /// assert!(unexpected.is_retryable());
+    /// assert!(!Error::UnexpectedStatus(StatusCode::NOT_FOUND).is_retryable());
+    /// ```
+    pub fn is_retryable(&self) -> bool {
+        match self.root() {
+            Self::RateLimited { retry_after } => {
+                retry_after.is_none_or(|after| after <= MAX_RETRY_AFTER)
+            }
+            _ => matches!(self.phase(), FailurePhase::Connect | FailurePhase::Response),
+        }
+    }
+}
+
+/// Where a failed request broke down, telling the local network apart from ActiveSG
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FailurePhase {
+    /// ActiveSG couldn't be reached at all: DNS, TCP or TLS
+    Connect,
+
+    /// ActiveSG was reached but didn't answer properly: a 5xx, or no complete response in time
+    Response,
+
+    /// anything else, e.g. a page that can't be parsed or rejected credentials
+    Other,
+}
+
+impl FailurePhase {
+    /// Phase of a request error from what reqwest reports about it
+    ///
+    /// `stalled` when the response didn't fully come in, a timeout or a body cut short.
+    /// A timeout while connecting is a connect failure
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::errors::FailurePhase::{self, *};
+    ///
+    /// // (connect, stalled, status) => phase
+    /// let table = [
+    ///     ((true, false, None), Connect),
+    ///     ((true, true, None), Connect),
+    ///     ((false, true, None), Response),
+    ///     ((false, false, Some(500)), Response),
+    ///     ((false, false, Some(503)), Response),
+    ///     ((false, true, Some(200)), Response),
+    ///     ((false, false, Some(404)), Other),
+    ///     ((false, false, Some(429)), Other),
+    ///     ((false, false, None), Other),
+    /// ];
+    /// for ((connect, stalled, status), phase) in table {
+    ///     assert_eq!(FailurePhase::of_request(connect, stalled, status), phase);
+    /// }
+    /// ```
+    pub fn of_request(connect: bool, stalled: bool, status: Option<u16>) -> Self {
+        match (connect, stalled, status) {
+            (true, _, _) => Self::Connect,
+            (_, true, _) => Self::Response,
+            (_, _, Some(status)) if (500..600).contains(&status) => Self::Response,
+            _ => Self::Other,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Connect => "connect",
+            Self::Response => "response",
+            Self::Other => "other",
+        }
+    }
 }
@@ -0,0 +1,182 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, FixedOffset, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clock::SGT,
+    diff::{SlotDiff, SnapshotDiff},
+    models::Activity,
+    venue::Venue,
+    DataMResult,
+};
+
+/// Directory of the event logs, relative to the output directory
+pub const EVENTS_DIR: &str = "events";
+
+/// What happened to the availability of a slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    /// the slot appeared on the booking page
+    Published,
+
+    /// the last slot was taken
+    Filled,
+
+    /// slots became available again after being filled
+    Reopened,
+}
+
+/// Change of availability of a single slot, one line of the event log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AvailabilityEvent {
+    pub activity: Activity,
+    pub gym: Venue,
+
+    /// start of the slot
+    pub time: DateTime<Utc>,
+    pub event: EventKind,
+
+    /// capture time of the snapshot the event was seen in
+    pub captured_at: NaiveDateTime,
+
+    /// slots available in the previous snapshot, none when published
+    pub before: Option<u16>,
+    pub after: u16,
+
+    /// the previous snapshot is older than expected, captures were missed in between
+    /// so the change may have happened any time since, and may have been undone meanwhile
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub across_gap: bool,
+}
+
+/// Line of the event log
+pub trait LoggedEvent: Serialize {
+    /// capture time of the snapshot the event was seen in, which picks the file
+    fn captured_at(&self) -> NaiveDateTime;
+}
+
+impl LoggedEvent for AvailabilityEvent {
+    fn captured_at(&self) -> NaiveDateTime {
+        self.captured_at
+    }
+}
+
+/// Availability events of the changes in `diff`, ordered by slot time
+///
+/// Captures more than `max_gap` apart mark the events [AvailabilityEvent::across_gap]
+///
+/// ## Example
+/// ```
+/// use std::time::Duration;
+/// use activesg_gym_datamine::{
+///     diff::SnapshotDiff,
+///     events::{self, EventKind},
+///     models::{Gym, GymSlotData, Timeslot},
+/// };
+/// use chrono::{NaiveDate, TimeZone, Utc};
+///
+/// let slot = Utc.ymd(2022, 1, 11).and_hms(11, 0, 0);
+/// let capture = |min, slots: &[u16]| {
+///     let at = NaiveDate::from_ymd(2022, 1, 11).and_hms(8, 0, 0) + chrono::Duration::minutes(min);
+///     let data = slots.iter().enumerate().map(|(i, s)| {
+///         Timeslot::new(slot + chrono::Duration::hours(i as i64), *s)
+///     });
+///     GymSlotData::new(Gym::BISHAN, at, data.collect())
+/// };
+///
+/// // every 20 min, then a 2 hour gap
+/// let snapshots = [
+///     capture(0, &[3]),
+///     capture(20, &[0, 5]),
+///     capture(40, &[0, 5]),
+///     capture(160, &[2, 0]),
+/// ];
+/// let max_gap = Duration::from_secs(2 * 20 * 60);
+///
+/// let stream = snapshots
+///     .windows(2)
+///     .flat_map(|w| events::detect(&SnapshotDiff::between(&w[0], &w[1]), max_gap))
+///     .map(|e| (e.time, e.event, e.before, e.after, e.across_gap))
+///     .collect::<Vec<_>>();
+///
+/// let next = slot + chrono::Duration::hours(1);
+/// assert_eq!(
+///     stream,
+///     vec![
+///         (slot, EventKind::Filled, Some(3), 0, false),
+///         (next, EventKind::Published, None, 5, false),
+///         (slot, EventKind::Reopened, Some(0), 2, true),
+///         (next, EventKind::Filled, Some(5), 0, true),
+///     ]
+/// );
+/// ```
+pub fn detect(diff: &SnapshotDiff, max_gap: Duration) -> Vec<AvailabilityEvent> {
+    let across_gap = (diff.to - diff.from)
+        .to_std()
+        .is_ok_and(|elapsed| elapsed > max_gap);
+
+    diff.changes
+        .iter()
+        .filter_map(|change| {
+            let (time, event, before, after) = match *change {
+                SlotDiff::Added { time, slots_avail } => {
+                    (time, EventKind::Published, None, slots_avail)
+                }
+                SlotDiff::Changed {
+                    time,
+                    before,
+                    after: 0,
+                } if before > 0 => (time, EventKind::Filled, Some(before), 0),
+                SlotDiff::Changed {
+                    time,
+                    before: 0,
+                    after,
+                } if after > 0 => (time, EventKind::Reopened, Some(0), after),
+                _ => return None,
+            };
+
+            Some(AvailabilityEvent {
+                activity: diff.activity,
+                gym: diff.gym,
+                time,
+                event,
+                captured_at: diff.to,
+                before,
+                after,
+                across_gap,
+            })
+        })
+        .collect()
+}
+
+/// Appends `events` as JSON lines to `<output_dir>/events/<date>.ndjson`,
+/// by Singapore date of capture, returning the bytes written
+pub fn append<E: LoggedEvent>(output_dir: &Path, events: &[E]) -> DataMResult<u64> {
+    let dir = output_dir.join(EVENTS_DIR);
+    std::fs::create_dir_all(&dir)?;
+
+    let mut written = 0;
+    for event in events {
+        let with_tz = DateTime::<FixedOffset>::from_utc(event.captured_at(), *SGT);
+        let path: PathBuf = dir.join(format!("{}.ndjson", with_tz.format("%Y-%m-%d")));
+
+        let mut line = serde_json::to_vec(event).map_err(std::io::Error::from)?;
+        line.push(b'\n');
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?
+            .write_all(&line)?;
+        written += line.len() as u64;
+    }
+
+    Ok(written)
+}
@@ -0,0 +1,198 @@
+use std::{ops::RangeInclusive, path::Path, time::Duration};
+
+use chrono::{DateTime, Utc};
+use scraper::Html;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors,
+    models::{activity_parser, venue_parser, Activity, Gym},
+    sink, DataMResult,
+};
+
+/// File the discoveries are written to, relative to the output directory
+pub const DISCOVERED_VENUES_FILENAME: &str = "discovered_venues.json";
+
+/// Most venue ids probed by a single run
+pub const MAX_PROBES: usize = 50;
+
+/// Wait between two probes, well above the politeness delay since the ids are guesses
+pub const PROBE_DELAY: Duration = Duration::from_secs(5);
+
+/// A run stops probing after this long, writing what it found so far
+pub const TIME_BOX: Duration = Duration::from_secs(10 * 60);
+
+/// Venue page as fetched, before any redirect is judged
+#[derive(Debug, Clone)]
+pub struct VenuePage {
+    pub status: u16,
+
+    /// url after redirects
+    pub url: String,
+    pub body: String,
+}
+
+/// What a venue id turned out to be
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum ProbeOutcome {
+    /// venue with a gym, bookable under `activity_id`
+    Gym { name: String, activity_id: u16 },
+
+    /// venue without a gym, e.g. a swimming complex, with the activities it offers
+    NonGym {
+        name: String,
+        activities: Vec<String>,
+    },
+
+    /// no such venue, the page was missing or redirected elsewhere
+    Missing { reason: String },
+}
+
+/// Outcome of probing a venue id, against the gyms already known
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VenueProbe {
+    pub id: u16,
+
+    #[serde(flatten)]
+    pub outcome: ProbeOutcome,
+
+    /// the gym of this id in [Gym], none for an unknown id
+    pub known: Option<Gym>,
+}
+
+impl VenueProbe {
+    pub fn new(id: u16, outcome: ProbeOutcome) -> Self {
+        let known = Gym::gym_slice().iter().copied().find(|g| *g as u16 == id);
+        Self { id, outcome, known }
+    }
+
+    /// A gym that isn't in [Gym] yet
+    pub fn is_new_gym(&self) -> bool {
+        matches!(self.outcome, ProbeOutcome::Gym { .. }) && self.known.is_none()
+    }
+
+    /// A known gym whose page no longer shows a gym
+    pub fn is_lost_gym(&self) -> bool {
+        !matches!(self.outcome, ProbeOutcome::Gym { .. }) && self.known.is_some()
+    }
+}
+
+/// Judges the venue page of `id`
+///
+/// A 404, or a redirect away from the venue page, means the id doesn't exist.
+/// An existing venue is a gym when it links to the gym activity
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::explore::{self, ProbeOutcome, VenuePage};
+///
+/// let url = |id| format!("https://members.myactivesg.com/facilities/view/venue/{}", id);
+/// let page = |id, status, body: &str| VenuePage { status, url: url(id), body: body.into() };
+///
+/// let gym = page(1101, 200, r#"<h1>Punggol ActiveSG Gym</h1>
+///     <a href="/facilities/view/activity/1031/venue/1101">Gym</a>
+///     <a href="/facilities/view/activity/18/venue/1101">Badminton</a>"#);
+/// assert_eq!(
+///     explore::classify(1101, &gym),
+///     ProbeOutcome::Gym { name: "Punggol ActiveSG Gym".into(), activity_id: 1031 }
+/// );
+///
+/// let pool = page(1102, 200, r#"<h1>Punggol Swimming Complex</h1>
+///     <a href="/facilities/view/activity/292/venue/1102">Swimming</a>"#);
+/// assert_eq!(
+///     explore::classify(1102, &pool),
+///     ProbeOutcome::NonGym { name: "Punggol Swimming Complex".into(), activities: vec!["Swimming".into()] }
+/// );
+///
+/// let missing = page(1103, 404, "<h1>Page not found</h1>");
+/// assert!(matches!(explore::classify(1103, &missing), ProbeOutcome::Missing { .. }));
+///
+/// let redirected = VenuePage {
+///     status: 200,
+///     url: "https://members.myactivesg.com/facilities".into(),
+///     body: "<h1>Facilities</h1>".into(),
+/// };
+/// let outcome = explore::classify(1104, &redirected);
+/// assert!(matches!(outcome, ProbeOutcome::Missing { reason } if reason.contains("redirected")));
+///
+/// // links to another venue don't count
+/// let empty = page(1105, 200, r#"<h1>Somewhere</h1>
+///     <a href="/facilities/view/activity/1031/venue/137">Gym</a>"#);
+/// assert!(matches!(explore::classify(1105, &empty), ProbeOutcome::NonGym { activities, .. } if activities.is_empty()));
+/// assert!(matches!(explore::classify(1106, &page(1106, 200, "")), ProbeOutcome::Missing { .. }));
+/// ```
+pub fn classify(id: u16, page: &VenuePage) -> ProbeOutcome {
+    let missing = |reason: String| ProbeOutcome::Missing { reason };
+
+    if page.status == 404 {
+        return missing("not found".into());
+    }
+    if !(200..300).contains(&page.status) {
+        return missing(format!("http status {}", page.status));
+    }
+
+    let venue_path = format!("/facilities/view/venue/{}", id);
+    if !page.url.trim_end_matches('/').ends_with(&venue_path) {
+        return missing(format!("redirected to {}", page.url));
+    }
+
+    let body = Html::parse_document(&page.body);
+    let name = match venue_parser::get_display_name(&body) {
+        Some(name) => name,
+        None => return missing("no venue name".into()),
+    };
+
+    let links = activity_parser::get_activity_links(&body, id);
+    let gym = links
+        .iter()
+        .find(|(_, text)| text.eq_ignore_ascii_case(Activity::GYM.display()));
+    match gym {
+        Some((activity_id, _)) => ProbeOutcome::Gym {
+            name,
+            activity_id: *activity_id,
+        },
+        None => ProbeOutcome::NonGym {
+            name,
+            activities: links.into_iter().map(|(_, text)| text).collect(),
+        },
+    }
+}
+
+/// Ids from `from` to `to` inclusive, refusing more than [MAX_PROBES] of them
+pub fn probe_range(from: u16, to: u16) -> DataMResult<RangeInclusive<u16>> {
+    if from > to {
+        return Err(errors::Error::InvalidConfig(format!(
+            "explore range {}..={} is empty",
+            from, to
+        )));
+    }
+
+    let count = usize::from(to - from) + 1;
+    if count > MAX_PROBES {
+        return Err(errors::Error::InvalidConfig(format!(
+            "explore range {}..={} has {} ids, at most {} are probed per run",
+            from, to, count, MAX_PROBES
+        )));
+    }
+
+    Ok(from..=to)
+}
+
+/// Result of an explore run, written to [DISCOVERED_VENUES_FILENAME]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Discoveries {
+    pub probed_at: DateTime<Utc>,
+    pub venues: Vec<VenueProbe>,
+}
+
+impl Discoveries {
+    /// Atomically writes the discoveries to `output_dir`/[DISCOVERED_VENUES_FILENAME]
+    pub fn write(&self, output_dir: &Path) -> DataMResult<()> {
+        std::fs::create_dir_all(output_dir)?;
+
+        let data = serde_json::to_vec_pretty(self).map_err(std::io::Error::from)?;
+        sink::write_atomic(&output_dir.join(DISCOVERED_VENUES_FILENAME), &data)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,612 @@
+//! Export of the archive to a hive-partitioned layout, as read by DuckDB
+//!
+//! Every timeslot of every snapshot becomes a row of a part file, of [PartFormat], e.g.
+//! `activity=gym/venue=BISHAN/date=2022-01-11/part-0.parquet`, readable with
+//! `read_parquet('export/*/*/*/*.parquet', hive_partitioning = true)`.
+//! Exports are incremental, only snapshots captured after the previous export are added
+//!
+//! ## Example
+//! ```
+//! use activesg_gym_datamine::export::{self, ExportOptions, PartFormat, METADATA_FILENAME};
+//!
+//! let dir = std::env::temp_dir().join(format!("export-doctest-{}", std::process::id()));
+//! let (archive, out) = (dir.join("output"), dir.join("duckdb"));
+//! std::fs::create_dir_all(archive.join("2022-01-11")).unwrap();
+//! let snapshot = |gym: &str, at: &str, slots: &str| {
+//!     let name = format!("{}-2022-01-11 {}.json", gym, at.replace(':', "-"));
+//!     let body = format!(r#"{{"gym":"{}","datetime":"2022-01-11T{}","data":[{}]}}"#, gym, at, slots);
+//!     std::fs::write(archive.join("2022-01-11").join(name), body).unwrap();
+//! };
+//! let slot = |hour: u32, avail: u16| {
+//!     format!(r#"{{"time":"2022-01-11T{:02}:00:00Z","slots_avail":{}}}"#, hour, avail)
+//! };
+//! snapshot("BISHAN", "05:57:33", &[slot(2, 25), slot(3, 20)].join(","));
+//! snapshot("CLEMENTI", "05:57:33", &slot(2, 3));
+//!
+//! let options = ExportOptions { format: PartFormat::Ndjson, ..Default::default() };
+//! let summary = export::run(&archive, &out, &options).unwrap();
+//! assert_eq!((summary.snapshots, summary.rows), (2, 3));
+//! assert!(out.join("activity=gym/venue=BISHAN/date=2022-01-11/part-0.ndjson").is_file());
+//! assert!(out.join(METADATA_FILENAME).is_file());
+//!
+//! let rows = |pattern: &str| -> usize {
+//!     export::glob(&out, pattern).unwrap().iter()
+//!         .map(|p| std::fs::read_to_string(p).unwrap().lines().count())
+//!         .sum()
+//! };
+//! assert_eq!(rows("*/*/*/*.ndjson"), 3);
+//! assert_eq!(rows("activity=gym/venue=BISHAN/*/*.ndjson"), 2);
+//! assert_eq!(rows("*/venue=CLEMENTI/date=2022-01-11/*.ndjson"), 1);
+//!
+//! // incremental, only the new snapshot is exported, to a new part
+//! assert_eq!(export::run(&archive, &out, &options).unwrap().rows, 0);
+//! snapshot("BISHAN", "06:17:33", &slot(2, 24));
+//! assert_eq!(export::run(&archive, &out, &options).unwrap().rows, 1);
+//! assert_eq!(rows("activity=gym/venue=BISHAN/*/*.ndjson"), 3);
+//! assert_eq!(export::glob(&out, "*/venue=BISHAN/*/part-*.ndjson").unwrap().len(), 2);
+//!
+//! // partitioned by venue only, the other columns are in the rows
+//! let by_venue = ExportOptions { partition_by: "venue".parse().unwrap(), ..options };
+//! let out = dir.join("by-venue");
+//! export::run(&archive, &out, &by_venue).unwrap();
+//! let line = std::fs::read_to_string(out.join("venue=CLEMENTI/part-0.ndjson")).unwrap();
+//! assert!(line.contains(r#""activity":"gym""#) && line.contains(r#""date":"2022-01-11""#));
+//! assert!(!line.contains("venue"));
+//!
+//! std::fs::remove_dir_all(&dir).unwrap();
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    archive::{SnapshotFilter, SnapshotReader},
+    clock::SGT,
+    errors,
+    models::GymSlotData,
+    sink, DataMResult,
+};
+
+/// Summary of every part file, written at the root of the export
+pub const METADATA_FILENAME: &str = "_metadata.json";
+
+/// Capture time of the latest snapshot exported, at the root of the export
+pub const EXPORT_STATE_FILENAME: &str = "_export_state.json";
+
+/// Column a directory level is named after, `<column>=<value>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PartitionColumn {
+    /// lowercase activity, e.g. `gym`
+    Activity,
+
+    /// gym the snapshot was captured for, e.g. `BISHAN`
+    Venue,
+
+    /// date of the slot in Singapore time
+    Date,
+}
+
+impl PartitionColumn {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Activity => "activity",
+            Self::Venue => "venue",
+            Self::Date => "date",
+        }
+    }
+}
+
+impl FromStr for PartitionColumn {
+    type Err = errors::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "activity" => Ok(Self::Activity),
+            "venue" => Ok(Self::Venue),
+            "date" => Ok(Self::Date),
+            _ => Err(errors::Error::InvalidConfig(format!(
+                "unknown partition column {}, expected activity, venue or date",
+                s
+            ))),
+        }
+    }
+}
+
+/// Partition columns in directory order, parsed from a comma separated list such as `venue,date`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionColumns(pub Vec<PartitionColumn>);
+
+impl Default for PartitionColumns {
+    fn default() -> Self {
+        Self(vec![
+            PartitionColumn::Activity,
+            PartitionColumn::Venue,
+            PartitionColumn::Date,
+        ])
+    }
+}
+
+impl FromStr for PartitionColumns {
+    type Err = errors::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut columns = vec![];
+        for column in s.split(',').filter(|c| !c.trim().is_empty()) {
+            let column = column.parse()?;
+            if columns.contains(&column) {
+                return Err(errors::Error::InvalidConfig(format!(
+                    "partition column {} given twice",
+                    column
+                )));
+            }
+            columns.push(column);
+        }
+        Ok(Self(columns))
+    }
+}
+
+impl fmt::Display for PartitionColumn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// File format of the part files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PartFormat {
+    /// a JSON object per timeslot and line, `part-<n>.ndjson`, with `captured_at`, `time`,
+    /// `slots_avail` and the columns that aren't partitions
+    Ndjson,
+
+    /// a single row group of [crate::parquet::ParquetRow], `part-<n>.parquet`, the default
+    ///
+    /// The rows keep every column, partitions included, but for `date`
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::{
+    ///     export::{self, ExportMetadata, ExportOptions, PartFormat, METADATA_FILENAME},
+    ///     models::Activity,
+    ///     parquet,
+    /// };
+    ///
+    /// let dir = std::env::temp_dir().join(format!("export-parquet-doctest-{}", std::process::id()));
+    /// let (archive, out) = (dir.join("output"), dir.join("duckdb"));
+    /// std::fs::create_dir_all(archive.join("2022-01-11")).unwrap();
+    /// let snapshot = |gym: &str, at: &str, slots: &str| {
+    ///     let name = format!("{}-2022-01-11 {}.json", gym, at.replace(':', "-"));
+    ///     let body = format!(r#"{{"gym":"{}","datetime":"2022-01-11T{}","data":[{}]}}"#, gym, at, slots);
+    ///     std::fs::write(archive.join("2022-01-11").join(name), body).unwrap();
+    /// };
+    /// let slot = |hour: u32, avail: u16| {
+    ///     format!(r#"{{"time":"2022-01-11T{:02}:00:00Z","slots_avail":{}}}"#, hour, avail)
+    /// };
+    /// snapshot("BISHAN", "05:57:33", &[slot(2, 25), slot(3, 20)].join(","));
+    /// snapshot("CLEMENTI", "05:57:33", &slot(2, 3));
+    ///
+    /// let options = ExportOptions::default();
+    /// assert_eq!(options.format, PartFormat::Parquet);
+    /// let summary = export::run(&archive, &out, &options).unwrap();
+    /// assert_eq!((summary.snapshots, summary.rows, summary.files), (2, 3, 2));
+    ///
+    /// let rows = |pattern: &str| -> Vec<parquet::ParquetRow> {
+    ///     let parts = export::glob(&out, pattern).unwrap();
+    ///     parts.iter().flat_map(|p| parquet::read(p).unwrap()).collect()
+    /// };
+    /// assert_eq!(rows("*/*/*/*.parquet").len(), 3);
+    /// let bishan = rows("activity=gym/venue=BISHAN/*/*.parquet");
+    /// let avail = bishan.iter().map(|r| r.slots_avail).collect::<Vec<_>>();
+    /// assert_eq!(avail, [25, 20]);
+    /// assert!(bishan.iter().all(|r| r.activity == Activity::GYM && r.gym_name == "Bishan ActiveSG Gym"));
+    /// assert_eq!(rows("*/venue=CLEMENTI/date=2022-01-11/*.parquet")[0].slots_avail, 3);
+    ///
+    /// // incremental, to a new part
+    /// snapshot("BISHAN", "06:17:33", &slot(2, 24));
+    /// assert_eq!(export::run(&archive, &out, &options).unwrap().rows, 1);
+    /// assert_eq!(rows("activity=gym/venue=BISHAN/*/*.parquet").len(), 3);
+    /// let parts = export::glob(&out, "*/venue=BISHAN/*/part-*.parquet").unwrap();
+    /// assert_eq!(parts.len(), 2);
+    ///
+    /// // the metadata lists every part with its rows and size
+    /// let raw = std::fs::read(out.join(METADATA_FILENAME)).unwrap();
+    /// let metadata: ExportMetadata = serde_json::from_slice(&raw).unwrap();
+    /// assert_eq!((metadata.format, metadata.rows, metadata.files.len()), (PartFormat::Parquet, 4, 3));
+    /// let part = &metadata.files[2];
+    /// assert_eq!(part.path, std::path::Path::new("activity=gym/venue=BISHAN/date=2022-01-11/part-1.parquet"));
+    /// assert_eq!(part.rows, 1);
+    /// assert_eq!(part.bytes, std::fs::metadata(&parts[1]).unwrap().len());
+    ///
+    /// // the parts of a directory are all of one format
+    /// let ndjson = ExportOptions { format: PartFormat::Ndjson, ..options };
+    /// assert!(export::run(&archive, &out, &ndjson).is_err());
+    /// std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl PartFormat {
+    pub const fn extension(&self) -> &'static str {
+        match self {
+            Self::Ndjson => "ndjson",
+            #[cfg(feature = "parquet")]
+            Self::Parquet => "parquet",
+        }
+    }
+
+    /// Format of the exports written before the format was recorded
+    fn legacy() -> Self {
+        Self::Ndjson
+    }
+}
+
+impl Default for PartFormat {
+    /// Parquet when built with the `parquet` feature
+    fn default() -> Self {
+        #[cfg(feature = "parquet")]
+        return Self::Parquet;
+        #[cfg(not(feature = "parquet"))]
+        return Self::Ndjson;
+    }
+}
+
+impl FromStr for PartFormat {
+    type Err = errors::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "ndjson" => Ok(Self::Ndjson),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(Self::Parquet),
+            #[cfg(not(feature = "parquet"))]
+            "parquet" => Err(errors::Error::InvalidConfig(
+                "built without the parquet feature".into(),
+            )),
+            _ => Err(errors::Error::InvalidConfig(format!(
+                "unknown part format {}, expected ndjson or parquet",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for PartFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// How the archive is laid out by [run]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportOptions {
+    pub partition_by: PartitionColumns,
+    pub format: PartFormat,
+
+    /// a part file is closed once it reaches this size and the next one started
+    pub target_file_bytes: u64,
+}
+
+impl ExportOptions {
+    /// 64 MiB per part file
+    pub const DEFAULT_TARGET_FILE_BYTES: u64 = 64 * 1024 * 1024;
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            partition_by: PartitionColumns::default(),
+            format: PartFormat::default(),
+            target_file_bytes: Self::DEFAULT_TARGET_FILE_BYTES,
+        }
+    }
+}
+
+/// Part file listed in the [METADATA_FILENAME]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartFile {
+    /// relative to the root of the export
+    pub path: PathBuf,
+    pub rows: u64,
+    pub bytes: u64,
+}
+
+/// Content of the [METADATA_FILENAME]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportMetadata {
+    pub exported_at: Option<DateTime<Utc>>,
+    pub partition_by: Vec<PartitionColumn>,
+
+    /// of every part file
+    #[serde(default = "PartFormat::legacy")]
+    pub format: PartFormat,
+    pub rows: u64,
+    pub files: Vec<PartFile>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct ExportState {
+    /// capture time of the latest snapshot exported, in UTC
+    last_captured: Option<NaiveDateTime>,
+}
+
+/// What a single [run] added
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExportSummary {
+    pub snapshots: usize,
+    pub rows: u64,
+    pub files: usize,
+    pub unreadable: usize,
+}
+
+fn read_json<T: Default + for<'de> Deserialize<'de>>(path: &Path) -> DataMResult<T> {
+    match std::fs::read(path) {
+        Ok(raw) => serde_json::from_slice(&raw).map_err(|e| std::io::Error::from(e).into()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> DataMResult<()> {
+    let data = serde_json::to_vec_pretty(value).map_err(std::io::Error::from)?;
+    sink::write_atomic(path, &data)?;
+    Ok(())
+}
+
+/// Rows of a partition not written yet
+#[derive(Debug, Default)]
+struct PendingPart {
+    /// lines of [PartFormat::Ndjson]
+    buf: Vec<u8>,
+
+    /// rows of [PartFormat::Parquet]
+    #[cfg(feature = "parquet")]
+    parquet: Vec<crate::parquet::ParquetRow>,
+    rows: u64,
+
+    /// size of the part file once written, estimated for Parquet
+    bytes: u64,
+}
+
+/// Writes the rows of one timeslot each into the partitions of `options`
+struct PartWriter<'a> {
+    out: &'a Path,
+    options: &'a ExportOptions,
+    pending: BTreeMap<PathBuf, PendingPart>,
+    written: Vec<PartFile>,
+}
+
+impl<'a> PartWriter<'a> {
+    fn add(&mut self, data: &GymSlotData) -> DataMResult<u64> {
+        let activity = format!("{:?}", data.activity()).to_ascii_lowercase();
+        let venue = format!("{:?}", data.gym());
+        #[cfg(feature = "parquet")]
+        let mut parquet_rows = crate::parquet::rows_of(data).into_iter();
+
+        let mut rows = 0;
+        for slot in data.data() {
+            let date = slot.time().with_timezone(&*SGT).date().naive_local();
+            let value_of = |column| match column {
+                PartitionColumn::Activity => activity.clone(),
+                PartitionColumn::Venue => venue.clone(),
+                PartitionColumn::Date => date.to_string(),
+            };
+            let partition = self
+                .options
+                .partition_by
+                .0
+                .iter()
+                .map(|c| format!("{}={}", c.name(), value_of(*c)))
+                .collect::<PathBuf>();
+
+            let pending = self.pending.entry(partition.clone()).or_default();
+            match self.options.format {
+                PartFormat::Ndjson => {
+                    let mut row = serde_json::json!({
+                        "captured_at": data.datetime(),
+                        "time": slot.time(),
+                        "slots_avail": slot.slots_avail(),
+                    });
+                    // partition columns are in the directory names only
+                    let columns = [
+                        PartitionColumn::Activity,
+                        PartitionColumn::Venue,
+                        PartitionColumn::Date,
+                    ];
+                    for column in columns {
+                        if !self.options.partition_by.0.contains(&column) {
+                            row[column.name()] = value_of(column).into();
+                        }
+                    }
+                    serde_json::to_writer(&mut pending.buf, &row).map_err(std::io::Error::from)?;
+                    pending.buf.push(b'\n');
+                    pending.bytes = pending.buf.len() as u64;
+                }
+                #[cfg(feature = "parquet")]
+                PartFormat::Parquet => {
+                    let row = parquet_rows.next().unwrap();
+                    // PLAIN encoded: a length before each text, ids and counts in 4 bytes, times in 8
+                    let texts = format!("{:?}", row.activity).len() + row.gym_name.len();
+                    pending.bytes += (texts + 2 * 4 + 2 * 4 + 2 * 8) as u64;
+                    pending.parquet.push(row);
+                }
+            }
+            pending.rows += 1;
+            rows += 1;
+
+            if pending.bytes >= self.options.target_file_bytes {
+                let pending = std::mem::take(pending);
+                self.flush(&partition, pending)?;
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Writes `pending` to the next free part file of `partition`
+    fn flush(&mut self, partition: &Path, pending: PendingPart) -> DataMResult<()> {
+        if pending.rows == 0 {
+            return Ok(());
+        }
+
+        let dir = self.out.join(partition);
+        std::fs::create_dir_all(&dir)?;
+        let name = (0..)
+            .map(|n| format!("part-{}.{}", n, self.options.format.extension()))
+            .find(|name| !dir.join(name).exists())
+            .unwrap_or_default();
+        let bytes = match self.options.format {
+            PartFormat::Ndjson => {
+                sink::write_atomic(&dir.join(&name), &pending.buf)?;
+                pending.buf.len() as u64
+            }
+            #[cfg(feature = "parquet")]
+            PartFormat::Parquet => {
+                crate::parquet::append_row_group(&dir.join(&name), &pending.parquet)?
+            }
+        };
+
+        self.written.push(PartFile {
+            path: partition.join(name),
+            rows: pending.rows,
+            bytes,
+        });
+        Ok(())
+    }
+
+    fn finish(mut self) -> DataMResult<Vec<PartFile>> {
+        for (partition, pending) in std::mem::take(&mut self.pending) {
+            self.flush(&partition, pending)?;
+        }
+        Ok(self.written)
+    }
+}
+
+/// Exports the snapshots of `archive` captured since the previous export to `out`
+///
+/// New rows always go to new part files, so a DuckDB query running meanwhile sees either
+/// all or none of a part. The [METADATA_FILENAME] and the state are updated last
+pub fn run(archive: &Path, out: &Path, options: &ExportOptions) -> DataMResult<ExportSummary> {
+    if options.partition_by.0.is_empty() {
+        return Err(errors::Error::InvalidConfig(
+            "at least one partition column is needed".into(),
+        ));
+    }
+    std::fs::create_dir_all(out)?;
+
+    let state_path = out.join(EXPORT_STATE_FILENAME);
+    let mut state = read_json::<ExportState>(&state_path)?;
+    let mut metadata = read_json::<ExportMetadata>(&out.join(METADATA_FILENAME))?;
+    if !metadata.files.is_empty() && metadata.partition_by != options.partition_by.0 {
+        return Err(errors::Error::InvalidConfig(format!(
+            "{} is partitioned by {:?}, export to another directory to change the partitions",
+            out.display(),
+            metadata.partition_by
+        )));
+    }
+    if !metadata.files.is_empty() && metadata.format != options.format {
+        return Err(errors::Error::InvalidConfig(format!(
+            "{} has {} part files, export to another directory to change the format",
+            out.display(),
+            metadata.format
+        )));
+    }
+
+    // capture dates are in Singapore time, so a day earlier than the last capture in UTC
+    let filter = SnapshotFilter {
+        from: state.last_captured.map(|c| c.date().pred()),
+        ..Default::default()
+    };
+
+    let mut writer = PartWriter {
+        out,
+        options,
+        pending: BTreeMap::new(),
+        written: vec![],
+    };
+    let mut summary = ExportSummary::default();
+    let mut last_captured = state.last_captured;
+    // the output may be inside the archive, its part files aren't snapshots anyway
+    for res in SnapshotReader::new(archive, filter) {
+        let snapshot = match res {
+            Ok(s) if s.path.starts_with(out) => continue,
+            Ok(s) => s,
+            Err(e) => {
+                warn!("{}", e);
+                summary.unreadable += 1;
+                continue;
+            }
+        };
+
+        let captured = snapshot.data.datetime();
+        if matches!(state.last_captured, Some(last) if captured <= last) {
+            continue;
+        }
+        summary.rows += writer.add(&snapshot.data)?;
+        summary.snapshots += 1;
+        last_captured = last_captured.max(Some(captured));
+    }
+
+    let written = writer.finish()?;
+    summary.files = written.len();
+    metadata.exported_at = Some(Utc::now());
+    metadata.partition_by = options.partition_by.0.clone();
+    metadata.format = options.format;
+    metadata.rows += summary.rows;
+    metadata.files.extend(written);
+    write_json(&out.join(METADATA_FILENAME), &metadata)?;
+
+    state.last_captured = last_captured;
+    write_json(&state_path, &state)?;
+    Ok(summary)
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of characters
+fn matches_segment(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let name = match name.strip_prefix(prefix) {
+                Some(name) => name,
+                None => return false,
+            };
+            (0..=name.len())
+                .filter(|i| name.is_char_boundary(*i))
+                .any(|i| matches_segment(rest, &name[i..]))
+        }
+    }
+}
+
+/// Files under `root` matching `pattern`, a `/` separated path where `*` matches
+/// within a single level, as DuckDB resolves its globs. Sorted by path
+pub fn glob(root: &Path, pattern: &str) -> DataMResult<Vec<PathBuf>> {
+    let mut found = vec![root.to_path_buf()];
+    for segment in pattern.split('/').filter(|s| !s.is_empty()) {
+        let mut next = vec![];
+        for dir in found.iter().filter(|d| d.is_dir()) {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                let name = path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or_default();
+                if matches_segment(segment, name) {
+                    next.push(path);
+                }
+            }
+        }
+        found = next;
+    }
+
+    found.retain(|p| p.is_file());
+    found.sort();
+    Ok(found)
+}
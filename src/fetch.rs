@@ -0,0 +1,438 @@
+use std::{fmt, future::Future, sync::Arc};
+
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use reqwest::header::HeaderMap;
+use scraper::Html;
+use tracing::{debug, warn};
+
+use crate::{
+    clock::{Clock, SharedClock, SystemClock},
+    config::Config,
+    headers, hours,
+    models::{venue_parser, Activity, Gym, GymSlotData, Timeslot},
+    skew,
+    venue::Venue,
+    DataMResult,
+};
+
+/// Raw booking page as returned by a [Fetcher]
+#[derive(Debug, Clone)]
+pub struct BookingPage {
+    /// url of the page after redirects
+    pub url: String,
+
+    /// response headers of the page
+    pub headers: HeaderMap,
+
+    /// html body of the page
+    pub body: String,
+
+    /// bytes of response bodies downloaded to obtain this page, including logins
+    pub bytes_downloaded: u64,
+
+    /// page didn't come from ActiveSG, see [crate::models::SnapshotMetadata::synthetic]
+    pub synthetic: bool,
+
+    /// page was browsed while logged in, see [crate::models::SnapshotMetadata::authenticated]
+    pub authenticated: bool,
+}
+
+impl BookingPage {
+    pub fn new<S: Into<String>>(url: S, headers: HeaderMap, body: S) -> Self {
+        let body = body.into();
+        Self {
+            url: url.into(),
+            headers,
+            bytes_downloaded: body.len() as u64,
+            body,
+            synthetic: false,
+            authenticated: true,
+        }
+    }
+}
+
+/// A single booking page to be fetched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FetchTask {
+    pub activity: Activity,
+    pub gym: Venue,
+    pub date: NaiveDate,
+}
+
+impl FetchTask {
+    pub fn new<V: Into<Venue>>(activity: Activity, gym: V, date: NaiveDate) -> Self {
+        Self {
+            activity,
+            gym: gym.into(),
+            date,
+        }
+    }
+}
+
+/// `BISHAN on 2022-01-11`, `BADMINTON at BISHAN on 2022-01-11` for other activities
+impl fmt::Display for FetchTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.activity {
+            Activity::GYM => write!(f, "{:?} on {}", self.gym, self.date),
+            activity => write!(f, "{:?} at {:?} on {}", activity, self.gym, self.date),
+        }
+    }
+}
+
+/// Source of booking pages
+///
+/// [crate::client::DataMiner] is the implementation that talks to ActiveSG,
+/// [FakeFetcher] serves canned pages without touching the network
+pub trait Fetcher {
+    /// Fetches the booking page described by `task`, logging in if required
+    fn fetch_booking_page(
+        &self,
+        task: FetchTask,
+    ) -> impl Future<Output = DataMResult<BookingPage>> + Send;
+
+    /// Current time, which the fetched pages are timestamped with
+    fn now(&self) -> DateTime<Utc> {
+        SystemClock.now()
+    }
+}
+
+/// Fetches and parses the timeslots described by `task`
+///
+/// The snapshot is returned rather than written, as by the `query` command
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     config::Config,
+///     errors::Error,
+///     fetch::{self, FakeFetcher, FetchTask},
+///     models::{Activity, Gym, User},
+///     venue::Venue,
+/// };
+/// use chrono::{NaiveDate, TimeZone, Utc};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let fetcher = FakeFetcher::new(|_| {
+///     Ok(r#"<div class="chkbox-grid"><label>07:00 AM</label><label>25 Left</label></div>"#.into())
+/// });
+/// let config = Config::new(User::new("user@example.com", "password"));
+///
+/// let date = NaiveDate::from_ymd(2022, 1, 11);
+/// let task = FetchTask::new(Activity::GYM, Gym::BISHAN, date);
+/// let data = fetch::fetch_slots(&fetcher, &config, task).await.unwrap();
+/// assert_eq!(data.gym(), Venue::from(Gym::BISHAN));
+/// assert_eq!(data.data().len(), 1);
+/// assert_eq!(data.data()[0].time(), Utc.ymd(2022, 1, 10).and_hms(23, 0, 0));
+/// assert_eq!(data.data()[0].slots_avail(), 25);
+///
+/// // failures tell which fetch it was
+/// let failing = FakeFetcher::new(|_| Err(Error::SessionExpired));
+/// let e = fetch::fetch_slots(&failing, &config, task).await.unwrap_err();
+/// assert_eq!(e.to_string(), "BISHAN on 2022-01-11: Session expired, got the login page!");
+/// # }
+/// ```
+pub async fn fetch_slots<F>(
+    fetcher: &F,
+    config: &Config,
+    task: FetchTask,
+) -> DataMResult<GymSlotData>
+where
+    F: Fetcher + Sync,
+{
+    let page = fetcher
+        .fetch_booking_page(task)
+        .await
+        .map_err(|e| e.with_fetch_context(task, None))?;
+    let now = fetcher.now();
+    let body = Html::parse_document(&page.body);
+    let (res, mut parse_warnings) = match (task.activity.books_courts(), page.authenticated) {
+        (true, _) => (
+            Timeslot::parse_court_timeslots(&body, task.date),
+            Timeslot::invalid_slot_counts(&body),
+        ),
+        (false, true) => Timeslot::parse_timeslots_with_warnings(&body, task.date),
+        (false, false) => (
+            Timeslot::parse_anonymous_timeslots(&body, task.date),
+            Timeslot::invalid_slot_counts(&body),
+        ),
+    };
+
+    let venue_display_name = venue_parser::get_display_name(&body);
+    if let Some(name) = &venue_display_name {
+        if !task.gym.matches_display_name(name) {
+            parse_warnings += 1;
+            // an early sign that the venue id was reassigned
+            warn!(
+                "{:?} booking page is titled {:?}, expected {:?}",
+                task.gym,
+                name,
+                task.gym.display()
+            );
+        }
+    }
+
+    debug!("{:?}", &res);
+    // the opening hours of a custom venue aren't known
+    let (res, suppressed) = match (task.activity, task.gym.gym(), config.keep_out_of_hours) {
+        (Activity::GYM, Some(gym), false) => {
+            let hours = config.opening_hours_of(gym);
+            let (res, suppressed) = hours::filter_opening_hours(res, hours, task.date.weekday());
+            (res, Some(suppressed))
+        }
+        _ => (res, None),
+    };
+
+    let cutoff = chrono::Duration::from_std(config.booking_cutoff)
+        .unwrap_or_else(|_| chrono::Duration::zero());
+    let res = res
+        .into_iter()
+        .map(|mut s| {
+            s.mut_past_cutoff(Timeslot::is_past_cutoff(now, s.time(), cutoff));
+            if config.slot_ids {
+                s.mut_slot_id(Some(Timeslot::slot_id_of(
+                    task.activity,
+                    task.gym,
+                    s.time(),
+                )));
+            }
+            s
+        })
+        .collect();
+
+    let mut data = GymSlotData::new(task.gym, now.naive_utc(), res)
+        .with_activity(task.activity)
+        .with_gym_name(config.gym_names)
+        .with_venue_display_name(venue_display_name);
+    data.mut_metadata().out_of_hours_suppressed = suppressed;
+    data.mut_metadata().parse_warnings = (parse_warnings > 0).then_some(parse_warnings);
+    data.mut_metadata().bytes_downloaded = Some(page.bytes_downloaded);
+    data.mut_metadata().synthetic = page.synthetic;
+    data.mut_metadata().authenticated = (!page.authenticated).then_some(false);
+    data.mut_metadata().clock_skew_secs =
+        skew::measure_skew(&page.headers, now).map(|s| s.num_seconds());
+    data.mut_metadata().headers = headers::capture_headers(&page.headers, &config.captured_headers);
+
+    Ok(data)
+}
+
+/// Outcome of [check_venue]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VenueCheck {
+    /// the page title refers to the gym
+    Match(String),
+
+    /// the page title refers to another venue, the venue id was likely reassigned
+    Mismatch(String),
+
+    /// no title found on the page
+    Unknown,
+}
+
+/// Fetches the booking page of `gym` on `date` and compares its title against [Gym::display]
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     client::DataMiner,
+///     fetch::{self, VenueCheck},
+///     models::{Gym, User},
+/// };
+/// use chrono::NaiveDate;
+/// use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// // venue 137 is still Bishan, Clementi's id now leads to another gym
+/// let booking_page = |path: &str| {
+///     let title = match path {
+///         p if p.starts_with("/facilities/view/activity/1031/venue/137?") => "Bishan ActiveSG Gym",
+///         p if p.contains(&format!("/venue/{}?", Gym::CLEMENTI as u16)) => "Jurong East ActiveSG Gym",
+///         _ => return None,
+///     };
+///     Some(format!(
+///         r#"<html><body><h1 class="venue-title">{}</h1><div class="chkbox-grid"></div></body></html>"#,
+///         title
+///     ))
+/// };
+/// let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+/// let url = format!("http://{}", listener.local_addr().unwrap());
+/// tokio::spawn(async move {
+///     loop {
+///         let (mut socket, _) = listener.accept().await.unwrap();
+///         let mut request = vec![];
+///         let mut buf = [0; 1024];
+///         while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+///             let n = socket.read(&mut buf).await.unwrap();
+///             request.extend_from_slice(&buf[..n]);
+///         }
+///         let request = String::from_utf8_lossy(&request);
+///         let path = request.split(' ').nth(1).unwrap();
+///         // the venue pages aren't needed, the default activity id is used without them
+///         let (status, body) = match path.starts_with("/facilities/view/venue/") {
+///             true => ("404 Not Found", String::new()),
+///             false => ("200 OK", booking_page(path).unwrap_or_else(|| "<html></html>".into())),
+///         };
+///         let response = format!(
+///             "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+///             status,
+///             body.len(),
+///             body
+///         );
+///         socket.write_all(response.as_bytes()).await.unwrap();
+///     }
+/// });
+///
+/// let miner = DataMiner::new(User::new("", "")).with_anonymous(true).with_base_url(&url);
+/// let date = NaiveDate::from_ymd(2022, 1, 11);
+/// assert_eq!(
+///     fetch::check_venue(&miner, Gym::BISHAN, date).await.unwrap(),
+///     VenueCheck::Match("Bishan ActiveSG Gym".into())
+/// );
+/// assert_eq!(
+///     fetch::check_venue(&miner, Gym::CLEMENTI, date).await.unwrap(),
+///     VenueCheck::Mismatch("Jurong East ActiveSG Gym".into())
+/// );
+/// assert_eq!(
+///     fetch::check_venue(&miner, Gym::TAMPINES, date).await.unwrap(),
+///     VenueCheck::Unknown
+/// );
+/// # }
+/// ```
+pub async fn check_venue<F>(fetcher: &F, gym: Gym, date: NaiveDate) -> DataMResult<VenueCheck>
+where
+    F: Fetcher + Sync,
+{
+    let page = fetcher
+        .fetch_booking_page(FetchTask::new(Activity::GYM, gym, date))
+        .await?;
+    let name = venue_parser::get_display_name(&Html::parse_document(&page.body));
+
+    Ok(match name {
+        Some(name) if gym.matches_display_name(&name) => VenueCheck::Match(name),
+        Some(name) => VenueCheck::Mismatch(name),
+        None => VenueCheck::Unknown,
+    })
+}
+
+/// [Fetcher] that serves pages generated by a closure
+///
+/// Useful for testing and for running the pipeline against captured html
+pub struct FakeFetcher<F> {
+    page_fn: F,
+    anonymous: bool,
+    clock: SharedClock,
+}
+
+impl<F> FakeFetcher<F>
+where
+    F: Fn(FetchTask) -> DataMResult<String> + Send + Sync,
+{
+    pub fn new(page_fn: F) -> Self {
+        Self {
+            page_fn,
+            anonymous: false,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Timestamps the pages with `clock` instead of the system time
+    ///
+    /// ## Example
+    /// ```
+    /// use std::sync::Arc;
+    /// use activesg_gym_datamine::{
+    ///     clock::FakeClock,
+    ///     config::Config,
+    ///     fetch::{self, FakeFetcher, FetchTask},
+    ///     models::{Activity, Gym, User},
+    /// };
+    /// use chrono::{NaiveDate, TimeZone, Utc};
+    ///
+    /// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+    /// # async fn main() {
+    /// let page = r#"<div class="chkbox-grid">
+    ///     <label>10:00 AM</label><label>25 Left</label><label>11:00 AM</label><label>3 Left</label>
+    /// </div>"#;
+    /// let config = Config::new(User::new("user@example.com", "password"));
+    /// let task = FetchTask::new(Activity::GYM, Gym::BISHAN, NaiveDate::from_ymd(2022, 1, 11));
+    ///
+    /// // 10:20 SGT, past the cut-off of the 10:00 slot but not of the 11:00 one
+    /// let clock = FakeClock::new(Utc.ymd(2022, 1, 11).and_hms(2, 20, 0));
+    /// let fetcher = FakeFetcher::new(|_| Ok(page.to_string())).with_clock(Arc::new(clock.clone()));
+    ///
+    /// let data = fetch::fetch_slots(&fetcher, &config, task).await.unwrap();
+    /// assert_eq!(data.datetime(), NaiveDate::from_ymd(2022, 1, 11).and_hms(2, 20, 0));
+    /// let past = data.data().iter().map(|s| s.past_cutoff()).collect::<Vec<_>>();
+    /// assert_eq!(past, vec![true, false]);
+    ///
+    /// clock.advance(chrono::Duration::minutes(20));
+    /// let data = fetch::fetch_slots(&fetcher, &config, task).await.unwrap();
+    /// let past = data.data().iter().map(|s| s.past_cutoff()).collect::<Vec<_>>();
+    /// assert_eq!(past, vec![true, true]);
+    /// # }
+    /// ```
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Serves the pages as if browsed without logging in
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::{
+    ///     config::Config,
+    ///     fetch::{self, FakeFetcher, FetchTask},
+    ///     models::{Activity, Gym, User},
+    /// };
+    /// use chrono::NaiveDate;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let page = r#"<div class="chkbox-grid">
+    ///     <label>10:00 AM</label><label>25 Left</label><label>11:00 AM</label>
+    /// </div>"#;
+    /// let mut config = Config::new(User::new("user@example.com", "password"));
+    /// config.keep_out_of_hours = true;
+    /// let task = FetchTask::new(Activity::GYM, Gym::BISHAN, NaiveDate::from_ymd(2022, 1, 11));
+    ///
+    /// let fetcher = FakeFetcher::new(|_| Ok(page.to_string())).with_anonymous(true);
+    /// let data = fetch::fetch_slots(&fetcher, &config, task).await.unwrap();
+    /// assert_eq!(data.metadata().authenticated, Some(false));
+    /// assert!(!data.metadata().is_authenticated());
+    /// let hidden = data.data().iter().map(|s| s.count_hidden()).collect::<Vec<_>>();
+    /// assert_eq!(hidden, vec![false, true]);
+    ///
+    /// let fetcher = FakeFetcher::new(|_| Ok(page.to_string()));
+    /// let data = fetch::fetch_slots(&fetcher, &config, task).await.unwrap();
+    /// assert_eq!(data.metadata().authenticated, None);
+    /// assert_eq!(data.data().len(), 1);
+    /// # }
+    /// ```
+    pub fn with_anonymous(mut self, anonymous: bool) -> Self {
+        self.anonymous = anonymous;
+        self
+    }
+}
+
+impl<F> Fetcher for FakeFetcher<F>
+where
+    F: Fn(FetchTask) -> DataMResult<String> + Send + Sync,
+{
+    async fn fetch_booking_page(&self, task: FetchTask) -> DataMResult<BookingPage> {
+        let body = (self.page_fn)(task)?;
+        let mut page = BookingPage::new(
+            format!("fake://{:?}/{:?}/{}", task.activity, task.gym, task.date),
+            HeaderMap::new(),
+            body,
+        );
+        page.synthetic = true;
+        page.authenticated = !self.anonymous;
+        Ok(page)
+    }
+
+    fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+}
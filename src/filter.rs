@@ -0,0 +1,319 @@
+//! Which snapshots each sink receives
+//!
+//! A [SinkFilter] is configured per sink, by [Sink::name], under `[filters.<sink>]`
+//! of the config file. The sink is wrapped in a [Filtered] sink, which evaluates the filter
+//! on every delivery and records the snapshots it kept from the sink in the [Delivery]
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{
+    cache::SnapshotKey,
+    clock::SGT,
+    config::Config,
+    diff::SnapshotDiff,
+    gymset::{GymGroups, GymSelection},
+    models::{Activity, Gym, GymSlotData},
+    sink::{Delivery, PublishKey, Sink},
+    venue::Venue,
+    DataMResult,
+};
+
+/// Snapshots delivered to a sink, every snapshot by default
+///
+/// ## Example
+/// ```toml
+/// [filters.file]
+/// activities = ["GYM"]
+/// gyms = "BISHAN, CLEMENTI"
+/// day_offsets = [0, 1]
+/// min_available = 1
+/// only_when_changed = true
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SinkFilter {
+    /// activities delivered, every activity when empty
+    pub activities: Vec<Activity>,
+
+    /// gyms delivered as a gym set, see [crate::gymset], every gym when empty
+    #[serde(skip_serializing_if = "GymSelection::is_empty")]
+    pub gyms: GymSelection,
+
+    /// days from the Singapore date of capture of the booking pages delivered,
+    /// every queried date when empty
+    pub day_offsets: Vec<i64>,
+
+    /// minimum slots left over the whole snapshot
+    pub min_available: u32,
+
+    /// only snapshots that differ from the last one delivered to the sink
+    pub only_when_changed: bool,
+
+    /// [SinkFilter::gyms] resolved by [SinkFilter::resolve_gyms]
+    #[serde(skip)]
+    venues: Option<BTreeSet<Gym>>,
+}
+
+impl SinkFilter {
+    pub fn admits_activity(&self, activity: Activity) -> bool {
+        self.activities.is_empty() || self.activities.contains(&activity)
+    }
+
+    /// Resolves [SinkFilter::gyms] once, so that groups can be referred to
+    pub fn resolve_gyms(&mut self, groups: &GymGroups) -> DataMResult<()> {
+        self.venues = match self.gyms.is_empty() {
+            true => None,
+            false => Some(self.gyms.resolve(groups)?),
+        };
+        Ok(())
+    }
+
+    /// Custom venues are only admitted when no gyms are selected
+    pub fn admits_gym(&self, gym: Venue) -> bool {
+        if self.gyms.is_empty() {
+            return true;
+        }
+        let gym = match gym.gym() {
+            Some(gym) => gym,
+            None => return false,
+        };
+
+        match &self.venues {
+            Some(venues) => venues.contains(&gym),
+            // not resolved, without groups
+            None => self
+                .gyms
+                .resolve(&GymGroups::new())
+                .is_ok_and(|v| v.contains(&gym)),
+        }
+    }
+}
+
+/// Why a snapshot wasn't delivered to a sink
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    Activity,
+    Gym,
+    Date,
+    Availability,
+    Unchanged,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Activity => "activity not selected",
+            Self::Gym => "gym not selected",
+            Self::Date => "date not selected",
+            Self::Availability => "not enough slots left",
+            Self::Unchanged => "unchanged",
+        })
+    }
+}
+
+/// Why `filter` keeps `snapshot` of `key` from its sink, none when it is delivered
+///
+/// `previous` is the last snapshot of the same gym and date delivered to the sink
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     filter::{self, Rejection, SinkFilter},
+///     models::{Activity, Gym, GymSlotData, Timeslot},
+///     sink::PublishKey,
+/// };
+/// use chrono::{NaiveDate, TimeZone, Utc};
+///
+/// let date = NaiveDate::from_ymd(2022, 1, 11);
+/// // 09:30 SGT on the day before
+/// let captured = NaiveDate::from_ymd(2022, 1, 10).and_hms(1, 30, 0);
+/// let snapshot = |gym, slots: &[u16]| {
+///     let slots = slots
+///         .iter()
+///         .enumerate()
+///         .map(|(h, n)| Timeslot::new(Utc.ymd(2022, 1, 11).and_hms(h as u32, 0, 0), *n))
+///         .collect();
+///     GymSlotData::new(gym, captured, slots)
+/// };
+/// let key = |gym| PublishKey::new(Activity::GYM, gym, date, 42);
+/// let badminton_key = PublishKey::new(Activity::BADMINTON, Gym::BISHAN, date, 42);
+///
+/// let mut watched = SinkFilter::default();
+/// watched.gyms = "BISHAN, CLEMENTI".into();
+/// watched.day_offsets = vec![1];
+/// watched.min_available = 5;
+/// watched.only_when_changed = true;
+/// watched.resolve_gyms(&Default::default()).unwrap();
+///
+/// let mut today_only = SinkFilter::default();
+/// today_only.day_offsets = vec![0];
+/// let everything = SinkFilter::default();
+/// let mut gyms_only = SinkFilter::default();
+/// gyms_only.activities = vec![Activity::GYM];
+///
+/// let bishan = snapshot(Gym::BISHAN, &[3, 4]);
+/// let filled = snapshot(Gym::BISHAN, &[0, 4]);
+/// let table = [
+///     // filter, gym, snapshot, previous => rejection
+///     (&everything, Gym::TAMPINES, snapshot(Gym::TAMPINES, &[]), None, None),
+///     (&watched, Gym::TAMPINES, snapshot(Gym::TAMPINES, &[25]), None, Some(Rejection::Gym)),
+///     (&today_only, Gym::BISHAN, bishan.clone(), None, Some(Rejection::Date)),
+///     (&watched, Gym::BISHAN, bishan.clone(), None, None),
+///     (&watched, Gym::BISHAN, filled.clone(), None, Some(Rejection::Availability)),
+///     (&watched, Gym::BISHAN, bishan.clone(), Some(&bishan), Some(Rejection::Unchanged)),
+///     (&watched, Gym::BISHAN, bishan.clone(), Some(&filled), None),
+///     (&everything, Gym::BISHAN, bishan.clone(), Some(&bishan), None),
+/// ];
+/// for (filter, gym, snapshot, previous, rejection) in table {
+///     assert_eq!(filter::evaluate(filter, &key(gym), &snapshot, previous), rejection);
+/// }
+///
+/// // other activities of a venue are told apart by the key
+/// let courts = bishan.clone().with_activity(Activity::BADMINTON);
+/// assert_eq!(filter::evaluate(&gyms_only, &key(Gym::BISHAN), &bishan, None), None);
+/// assert_eq!(
+///     filter::evaluate(&gyms_only, &badminton_key, &courts, None),
+///     Some(Rejection::Activity)
+/// );
+/// assert_eq!(filter::evaluate(&everything, &badminton_key, &courts, None), None);
+/// ```
+pub fn evaluate(
+    filter: &SinkFilter,
+    key: &PublishKey,
+    snapshot: &GymSlotData,
+    previous: Option<&GymSlotData>,
+) -> Option<Rejection> {
+    if !filter.admits_activity(key.activity) {
+        return Some(Rejection::Activity);
+    }
+    if !filter.admits_gym(key.gym) {
+        return Some(Rejection::Gym);
+    }
+
+    let captured = (snapshot.datetime() + *SGT).date();
+    let offset = (key.date - captured).num_days();
+    if !filter.day_offsets.is_empty() && !filter.day_offsets.contains(&offset) {
+        return Some(Rejection::Date);
+    }
+
+    let available: u32 = snapshot
+        .data()
+        .iter()
+        .map(|s| u32::from(s.slots_avail()))
+        .sum();
+    if available < filter.min_available {
+        return Some(Rejection::Availability);
+    }
+
+    let unchanged = previous.is_some_and(|p| SnapshotDiff::between(p, snapshot).is_empty());
+    if filter.only_when_changed && unchanged {
+        return Some(Rejection::Unchanged);
+    }
+
+    None
+}
+
+/// Delivers to `S` only the snapshots its [SinkFilter] admits
+///
+/// Only [Sink::deliver] and [Sink::publish_diff] are filtered, a plain [Sink::publish]
+/// goes through as is
+#[derive(Debug)]
+pub struct Filtered<S> {
+    sink: S,
+    filter: SinkFilter,
+
+    /// last snapshot delivered of every gym and date, kept for [SinkFilter::only_when_changed]
+    delivered: Mutex<HashMap<SnapshotKey, GymSlotData>>,
+}
+
+impl<S> Filtered<S>
+where
+    S: Sink,
+{
+    pub fn new(sink: S, filter: SinkFilter) -> Self {
+        Self {
+            sink,
+            filter,
+            delivered: Mutex::default(),
+        }
+    }
+
+    /// Filters `sink` with the filter of its name in [Config::sink_filters],
+    /// every snapshot goes through without one
+    pub fn from_config(sink: S, config: &Config) -> Self {
+        let filter = config
+            .sink_filters
+            .get(sink.name())
+            .cloned()
+            .unwrap_or_default();
+        Self::new(sink, filter)
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.sink
+    }
+}
+
+impl<S> Sink for Filtered<S>
+where
+    S: Sink + Send + Sync,
+{
+    fn name(&self) -> &str {
+        self.sink.name()
+    }
+
+    async fn publish(&self, data: &GymSlotData) -> DataMResult<u64> {
+        self.sink.publish(data).await
+    }
+
+    async fn publish_diff(&self, diff: &SnapshotDiff) -> DataMResult<u64> {
+        match self.filter.admits_activity(diff.activity) && self.filter.admits_gym(diff.gym) {
+            true => self.sink.publish_diff(diff).await,
+            false => Ok(0),
+        }
+    }
+
+    async fn publish_keyed(&self, key: &PublishKey, data: &GymSlotData) -> DataMResult<u64> {
+        self.sink.publish_keyed(key, data).await
+    }
+
+    async fn flush(&self) -> DataMResult<u64> {
+        self.sink.flush().await
+    }
+
+    /// Records the snapshot as filtered out rather than delivered when the filter rejects it
+    async fn deliver(
+        &self,
+        key: &PublishKey,
+        data: &GymSlotData,
+        delivery: &mut Delivery,
+    ) -> DataMResult<()> {
+        if delivery.is_delivered(self.name()) || delivery.is_filtered(self.name()) {
+            return Ok(());
+        }
+
+        let snapshot_key = (key.activity, key.gym, key.date);
+        let previous = self.delivered.lock().unwrap().get(&snapshot_key).cloned();
+        if let Some(rejection) = evaluate(&self.filter, key, data, previous.as_ref()) {
+            debug!("{}, {} filtered out: {}", self.name(), key, rejection);
+            delivery.mark_filtered(self.name());
+            return Ok(());
+        }
+
+        self.sink.deliver(key, data, delivery).await?;
+        if self.filter.only_when_changed {
+            self.delivered
+                .lock()
+                .unwrap()
+                .insert(snapshot_key, data.clone());
+        }
+        Ok(())
+    }
+}
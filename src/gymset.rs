@@ -0,0 +1,349 @@
+//! Symbolic sets of gyms, accepted wherever a list of gyms is
+//!
+//! ```text
+//! list := expr (',' expr)*           union
+//! expr := atom (('-' | '+') atom)*   difference and union, left to right
+//! atom := '(' list ')' | all | none | region:REGION | group:NAME | GYM
+//! ```
+//!
+//! `-` and `+` bind tighter than `,`, so `all - TAMPINES, PASIR_RIS` is every gym but
+//! Tampines, Pasir Ris included. Group names may contain `-`, so a `-` directly following
+//! a group name is part of it, put a space before the operator
+//!
+//! ## Example
+//! ```
+//! use std::collections::BTreeMap;
+//! use activesg_gym_datamine::{
+//!     gymset::{self, GymSelection},
+//!     models::{Gym, Region},
+//! };
+//!
+//! let groups = BTreeMap::from([
+//!     ("near-home".to_string(), GymSelection::from("BISHAN, TOA_PAYOH")),
+//!     ("weekend".to_string(), GymSelection::from("group:near-home + CLEMENTI")),
+//! ]);
+//! let parse = |expr| gymset::parse(expr, &groups);
+//!
+//! assert_eq!(parse("all").unwrap().len(), Gym::gym_slice().len());
+//! assert!(parse("none").unwrap().is_empty());
+//! assert_eq!(parse("BISHAN,CLEMENTI").unwrap().len(), 2);
+//!
+//! let east = parse("region:EAST").unwrap();
+//! assert!(east.iter().all(|g| g.region() == Region::EAST));
+//! assert!(east.contains(&Gym::TAMPINES));
+//!
+//! let west = parse("all - (TAMPINES, PASIR_RIS)").unwrap();
+//! assert!(!west.contains(&Gym::TAMPINES) && !west.contains(&Gym::PASIR_RIS));
+//! assert_eq!(west.len(), Gym::gym_slice().len() - 2);
+//!
+//! // `-` binds tighter than `,`
+//! assert!(parse("all - TAMPINES, TAMPINES").unwrap().contains(&Gym::TAMPINES));
+//! // left to right
+//! assert!(parse("BISHAN - BISHAN + BISHAN").unwrap().contains(&Gym::BISHAN));
+//! assert!(parse("BISHAN - (BISHAN + BISHAN)").unwrap().is_empty());
+//! // whitespace doesn't matter
+//! assert_eq!(parse("  all-(TAMPINES ,PASIR_RIS)  ").unwrap(), west);
+//!
+//! let weekend = parse("group:weekend - TOA_PAYOH").unwrap();
+//! assert_eq!(weekend.into_iter().collect::<Vec<_>>(), vec![Gym::BISHAN, Gym::CLEMENTI]);
+//! assert!(parse("region:EAST - region:EAST").unwrap().is_empty());
+//!
+//! let err = parse("all - (TAMPINES, PASIR_RIZ)").unwrap_err().to_string();
+//! assert!(err.contains("unknown gym PASIR_RIZ at column 18"), "{}", err);
+//! assert!(err.contains("valid gyms are AMK_CC, FERNVALE_SQ,"), "{}", err);
+//! assert!(parse("group:far").unwrap_err().to_string().contains("unknown group far"));
+//! assert!(parse("region:MARS").is_err());
+//! assert!(parse("(BISHAN").unwrap_err().to_string().contains("expected )"));
+//! assert!(parse("BISHAN)").is_err());
+//! assert!(parse("BISHAN -").is_err());
+//! assert!(parse("").is_err());
+//!
+//! let cyclic = BTreeMap::from([("a".to_string(), GymSelection::from("group:a"))]);
+//! assert!(gymset::parse("group:a", &cyclic).unwrap_err().to_string().contains("cycle"));
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors,
+    models::{Gym, Region},
+    DataMResult,
+};
+
+/// Named gym sets that expressions refer to with `group:NAME`
+pub type GymGroups = BTreeMap<String, GymSelection>;
+
+/// Gyms as written in a config file, either a single expression
+/// or a list whose expressions are unioned, e.g. `["BISHAN", "region:EAST"]`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum GymSelection {
+    Expr(String),
+    List(Vec<String>),
+}
+
+impl Default for GymSelection {
+    fn default() -> Self {
+        Self::List(vec![])
+    }
+}
+
+impl From<&str> for GymSelection {
+    fn from(expr: &str) -> Self {
+        Self::Expr(expr.into())
+    }
+}
+
+impl GymSelection {
+    /// An empty list, which some callers take as every gym
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Self::List(l) if l.is_empty())
+    }
+
+    pub fn resolve(&self, groups: &GymGroups) -> DataMResult<BTreeSet<Gym>> {
+        Resolver {
+            groups,
+            visiting: vec![],
+        }
+        .resolve(self)
+    }
+}
+
+/// Parses `expr` into the gyms it refers to, see the [module docs](self)
+pub fn parse(expr: &str, groups: &GymGroups) -> DataMResult<BTreeSet<Gym>> {
+    Resolver {
+        groups,
+        visiting: vec![],
+    }
+    .parse(expr)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    Comma,
+    Minus,
+    Plus,
+    Word(String),
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Open => "(".into(),
+            Token::Close => ")".into(),
+            Token::Comma => ",".into(),
+            Token::Minus => "-".into(),
+            Token::Plus => "+".into(),
+            Token::Word(w) => w.clone(),
+        }
+    }
+}
+
+/// Splits `expr` into tokens with the 0 based column they start at
+fn tokenize(expr: &str) -> Result<Vec<(usize, Token)>, String> {
+    let chars = expr.char_indices().collect::<Vec<_>>();
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == ':';
+
+    let mut buf = vec![];
+    let mut i = 0;
+    while let Some(&(col, c)) = chars.get(i) {
+        let token = match c {
+            c if c.is_whitespace() => None,
+            '(' => Some(Token::Open),
+            ')' => Some(Token::Close),
+            ',' => Some(Token::Comma),
+            '-' => Some(Token::Minus),
+            '+' => Some(Token::Plus),
+            c if is_word(c) => {
+                let mut word = String::new();
+                while let Some(&(_, c)) = chars.get(i) {
+                    let in_group_name = word.starts_with("group:")
+                        && c == '-'
+                        && chars.get(i + 1).is_some_and(|(_, n)| is_word(*n));
+                    if !is_word(c) && !in_group_name {
+                        break;
+                    }
+                    word.push(c);
+                    i += 1;
+                }
+                buf.push((col, Token::Word(word)));
+                continue;
+            }
+            c => return Err(format!("unexpected {} at column {}", c, col + 1)),
+        };
+
+        buf.extend(token.map(|t| (col, t)));
+        i += 1;
+    }
+
+    Ok(buf)
+}
+
+struct Resolver<'a> {
+    groups: &'a GymGroups,
+
+    /// groups being resolved, to detect cycles
+    visiting: Vec<String>,
+}
+
+impl Resolver<'_> {
+    fn resolve(&mut self, selection: &GymSelection) -> DataMResult<BTreeSet<Gym>> {
+        match selection {
+            GymSelection::Expr(expr) => self.parse(expr),
+            GymSelection::List(list) => {
+                let mut buf = BTreeSet::new();
+                for expr in list {
+                    buf.extend(self.parse(expr)?);
+                }
+                Ok(buf)
+            }
+        }
+    }
+
+    fn parse(&mut self, expr: &str) -> DataMResult<BTreeSet<Gym>> {
+        let invalid = |msg: String| errors::Error::InvalidGymSet(format!("{} in `{}`", msg, expr));
+
+        let tokens = tokenize(expr).map_err(invalid)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            end: expr.len(),
+        };
+
+        let res = parser
+            .list(self)
+            .map_err(|msg| match msg.contains("unknown gym") {
+                true => errors::Error::InvalidGymSet(format!(
+                    "{} in `{}`, valid gyms are {}",
+                    msg,
+                    expr,
+                    Gym::names()
+                )),
+                false => invalid(msg),
+            })?;
+        match parser.peek() {
+            None => Ok(res),
+            Some((col, t)) => Err(invalid(format!(
+                "unexpected {} at column {}",
+                t.describe(),
+                col + 1
+            ))),
+        }
+    }
+
+    fn word(&mut self, word: &str) -> Result<BTreeSet<Gym>, String> {
+        let all = Gym::gym_slice().iter().copied();
+
+        if word.eq_ignore_ascii_case("all") {
+            return Ok(all.collect());
+        }
+        if word.eq_ignore_ascii_case("none") {
+            return Ok(BTreeSet::new());
+        }
+        if let Some(region) = word.strip_prefix("region:") {
+            let region = region
+                .parse::<Region>()
+                .map_err(|_| format!("unknown region {}", region))?;
+            return Ok(all.filter(|g| g.region() == region).collect());
+        }
+        if let Some(name) = word.strip_prefix("group:") {
+            let group = self
+                .groups
+                .get(name)
+                .ok_or_else(|| format!("unknown group {}", name))?;
+            if self.visiting.iter().any(|v| v == name) {
+                return Err(format!("group {} refers to itself, a cycle", name));
+            }
+
+            self.visiting.push(name.to_string());
+            let res = self
+                .resolve(group)
+                .map_err(|e| format!("in group {}, {}", name, e));
+            self.visiting.pop();
+            return res;
+        }
+
+        word.parse::<Gym>()
+            .map(|g| BTreeSet::from([g]))
+            .map_err(|_| format!("unknown gym {}", word))
+    }
+}
+
+struct Parser<'t> {
+    tokens: &'t [(usize, Token)],
+    pos: usize,
+
+    /// column reported when the expression ends early
+    end: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&(usize, Token)> {
+        self.tokens.get(self.pos)
+    }
+
+    fn list(&mut self, resolver: &mut Resolver) -> Result<BTreeSet<Gym>, String> {
+        let mut buf = self.expr(resolver)?;
+        while let Some((_, Token::Comma)) = self.peek() {
+            self.pos += 1;
+            buf.extend(self.expr(resolver)?);
+        }
+        Ok(buf)
+    }
+
+    fn expr(&mut self, resolver: &mut Resolver) -> Result<BTreeSet<Gym>, String> {
+        let mut buf = self.atom(resolver)?;
+        loop {
+            match self.peek() {
+                Some((_, Token::Minus)) => {
+                    self.pos += 1;
+                    let rhs = self.atom(resolver)?;
+                    buf.retain(|g| !rhs.contains(g));
+                }
+                Some((_, Token::Plus)) => {
+                    self.pos += 1;
+                    buf.extend(self.atom(resolver)?);
+                }
+                _ => return Ok(buf),
+            }
+        }
+    }
+
+    fn atom(&mut self, resolver: &mut Resolver) -> Result<BTreeSet<Gym>, String> {
+        let (col, token) = match self.peek() {
+            Some((col, token)) => (*col, token.clone()),
+            None => return Err(format!("expected a gym at column {}", self.end + 1)),
+        };
+        self.pos += 1;
+
+        match token {
+            Token::Word(word) => resolver
+                .word(&word)
+                .map_err(|e| format!("{} at column {}", e, col + 1)),
+            Token::Open => {
+                let res = self.list(resolver)?;
+                match self.peek() {
+                    Some((_, Token::Close)) => {
+                        self.pos += 1;
+                        Ok(res)
+                    }
+                    Some((col, t)) => Err(format!(
+                        "expected ) at column {}, found {}",
+                        col + 1,
+                        t.describe()
+                    )),
+                    None => Err(format!("expected ) at column {}", self.end + 1)),
+                }
+            }
+            t => Err(format!(
+                "expected a gym at column {}, found {}",
+                col + 1,
+                t.describe()
+            )),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+
+use reqwest::header::HeaderMap;
+
+/// Headers copied into snapshot metadata by default
+///
+/// Entries starting with `*` match any header name ending with the rest of the entry
+pub const DEFAULT_CAPTURED_HEADERS: [&str; 6] = [
+    "server",
+    "date",
+    "cf-ray",
+    "*request-id",
+    "cache-control",
+    "content-length",
+];
+
+/// Values longer than this are truncated
+pub const MAX_HEADER_VALUE_LEN: usize = 256;
+
+fn is_allowed(name: &str, allow_list: &[String]) -> bool {
+    allow_list.iter().any(|a| {
+        let a = a.to_ascii_lowercase();
+        match a.strip_prefix('*') {
+            Some(suffix) => name.ends_with(suffix),
+            None => name == a,
+        }
+    })
+}
+
+fn truncate(value: &str) -> String {
+    match value.char_indices().nth(MAX_HEADER_VALUE_LEN) {
+        Some((idx, _)) => value[..idx].to_string(),
+        None => value.to_string(),
+    }
+}
+
+/// Copies the headers in `allow_list` out of `headers`
+///
+/// Names are lowercased, non UTF-8 values are skipped and repeated headers are joined with `, `
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::headers::{self, DEFAULT_CAPTURED_HEADERS, MAX_HEADER_VALUE_LEN};
+/// use reqwest::header::{HeaderMap, HeaderValue};
+///
+/// let mut response = HeaderMap::new();
+/// response.insert("Server", HeaderValue::from_static("cloudflare"));
+/// response.insert("CF-Ray", HeaderValue::from_static("6cb1e5c3bd5f4a2e-SIN"));
+/// response.insert("X-Request-Id", HeaderValue::from_static("abc123"));
+/// response.append("Cache-Control", HeaderValue::from_static("no-store"));
+/// response.append("Cache-Control", HeaderValue::from_static("no-cache"));
+/// response.insert("Set-Cookie", HeaderValue::from_static("PHPSESSID=secret"));
+/// response.insert("Server-Timing", HeaderValue::from_bytes(b"\xff\xfe").unwrap());
+///
+/// let allow_list = DEFAULT_CAPTURED_HEADERS.map(String::from);
+/// let captured = headers::capture_headers(&response, &allow_list);
+/// assert_eq!(
+///     captured.into_iter().collect::<Vec<_>>(),
+///     [
+///         ("cache-control", "no-store, no-cache"),
+///         ("cf-ray", "6cb1e5c3bd5f4a2e-SIN"),
+///         ("server", "cloudflare"),
+///         ("x-request-id", "abc123"),
+///     ]
+///     .map(|(k, v)| (k.to_string(), v.to_string()))
+/// );
+///
+/// // allow list entries are case insensitive, non UTF-8 values skipped
+/// let allow_list = ["SERVER-TIMING".to_string(), "Set-Cookie".to_string()];
+/// let captured = headers::capture_headers(&response, &allow_list);
+/// assert_eq!(captured.len(), 1);
+/// assert_eq!(captured["set-cookie"], "PHPSESSID=secret");
+/// assert!(headers::capture_headers(&response, &[]).is_empty());
+///
+/// let mut long = HeaderMap::new();
+/// long.insert("server", HeaderValue::from_str(&"a".repeat(300)).unwrap());
+/// let captured = headers::capture_headers(&long, &["server".to_string()]);
+/// assert_eq!(captured["server"].len(), MAX_HEADER_VALUE_LEN);
+/// ```
+pub fn capture_headers(headers: &HeaderMap, allow_list: &[String]) -> BTreeMap<String, String> {
+    let mut buf = BTreeMap::<String, String>::new();
+
+    for (name, value) in headers {
+        let name = name.as_str();
+        if !is_allowed(name, allow_list) {
+            continue;
+        }
+
+        if let Ok(value) = value.to_str() {
+            let entry = buf.entry(name.to_string()).or_default();
+            if !entry.is_empty() {
+                entry.push_str(", ");
+            }
+            entry.push_str(value);
+        }
+    }
+
+    buf.into_iter().map(|(k, v)| (k, truncate(&v))).collect()
+}
@@ -0,0 +1,119 @@
+use std::{path::Path, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{sink, DataMResult};
+
+/// File name of the [Heartbeat], relative to the output directory
+pub const HEARTBEAT_FILENAME: &str = "heartbeat.json";
+
+/// Written after every iteration so that liveness can be checked from outside the process
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Heartbeat {
+    /// end of the last iteration
+    pub at: DateTime<Utc>,
+    pub fetched: usize,
+    pub failed: usize,
+
+    /// number of the last iteration, see [crate::iteration::IterationCounter]
+    #[serde(default)]
+    pub iteration: u64,
+}
+
+impl Heartbeat {
+    /// Atomically writes the heartbeat to `output_dir`/[HEARTBEAT_FILENAME]
+    pub fn write(&self, output_dir: &Path) -> DataMResult<()> {
+        std::fs::create_dir_all(output_dir)?;
+
+        let data = serde_json::to_vec_pretty(self).map_err(std::io::Error::from)?;
+        sink::write_atomic(&output_dir.join(HEARTBEAT_FILENAME), &data)?;
+        Ok(())
+    }
+
+    /// Reads the heartbeat in `output_dir`, [None] if the daemon never wrote one
+    pub fn read(output_dir: &Path) -> DataMResult<Option<Self>> {
+        let raw = match std::fs::read(output_dir.join(HEARTBEAT_FILENAME)) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        serde_json::from_slice(&raw)
+            .map(Some)
+            .map_err(|e| std::io::Error::from(e).into())
+    }
+}
+
+/// Liveness of the daemon judged from its [Heartbeat]
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::heartbeat::{Health, Heartbeat, HEARTBEAT_FILENAME};
+/// use chrono::{TimeZone, Utc};
+/// use std::time::Duration;
+///
+/// let dir = std::env::temp_dir().join(format!("heartbeat-doctest-{}", std::process::id()));
+/// let max_age = Health::default_max_age(Duration::from_secs(20 * 60));
+/// let at = Utc.ymd(2022, 1, 11).and_hms(1, 30, 0);
+/// let minutes = |m| at + chrono::Duration::minutes(m);
+///
+/// // never written
+/// assert_eq!(Heartbeat::read(&dir).unwrap(), None);
+/// assert_eq!(Health::of(None, at, max_age), Health::Missing);
+///
+/// let written = Heartbeat { at, fetched: 24, failed: 1, iteration: 42 };
+/// written.write(&dir).unwrap();
+/// let heartbeat = Heartbeat::read(&dir).unwrap().unwrap();
+/// assert_eq!(heartbeat, written);
+///
+/// // fresh until twice the interval has passed
+/// let health = Health::of(Some(&heartbeat), minutes(39), max_age);
+/// assert_eq!(health, Health::Healthy { age: Duration::from_secs(39 * 60) });
+/// assert!(health.is_healthy());
+/// let health = Health::of(Some(&heartbeat), minutes(40), max_age);
+/// assert_eq!(health, Health::Stale { age: Duration::from_secs(40 * 60) });
+/// assert!(!health.is_healthy());
+///
+/// // the clock went back
+/// let health = Health::of(Some(&heartbeat), minutes(-5), max_age);
+/// assert_eq!(health, Health::Healthy { age: Duration::ZERO });
+///
+/// // a heartbeat that can't be read isn't missing
+/// std::fs::write(dir.join(HEARTBEAT_FILENAME), "{").unwrap();
+/// assert!(Heartbeat::read(&dir).is_err());
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    Healthy { age: Duration },
+    Stale { age: Duration },
+    Missing,
+}
+
+impl Health {
+    /// Healthy when the last iteration ended less than `max_age` before `now`
+    ///
+    /// A heartbeat from the future, e.g. after the clock was adjusted, counts as fresh
+    pub fn of(heartbeat: Option<&Heartbeat>, now: DateTime<Utc>, max_age: Duration) -> Self {
+        match heartbeat {
+            None => Self::Missing,
+            Some(hb) => {
+                let age = (now - hb.at).to_std().unwrap_or_default();
+                match age < max_age {
+                    true => Self::Healthy { age },
+                    false => Self::Stale { age },
+                }
+            }
+        }
+    }
+
+    /// Default `max_age` of [Health::of], twice the interval so a slow iteration isn't stale
+    pub fn default_max_age(interval: Duration) -> Duration {
+        interval * 2
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        matches!(self, Self::Healthy { .. })
+    }
+}
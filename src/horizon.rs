@@ -0,0 +1,177 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::Mutex,
+};
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clock::SGT,
+    events::LoggedEvent,
+    models::{Activity, Gym, GymSlotData},
+    venue::Venue,
+};
+
+/// Iterations of history the usual horizon of a gym is taken from
+pub const HORIZON_WINDOW: usize = 24;
+
+/// Iterations in a row a new horizon must be seen in before it is reported,
+/// so that a day without slots, e.g. a closed weekend, isn't mistaken for a change
+pub const HORIZON_PERSISTENCE: usize = 3;
+
+/// Booking horizon of a gym in days: the furthest queried date with slots on the booking page,
+/// relative to the Singapore date of capture
+///
+/// `snapshots` are the queried dates with the snapshots of a single iteration.
+/// None when no date had any slot
+pub fn horizon_days<'a, I>(snapshots: I) -> Option<i64>
+where
+    I: IntoIterator<Item = (NaiveDate, &'a GymSlotData)>,
+{
+    snapshots
+        .into_iter()
+        .filter(|(_, data)| !data.data().is_empty())
+        .map(|(date, data)| {
+            let captured = (data.datetime() + *SGT).date();
+            (date - captured).num_days()
+        })
+        .max()
+}
+
+/// Usual horizon and the new one, when the last `persistence` observations of `history`
+/// all differ from the most common earlier observation
+///
+/// `history` is oldest first. Ties of the most common value go to the most recent one
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::horizon;
+///
+/// // ActiveSG stopped publishing the third day
+/// assert_eq!(horizon::detect_change(&[3, 3, 3, 3, 2, 2, 2], 3), Some((3, 2)));
+///
+/// // not for long enough yet
+/// assert_eq!(horizon::detect_change(&[3, 3, 3, 3, 3, 2, 2], 3), None);
+///
+/// // an empty weekend dips once, then recovers
+/// assert_eq!(horizon::detect_change(&[3, 3, 2, 3, 3, 3, 3], 3), None);
+/// assert_eq!(horizon::detect_change(&[3, 3, 3, 3, 2, 3, 2], 3), None);
+///
+/// // the recent observations must agree on the new horizon
+/// assert_eq!(horizon::detect_change(&[3, 3, 3, 3, 2, 1, 2], 3), None);
+///
+/// // a history too short has no norm
+/// assert_eq!(horizon::detect_change(&[2, 2, 2], 3), None);
+/// assert_eq!(horizon::detect_change(&[3, 4, 4, 4], 3), Some((3, 4)));
+/// ```
+pub fn detect_change(history: &[i64], persistence: usize) -> Option<(i64, i64)> {
+    if persistence == 0 || history.len() <= persistence {
+        return None;
+    }
+
+    let (earlier, recent) = history.split_at(history.len() - persistence);
+    let new = recent[0];
+    if recent.iter().any(|h| *h != new) {
+        return None;
+    }
+
+    let mut counts = HashMap::<i64, usize>::new();
+    for h in earlier {
+        *counts.entry(*h).or_default() += 1;
+    }
+    let norm = earlier
+        .iter()
+        .rev()
+        .copied()
+        .max_by_key(|h| counts[h])
+        .unwrap_or(new);
+
+    (norm != new).then_some((norm, new))
+}
+
+/// The booking horizon of a gym changed, recorded in the event log as a `horizon_changed` event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename = "horizon_changed")]
+pub struct HorizonChange {
+    pub activity: Activity,
+    pub gym: Venue,
+
+    /// capture time of the snapshots that confirmed the change
+    pub captured_at: NaiveDateTime,
+
+    /// usual horizon in days
+    pub before: i64,
+    pub after: i64,
+}
+
+impl fmt::Display for HorizonChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} {:?} booking horizon changed from {} to {} days ahead",
+            self.gym, self.activity, self.before, self.after
+        )
+    }
+}
+
+impl LoggedEvent for HorizonChange {
+    fn captured_at(&self) -> NaiveDateTime {
+        self.captured_at
+    }
+}
+
+/// Rolling history of the booking horizon of every gym
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{horizon::HorizonTracker, models::Gym};
+/// use chrono::NaiveDate;
+///
+/// let tracker = HorizonTracker::default();
+/// let at = NaiveDate::from_ymd(2022, 1, 11).and_hms(1, 0, 0);
+///
+/// for horizon in [3, 3, 3, 2, 2] {
+///     assert_eq!(tracker.record(Gym::BISHAN, horizon, at), None);
+/// }
+/// let change = tracker.record(Gym::BISHAN, 2, at).unwrap();
+/// assert_eq!((change.before, change.after), (3, 2));
+///
+/// // the new horizon is the norm from then on
+/// assert_eq!(tracker.record(Gym::BISHAN, 2, at), None);
+/// assert_eq!(tracker.record(Gym::CLEMENTI, 2, at), None);
+/// ```
+#[derive(Debug, Default)]
+pub struct HorizonTracker {
+    history: Mutex<HashMap<Gym, VecDeque<i64>>>,
+}
+
+impl HorizonTracker {
+    /// Records the horizon of `gym` seen at `captured_at`, returning the change it confirms
+    pub fn record(
+        &self,
+        gym: Gym,
+        horizon: i64,
+        captured_at: NaiveDateTime,
+    ) -> Option<HorizonChange> {
+        let mut history = self.history.lock().unwrap();
+        let history = history.entry(gym).or_default();
+        history.push_back(horizon);
+        while history.len() > HORIZON_WINDOW + HORIZON_PERSISTENCE {
+            history.pop_front();
+        }
+
+        let (before, after) = detect_change(history.make_contiguous(), HORIZON_PERSISTENCE)?;
+        // only the observations of the new horizon are kept, so it becomes the norm
+        history.drain(..history.len() - HORIZON_PERSISTENCE);
+
+        Some(HorizonChange {
+            activity: Activity::GYM,
+            gym: gym.into(),
+            captured_at,
+            before,
+            after,
+        })
+    }
+}
@@ -0,0 +1,255 @@
+use std::{fmt, str::FromStr};
+
+use chrono::{NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clock::SGT,
+    errors,
+    models::{Gym, Timeslot},
+};
+
+/// Range of time within a day, in Singapore time, `open` inclusive and `close` exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct HoursRange {
+    pub open: NaiveTime,
+    pub close: NaiveTime,
+}
+
+impl HoursRange {
+    pub fn new(open: NaiveTime, close: NaiveTime) -> Self {
+        Self { open, close }
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        self.open <= time && time < self.close
+    }
+}
+
+impl FromStr for HoursRange {
+    type Err = errors::Error;
+
+    /// Parses `HH:MM-HH:MM`, for example `07:00-22:00`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || errors::Error::InvalidConfig(format!("invalid opening hours {}", s));
+        let (open, close) = s.split_once('-').ok_or_else(invalid)?;
+        let open = NaiveTime::parse_from_str(open.trim(), "%H:%M").map_err(|_| invalid())?;
+        let close = NaiveTime::parse_from_str(close.trim(), "%H:%M").map_err(|_| invalid())?;
+
+        match open < close {
+            true => Ok(Self::new(open, close)),
+            false => Err(invalid()),
+        }
+    }
+}
+
+impl TryFrom<String> for HoursRange {
+    type Error = errors::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for HoursRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.open.format("%H:%M"),
+            self.close.format("%H:%M")
+        )
+    }
+}
+
+impl From<HoursRange> for String {
+    fn from(range: HoursRange) -> Self {
+        range.to_string()
+    }
+}
+
+/// Opening hours of a gym
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct OpeningHours {
+    /// Monday to Friday
+    pub weekday: HoursRange,
+
+    /// Saturday and Sunday
+    pub weekend: HoursRange,
+}
+
+impl OpeningHours {
+    /// 7am to 10pm daily, which is what most ActiveSG gyms keep
+    pub fn standard() -> Self {
+        let range = HoursRange {
+            open: NaiveTime::from_hms(7, 0, 0),
+            close: NaiveTime::from_hms(22, 0, 0),
+        };
+
+        Self {
+            weekday: range,
+            weekend: range,
+        }
+    }
+
+    pub fn on(&self, weekday: Weekday) -> HoursRange {
+        match weekday {
+            Weekday::Sat | Weekday::Sun => self.weekend,
+            _ => self.weekday,
+        }
+    }
+}
+
+impl Gym {
+    /// Opening hours of the gym, which [crate::config::Config::opening_hours] overrides
+    ///
+    /// Every gym is listed so that a new one can't be added without its hours
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::{hours::OpeningHours, models::Gym};
+    /// use chrono::{NaiveTime, Weekday};
+    ///
+    /// assert_eq!(Gym::BISHAN.opening_hours(), OpeningHours::standard());
+    ///
+    /// let hours = Gym::ENABLING_VILLAGE.opening_hours();
+    /// assert_eq!(hours.on(Weekday::Tue).to_string(), "08:00-21:00");
+    /// assert_eq!(hours.on(Weekday::Sun).to_string(), "08:00-18:00");
+    /// assert!(!hours.on(Weekday::Sun).contains(NaiveTime::from_hms(19, 0, 0)));
+    ///
+    /// let hours = Gym::SILVER_CIRCLE.opening_hours();
+    /// assert_eq!(hours.on(Weekday::Wed).to_string(), "07:00-21:00");
+    /// assert_eq!(hours.on(Weekday::Sat).to_string(), "08:00-18:00");
+    /// ```
+    pub fn opening_hours(&self) -> OpeningHours {
+        let hours = |weekday: &str, weekend: &str| OpeningHours {
+            weekday: weekday.parse().expect("valid opening hours"),
+            weekend: weekend.parse().expect("valid opening hours"),
+        };
+
+        match self {
+            Self::ENABLING_VILLAGE => hours("08:00-21:00", "08:00-18:00"),
+            Self::SILVER_CIRCLE | Self::SENJA_CASHEW | Self::HOKEY_VILLAGE_BOONLAY => {
+                hours("07:00-21:00", "08:00-18:00")
+            }
+            Self::AMK_CC | Self::TOA_PAYOH_CC | Self::FERNVALE_SQ => {
+                hours("07:00-22:00", "08:00-22:00")
+            }
+            Self::BISHAN
+            | Self::BUKIT_BATOK
+            | Self::BUKIT_GOMBAK
+            | Self::CHOA_CHU_KANG
+            | Self::CLEMENTI
+            | Self::HEARTBEAT_BEDOK
+            | Self::HOUGANG
+            | Self::JALAN_BESAR
+            | Self::JURONG_EAST
+            | Self::JURONG_LAKE
+            | Self::JURONG_WEST
+            | Self::PASIR_RIS
+            | Self::SENGKANG
+            | Self::TAMPINES
+            | Self::TOA_PAYOH
+            | Self::WOODLANDS
+            | Self::YIO_CHU_KANG
+            | Self::YISHUN => OpeningHours::standard(),
+        }
+    }
+}
+
+/// Drops the slots starting outside of `hours` on `weekday`
+///
+/// Returns the remaining slots and how many were dropped
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     hours::{self, OpeningHours},
+///     models::Timeslot,
+/// };
+/// use chrono::{TimeZone, Utc, Weekday};
+///
+/// // 06:00 AM to 10:00 PM in Singapore
+/// let slots = (22..24)
+///     .map(|h| Utc.ymd(2022, 1, 10).and_hms(h, 0, 0))
+///     .chain((0..15).map(|h| Utc.ymd(2022, 1, 11).and_hms(h, 0, 0)))
+///     .map(|time| Timeslot::new(time, 10))
+///     .collect::<Vec<_>>();
+///
+/// // the first slot of the day opens at 07:00, the last starts at 09:00 PM before closing at 10
+/// let (kept, dropped) = hours::filter_opening_hours(slots.clone(), OpeningHours::standard(), Weekday::Tue);
+/// assert_eq!(dropped, 2);
+/// assert_eq!(kept.first().unwrap().time(), Utc.ymd(2022, 1, 10).and_hms(23, 0, 0));
+/// assert_eq!(kept.last().unwrap().time(), Utc.ymd(2022, 1, 11).and_hms(13, 0, 0));
+///
+/// let short = OpeningHours {
+///     weekday: OpeningHours::standard().weekday,
+///     weekend: "08:00-18:00".parse().unwrap(),
+/// };
+/// let (kept, dropped) = hours::filter_opening_hours(slots, short, Weekday::Sat);
+/// assert_eq!((kept.len(), dropped), (10, 7));
+/// ```
+///
+/// Fetches drop the slots outside of the hours of the gym, counted in the snapshot metadata
+/// ```
+/// use activesg_gym_datamine::{
+///     config::Config,
+///     fetch::{self, FakeFetcher, FetchTask},
+///     hours::OpeningHours,
+///     models::{Activity, Gym, User},
+/// };
+/// use chrono::NaiveDate;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let fetcher = FakeFetcher::new(|_| {
+///     Ok(r#"<div class="chkbox-grid">
+///         <label>07:00 AM</label><label>25 Left</label>
+///         <label>08:00 AM</label><label>25 Left</label>
+///         <label>09:00 PM</label><label>25 Left</label>
+///     </div>"#
+///         .into())
+/// });
+/// let mut config = Config::new(User::new("user@example.com", "password"));
+/// // a Tuesday
+/// let task = |gym| FetchTask::new(Activity::GYM, gym, NaiveDate::from_ymd(2022, 1, 11));
+///
+/// let data = fetch::fetch_slots(&fetcher, &config, task(Gym::BISHAN)).await.unwrap();
+/// assert_eq!(data.data().len(), 3);
+/// assert_eq!(data.metadata().out_of_hours_suppressed, Some(0));
+///
+/// // Enabling Village opens at 8 and closes at 9 on weekdays
+/// let data = fetch::fetch_slots(&fetcher, &config, task(Gym::ENABLING_VILLAGE)).await.unwrap();
+/// assert_eq!(data.data().len(), 1);
+/// assert_eq!(data.metadata().out_of_hours_suppressed, Some(2));
+///
+/// // overridden in the config, a window closed to every slot of the page
+/// let closed = "12:00-13:00".parse().unwrap();
+/// config.opening_hours.insert(Gym::BISHAN, OpeningHours { weekday: closed, weekend: closed });
+/// let data = fetch::fetch_slots(&fetcher, &config, task(Gym::BISHAN)).await.unwrap();
+/// assert!(data.data().is_empty());
+/// assert_eq!(data.metadata().out_of_hours_suppressed, Some(3));
+///
+/// config.keep_out_of_hours = true;
+/// let data = fetch::fetch_slots(&fetcher, &config, task(Gym::BISHAN)).await.unwrap();
+/// assert_eq!(data.data().len(), 3);
+/// assert_eq!(data.metadata().out_of_hours_suppressed, None);
+/// # }
+/// ```
+pub fn filter_opening_hours(
+    slots: Vec<Timeslot>,
+    hours: OpeningHours,
+    weekday: Weekday,
+) -> (Vec<Timeslot>, usize) {
+    let range = hours.on(weekday);
+    let total = slots.len();
+
+    let kept = slots
+        .into_iter()
+        .filter(|s| range.contains(s.time().with_timezone(&*SGT).time()))
+        .collect::<Vec<_>>();
+
+    let dropped = total - kept.len();
+    (kept, dropped)
+}
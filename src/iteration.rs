@@ -0,0 +1,132 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{errors, sink, DataMResult};
+
+/// File name of the [IterationCounter] state, relative to the output directory
+pub const ITERATION_STATE_FILENAME: &str = "iteration.json";
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct IterationState {
+    /// number of the last iteration started
+    last: u64,
+}
+
+/// Numbers the iterations, never repeating a number even across restarts
+///
+/// The number is the join key between the snapshots, run reports, heartbeat and logs
+/// of an iteration. Each number is persisted to `output_dir`/[ITERATION_STATE_FILENAME]
+/// before being handed out, so a crash skips numbers rather than reusing them
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::iteration::{IterationCounter, ITERATION_STATE_FILENAME};
+///
+/// let dir = std::env::temp_dir().join(format!("iteration-doctest-{}", std::process::id()));
+/// let counter = IterationCounter::new(&dir);
+/// assert_eq!(counter.last().unwrap(), 0);
+/// assert_eq!(counter.next().unwrap(), 1);
+/// assert_eq!(counter.next().unwrap(), 2);
+///
+/// // a restart carries on
+/// let counter = IterationCounter::new(&dir);
+/// assert_eq!(counter.last().unwrap(), 2);
+/// assert_eq!(counter.next().unwrap(), 3);
+///
+/// // numbers are never reused, the counter stops rather than wrapping around
+/// let state = dir.join(ITERATION_STATE_FILENAME);
+/// std::fs::write(&state, format!(r#"{{"last":{}}}"#, u64::MAX - 1)).unwrap();
+/// let counter = IterationCounter::new(&dir);
+/// assert_eq!(counter.next().unwrap(), u64::MAX);
+/// assert!(counter.next().is_err());
+/// assert!(IterationCounter::new(&dir).next().is_err());
+///
+/// std::fs::write(&state, "{ not json").unwrap();
+/// assert!(IterationCounter::new(&dir).next().is_err());
+///
+/// // a dry run counts on without writing
+/// std::fs::write(&state, r#"{"last":7}"#).unwrap();
+/// let counter = IterationCounter::new(&dir).without_persisting();
+/// assert_eq!(counter.next().unwrap(), 8);
+/// assert_eq!(counter.next().unwrap(), 9);
+/// assert_eq!(std::fs::read_to_string(&state).unwrap(), r#"{"last":7}"#);
+///
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct IterationCounter {
+    path: PathBuf,
+
+    /// loaded from the state file on first use
+    last: Mutex<Option<u64>>,
+
+    /// see [IterationCounter::without_persisting]
+    persist: bool,
+}
+
+impl IterationCounter {
+    pub fn new(output_dir: &Path) -> Self {
+        Self {
+            path: output_dir.join(ITERATION_STATE_FILENAME),
+            last: Mutex::default(),
+            persist: true,
+        }
+    }
+
+    /// Counts on from the state file without ever writing it, for a dry run
+    pub fn without_persisting(mut self) -> Self {
+        self.persist = false;
+        self
+    }
+
+    fn load(&self) -> DataMResult<u64> {
+        let raw = match std::fs::read(&self.path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+
+        serde_json::from_slice::<IterationState>(&raw)
+            .map(|s| s.last)
+            .map_err(|e| std::io::Error::from(e).into())
+    }
+
+    /// Number of the last iteration started, 0 if none ever was
+    pub fn last(&self) -> DataMResult<u64> {
+        let mut last = self.last.lock().unwrap();
+        match *last {
+            Some(n) => Ok(n),
+            None => Ok(*last.insert(self.load()?)),
+        }
+    }
+
+    /// Persists and returns the number of the next iteration
+    pub fn next(&self) -> DataMResult<u64> {
+        let mut last = self.last.lock().unwrap();
+        let current = match *last {
+            Some(n) => n,
+            None => self.load()?,
+        };
+        let next = current
+            .checked_add(1)
+            .ok_or(errors::Error::IterationCounterExhausted)?;
+        if !self.persist {
+            *last = Some(next);
+            return Ok(next);
+        }
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let data =
+            serde_json::to_vec(&IterationState { last: next }).map_err(std::io::Error::from)?;
+        sink::write_atomic(&self.path, &data)?;
+
+        *last = Some(next);
+        Ok(next)
+    }
+}
@@ -0,0 +1,170 @@
+use tokio_util::sync::CancellationToken;
+
+use crate::{client::DataMiner, config::Config, fetch::Fetcher, models::GymSlotData, pacer::Pacer};
+
+pub mod aliases;
+pub mod archive;
+pub mod audit;
+pub mod breaker;
+pub mod cache;
+pub mod client;
+pub mod clock;
+pub mod compaction;
+pub mod compress;
+pub mod config;
+pub mod cookies;
+pub mod daemon;
+pub mod dashboard;
+#[cfg(feature = "dev")]
+pub mod dev;
+pub mod diff;
+pub mod discovery;
+pub mod distance;
+pub mod encryption;
+pub mod errors;
+pub mod events;
+pub mod explore;
+pub mod export;
+pub mod fetch;
+pub mod filter;
+pub mod gymset;
+pub mod headers;
+pub mod heartbeat;
+pub mod horizon;
+pub mod hours;
+pub mod iteration;
+pub mod metrics;
+pub mod models;
+pub mod notify;
+pub mod outage;
+pub mod pacer;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod ratelimit;
+pub mod report;
+pub mod retention;
+pub mod retry;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod schedule;
+pub mod selftest;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod setup;
+pub mod sink;
+pub mod sinkhealth;
+pub mod skew;
+pub mod stats;
+#[cfg(feature = "sqlite")]
+pub mod storage;
+pub mod template;
+pub mod trace;
+pub mod venue;
+pub mod watch;
+
+pub type DataMResult<T> = Result<T, crate::errors::Error>;
+
+/// Logins and fetches every gym and date in `config` once, returning the parsed snapshots
+///
+/// Nothing is written to the filesystem and no background tasks are spawned.
+/// Fetches are spaced by [Config::politeness_delay] through a [Pacer] and retried as configured in
+/// [Config::retry], and if `cancel` is triggered the in-flight fetch is dropped and
+/// [errors::Error::Cancelled] is returned.
+pub async fn mine_once(
+    config: &Config,
+    cancel: Option<&CancellationToken>,
+) -> DataMResult<Vec<GymSlotData>> {
+    let data_miner = DataMiner::from_config(config);
+    mine_once_with(&data_miner, config, cancel).await
+}
+
+/// Same as [mine_once] but fetches pages through the provided [Fetcher]
+///
+/// ## Example
+/// ```
+/// use std::time::Duration;
+/// use activesg_gym_datamine::{config::Config, fetch::FakeFetcher, models::{Gym, User}};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let fetcher = FakeFetcher::new(|_| {
+///     Ok(r#"<div class="chkbox-grid"><label>07:00 AM</label><label>25 Left</label></div>"#.into())
+/// });
+///
+/// let mut config = Config::new(User::new("user@example.com", "password"));
+/// config.gyms = vec![Gym::BISHAN, Gym::CLEMENTI];
+/// config.inter_gym_delay = Duration::ZERO;
+///
+/// let data = activesg_gym_datamine::mine_once_with(&fetcher, &config, None)
+///     .await
+///     .unwrap();
+///
+/// assert_eq!(data.len(), 2 * config.day_offsets.len());
+/// # }
+/// ```
+///
+/// A fetch failing for a transient reason is retried
+/// ```
+/// use std::{sync::atomic::{AtomicUsize, Ordering}, time::Duration};
+/// use activesg_gym_datamine::{
+///     config::Config,
+///     errors::Error,
+///     fetch::FakeFetcher,
+///     models::{Gym, User},
+///     retry::RetryPolicy,
+/// };
+/// use reqwest::StatusCode;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let calls = AtomicUsize::new(0);
+/// let fetcher = FakeFetcher::new(|_| match calls.fetch_add(1, Ordering::SeqCst) {
+///     0 => Err(Error::UnexpectedStatus(StatusCode::BAD_GATEWAY)),
+///     _ => Ok(r#"<div class="chkbox-grid"><label>07:00 AM</label><label>25 Left</label></div>"#.into()),
+/// });
+///
+/// let mut config = Config::new(User::new("user@example.com", "password"));
+/// config.gyms = vec![Gym::BISHAN];
+/// config.day_offsets = vec![0];
+/// config.inter_gym_delay = Duration::ZERO;
+/// config.retry = RetryPolicy::new(1, Duration::from_millis(1));
+///
+/// let data = activesg_gym_datamine::mine_once_with(&fetcher, &config, None)
+///     .await
+///     .unwrap();
+/// assert_eq!(data.len(), 1);
+/// assert_eq!(calls.load(Ordering::SeqCst), 2);
+/// # }
+/// ```
+pub async fn mine_once_with<F>(
+    fetcher: &F,
+    config: &Config,
+    cancel: Option<&CancellationToken>,
+) -> DataMResult<Vec<GymSlotData>>
+where
+    F: Fetcher + Sync,
+{
+    let tasks = config.fetch_tasks(&config.target_dates_at(fetcher.now()));
+    let mut buf = Vec::with_capacity(tasks.len());
+    let never = CancellationToken::new();
+    let cancel = cancel.unwrap_or(&never);
+    let pacer = Pacer::from_config(config);
+
+    for task in tasks {
+        if !buf.is_empty() {
+            tokio::select! {
+                _ = cancel.cancelled() => return Err(errors::Error::Cancelled),
+                _ = pacer.between_fetches(task) => (),
+            }
+        }
+
+        tokio::select! {
+            _ = cancel.cancelled() => return Err(errors::Error::Cancelled),
+            retried = retry::retry(&config.retry, &pacer, task, || {
+                fetch::fetch_slots(fetcher, config, task)
+            }) => buf.push(retried.res?),
+        }
+    }
+
+    Ok(buf)
+}
@@ -0,0 +1,81 @@
+use std::{io::IsTerminal, str::FromStr};
+
+use activesg_gym_datamine::errors;
+#[cfg(feature = "otel")]
+use activesg_gym_datamine::{trace, DataMResult};
+use tracing::Subscriber;
+use tracing_subscriber::{
+    layer::SubscriberExt, registry::LookupSpan, util::SubscriberInitExt, EnvFilter, Layer,
+};
+
+/// How log lines are written to stderr, see `--log-format`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// human readable lines, the spans of a line before its message
+    #[default]
+    Text,
+
+    /// a JSON object per line, the spans of a line in its `spans` array
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = errors::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(errors::Error::InvalidConfig(format!(
+                "unknown log format {}, expected text or json",
+                s
+            ))),
+        }
+    }
+}
+
+/// Logs to stderr in `format`, filtered by `RUST_LOG`, only errors without it,
+/// colored when stderr is a terminal
+///
+/// The lines of the dependencies still using `log` go through the same filter
+pub fn init(format: LogFormat) {
+    tracing_subscriber::registry().with(stderr(format)).init();
+}
+
+/// Logs like [init], also exporting the spans of the miner at info level, whatever `RUST_LOG`,
+/// to the OpenTelemetry collector at `endpoint`
+///
+/// The returned provider is flushed before exiting, see [trace::otlp_provider]
+#[cfg(feature = "otel")]
+pub fn init_with_otlp(
+    format: LogFormat,
+    endpoint: &str,
+) -> DataMResult<opentelemetry_sdk::trace::TracerProvider> {
+    use opentelemetry::trace::TracerProvider;
+    use tracing::Level;
+    use tracing_subscriber::filter::Targets;
+
+    let provider = trace::otlp_provider(endpoint)?;
+    let spans = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+        .with_filter(Targets::new().with_target(env!("CARGO_CRATE_NAME"), Level::INFO));
+    tracing_subscriber::registry()
+        .with(stderr(format))
+        .with(spans)
+        .init();
+    Ok(provider)
+}
+
+fn stderr<S>(format: LogFormat) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal());
+    let layer = match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    };
+    layer.with_filter(EnvFilter::from_default_env()).boxed()
+}
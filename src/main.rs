@@ -1,19 +1,512 @@
-use args::Args;
-use client::DataMiner;
-use models::User;
+use std::{io::IsTerminal, process::ExitCode};
+
+use activesg_gym_datamine::{
+    aliases::{self, AliasMap},
+    client::{login::OtpSource, DataMiner},
+    config::{self, ActivityVenues, Config, ConfigFile},
+    errors,
+    filter::Filtered,
+    models::Activity,
+    notify::NotifierConfig,
+    retention::RetentionPolicy,
+    sink::{DryRunSink, FileSink, OutputFormat},
+    sinkhealth::SlowSinkPolicy,
+    watch::Profile,
+    DataMResult,
+};
+use args::{Args, Command, TuiCommand};
+use tracing::{error, warn};
 
-mod models;
-mod client;
-mod errors;
 mod args;
+mod commands;
+mod logging;
+
+/// Asks for the password without echoing it, none when stdin isn't a terminal, e.g. in Docker
+fn prompt_password() -> DataMResult<Option<String>> {
+    if !std::io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    let password = rpassword::prompt_password("ActiveSG password: ")?;
+    Ok(Some(password))
+}
+
+/// Merges the flags in `args` over the optional config file
+fn resolve_config(args: Args) -> DataMResult<(Config, FileSink)> {
+    let file = match &args.config {
+        Some(path) => ConfigFile::load(path)?,
+        None => ConfigFile::default(),
+    };
+
+    let user = config::resolve_user(
+        (args.username, args.password),
+        |key| std::env::var(key).ok(),
+        &file,
+        prompt_password,
+    )?;
+
+    let mut config = Config::new(user);
+    let exclude_gyms = match args.exclude_gyms.is_empty() {
+        true => match &file.exclude_gyms {
+            Some(exclude) => file.gym_set(exclude)?,
+            None => vec![],
+        },
+        false => args.exclude_gyms,
+    };
+    let gyms = match args.gyms.or_else(|| file.gyms.clone()) {
+        Some(gyms) => Some(file.gym_set(&gyms)?),
+        None => None,
+    };
+    config.gyms = config::resolve_gyms(gyms.as_deref(), &exclude_gyms)?;
+    config.priority_gyms = match args.priority_gym.is_empty() {
+        true => file.priority_gyms.unwrap_or_default(),
+        false => args.priority_gym,
+    };
+    config.custom_venues = match args.venue_id.is_empty() {
+        true => file.venue_ids.unwrap_or_default(),
+        false => args.venue_id,
+    };
+    config.iteration_budget = args.iteration_budget.or(file.iteration_budget);
+    config.blackout = args.blackout.or(file.blackout);
+    config.catch_up_budget = args.catch_up_budget.or(file.catch_up_budget);
+    config.max_daily_download_bytes = args
+        .max_daily_download_mb
+        .or(file.max_daily_download_mb)
+        .map(|mb| mb * 1024 * 1024);
+    if let Some(interval) = args
+        .interval
+        .or_else(|| file.interval_secs.map(std::time::Duration::from_secs))
+    {
+        if interval < Config::MIN_INTERVAL {
+            return Err(errors::Error::InvalidConfig(format!(
+                "an interval of {}s is too short, it must be at least {}s",
+                interval.as_secs_f64(),
+                Config::MIN_INTERVAL.as_secs()
+            )));
+        }
+        config.interval = interval;
+    }
+    config.overlap_policy = args
+        .overlap_policy
+        .or(file.overlap_policy)
+        .unwrap_or_default();
+    if let Some(delay) = args.inter_gym_delay.or_else(|| {
+        file.inter_gym_delay_ms
+            .map(std::time::Duration::from_millis)
+    }) {
+        config.inter_gym_delay = delay;
+    }
+    if let Some(concurrency) = args.concurrency.or(file.concurrency) {
+        if concurrency == 0 {
+            return Err(errors::Error::InvalidConfig(
+                "the concurrency must be at least 1".to_string(),
+            ));
+        }
+        config.concurrency = concurrency;
+    }
+    config.rps = args.rps.or(file.rps);
+    if let Some(splay) = args
+        .max_splay
+        .or_else(|| file.max_splay_secs.map(std::time::Duration::from_secs))
+    {
+        config.max_splay = splay;
+    }
+    config.splay_seed = args.splay_seed.or(file.splay_seed);
+    if let Some(cutoff) = args.booking_cutoff.or_else(|| {
+        file.booking_cutoff_mins
+            .map(|m| std::time::Duration::from_secs(m * 60))
+    }) {
+        config.booking_cutoff = cutoff;
+    }
+    config.opening_hours = file.opening_hours;
+    config.profiles = file.profiles;
+    let telegram = match (args.telegram_token, args.telegram_chat_id) {
+        (Some(token), Some(chat_id)) => Some(NotifierConfig::Telegram { token, chat_id }),
+        (None, None) => None,
+        _ => {
+            return Err(errors::Error::InvalidConfig(
+                "--telegram-token and --telegram-chat-id must be given together".to_string(),
+            ))
+        }
+    };
+    config.failure_notifier = args
+        .discord_webhook
+        .or(file.discord_webhook)
+        .map(|url| NotifierConfig::Discord { url });
+    if let Some(threshold) = args
+        .failure_alert_threshold
+        .or(file.failure_alert_threshold)
+    {
+        config.failure_alert_threshold = threshold;
+    }
+    // watches of the command line alert through Telegram, or the log without it
+    if !args.watch.is_empty() || telegram.is_some() {
+        config.profiles.push(Profile {
+            name: Profile::CLI.to_string(),
+            watches: args.watch,
+            notifiers: vec![telegram.unwrap_or(NotifierConfig::Log)],
+        });
+    }
+
+    // a filter of a sink that isn't there would keep nothing from anything, silently
+    let sinks = [
+        FileSink::NAME,
+        #[cfg(feature = "sqlite")]
+        activesg_gym_datamine::storage::SqliteSink::NAME,
+    ];
+    if let Some(name) = file.filters.keys().find(|n| !sinks.contains(&n.as_str())) {
+        return Err(errors::Error::InvalidConfig(format!(
+            "filter of unknown sink {}, expected one of {}",
+            name,
+            sinks.join(", ")
+        )));
+    }
+    config.sink_filters = file.filters;
+    config.trust_server_time = args.trust_server_time;
+    config.keep_out_of_hours = args.keep_out_of_hours;
+    config.slot_ids = args.slot_ids || file.slot_ids;
+    config.gym_names = args.gym_names || file.gym_names;
+    config.write_stats = args.write_stats || file.write_stats;
+    config.validate_venues = args.validate_venues || file.validate_venues;
+    config.emit_diffs = args.emit_diffs || file.emit_diffs;
+    config.event_log = args.event_log || file.event_log;
+    config.compact_days = args.compact_days || file.compact_days;
+    config.anonymous_fallback = args.anonymous_fallback || file.anonymous_fallback;
+    config.once = args.once;
+    config.dry_run = args.dry_run;
+    config.home_location = file.home_location;
+    if let Some(attempts) = args.publish_attempts.or(file.publish_attempts) {
+        config.publish_attempts = attempts.max(1);
+    }
+    let file_days = match (file.days, &file.day_offsets) {
+        (Some(_), Some(_)) => {
+            return Err(errors::Error::InvalidConfig(
+                "days and day_offsets can't both be given".into(),
+            ))
+        }
+        (Some(days), None) => Some(config::first_days(days)),
+        (None, Some(offsets)) => Some(config::check_day_offsets(offsets).map(|_| offsets.clone())),
+        (None, None) => None,
+    };
+    let day_offsets = match (args.days, args.day_offsets) {
+        (Some(_), Some(_)) => {
+            return Err(errors::Error::InvalidConfig(
+                "--days and --day-offsets can't both be given".into(),
+            ))
+        }
+        (Some(offsets), None) | (None, Some(offsets)) => Some(offsets),
+        (None, None) => file_days
+            .transpose()
+            .map_err(errors::Error::InvalidConfig)?,
+    };
+    if let Some(offsets) = day_offsets {
+        config.day_offsets = offsets;
+    }
+    if let Some(retries) = args.retries.or(file.retries) {
+        config.retry.retries = retries;
+    }
+    if let Some(ms) = args.retry_base_ms.or(file.retry_base_ms) {
+        config.retry.base = std::time::Duration::from_millis(ms);
+    }
+    if let Some(secs) = args.timeout_secs.or(file.timeout_secs) {
+        config.timeout = std::time::Duration::from_secs(secs);
+    }
+    if let Some(secs) = args.connect_timeout_secs.or(file.connect_timeout_secs) {
+        config.connect_timeout = std::time::Duration::from_secs(secs);
+    }
+    if config.timeout.is_zero() || config.connect_timeout.is_zero() {
+        return Err(errors::Error::InvalidConfig(
+            "timeouts must be at least a second".into(),
+        ));
+    }
+    config.password_encryption = args
+        .password_encryption
+        .or(file.password_encryption)
+        .unwrap_or_default();
+    config.cookie_file = args.cookie_file.or(file.cookie_file);
+    config.otp = match args.otp_command.or(file.otp_command) {
+        Some(command) if command.trim().is_empty() => {
+            return Err(errors::Error::InvalidConfig(
+                "the otp command can't be empty".into(),
+            ));
+        }
+        Some(command) => Some(OtpSource::Command(command)),
+        None if std::io::stdin().is_terminal() => Some(OtpSource::Prompt),
+        None => None,
+    };
+    if let Some(url) = args.reference_url.or(file.reference_url) {
+        let url = reqwest::Url::parse(&url).map_err(|e| {
+            errors::Error::InvalidConfig(format!("invalid reference url {:?}: {}", url, e))
+        })?;
+        config.reference_url = Some(url);
+    }
+    let slow_sink_p95 = args
+        .slow_sink_p95
+        .or_else(|| file.slow_sink_p95_ms.map(std::time::Duration::from_millis));
+    config.slow_sink = slow_sink_p95.map(|p95| {
+        let mut policy = SlowSinkPolicy::new(p95);
+        if let Some(n) = args.slow_sink_iterations.or(file.slow_sink_iterations) {
+            policy.persistence = n;
+        }
+        policy.auto_disable = args.disable_slow_sinks || file.disable_slow_sinks;
+        policy
+    });
+    config.run_reports = !args.no_run_reports && file.run_reports.unwrap_or(true);
+    config.other_activities = args.activity_venues;
+    let activities = match args.activity.is_empty() {
+        true => file.activities.unwrap_or_default(),
+        false => args.activity,
+    };
+    for activity in activities.into_iter().filter(|a| *a != Activity::GYM) {
+        config.other_activities.push(ActivityVenues {
+            activity,
+            venues: config.gyms.clone(),
+        });
+    }
+    if !args.capture_header.is_empty() {
+        config.captured_headers = args.capture_header;
+    }
+
+    let formats = [
+        args.is_soa.then_some(OutputFormat::Soa),
+        args.jsonl.then_some(OutputFormat::Jsonl),
+        args.format,
+    ];
+    let format = match formats.into_iter().flatten().collect::<Vec<_>>()[..] {
+        [] => file.format.unwrap_or_default(),
+        [format] => format,
+        _ => {
+            return Err(errors::Error::InvalidConfig(
+                "only one of -s, --jsonl and --format can be given".into(),
+            ))
+        }
+    };
+    if let Some(output_dir) = file.output_dir {
+        config.output_dir = output_dir;
+    }
+    config.retention = RetentionPolicy {
+        max_age: args
+            .retention_days
+            .or(file.retention_days)
+            .map(|d| chrono::Duration::days(d.into())),
+        keep_per_gym: args.retention_keep_per_gym.or(file.retention_keep_per_gym),
+        dry_run: args.retention_dry_run,
+    };
+
+    #[cfg(feature = "server")]
+    {
+        if let (Some(listen), Some(serve)) = (args.http_listen, args.serve) {
+            if listen != serve {
+                return Err(errors::Error::InvalidConfig(format!(
+                    "--http-listen {} and --serve {} differ, --serve is the same flag",
+                    listen, serve
+                )));
+            }
+        }
+        config.http_listen = args.http_listen.or(args.serve).or(file.http_listen);
+        config.api_token = args.api_token.or(file.api_token);
+    }
+
+    #[cfg(feature = "sqlite")]
+    {
+        config.sqlite = args.sqlite.or(file.sqlite);
+    }
+
+    #[cfg(feature = "s3")]
+    {
+        config.s3_bucket = args.s3_bucket.or(file.s3_bucket);
+        config.s3_prefix = args.s3_prefix.or(file.s3_prefix).unwrap_or_default();
+        config.no_local = args.no_local || file.no_local;
+        config.s3_delete_local = args.s3_delete_local || file.s3_delete_local;
+        if config.s3_bucket.is_none() && (config.no_local || config.s3_delete_local) {
+            return Err(errors::Error::InvalidConfig(
+                "--no-local and --s3-delete-local need --s3-bucket".into(),
+            ));
+        }
+        if config.s3_bucket.is_some() && format.is_appended() {
+            return Err(errors::Error::InvalidConfig(format!(
+                "the {:?} format can't be uploaded to S3, only json and soa can",
+                format
+            )));
+        }
+    }
+
+    #[cfg(feature = "dev")]
+    {
+        use activesg_gym_datamine::dev::DevArtifacts;
+
+        let ttl = args
+            .dev_artifacts_ttl_secs
+            .map(std::time::Duration::from_secs)
+            .unwrap_or(DevArtifacts::DEFAULT_TTL);
+        config.dev_artifacts = args.dev_artifacts.map(|dir| DevArtifacts::new(dir, ttl));
+    }
+
+    if let Err(e) = config.check_politeness() {
+        match args.i_know_what_im_doing {
+            true => warn!("{}", e),
+            false => return Err(e),
+        }
+    }
+
+    let compression = args.compress.or(file.compress).unwrap_or_default();
+    let level = args.compress_level.or(file.compress_level);
+    compression.check_level(level)?;
+    let template = args
+        .filename_template
+        .or(file.filename_template)
+        .unwrap_or_default();
+    let sink = FileSink::new(config.output_dir.clone(), format)
+        .with_compression(compression, level)
+        .with_filename_template(template);
+    Ok((config, sink))
+}
 
-type DataMResult<T> = Result<T, crate::errors::Error>;
+/// Runs the miner forever, or once with `--once`, storing the snapshots in SQLite rather than `sink` when configured,
+/// each sink behind its filter
+async fn run_daemon(config: Config, sink: FileSink) -> DataMResult<()> {
+    if config.dry_run {
+        let sink = Filtered::from_config(DryRunSink, &config);
+        return DataMiner::exec(config, sink).await;
+    }
+
+    #[cfg(feature = "s3")]
+    let sink = with_s3(sink, &config).await;
+
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &config.sqlite {
+        let sink = activesg_gym_datamine::storage::SqliteSink::open(path)?;
+        let sink = Filtered::from_config(sink, &config);
+        return DataMiner::exec(config, sink).await;
+    }
+
+    let sink = Filtered::from_config(sink, &config);
+    DataMiner::exec(config, sink).await
+}
+
+/// Uploads the files `sink` writes to [Config::s3_bucket], if any
+#[cfg(feature = "s3")]
+async fn with_s3(sink: FileSink, config: &Config) -> FileSink {
+    use activesg_gym_datamine::s3::{S3Store, Uploader};
+
+    match &config.s3_bucket {
+        Some(bucket) => {
+            let store = S3Store::from_env(bucket.clone()).await;
+            let uploader = Uploader::spawn(
+                store,
+                &config.s3_prefix,
+                config.retry,
+                config.s3_delete_local,
+            );
+            sink.with_uploader(uploader, !config.no_local)
+        }
+        None => sink,
+    }
+}
+
+/// Runs the miner behind the dashboard, storing the snapshots like [run_daemon]
+async fn run_tui(mut config: Config, sink: FileSink, cmd: TuiCommand) -> DataMResult<()> {
+    // the dashboard reads its commands from stdin
+    if config.otp == Some(OtpSource::Prompt) {
+        config.otp = None;
+    }
+
+    if config.dry_run {
+        let sink = Filtered::from_config(DryRunSink, &config);
+        return commands::tui::run(config, sink, cmd).await;
+    }
+
+    #[cfg(feature = "s3")]
+    let sink = with_s3(sink, &config).await;
+
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &config.sqlite {
+        let sink = activesg_gym_datamine::storage::SqliteSink::open(path)?;
+        let sink = Filtered::from_config(sink, &config);
+        return commands::tui::run(config, sink, cmd).await;
+    }
+
+    let sink = Filtered::from_config(sink, &config);
+    commands::tui::run(config, sink, cmd).await
+}
 
 #[tokio::main]
-async fn main() {
-    env_logger::init();
-    let args = argh::from_env::<Args>();
-    let user = User::new(args.username, args.password);
+async fn main() -> ExitCode {
+    // before the flags are parsed, so that gyms named in them can be old names
+    if let Some(path) = aliases::default_path() {
+        match AliasMap::load(&path) {
+            Ok(map) => aliases::install(map),
+            Err(e) => {
+                logging::init(logging::LogFormat::default());
+                error!("{}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let mut args = argh::from_env::<Args>();
+    let log_format = args.log_format.unwrap_or_default();
+    #[cfg(feature = "otel")]
+    let spans = match &args.otlp_endpoint {
+        Some(endpoint) => match logging::init_with_otlp(log_format, endpoint) {
+            Ok(provider) => Some(provider),
+            Err(e) => {
+                logging::init(log_format);
+                error!("{}", e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => {
+            logging::init(log_format);
+            None
+        }
+    };
+    #[cfg(not(feature = "otel"))]
+    logging::init(log_format);
+
+    let res = match args.command.take() {
+        Some(Command::Setup(cmd)) => commands::setup::run(cmd).await,
+        Some(Command::EncryptPassword(cmd)) => commands::encrypt_password::run(cmd),
+        Some(Command::Health(cmd)) => return commands::health::run(&args, cmd),
+        Some(Command::Summary(cmd)) => commands::summary::run(&args, cmd),
+        Some(Command::Compact(cmd)) => commands::compact::run(&args, cmd),
+        Some(Command::ListGyms(cmd)) => commands::list_gyms::run(cmd),
+        Some(Command::ExportDuckdb(cmd)) => commands::export_duckdb::run(&args, cmd),
+        Some(Command::SelfTest(cmd)) => match resolve_config(args) {
+            Ok((config, _)) => commands::selftest::run(config, cmd).await,
+            Err(e) => Err(e),
+        },
+        Some(Command::Explore(cmd)) => match resolve_config(args) {
+            Ok((config, _)) => commands::explore::run(config, cmd).await,
+            Err(e) => Err(e),
+        },
+        Some(Command::Tui(cmd)) => match resolve_config(args) {
+            Ok((config, sink)) => run_tui(config, sink, cmd).await,
+            Err(e) => Err(e),
+        },
+        Some(Command::Query(cmd)) => match resolve_config(args) {
+            Ok((config, _)) => commands::query::run(config, cmd).await,
+            Err(e) => Err(e),
+        },
+        Some(Command::Audit(cmd)) => match resolve_config(args) {
+            Ok((config, _)) => commands::audit::run(config, cmd).await,
+            Err(e) => Err(e),
+        },
+        None => match resolve_config(args) {
+            Ok((config, sink)) => run_daemon(config, sink).await,
+            Err(e) => Err(e),
+        },
+    };
+
+    // the spans of a single iteration are still waiting to be exported
+    #[cfg(feature = "otel")]
+    if let Some(provider) = spans {
+        let _ = tokio::task::spawn_blocking(move || provider.force_flush()).await;
+    }
 
-    DataMiner::exec(user, args.is_soa).await;
+    match res {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
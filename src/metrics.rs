@@ -0,0 +1,679 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::{
+    clock::SGT,
+    errors::{Error, FailurePhase},
+    fetch::FetchTask,
+    models::Activity,
+    outage::PhaseCounts,
+    pacer::WaitReason,
+    report::TaskReport,
+    sinkhealth::{SinkState, SinkStatus},
+    stats::DeliveryOutcomes,
+};
+
+/// Counters since process start, or since the last [Metrics::reset]
+#[derive(Debug)]
+pub struct Metrics {
+    pub iterations: AtomicU64,
+
+    /// number of the last iteration started, kept on reset
+    pub last_iteration: AtomicU64,
+    pub fetches_ok: AtomicU64,
+    pub fetches_failed: AtomicU64,
+
+    /// failed fetches by [FailurePhase], see [Metrics::record_failure]
+    pub connect_failures: AtomicU64,
+    pub response_failures: AtomicU64,
+    pub bytes_downloaded: AtomicU64,
+
+    /// see [crate::models::SnapshotMetadata::parse_warnings]
+    pub parse_warnings: AtomicU64,
+
+    /// see [crate::sinkhealth::SinkMonitor::last_peak_in_flight], kept on reset
+    pub delivery_queue_peak: AtomicU64,
+    bytes_written: Mutex<BTreeMap<String, u64>>,
+    waited_ms: Mutex<BTreeMap<WaitReason, u64>>,
+
+    /// alert deliveries per `<profile>/<notifier>`, see [Metrics::record_delivery]
+    notifier_deliveries: Mutex<BTreeMap<String, DeliveryOutcomes>>,
+
+    /// as of the last iteration, kept on reset
+    sinks: Mutex<Vec<SinkStatus>>,
+    since: Mutex<DateTime<Utc>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            iterations: AtomicU64::default(),
+            last_iteration: AtomicU64::default(),
+            fetches_ok: AtomicU64::default(),
+            fetches_failed: AtomicU64::default(),
+            connect_failures: AtomicU64::default(),
+            response_failures: AtomicU64::default(),
+            bytes_downloaded: AtomicU64::default(),
+            parse_warnings: AtomicU64::default(),
+            delivery_queue_peak: AtomicU64::default(),
+            bytes_written: Mutex::default(),
+            waited_ms: Mutex::default(),
+            notifier_deliveries: Mutex::default(),
+            sinks: Mutex::default(),
+            since: Mutex::new(Utc::now()),
+        }
+    }
+}
+
+impl Metrics {
+    /// Zeroes every counter, starting a new period at `now`
+    pub fn reset(&self, now: DateTime<Utc>) {
+        let counters = [
+            &self.iterations,
+            &self.fetches_ok,
+            &self.fetches_failed,
+            &self.connect_failures,
+            &self.response_failures,
+            &self.bytes_downloaded,
+            &self.parse_warnings,
+        ];
+        for counter in counters {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.bytes_written.lock().unwrap().clear();
+        self.waited_ms.lock().unwrap().clear();
+        self.notifier_deliveries.lock().unwrap().clear();
+        *self.since.lock().unwrap() = now;
+    }
+
+    /// Start of the period the counters cover
+    pub fn since(&self) -> DateTime<Utc> {
+        *self.since.lock().unwrap()
+    }
+
+    /// Counts a failed fetch, along with the phase it failed in
+    pub fn record_failure(&self, e: &Error) {
+        self.fetches_failed.fetch_add(1, Ordering::Relaxed);
+        match e.phase() {
+            FailurePhase::Connect => self.connect_failures.fetch_add(1, Ordering::Relaxed),
+            FailurePhase::Response => self.response_failures.fetch_add(1, Ordering::Relaxed),
+            FailurePhase::Other => 0,
+        };
+    }
+
+    pub fn add_bytes_written(&self, sink: &str, bytes: u64) {
+        *self
+            .bytes_written
+            .lock()
+            .unwrap()
+            .entry(sink.to_string())
+            .or_default() += bytes;
+    }
+
+    /// Bytes written by every sink so far
+    pub fn bytes_written(&self) -> BTreeMap<String, u64> {
+        self.bytes_written.lock().unwrap().clone()
+    }
+
+    /// Records time spent waiting by the [crate::pacer::Pacer]
+    pub fn add_waited(&self, reason: WaitReason, waited: Duration) {
+        *self.waited_ms.lock().unwrap().entry(reason).or_default() += waited.as_millis() as u64;
+    }
+
+    /// Milliseconds waited so far per reason
+    pub fn waited_ms(&self) -> BTreeMap<WaitReason, u64> {
+        self.waited_ms.lock().unwrap().clone()
+    }
+
+    /// Counts a delivery of alerts by `notifier`, named `<profile>/<notifier>`,
+    /// `ok` when it succeeded
+    pub fn record_delivery(&self, notifier: &str, ok: bool) {
+        let mut deliveries = self.notifier_deliveries.lock().unwrap();
+        let outcomes = deliveries.entry(notifier.to_string()).or_default();
+        match ok {
+            true => outcomes.ok += 1,
+            false => outcomes.failed += 1,
+        }
+    }
+
+    /// Alert deliveries so far per `<profile>/<notifier>`
+    pub fn notifier_deliveries(&self) -> BTreeMap<String, DeliveryOutcomes> {
+        self.notifier_deliveries.lock().unwrap().clone()
+    }
+
+    pub fn set_sink_statuses(&self, statuses: Vec<SinkStatus>) {
+        *self.sinks.lock().unwrap() = statuses;
+    }
+
+    /// Renders the counters in the Prometheus text exposition format
+    pub fn render_prometheus(&self) -> String {
+        let mut buf = String::new();
+        let counters = [
+            ("activesg_iterations_total", &self.iterations),
+            ("activesg_bytes_downloaded_total", &self.bytes_downloaded),
+        ];
+
+        for (name, counter) in counters {
+            let _ = writeln!(buf, "# TYPE {} counter", name);
+            let _ = writeln!(buf, "{} {}", name, counter.load(Ordering::Relaxed));
+        }
+
+        let _ = writeln!(buf, "# TYPE activesg_last_iteration counter");
+        let _ = writeln!(
+            buf,
+            "activesg_last_iteration {}",
+            self.last_iteration.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(buf, "# TYPE activesg_fetches_total counter");
+        for (outcome, counter) in [("ok", &self.fetches_ok), ("failed", &self.fetches_failed)] {
+            let _ = writeln!(
+                buf,
+                "activesg_fetches_total{{outcome=\"{}\"}} {}",
+                outcome,
+                counter.load(Ordering::Relaxed)
+            );
+        }
+
+        let _ = writeln!(buf, "# TYPE activesg_fetch_failures_total counter");
+        for (phase, counter) in [
+            (FailurePhase::Connect, &self.connect_failures),
+            (FailurePhase::Response, &self.response_failures),
+        ] {
+            let _ = writeln!(
+                buf,
+                "activesg_fetch_failures_total{{phase=\"{}\"}} {}",
+                phase.as_str(),
+                counter.load(Ordering::Relaxed)
+            );
+        }
+
+        let _ = writeln!(buf, "# TYPE activesg_bytes_written_total counter");
+        for (sink, bytes) in self.bytes_written() {
+            let _ = writeln!(
+                buf,
+                "activesg_bytes_written_total{{sink=\"{}\"}} {}",
+                sink, bytes
+            );
+        }
+
+        let _ = writeln!(buf, "# TYPE activesg_wait_seconds_total counter");
+        for (reason, ms) in self.waited_ms() {
+            let _ = writeln!(
+                buf,
+                "activesg_wait_seconds_total{{reason=\"{}\"}} {:.3}",
+                reason.as_str(),
+                ms as f64 / 1000.0
+            );
+        }
+
+        let _ = writeln!(buf, "# TYPE activesg_sink_delivery_queue_peak gauge");
+        let _ = writeln!(
+            buf,
+            "activesg_sink_delivery_queue_peak {}",
+            self.delivery_queue_peak.load(Ordering::Relaxed)
+        );
+
+        let sinks = self.sinks.lock().unwrap();
+        let _ = writeln!(buf, "# TYPE activesg_sink_latency_p95_seconds gauge");
+        for status in sinks.iter() {
+            if let Some(ms) = status.p95_ms {
+                let _ = writeln!(
+                    buf,
+                    "activesg_sink_latency_p95_seconds{{sink=\"{}\"}} {:.3}",
+                    status.sink,
+                    ms as f64 / 1000.0
+                );
+            }
+        }
+        let _ = writeln!(buf, "# TYPE activesg_sink_state gauge");
+        for status in sinks.iter() {
+            for state in [SinkState::Healthy, SinkState::Slow, SinkState::Disabled] {
+                let _ = writeln!(
+                    buf,
+                    "activesg_sink_state{{sink=\"{}\",state=\"{}\"}} {}",
+                    status.sink,
+                    state.as_str(),
+                    u8::from(status.state == state)
+                );
+            }
+        }
+
+        buf
+    }
+}
+
+/// Volume of a single iteration, logged as the iteration summary
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IterationStats {
+    /// see [crate::iteration::IterationCounter], 0 when not numbered
+    pub iteration: u64,
+    pub fetched: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub bytes_downloaded: u64,
+    pub bytes_written: BTreeMap<String, u64>,
+
+    /// snapshots delivered per sink
+    pub deliveries: BTreeMap<String, usize>,
+
+    /// snapshots kept from each sink by its filter, see [crate::filter]
+    pub filtered: BTreeMap<String, usize>,
+
+    /// time spent waiting between requests, see [crate::pacer::Pacer]
+    pub waited: Duration,
+    pub errors: ErrorSummary,
+
+    /// fetches attempted and failed per phase, see [crate::outage]
+    pub phases: PhaseCounts,
+
+    /// outcome of every fetch, in the order they were attempted
+    pub tasks: Vec<TaskReport>,
+
+    /// see [crate::trace], none outside of a traced iteration
+    pub trace_id: Option<String>,
+}
+
+impl IterationStats {
+    /// Adds `other`, the statistics of tasks of the same iteration, to these
+    ///
+    /// Concurrent fetches each count on their own, see [crate::config::Config::concurrency]
+    ///
+    /// ## Example
+    /// ```
+    /// use std::time::Duration;
+    /// use activesg_gym_datamine::{
+    ///     errors::Error,
+    ///     fetch::FetchTask,
+    ///     metrics::IterationStats,
+    ///     models::{Activity, Gym},
+    ///     report::TaskReport,
+    /// };
+    ///
+    /// let date = chrono::NaiveDate::from_ymd(2022, 1, 11);
+    /// let clementi = FetchTask::new(Activity::GYM, Gym::CLEMENTI, date);
+    ///
+    /// let mut stats = IterationStats { iteration: 7, ..Default::default() };
+    /// let mut ok = IterationStats { fetched: 1, bytes_downloaded: 100, ..Default::default() };
+    /// ok.bytes_written.insert("file".to_string(), 10);
+    /// ok.waited = Duration::from_secs(1);
+    /// let mut failed = IterationStats { failed: 1, ..Default::default() };
+    /// failed.errors.record(clementi, &Error::Cancelled);
+    /// failed.tasks.push(TaskReport::skipped(clementi, "for the example"));
+    /// failed.waited = Duration::from_secs(1);
+    ///
+    /// stats.merge(ok.clone());
+    /// stats.merge(ok);
+    /// stats.merge(failed);
+    /// assert_eq!((stats.iteration, stats.fetched, stats.failed), (7, 2, 1));
+    /// assert_eq!(stats.bytes_downloaded, 200);
+    /// assert_eq!(stats.bytes_written["file"], 20);
+    /// assert_eq!(stats.waited, Duration::from_secs(3));
+    /// assert_eq!(stats.errors.failures("cancelled").len(), 1);
+    /// assert_eq!(stats.tasks.len(), 1);
+    /// ```
+    pub fn merge(&mut self, other: IterationStats) {
+        self.fetched += other.fetched;
+        self.failed += other.failed;
+        self.skipped += other.skipped;
+        self.bytes_downloaded += other.bytes_downloaded;
+        for (sink, bytes) in other.bytes_written {
+            *self.bytes_written.entry(sink).or_default() += bytes;
+        }
+        for (sink, count) in other.deliveries {
+            *self.deliveries.entry(sink).or_default() += count;
+        }
+        for (sink, count) in other.filtered {
+            *self.filtered.entry(sink).or_default() += count;
+        }
+        self.waited += other.waited;
+        for (class, failures) in other.errors.by_class {
+            self.errors
+                .by_class
+                .entry(class)
+                .or_default()
+                .extend(failures);
+        }
+        self.phases.attempted += other.phases.attempted;
+        self.phases.connect += other.phases.connect;
+        self.phases.response += other.phases.response;
+        self.tasks.extend(other.tasks);
+    }
+
+    /// Whether the failures of this iteration are worth alerting about: more than `threshold`
+    /// fetches failed, or the credentials or the one-time PIN were rejected, or ActiveSG asked
+    /// for a captcha or is under maintenance, see [crate::config::Config::failure_notifier]
+    pub fn failure_alert_due(&self, threshold: usize) -> bool {
+        let counts = self.errors.counts();
+        self.failed > threshold
+            || [
+                Error::InvalidCredentialsSessionExpired,
+                Error::OtpRequired,
+                Error::OtpRejected,
+                Error::CaptchaRequired,
+                Error::SiteMaintenance,
+            ]
+            .iter()
+            .any(|e| counts.contains_key(e.class()))
+    }
+
+    /// Fetches failed with the count and gyms of every kind of error, one line each
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::{
+    ///     errors::Error,
+    ///     fetch::FetchTask,
+    ///     metrics::IterationStats,
+    ///     models::{Activity, Gym},
+    /// };
+    ///
+    /// let date = chrono::NaiveDate::from_ymd(2022, 1, 11);
+    /// let mut stats = IterationStats { iteration: 42, fetched: 70, ..Default::default() };
+    /// for gym in [Gym::BISHAN, Gym::CLEMENTI] {
+    ///     stats.errors.record(FetchTask::new(Activity::GYM, gym, date), &Error::Cancelled);
+    ///     stats.failed += 1;
+    /// }
+    /// assert!(!stats.failure_alert_due(5));
+    /// assert!(stats.failure_alert_due(1));
+    /// assert_eq!(
+    ///     stats.failure_report(),
+    ///     "Iteration 42: 2 of 72 fetches failed\ncancelled: 2 gyms affected (BISHAN, CLEMENTI)"
+    /// );
+    ///
+    /// // a single rejected login is enough
+    /// let task = FetchTask::new(Activity::GYM, Gym::TAMPINES, date);
+    /// stats.errors.record(task, &Error::InvalidCredentialsSessionExpired);
+    /// stats.failed += 1;
+    /// assert!(stats.failure_alert_due(5));
+    ///
+    /// // and so is a single captcha
+    /// let mut stats = IterationStats { iteration: 43, fetched: 70, ..Default::default() };
+    /// stats.errors.record(task, &Error::CaptchaRequired);
+    /// stats.failed += 1;
+    /// assert!(stats.failure_alert_due(5));
+    /// assert_eq!(
+    ///     stats.failure_report(),
+    ///     "Iteration 43: 1 of 71 fetches failed\ncaptcha required: 1 gym affected (TAMPINES)"
+    /// );
+    /// ```
+    pub fn failure_report(&self) -> String {
+        let mut lines = vec![format!(
+            "Iteration {}: {} of {} fetches failed",
+            self.iteration,
+            self.failed,
+            self.fetched + self.failed
+        )];
+        lines.extend(self.errors.lines());
+        lines.join("\n")
+    }
+
+    pub fn summary(&self) -> String {
+        let written = self
+            .bytes_written
+            .iter()
+            .map(|(sink, bytes)| format!("{} {}", sink, human_bytes(*bytes)))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut buf = format!(
+            "{} fetched, {} failed, {} skipped, {} downloaded, waited {:.1}s, written: [{}]",
+            self.fetched,
+            self.failed,
+            self.skipped,
+            human_bytes(self.bytes_downloaded),
+            self.waited.as_secs_f64(),
+            written
+        );
+        if let Some(trace_id) = &self.trace_id {
+            let _ = write!(buf, ", trace {}", trace_id);
+        }
+        buf
+    }
+}
+
+/// Failures of an iteration grouped by [Error::class]
+///
+/// Keeps the full message of every failure so that an outage
+/// can be logged as one line per class instead of one line per gym
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     errors::Error,
+///     fetch::FetchTask,
+///     metrics::ErrorSummary,
+///     models::{Activity, Gym},
+/// };
+/// use chrono::NaiveDate;
+/// use reqwest::StatusCode;
+///
+/// let date = NaiveDate::from_ymd(2022, 1, 11);
+/// let mut summary = ErrorSummary::default();
+/// assert!(summary.is_empty());
+///
+/// // an outage, every gym failing the same way on both days
+/// let gyms = [Gym::BISHAN, Gym::CLEMENTI, Gym::JURONG_EAST, Gym::TAMPINES, Gym::WOODLANDS];
+/// for gym in gyms {
+///     for date in [date, date.succ()] {
+///         let task = FetchTask::new(Activity::GYM, gym, date);
+///         let e = Error::UnexpectedStatus(StatusCode::SERVICE_UNAVAILABLE)
+///             .with_fetch_context(task, Some("https://example.com".into()));
+///         summary.record(task, &e);
+///     }
+/// }
+/// let courts = FetchTask::new(Activity::BADMINTON, Gym::BISHAN, date);
+/// summary.record(courts, &Error::SiteMaintenance.with_fetch_context(courts, None));
+///
+/// assert_eq!(
+///     summary.counts().into_iter().collect::<Vec<_>>(),
+///     [("site under maintenance", 1), ("unexpected http status", 10)]
+/// );
+/// assert_eq!(
+///     summary.lines(),
+///     [
+///         "site under maintenance: 1 gym affected (BADMINTON-BISHAN)",
+///         "unexpected http status: 5 gyms affected (BISHAN, CLEMENTI, JURONG_EAST, …+2)",
+///     ]
+/// );
+///
+/// // the full messages are kept
+/// let failures = summary.failures("site under maintenance");
+/// assert_eq!(failures.len(), 1);
+/// assert_eq!(failures[0].0, courts);
+/// assert!(failures[0].1.starts_with("BADMINTON at BISHAN on 2022-01-11: "));
+/// assert!(summary.failures("timeout fetching booking page").is_empty());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorSummary {
+    by_class: BTreeMap<&'static str, Vec<(FetchTask, String)>>,
+}
+
+impl ErrorSummary {
+    /// Gyms named in a summary line before the rest are only counted
+    pub const MAX_LISTED: usize = 3;
+
+    pub fn record(&mut self, task: FetchTask, e: &Error) {
+        self.by_class
+            .entry(e.class())
+            .or_default()
+            .push((task, e.to_string()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_class.is_empty()
+    }
+
+    /// Number of failures per class
+    pub fn counts(&self) -> BTreeMap<&'static str, usize> {
+        self.by_class
+            .iter()
+            .map(|(class, failures)| (*class, failures.len()))
+            .collect()
+    }
+
+    /// Every failure of `class` with its full message
+    pub fn failures(&self, class: &str) -> &[(FetchTask, String)] {
+        self.by_class
+            .get(class)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// One line per error class, e.g.
+    /// `timeout fetching booking page: 23 gyms affected (BISHAN, CLEMENTI, JURONG_EAST, …+20)`
+    pub fn lines(&self) -> Vec<String> {
+        self.by_class
+            .iter()
+            .map(|(class, failures)| {
+                let mut gyms = Vec::<String>::new();
+                for (task, _) in failures {
+                    let name = match task.activity {
+                        Activity::GYM => format!("{:?}", task.gym),
+                        activity => format!("{:?}-{:?}", activity, task.gym),
+                    };
+                    if !gyms.contains(&name) {
+                        gyms.push(name);
+                    }
+                }
+
+                format!(
+                    "{}: {} {} affected ({})",
+                    class,
+                    gyms.len(),
+                    if gyms.len() == 1 { "gym" } else { "gyms" },
+                    truncated_list(&gyms, Self::MAX_LISTED)
+                )
+            })
+            .collect()
+    }
+}
+
+/// Joins the first `max` items, counting the rest, e.g. `A, B, …+3`
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::metrics::truncated_list;
+///
+/// let gyms = ["BISHAN", "CLEMENTI", "JURONG_EAST", "TAMPINES", "WOODLANDS"];
+/// assert_eq!(truncated_list(&gyms, 3), "BISHAN, CLEMENTI, JURONG_EAST, …+2");
+/// assert_eq!(truncated_list(&gyms[..3], 3), "BISHAN, CLEMENTI, JURONG_EAST");
+/// assert_eq!(truncated_list(&gyms[..1], 3), "BISHAN");
+/// assert_eq!(truncated_list::<&str>(&[], 3), "");
+/// ```
+pub fn truncated_list<S: AsRef<str>>(items: &[S], max: usize) -> String {
+    let mut buf = items
+        .iter()
+        .take(max)
+        .map(AsRef::as_ref)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    if items.len() > max {
+        let _ = write!(buf, ", …+{}", items.len() - max);
+    }
+    buf
+}
+
+/// Formats bytes with a binary unit, e.g. `1.5 MiB`
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
+/// Calendar day in Singapore of `now`
+fn sgt_date(now: DateTime<Utc>) -> NaiveDate {
+    now.with_timezone(&*SGT).date().naive_local()
+}
+
+/// Caps how much is downloaded per Singapore calendar day
+#[derive(Debug)]
+pub struct DownloadBudget {
+    limit_bytes: u64,
+    used: Mutex<(NaiveDate, u64)>,
+}
+
+impl DownloadBudget {
+    pub fn new(limit_bytes: u64) -> Self {
+        Self {
+            limit_bytes,
+            used: Mutex::new((NaiveDate::from_ymd(1970, 1, 1), 0)),
+        }
+    }
+
+    /// Bytes downloaded so far on the day of `now`, resetting at Singapore midnight
+    pub fn used(&self, now: DateTime<Utc>) -> u64 {
+        let mut used = self.used.lock().unwrap();
+        Self::today(&mut used, now).1
+    }
+
+    /// Counts `bytes` downloaded at `now`, whatever the response was,
+    /// returning whether the budget is exhausted with them
+    ///
+    /// The day is checked and the bytes added under the same lock, the bytes
+    /// of a response received around midnight aren't lost to the reset
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::metrics::DownloadBudget;
+    /// use chrono::{TimeZone, Utc};
+    ///
+    /// let budget = DownloadBudget::new(1000);
+    /// // 23:00 SGT
+    /// let evening = Utc.ymd(2022, 1, 11).and_hms(15, 0, 0);
+    /// assert!(!budget.record(evening, 600));
+    /// assert!(!budget.is_exhausted(evening));
+    /// // an error page counts as much as a booking page
+    /// assert!(budget.record(evening, 400));
+    /// assert!(budget.is_exhausted(evening));
+    /// assert_eq!(budget.used(evening), 1000);
+    ///
+    /// // 00:00 SGT the next day
+    /// let midnight = Utc.ymd(2022, 1, 11).and_hms(16, 0, 0);
+    /// assert!(!budget.is_exhausted(midnight));
+    /// assert!(!budget.record(midnight, 10));
+    /// assert_eq!(budget.used(midnight), 10);
+    /// ```
+    pub fn record(&self, now: DateTime<Utc>, bytes: u64) -> bool {
+        let mut used = self.used.lock().unwrap();
+        let used = Self::today(&mut used, now);
+        used.1 += bytes;
+        used.1 >= self.limit_bytes
+    }
+
+    pub fn is_exhausted(&self, now: DateTime<Utc>) -> bool {
+        self.used(now) >= self.limit_bytes
+    }
+
+    /// `used`, reset first when `now` is on another day
+    fn today(used: &mut (NaiveDate, u64), now: DateTime<Utc>) -> &mut (NaiveDate, u64) {
+        let today = sgt_date(now);
+        if used.0 != today {
+            *used = (today, 0);
+        }
+        used
+    }
+
+    pub fn limit_bytes(&self) -> u64 {
+        self.limit_bytes
+    }
+}
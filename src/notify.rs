@@ -0,0 +1,594 @@
+use std::{
+    fmt,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{info, warn, Instrument, Span};
+
+use crate::{
+    client::{DEFAULT_CONNECT_TIMEOUT, DEFAULT_TIMEOUT},
+    distance::LatLng,
+    errors,
+    metrics::Metrics,
+    models::GymSlotData,
+    trace,
+    watch::{Alert, AlertTracker, Profile},
+    DataMResult,
+};
+
+/// Client of the notifiers, giving up on a request like the miner does,
+/// after [DEFAULT_TIMEOUT] or [DEFAULT_CONNECT_TIMEOUT] to connect
+pub fn http_client() -> Client {
+    Client::builder()
+        .timeout(DEFAULT_TIMEOUT)
+        .connect_timeout(DEFAULT_CONNECT_TIMEOUT)
+        .build()
+        .unwrap()
+}
+
+/// Destination of [Alert]s
+pub trait Notifier {
+    /// Name used in logs
+    fn name(&self) -> &str;
+
+    /// Delivers the alerts raised by a single snapshot
+    fn notify(&self, alerts: &[Alert]) -> impl Future<Output = DataMResult<()>> + Send;
+
+    /// Delivers an alert about ActiveSG itself rather than a slot,
+    /// e.g. a [crate::horizon::HorizonChange]
+    fn notify_operational(&self, message: &str) -> impl Future<Output = DataMResult<()>> + Send;
+}
+
+/// Notifier as written in the config file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum NotifierConfig {
+    /// logs the alerts at info level
+    Log,
+
+    /// POSTs the alerts as a JSON array
+    Webhook { url: String },
+
+    /// sends the alerts as a message from the bot of `token` to `chat_id`
+    Telegram { token: String, chat_id: String },
+
+    /// posts the alerts as a message to the Discord webhook at `url`
+    Discord { url: String },
+}
+
+impl NotifierConfig {
+    pub fn build(&self) -> AnyNotifier {
+        match self {
+            Self::Log => AnyNotifier::Log(LogNotifier),
+            Self::Webhook { url } => AnyNotifier::Webhook(WebhookNotifier::new(url.clone())),
+            Self::Telegram { token, chat_id } => {
+                AnyNotifier::Telegram(TelegramNotifier::new(token.clone(), chat_id.clone()))
+            }
+            Self::Discord { url } => AnyNotifier::Discord(DiscordNotifier::new(url.clone())),
+        }
+    }
+}
+
+/// Logs every alert
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn name(&self) -> &str {
+        "log"
+    }
+
+    async fn notify(&self, alerts: &[Alert]) -> DataMResult<()> {
+        for alert in alerts {
+            info!("[{}] {}", alert.profile, alert);
+        }
+        Ok(())
+    }
+
+    async fn notify_operational(&self, message: &str) -> DataMResult<()> {
+        info!("[operational] {}", message);
+        Ok(())
+    }
+}
+
+/// POSTs the alerts as JSON to `url`, with the `traceparent` of the iteration that raised them
+#[derive(Debug, Clone)]
+pub struct WebhookNotifier {
+    client: Client,
+    url: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: String) -> Self {
+        Self {
+            client: http_client(),
+            url,
+        }
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    async fn notify(&self, alerts: &[Alert]) -> DataMResult<()> {
+        trace::propagate(self.client.post(&self.url))
+            .json(alerts)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// POSTs `{"operational": message}`, told apart from the array of slot alerts
+    async fn notify_operational(&self, message: &str) -> DataMResult<()> {
+        trace::propagate(self.client.post(&self.url))
+            .json(&serde_json::json!({ "operational": message }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Sends the alerts of a snapshot as a single Telegram message, one line per slot
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     models::{Activity, Gym},
+///     notify::{Notifier, TelegramNotifier},
+///     watch::Alert,
+/// };
+/// use chrono::{TimeZone, Utc};
+/// use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+/// let api_url = format!("http://{}", listener.local_addr().unwrap());
+/// let server = tokio::spawn(async move {
+///     let mut requests = vec![];
+///     for status in ["200 OK", "401 Unauthorized"] {
+///         let (mut socket, _) = listener.accept().await.unwrap();
+///         let mut buf = vec![0; 4096];
+///         let n = socket.read(&mut buf).await.unwrap();
+///         requests.push(String::from_utf8_lossy(&buf[..n]).to_string());
+///         let response = format!("HTTP/1.1 {}\r\ncontent-length: 2\r\nconnection: close\r\n\r\n{{}}", status);
+///         socket.write_all(response.as_bytes()).await.unwrap();
+///     }
+///     requests
+/// });
+///
+/// let telegram = TelegramNotifier::new("123:secret".into(), "42".into()).with_api_url(api_url);
+/// let alert = Alert {
+///     profile: "cli".into(),
+///     activity: Activity::GYM,
+///     gym: Gym::BISHAN.into(),
+///     // 7 PM in Singapore
+///     time: Utc.ymd(2022, 1, 11).and_hms(11, 0, 0),
+///     slots_avail: 3,
+///     distance_m: None,
+/// };
+/// telegram.notify(&[alert.clone()]).await.unwrap();
+///
+/// // the token stays out of the error, and out of the logs
+/// let e = telegram.notify(&[alert]).await.err().unwrap();
+/// assert!(e.to_string().contains("401"), "{}", e);
+/// assert!(!e.to_string().contains("secret"), "{}", e);
+///
+/// let requests = server.await.unwrap();
+/// assert!(requests[0].starts_with("POST /bot123:secret/sendMessage "), "{}", requests[0]);
+/// assert!(requests[0].contains(r#""chat_id":"42""#), "{}", requests[0]);
+/// assert!(requests[0].contains("BISHAN GYM at Tue 2022-01-11 19:00: 3 slots available"), "{}", requests[0]);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct TelegramNotifier {
+    client: Client,
+    token: String,
+    chat_id: String,
+    api_url: String,
+}
+
+impl TelegramNotifier {
+    pub const API_URL: &'static str = "https://api.telegram.org";
+
+    pub fn new(token: String, chat_id: String) -> Self {
+        Self {
+            client: http_client(),
+            token,
+            chat_id,
+            api_url: Self::API_URL.to_string(),
+        }
+    }
+
+    /// Sends to another Bot API server than [TelegramNotifier::API_URL]
+    pub fn with_api_url(mut self, api_url: String) -> Self {
+        self.api_url = api_url;
+        self
+    }
+
+    async fn send(&self, text: &str) -> DataMResult<()> {
+        let url = format!("{}/bot{}/sendMessage", self.api_url, self.token);
+        let res = self
+            .client
+            .post(&url)
+            .json(&serde_json::json!({ "chat_id": self.chat_id, "text": text }))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        // the url carries the token
+        res.map(|_| ()).map_err(|e| {
+            errors::Error::Notifier(format!(
+                "telegram: {}",
+                e.to_string().replace(&self.token, "<token>")
+            ))
+        })
+    }
+}
+
+impl fmt::Debug for TelegramNotifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TelegramNotifier")
+            .field("chat_id", &self.chat_id)
+            .field("api_url", &self.api_url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Notifier for TelegramNotifier {
+    fn name(&self) -> &str {
+        "telegram"
+    }
+
+    async fn notify(&self, alerts: &[Alert]) -> DataMResult<()> {
+        let text = alerts
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.send(&text).await
+    }
+
+    async fn notify_operational(&self, message: &str) -> DataMResult<()> {
+        self.send(message).await
+    }
+}
+
+/// Posts the alerts of a snapshot as a single message to a Discord webhook, one line per slot
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::notify::{DiscordNotifier, Notifier};
+/// use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+/// let url = format!("http://{}/api/webhooks/1/secret", listener.local_addr().unwrap());
+/// let server = tokio::spawn(async move {
+///     let (mut socket, _) = listener.accept().await.unwrap();
+///     let mut buf = vec![0; 4096];
+///     let n = socket.read(&mut buf).await.unwrap();
+///     let response = "HTTP/1.1 204 No Content\r\nconnection: close\r\n\r\n";
+///     socket.write_all(response.as_bytes()).await.unwrap();
+///     String::from_utf8_lossy(&buf[..n]).to_string()
+/// });
+///
+/// let discord = DiscordNotifier::new(url);
+/// discord.notify_operational("Iteration 42: 3 of 75 fetches failed").await.unwrap();
+///
+/// let request = server.await.unwrap();
+/// assert!(request.starts_with("POST /api/webhooks/1/secret "), "{}", request);
+/// assert!(request.contains(r#"{"content":"Iteration 42: 3 of 75 fetches failed"}"#), "{}", request);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct DiscordNotifier {
+    client: Client,
+    url: String,
+}
+
+impl DiscordNotifier {
+    /// Longest message Discord accepts, in characters
+    pub const MAX_CONTENT_CHARS: usize = 2000;
+
+    pub fn new(url: String) -> Self {
+        Self {
+            client: http_client(),
+            url,
+        }
+    }
+
+    async fn send(&self, content: &str) -> DataMResult<()> {
+        // rather cut than rejected
+        let content = match content.chars().count() > Self::MAX_CONTENT_CHARS {
+            true => {
+                let cut = content.chars().take(Self::MAX_CONTENT_CHARS - 1);
+                cut.chain(std::iter::once('…')).collect()
+            }
+            false => content.to_string(),
+        };
+        let res = self
+            .client
+            .post(&self.url)
+            .json(&serde_json::json!({ "content": content }))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        // the url carries the token of the webhook
+        res.map(|_| ()).map_err(|e| {
+            let e = e.to_string().replace(&self.url, "<webhook>");
+            errors::Error::Notifier(format!("discord: {}", e))
+        })
+    }
+}
+
+impl fmt::Debug for DiscordNotifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DiscordNotifier").finish_non_exhaustive()
+    }
+}
+
+impl Notifier for DiscordNotifier {
+    fn name(&self) -> &str {
+        "discord"
+    }
+
+    async fn notify(&self, alerts: &[Alert]) -> DataMResult<()> {
+        let content = alerts
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.send(&content).await
+    }
+
+    async fn notify_operational(&self, message: &str) -> DataMResult<()> {
+        self.send(message).await
+    }
+}
+
+/// Any of the built-in notifiers
+#[derive(Debug, Clone)]
+pub enum AnyNotifier {
+    Log(LogNotifier),
+    Webhook(WebhookNotifier),
+    Telegram(TelegramNotifier),
+    Discord(DiscordNotifier),
+}
+
+impl Notifier for AnyNotifier {
+    fn name(&self) -> &str {
+        match self {
+            Self::Log(n) => n.name(),
+            Self::Webhook(n) => n.name(),
+            Self::Telegram(n) => n.name(),
+            Self::Discord(n) => n.name(),
+        }
+    }
+
+    async fn notify(&self, alerts: &[Alert]) -> DataMResult<()> {
+        match self {
+            Self::Log(n) => n.notify(alerts).await,
+            Self::Webhook(n) => n.notify(alerts).await,
+            Self::Telegram(n) => n.notify(alerts).await,
+            Self::Discord(n) => n.notify(alerts).await,
+        }
+    }
+
+    async fn notify_operational(&self, message: &str) -> DataMResult<()> {
+        match self {
+            Self::Log(n) => n.notify_operational(message).await,
+            Self::Webhook(n) => n.notify_operational(message).await,
+            Self::Telegram(n) => n.notify_operational(message).await,
+            Self::Discord(n) => n.notify_operational(message).await,
+        }
+    }
+}
+
+/// Delivers the alerts of every profile to its own notifiers
+///
+/// Every delivery runs in a task of its own, so that a slow or failing notifier holds up
+/// neither the fetches nor the other notifiers. Deliveries are counted in the [Metrics]
+/// per `<profile>/<notifier>`
+///
+/// ## Example
+/// Two profiles watching the same 7 PM slot, the first notifier of `seven` failing
+/// ```
+/// use std::sync::Arc;
+/// use activesg_gym_datamine::{
+///     metrics::Metrics,
+///     models::{Gym, GymSlotData, Timeslot},
+///     notify::{AlertDispatcher, NotifierConfig},
+///     watch::{Alert, Profile},
+/// };
+/// use chrono::{NaiveDate, TimeZone, Utc};
+/// use tokio::{
+///     io::{AsyncReadExt, AsyncWriteExt},
+///     net::TcpListener,
+///     sync::mpsc,
+/// };
+///
+/// /// Webhook answering `status`, sending on the alerts it received
+/// async fn webhook(status: &'static str) -> (NotifierConfig, mpsc::UnboundedReceiver<Vec<Alert>>) {
+///     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+///     let url = format!("http://{}/hook", listener.local_addr().unwrap());
+///     let (tx, rx) = mpsc::unbounded_channel();
+///     tokio::spawn(async move {
+///         loop {
+///             let (mut socket, _) = listener.accept().await.unwrap();
+///             let mut request = vec![];
+///             let mut buf = [0; 4096];
+///             while !request.ends_with(b"]") {
+///                 let n = socket.read(&mut buf).await.unwrap();
+///                 request.extend_from_slice(&buf[..n]);
+///             }
+///             let request = String::from_utf8(request).unwrap();
+///             let (_, body) = request.split_once("\r\n\r\n").unwrap();
+///             tx.send(serde_json::from_str(body).unwrap()).unwrap();
+///             let response = format!("HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status);
+///             socket.write_all(response.as_bytes()).await.unwrap();
+///         }
+///     });
+///     (NotifierConfig::Webhook { url }, rx)
+/// }
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let (evenings_hook, mut evenings) = webhook("200 OK").await;
+/// let (broken_hook, mut broken) = webhook("500 Internal Server Error").await;
+/// let (seven_hook, mut seven) = webhook("200 OK").await;
+/// let profiles = [
+///     Profile {
+///         name: "evenings".into(),
+///         watches: vec!["BISHAN=19:00-21:00".parse().unwrap()],
+///         notifiers: vec![evenings_hook],
+///     },
+///     Profile {
+///         name: "seven".into(),
+///         watches: vec!["BISHAN=19:00".parse().unwrap()],
+///         notifiers: vec![broken_hook, seven_hook],
+///     },
+/// ];
+/// let metrics = Arc::new(Metrics::default());
+/// let dispatcher = AlertDispatcher::new(&profiles, None, metrics.clone());
+///
+/// // 7 PM, 8 PM and 9 PM in Singapore
+/// let slots = (11..14).map(|h| Timeslot::new(Utc.ymd(2022, 1, 11).and_hms(h, 0, 0), 5)).collect();
+/// let captured = NaiveDate::from_ymd(2022, 1, 11).and_hms(1, 0, 0);
+/// let data = GymSlotData::new(Gym::BISHAN, captured, slots);
+/// assert_eq!(dispatcher.dispatch(&data, Utc.ymd(2022, 1, 11).and_hms(1, 0, 0)), 3);
+/// dispatcher.wait().await;
+///
+/// let hours = |alerts: Vec<Alert>| {
+///     alerts
+///         .iter()
+///         .map(|a| (a.profile.clone(), a.time.format("%H").to_string()))
+///         .collect::<Vec<_>>()
+/// };
+/// let evenings_alerts = hours(evenings.recv().await.unwrap());
+/// assert_eq!(evenings_alerts, [("evenings".into(), "11".into()), ("evenings".into(), "12".into())]);
+/// assert_eq!(hours(broken.recv().await.unwrap()), [("seven".into(), "11".into())]);
+/// // delivered despite the notifier before it failing
+/// assert_eq!(hours(seven.recv().await.unwrap()), [("seven".into(), "11".into())]);
+///
+/// let deliveries = metrics.notifier_deliveries();
+/// assert_eq!((deliveries["evenings/webhook"].ok, deliveries["evenings/webhook"].failed), (1, 0));
+/// assert_eq!((deliveries["seven/webhook"].ok, deliveries["seven/webhook"].failed), (1, 1));
+///
+/// // alerted once per slot
+/// assert_eq!(dispatcher.dispatch(&data, Utc.ymd(2022, 1, 11).and_hms(1, 0, 0)), 0);
+/// # }
+/// ```
+pub struct AlertDispatcher {
+    profiles: Vec<(Profile, Vec<AnyNotifier>)>,
+
+    /// alerts sent already, see [AlertTracker::fresh]
+    tracker: AlertTracker,
+
+    /// alerts are sorted nearest first from here, see [Alert::sort_by_distance]
+    home: Option<LatLng>,
+    metrics: Arc<Metrics>,
+
+    /// deliveries not awaited yet, see [AlertDispatcher::wait]
+    pending: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl AlertDispatcher {
+    pub fn new(profiles: &[Profile], home: Option<LatLng>, metrics: Arc<Metrics>) -> Self {
+        Self {
+            profiles: profiles
+                .iter()
+                .map(|p| (p.clone(), p.notifiers.iter().map(|n| n.build()).collect()))
+                .collect(),
+            tracker: AlertTracker::default(),
+            home,
+            metrics,
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Evaluates every profile against `data` and starts delivering the new alerts
+    /// without waiting for them, returning the number of deliveries started
+    ///
+    /// The deliveries carry the trace of the iteration, see [crate::trace]
+    pub fn dispatch(&self, data: &GymSlotData, now: DateTime<Utc>) -> usize {
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|delivery| !delivery.is_finished());
+
+        let mut started = 0;
+        for (profile, notifiers) in &self.profiles {
+            let mut alerts = self.tracker.fresh(profile, data, now);
+            if alerts.is_empty() {
+                continue;
+            }
+            Alert::sort_by_distance(&mut alerts, self.home);
+            let alerts = Arc::new(alerts);
+
+            for notifier in notifiers {
+                let (notifier, alerts) = (notifier.clone(), alerts.clone());
+                let (metrics, profile) = (self.metrics.clone(), profile.name.clone());
+                let span = trace::notifier_span(&profile, notifier.name());
+                let delivery = async move {
+                    let res = notifier.notify(&alerts).await;
+                    record_delivery(&metrics, &profile, &notifier, res);
+                }
+                .instrument(span);
+                pending.push(match trace::current() {
+                    Some(context) => tokio::spawn(trace::scope(context, delivery)),
+                    None => tokio::spawn(delivery),
+                });
+                started += 1;
+            }
+        }
+        started
+    }
+
+    /// Sends `message` through the notifiers of every profile, one after the other
+    pub async fn notify_operational(&self, message: &str) {
+        for (profile, notifiers) in &self.profiles {
+            for notifier in notifiers {
+                let span = trace::notifier_span(&profile.name, notifier.name());
+                async {
+                    let res = notifier.notify_operational(message).await;
+                    record_delivery(&self.metrics, &profile.name, notifier, res);
+                }
+                .instrument(span)
+                .await;
+            }
+        }
+    }
+
+    /// Waits for the deliveries started so far
+    pub async fn wait(&self) {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        for delivery in pending {
+            if let Err(e) = delivery.await {
+                warn!("alert delivery panicked: {}", e);
+            }
+        }
+    }
+}
+
+/// Counts a delivery by `notifier` of `profile` in `metrics`, logging a failure
+/// and recording it on the current span
+fn record_delivery(metrics: &Metrics, profile: &str, notifier: &AnyNotifier, res: DataMResult<()>) {
+    trace::record_outcome(&Span::current(), &res);
+    if let Err(e) = &res {
+        warn!(
+            "profile {}, notifier {} failed: {}",
+            profile,
+            notifier.name(),
+            e
+        );
+    }
+    metrics.record_delivery(&format!("{}/{}", profile, notifier.name()), res.is_ok());
+}
@@ -0,0 +1,113 @@
+use crate::errors::{Error, FailurePhase};
+
+/// Share of the attempted fetches of an iteration that must fail in the response phase
+/// before ActiveSG is suspected to be degraded
+pub const DEGRADED_SHARE: f64 = 0.5;
+
+/// Failures of an iteration per [FailurePhase]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PhaseCounts {
+    pub attempted: usize,
+    pub connect: usize,
+    pub response: usize,
+}
+
+impl PhaseCounts {
+    pub fn record_ok(&mut self) {
+        self.attempted += 1;
+    }
+
+    pub fn record_failure(&mut self, e: &Error) {
+        self.attempted += 1;
+        match e.phase() {
+            FailurePhase::Connect => self.connect += 1,
+            FailurePhase::Response => self.response += 1,
+            FailurePhase::Other => (),
+        }
+    }
+
+    /// Response failures outnumber the connect failures and reach [DEGRADED_SHARE] of the
+    /// attempted fetches, the reference probe is only worth sending then
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::outage::PhaseCounts;
+    ///
+    /// // (attempted, connect, response) => dominates
+    /// let table = [
+    ///     ((0, 0, 0), false),
+    ///     ((20, 0, 0), false),
+    ///     ((20, 0, 9), false),
+    ///     ((20, 0, 10), true),
+    ///     ((20, 0, 20), true),
+    ///     ((20, 10, 10), false),
+    ///     ((20, 9, 11), true),
+    ///     ((20, 20, 0), false),
+    ///     ((1, 0, 1), true),
+    /// ];
+    /// for ((attempted, connect, response), dominates) in table {
+    ///     let counts = PhaseCounts { attempted, connect, response };
+    ///     assert_eq!(counts.response_dominates(), dominates, "{:?}", counts);
+    /// }
+    /// ```
+    pub fn response_dominates(&self) -> bool {
+        self.response > self.connect
+            && self.response as f64 >= self.attempted as f64 * DEGRADED_SHARE
+    }
+}
+
+/// Who is to blame for the failures of an iteration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// no failures, or too few to tell
+    Healthy,
+
+    /// ActiveSG couldn't be reached, or neither could the reference probe
+    LocalNetwork,
+
+    /// ActiveSG was reached but kept failing while the rest of the internet works
+    Degraded,
+}
+
+/// Judges the failures of an iteration
+///
+/// `probe` is the outcome of the reference probe, none when there is no reference url,
+/// in which case the failures alone decide
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::outage::{self, PhaseCounts, Verdict::*};
+///
+/// let counts = |connect, response| PhaseCounts { attempted: 20, connect, response };
+///
+/// // (connect, response, probe) => verdict
+/// let table = [
+///     ((0, 0, None), Healthy),
+///     ((0, 0, Some(false)), Healthy),
+///     ((0, 3, None), Healthy),
+///     ((0, 3, Some(true)), Healthy),
+///     ((0, 15, Some(true)), Degraded),
+///     ((0, 15, None), Degraded),
+///     ((0, 15, Some(false)), LocalNetwork),
+///     ((15, 0, Some(true)), LocalNetwork),
+///     ((15, 0, None), LocalNetwork),
+///     ((3, 0, None), LocalNetwork),
+///     ((10, 10, Some(true)), LocalNetwork),
+///     ((2, 3, None), Healthy),
+/// ];
+/// for ((connect, response, probe), verdict) in table {
+///     assert_eq!(outage::assess(&counts(connect, response), probe), verdict);
+/// }
+/// ```
+pub fn assess(counts: &PhaseCounts, probe: Option<bool>) -> Verdict {
+    if counts.connect == 0 && counts.response == 0 {
+        return Verdict::Healthy;
+    }
+
+    match (counts.response_dominates(), probe) {
+        (true, Some(false)) => Verdict::LocalNetwork,
+        (true, _) => Verdict::Degraded,
+        (false, _) if counts.connect >= counts.response => Verdict::LocalNetwork,
+        (false, _) => Verdict::Healthy,
+    }
+}
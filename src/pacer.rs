@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{config::Config, fetch::FetchTask};
+
+/// Why the [Pacer] waited before a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WaitReason {
+    /// [Config::inter_gym_delay] between consecutive fetches
+    InterGymDelay,
+
+    /// spacing enforced by a global rate limiter
+    RateLimiter,
+
+    /// backoff before retrying a failed fetch
+    RetryBackoff,
+
+    /// pause asked for by ActiveSG with a 429
+    TooManyRequests,
+}
+
+impl WaitReason {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::InterGymDelay => "inter_gym_delay",
+            Self::RateLimiter => "rate_limiter",
+            Self::RetryBackoff => "retry_backoff",
+            Self::TooManyRequests => "too_many_requests",
+        }
+    }
+}
+
+/// Every wait between requests goes through here, so that it is logged with the fetch
+/// it precedes and the caller can account for the time spent waiting
+///
+/// ## Example
+/// ```
+/// use std::time::Duration;
+/// use activesg_gym_datamine::{
+///     fetch::FetchTask,
+///     models::{Activity, Gym},
+///     pacer::{Pacer, WaitReason},
+/// };
+///
+/// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+/// # async fn main() {
+/// let pacer = Pacer::new(Duration::from_secs(2));
+/// let task = FetchTask::new(Activity::GYM, Gym::BISHAN, chrono::NaiveDate::from_ymd(2022, 1, 11));
+///
+/// let start = tokio::time::Instant::now();
+/// assert_eq!(pacer.between_fetches(task).await, Duration::from_secs(2));
+/// assert_eq!(
+///     pacer.wait(task, Duration::from_millis(500), WaitReason::RetryBackoff).await,
+///     Duration::from_millis(500)
+/// );
+/// assert_eq!(start.elapsed(), Duration::from_millis(2500));
+///
+/// assert_eq!(Pacer::new(Duration::ZERO).between_fetches(task).await, Duration::ZERO);
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pacer {
+    inter_gym_delay: Duration,
+}
+
+impl Pacer {
+    pub fn new(inter_gym_delay: Duration) -> Self {
+        Self { inter_gym_delay }
+    }
+
+    /// Paces with [Config::politeness_delay] between fetches
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.politeness_delay())
+    }
+
+    /// Waits the delay between two consecutive fetches before fetching `next`
+    pub async fn between_fetches(&self, next: FetchTask) -> Duration {
+        self.wait(next, self.inter_gym_delay, WaitReason::InterGymDelay)
+            .await
+    }
+
+    /// Waits `delay` before fetching `task`, returning the time waited
+    pub async fn wait(&self, task: FetchTask, delay: Duration, reason: WaitReason) -> Duration {
+        if delay.is_zero() {
+            return Duration::ZERO;
+        }
+
+        debug!(
+            "{:?} {:?} {}: waiting {}ms, {}",
+            task.activity,
+            task.gym,
+            task.date,
+            delay.as_millis(),
+            reason.as_str()
+        );
+        tokio::time::sleep(delay).await;
+        delay
+    }
+}
@@ -0,0 +1,843 @@
+//! Parquet output, one file per day with a row group per iteration, only built with the
+//! `parquet` feature
+//!
+//! Written without a Parquet library: every column is required, PLAIN encoded and uncompressed
+//! in a single data page per row group, and the footer is encoded with the Thrift compact
+//! protocol by hand. Snapshots written as [crate::sink::OutputFormat::Parquet] land in
+//! `<output_dir>/<date>.parquet`, by Singapore date of capture, with the columns:
+//!
+//! | column        | type                     |
+//! |---------------|--------------------------|
+//! | `activity`    | BYTE_ARRAY (UTF8)        |
+//! | `gym_id`      | INT32 (UINT_16)          |
+//! | `gym_name`    | BYTE_ARRAY (UTF8)        |
+//! | `scraped_at`  | INT64 (TIMESTAMP_MILLIS) |
+//! | `slot_time`   | INT64 (TIMESTAMP_MILLIS) |
+//! | `slots_avail` | INT32 (UINT_16)          |
+//!
+//! Activities are named like [Activity], e.g. `GYM`. Rows are buffered by a [RowGroupBuffer] and
+//! written when the sink is flushed, at the end of every iteration. Appending a row group rewrites
+//! the file with a new footer, atomically. A file written before the `activity` column is read as
+//! gym rows, and rewritten with the column when appended to
+//!
+//! ## Example
+//! ```
+//! use activesg_gym_datamine::{
+//!     models::{Activity, Gym, GymSlotData, Timeslot},
+//!     parquet::{self, ParquetRow},
+//! };
+//! use chrono::{NaiveDate, TimeZone, Utc};
+//!
+//! let dir = std::env::temp_dir().join(format!("parquet-doctest-{}", std::process::id()));
+//! let path = dir.join("2022-01-11.parquet");
+//! let snapshot = |gym, minute, slots: &[u16]| {
+//!     let captured = NaiveDate::from_ymd(2022, 1, 11).and_hms_milli(1, minute, 33, 621);
+//!     let slots = slots
+//!         .iter()
+//!         .enumerate()
+//!         .map(|(h, n)| Timeslot::new(Utc.ymd(2022, 1, 11).and_hms(h as u32, 0, 0), *n))
+//!         .collect();
+//!     GymSlotData::new(gym, captured, slots)
+//! };
+//!
+//! // two iterations, two row groups
+//! let courts = snapshot(Gym::BISHAN, 30, &[3]).with_activity(Activity::BADMINTON);
+//! let first = [snapshot(Gym::BISHAN, 30, &[25, 20]), snapshot(Gym::CLEMENTI, 30, &[3]), courts];
+//! let second = [snapshot(Gym::BISHAN, 50, &[24, 0])];
+//! let rows = |snapshots: &[GymSlotData]| {
+//!     snapshots.iter().flat_map(parquet::rows_of).collect::<Vec<_>>()
+//! };
+//! assert!(parquet::append_row_group(&path, &rows(&first)).unwrap() > 0);
+//! assert!(parquet::append_row_group(&path, &rows(&second)).unwrap() > 0);
+//!
+//! let raw = std::fs::read(&path).unwrap();
+//! assert!(raw.starts_with(b"PAR1") && raw.ends_with(b"PAR1"));
+//!
+//! let read = parquet::read(&path).unwrap();
+//! assert_eq!(read, [rows(&first), rows(&second)].concat());
+//! assert_eq!(
+//!     read[0],
+//!     ParquetRow {
+//!         activity: Activity::GYM,
+//!         gym_id: 137,
+//!         gym_name: "Bishan ActiveSG Gym".into(),
+//!         scraped_at: first[0].datetime(),
+//!         slot_time: first[0].data()[0].time(),
+//!         slots_avail: 25,
+//!     }
+//! );
+//! let avail = read.iter().map(|r| r.slots_avail).collect::<Vec<_>>();
+//! assert_eq!(avail, [25, 20, 3, 3, 24, 0]);
+//! assert_eq!(read[3].activity, Activity::BADMINTON);
+//!
+//! // the same rows through the parquet crate
+//! use apache_parquet::{
+//!     file::reader::{FileReader, SerializedFileReader},
+//!     record::Field,
+//! };
+//! let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+//! assert_eq!(reader.metadata().num_row_groups(), 2);
+//! assert_eq!(reader.metadata().file_metadata().num_rows(), 6);
+//! let records = reader.get_row_iter(None).unwrap().collect::<Vec<_>>();
+//! let fields = records[3].get_column_iter().map(|(name, field)| (name.as_str(), field.clone()));
+//! assert_eq!(
+//!     fields.collect::<Vec<_>>(),
+//!     [
+//!         ("activity", Field::Str("BADMINTON".into())),
+//!         ("gym_id", Field::UShort(137)),
+//!         ("gym_name", Field::Str("Bishan ActiveSG Gym".into())),
+//!         ("scraped_at", Field::TimestampMillis(first[2].datetime().timestamp_millis() as u64)),
+//!         ("slot_time", Field::TimestampMillis(first[2].data()[0].time().timestamp_millis() as u64)),
+//!         ("slots_avail", Field::UShort(3)),
+//!     ]
+//! );
+//!
+//! std::fs::write(&path, b"PAR1 not quite PAR1").unwrap();
+//! assert!(parquet::read(&path).is_err());
+//! assert!(parquet::append_row_group(&path, &rows(&first)).is_err());
+//! std::fs::remove_dir_all(&dir).unwrap();
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use tracing::info;
+
+use crate::{
+    clock::SGT,
+    errors,
+    models::{Activity, GymSlotData},
+    sink, DataMResult,
+};
+
+const MAGIC: &[u8; 4] = b"PAR1";
+
+// physical types
+const INT32: i32 = 1;
+const INT64: i32 = 2;
+const BYTE_ARRAY: i32 = 6;
+
+// converted types
+const UTF8: i32 = 0;
+const TIMESTAMP_MILLIS: i32 = 9;
+const UINT_16: i32 = 12;
+
+const REQUIRED: i32 = 0;
+const DATA_PAGE: i32 = 0;
+const PLAIN: i32 = 0;
+const RLE: i32 = 3;
+const UNCOMPRESSED: i32 = 0;
+
+/// Name, physical type and converted type of a column
+type Column = (&'static str, i32, i32);
+
+/// Every column, in order
+const COLUMNS: [Column; 6] = [
+    ("activity", BYTE_ARRAY, UTF8),
+    ("gym_id", INT32, UINT_16),
+    ("gym_name", BYTE_ARRAY, UTF8),
+    ("scraped_at", INT64, TIMESTAMP_MILLIS),
+    ("slot_time", INT64, TIMESTAMP_MILLIS),
+    ("slots_avail", INT32, UINT_16),
+];
+
+/// Columns of the files written before the activity, all gym rows
+const LEGACY_COLUMNS: &[Column] = COLUMNS.split_at(1).1;
+
+/// A row of a Parquet file, one per timeslot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParquetRow {
+    pub activity: Activity,
+    pub gym_id: u16,
+    pub gym_name: String,
+
+    /// to the millisecond
+    pub scraped_at: NaiveDateTime,
+    pub slot_time: DateTime<Utc>,
+    pub slots_avail: u16,
+}
+
+fn from_millis(ms: i64) -> Option<NaiveDateTime> {
+    let nanos = ms.rem_euclid(1000) as u32 * 1_000_000;
+    NaiveDateTime::from_timestamp_opt(ms.div_euclid(1000), nanos)
+}
+
+/// Rows of every timeslot of `data`, as they are read back
+pub fn rows_of(data: &GymSlotData) -> Vec<ParquetRow> {
+    let gym = data.gym();
+    let scraped_at = from_millis(data.datetime().timestamp_millis()).unwrap();
+    data.data()
+        .iter()
+        .map(|slot| ParquetRow {
+            activity: data.activity(),
+            gym_id: gym.id(),
+            gym_name: gym.display().to_string(),
+            scraped_at,
+            slot_time: Utc.from_utc_datetime(&from_millis(slot.time().timestamp_millis()).unwrap()),
+            slots_avail: slot.slots_avail(),
+        })
+        .collect()
+}
+
+/// Value of the Thrift compact protocol, only the types found in Parquet metadata
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Thrift {
+    Bool(bool),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    Binary(Vec<u8>),
+
+    /// type of the elements, and the elements
+    List(u8, Vec<Thrift>),
+
+    /// fields by ascending id
+    Struct(Vec<(i16, Thrift)>),
+}
+
+const STRUCT: u8 = 12;
+
+fn write_varint(buf: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        buf.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.push(v as u8);
+}
+
+fn zigzag(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+impl Thrift {
+    fn binary(s: &str) -> Self {
+        Self::Binary(s.as_bytes().to_vec())
+    }
+
+    fn type_code(&self) -> u8 {
+        match self {
+            Self::Bool(true) => 1,
+            Self::Bool(false) => 2,
+            Self::I16(_) => 4,
+            Self::I32(_) => 5,
+            Self::I64(_) => 6,
+            Self::Binary(_) => 8,
+            Self::List(..) => 9,
+            Self::Struct(_) => STRUCT,
+        }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Self::Bool(b) => buf.push(if *b { 1 } else { 2 }),
+            Self::I16(v) => write_varint(buf, zigzag(*v as i64)),
+            Self::I32(v) => write_varint(buf, zigzag(*v as i64)),
+            Self::I64(v) => write_varint(buf, zigzag(*v)),
+            Self::Binary(b) => {
+                write_varint(buf, b.len() as u64);
+                buf.extend_from_slice(b);
+            }
+            Self::List(elem, items) => {
+                match items.len() {
+                    n if n < 15 => buf.push((n as u8) << 4 | elem),
+                    n => {
+                        buf.push(0xf0 | elem);
+                        write_varint(buf, n as u64);
+                    }
+                }
+                for item in items {
+                    item.encode(buf);
+                }
+            }
+            Self::Struct(fields) => {
+                let mut last = 0;
+                for (id, value) in fields {
+                    let ty = value.type_code();
+                    match id - last {
+                        delta @ 1..=15 => buf.push((delta as u8) << 4 | ty),
+                        _ => {
+                            buf.push(ty);
+                            write_varint(buf, zigzag(*id as i64));
+                        }
+                    }
+                    // booleans are carried by the type of the field
+                    if !matches!(value, Self::Bool(_)) {
+                        value.encode(buf);
+                    }
+                    last = *id;
+                }
+                buf.push(0);
+            }
+        }
+    }
+
+    fn field(&self, id: i16) -> Result<&Thrift, String> {
+        match self {
+            Self::Struct(fields) => fields
+                .iter()
+                .find(|(i, _)| *i == id)
+                .map(|(_, v)| v)
+                .ok_or_else(|| format!("missing field {}", id)),
+            _ => Err(format!("field {} of a value that isn't a struct", id)),
+        }
+    }
+
+    fn as_i64(&self) -> Result<i64, String> {
+        match self {
+            Self::I16(v) => Ok(*v as i64),
+            Self::I32(v) => Ok(*v as i64),
+            Self::I64(v) => Ok(*v),
+            v => Err(format!("expected an integer, found {:?}", v)),
+        }
+    }
+
+    fn as_list(&self) -> Result<&[Thrift], String> {
+        match self {
+            Self::List(_, items) => Ok(items),
+            v => Err(format!("expected a list, found {:?}", v)),
+        }
+    }
+}
+
+/// Reads the values of the Thrift compact protocol, and PLAIN encoded values, from a buffer
+struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn at(buf: &'a [u8], pos: usize) -> Self {
+        Self { buf, pos }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|end| *end <= self.buf.len())
+            .ok_or_else(|| format!("truncated at byte {}", self.pos))?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn i32_le(&mut self) -> Result<i32, String> {
+        Ok(i32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn i64_le(&mut self) -> Result<i64, String> {
+        Ok(i64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut v = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            v |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err(format!("varint too long at byte {}", self.pos))
+    }
+
+    fn zigzag(&mut self) -> Result<i64, String> {
+        let v = self.varint()?;
+        Ok((v >> 1) as i64 ^ -((v & 1) as i64))
+    }
+
+    fn value(&mut self, ty: u8) -> Result<Thrift, String> {
+        Ok(match ty {
+            1 => Thrift::Bool(true),
+            2 => Thrift::Bool(false),
+            4 => Thrift::I16(self.zigzag()? as i16),
+            5 => Thrift::I32(self.zigzag()? as i32),
+            6 => Thrift::I64(self.zigzag()?),
+            8 => {
+                let len = self.varint()? as usize;
+                Thrift::Binary(self.bytes(len)?.to_vec())
+            }
+            9 | 10 => {
+                let header = self.byte()?;
+                let (elem, len) = match header >> 4 {
+                    15 => (header & 0x0f, self.varint()? as usize),
+                    len => (header & 0x0f, len as usize),
+                };
+                let mut items = Vec::with_capacity(len.min(self.buf.len() - self.pos));
+                for _ in 0..len {
+                    items.push(match elem {
+                        1 | 2 => Thrift::Bool(self.byte()? == 1),
+                        elem => self.value(elem)?,
+                    });
+                }
+                Thrift::List(elem, items)
+            }
+            STRUCT => {
+                let mut fields = vec![];
+                let mut last = 0;
+                loop {
+                    let header = self.byte()?;
+                    if header == 0 {
+                        break Thrift::Struct(fields);
+                    }
+                    let id = match header >> 4 {
+                        0 => self.zigzag()? as i16,
+                        delta => last + delta as i16,
+                    };
+                    fields.push((id, self.value(header & 0x0f)?));
+                    last = id;
+                }
+            }
+            ty => return Err(format!("unsupported Thrift type {}", ty)),
+        })
+    }
+}
+
+fn schema(columns: &[Column]) -> Vec<Thrift> {
+    let root = Thrift::Struct(vec![
+        (4, Thrift::binary("schema")),
+        (5, Thrift::I32(columns.len() as i32)),
+    ]);
+    let columns = columns.iter().map(|(name, ty, converted)| {
+        Thrift::Struct(vec![
+            (1, Thrift::I32(*ty)),
+            (3, Thrift::I32(REQUIRED)),
+            (4, Thrift::binary(name)),
+            (6, Thrift::I32(*converted)),
+        ])
+    });
+    std::iter::once(root).chain(columns).collect()
+}
+
+/// PLAIN encoded values of column `index` of `rows`
+fn column_values(rows: &[ParquetRow], index: usize) -> Vec<u8> {
+    let mut buf = vec![];
+    let text = |buf: &mut Vec<u8>, s: &str| {
+        buf.extend((s.len() as u32).to_le_bytes());
+        buf.extend(s.as_bytes());
+    };
+    for row in rows {
+        match index {
+            0 => text(&mut buf, &format!("{:?}", row.activity)),
+            1 => buf.extend((row.gym_id as i32).to_le_bytes()),
+            2 => text(&mut buf, &row.gym_name),
+            3 => buf.extend(row.scraped_at.timestamp_millis().to_le_bytes()),
+            4 => buf.extend(row.slot_time.timestamp_millis().to_le_bytes()),
+            _ => buf.extend((row.slots_avail as i32).to_le_bytes()),
+        }
+    }
+    buf
+}
+
+/// Appends the column chunks of `rows` to `buf`, returning the metadata of the row group
+fn write_row_group(rows: &[ParquetRow], buf: &mut Vec<u8>) -> Thrift {
+    let num_rows = rows.len() as i64;
+    let mut columns = vec![];
+    let mut total = 0;
+    for (index, (name, ty, _)) in COLUMNS.iter().enumerate() {
+        let values = column_values(rows, index);
+        let page_header = Thrift::Struct(vec![
+            (1, Thrift::I32(DATA_PAGE)),
+            (2, Thrift::I32(values.len() as i32)),
+            (3, Thrift::I32(values.len() as i32)),
+            (
+                5,
+                Thrift::Struct(vec![
+                    (1, Thrift::I32(rows.len() as i32)),
+                    (2, Thrift::I32(PLAIN)),
+                    (3, Thrift::I32(RLE)),
+                    (4, Thrift::I32(RLE)),
+                ]),
+            ),
+        ]);
+
+        let offset = buf.len() as i64;
+        page_header.encode(buf);
+        buf.extend(values);
+        let size = buf.len() as i64 - offset;
+        total += size;
+
+        let metadata = Thrift::Struct(vec![
+            (1, Thrift::I32(*ty)),
+            (2, Thrift::List(5, vec![Thrift::I32(PLAIN)])),
+            (3, Thrift::List(8, vec![Thrift::binary(name)])),
+            (4, Thrift::I32(UNCOMPRESSED)),
+            (5, Thrift::I64(num_rows)),
+            (6, Thrift::I64(size)),
+            (7, Thrift::I64(size)),
+            (9, Thrift::I64(offset)),
+        ]);
+        columns.push(Thrift::Struct(vec![
+            (2, Thrift::I64(offset)),
+            (3, metadata),
+        ]));
+    }
+
+    Thrift::Struct(vec![
+        (1, Thrift::List(STRUCT, columns)),
+        (2, Thrift::I64(total)),
+        (3, Thrift::I64(num_rows)),
+    ])
+}
+
+/// Decodes the footer of `raw`, returning it with the offset it starts at and the columns of
+/// the file, [COLUMNS] or [LEGACY_COLUMNS]
+fn read_footer(raw: &[u8]) -> Result<(Thrift, usize, &'static [Column]), String> {
+    if raw.len() < 12 || !raw.starts_with(MAGIC) || !raw.ends_with(MAGIC) {
+        return Err("not a Parquet file".into());
+    }
+    let len = u32::from_le_bytes(raw[raw.len() - 8..raw.len() - 4].try_into().unwrap());
+    let start = (raw.len() - 8)
+        .checked_sub(len as usize)
+        .filter(|start| *start >= MAGIC.len())
+        .ok_or("footer longer than the file")?;
+
+    let footer = Decoder::at(&raw[..raw.len() - 8], start).value(STRUCT)?;
+    let file_schema = footer.field(2)?.as_list()?;
+    let columns = [&COLUMNS[..], LEGACY_COLUMNS]
+        .into_iter()
+        .find(|columns| file_schema == schema(columns))
+        .ok_or("not written by this miner, the schema differs")?;
+    Ok((footer, start, columns))
+}
+
+/// Appends `rows` as a row group to the file at `path`, creating it when missing,
+/// and returns the number of bytes the file grew by
+pub fn append_row_group(path: &Path, rows: &[ParquetRow]) -> DataMResult<u64> {
+    if rows.is_empty() {
+        return Ok(0);
+    }
+    let corrupt = |e: String| errors::Error::Parquet(format!("{}: {}", path.display(), e));
+
+    let (mut buf, mut row_groups, mut num_rows, before) = match fs::read(path) {
+        Ok(raw) if read_footer(&raw).map_err(corrupt)?.2 == LEGACY_COLUMNS => {
+            info!("{}, rewriting with the activity column", path.display());
+            let mut buf = MAGIC.to_vec();
+            let mut row_groups = vec![];
+            let mut num_rows = 0;
+            for rows in read_row_groups(&raw).map_err(corrupt)? {
+                row_groups.push(write_row_group(&rows, &mut buf));
+                num_rows += rows.len() as i64;
+            }
+            (buf, row_groups, num_rows, raw.len())
+        }
+        Ok(mut raw) => {
+            let (footer, start, _) = read_footer(&raw).map_err(corrupt)?;
+            let row_groups = footer.field(4).and_then(Thrift::as_list).map_err(corrupt)?;
+            let num_rows = footer.field(3).and_then(Thrift::as_i64).map_err(corrupt)?;
+            let (row_groups, before) = (row_groups.to_vec(), raw.len());
+            raw.truncate(start);
+            (raw, row_groups, num_rows, before)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (MAGIC.to_vec(), vec![], 0, 0),
+        Err(e) => return Err(e.into()),
+    };
+
+    row_groups.push(write_row_group(rows, &mut buf));
+    num_rows += rows.len() as i64;
+    let footer = Thrift::Struct(vec![
+        (1, Thrift::I32(1)),
+        (2, Thrift::List(STRUCT, schema(&COLUMNS))),
+        (3, Thrift::I64(num_rows)),
+        (4, Thrift::List(STRUCT, row_groups)),
+        (
+            6,
+            Thrift::binary(concat!(
+                "activesg_gym_datamine version ",
+                env!("CARGO_PKG_VERSION")
+            )),
+        ),
+    ]);
+    let start = buf.len();
+    footer.encode(&mut buf);
+    let len = (buf.len() - start) as u32;
+    buf.extend(len.to_le_bytes());
+    buf.extend(MAGIC);
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    sink::write_atomic(path, &buf)?;
+    Ok(buf.len().saturating_sub(before) as u64)
+}
+
+/// Every row of a file written by [append_row_group], in the order they were written
+///
+/// Files with the same columns written by other tools are read too, as long as their pages are
+/// PLAIN encoded and uncompressed
+///
+/// ## Example
+/// ```
+/// use std::sync::Arc;
+/// use activesg_gym_datamine::{
+///     models::{Activity, Gym, GymSlotData, Timeslot},
+///     parquet,
+/// };
+/// use apache_parquet::{
+///     basic::Compression,
+///     column::writer::ColumnWriter,
+///     data_type::ByteArray,
+///     file::{
+///         properties::{WriterProperties, WriterVersion},
+///         reader::{FileReader, SerializedFileReader},
+///         writer::SerializedFileWriter,
+///     },
+///     schema::parser::parse_message_type,
+/// };
+/// use chrono::{NaiveDate, TimeZone, Utc};
+///
+/// let dir = std::env::temp_dir().join(format!("parquet-read-doctest-{}", std::process::id()));
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let path = dir.join("2022-01-11.parquet");
+///
+/// // a file of the columns before the activity, written by the parquet crate
+/// let schema = parse_message_type(
+///     "message schema {
+///         REQUIRED INT32 gym_id (UINT_16);
+///         REQUIRED BYTE_ARRAY gym_name (UTF8);
+///         REQUIRED INT64 scraped_at (TIMESTAMP_MILLIS);
+///         REQUIRED INT64 slot_time (TIMESTAMP_MILLIS);
+///         REQUIRED INT32 slots_avail (UINT_16);
+///     }",
+/// )
+/// .unwrap();
+/// let properties = WriterProperties::builder()
+///     .set_writer_version(WriterVersion::PARQUET_1_0)
+///     .set_dictionary_enabled(false)
+///     .set_compression(Compression::UNCOMPRESSED)
+///     .build();
+/// let file = std::fs::File::create(&path).unwrap();
+/// let mut writer = SerializedFileWriter::new(file, Arc::new(schema), Arc::new(properties)).unwrap();
+/// let mut group = writer.next_row_group().unwrap();
+/// let scraped_at = Utc.ymd(2022, 1, 11).and_hms(1, 30, 0).timestamp_millis();
+/// let slot_times = [2, 3].map(|h| Utc.ymd(2022, 1, 11).and_hms(h, 0, 0).timestamp_millis());
+/// let mut index = 0;
+/// while let Some(mut column) = group.next_column().unwrap() {
+///     match (index, column.untyped()) {
+///         (0, ColumnWriter::Int32ColumnWriter(w)) => w.write_batch(&[137, 137], None, None),
+///         (1, ColumnWriter::ByteArrayColumnWriter(w)) => {
+///             let name = ByteArray::from("Bishan ActiveSG Gym");
+///             w.write_batch(&[name.clone(), name], None, None)
+///         }
+///         (2, ColumnWriter::Int64ColumnWriter(w)) => w.write_batch(&[scraped_at; 2], None, None),
+///         (3, ColumnWriter::Int64ColumnWriter(w)) => w.write_batch(&slot_times, None, None),
+///         (4, ColumnWriter::Int32ColumnWriter(w)) => w.write_batch(&[25, 20], None, None),
+///         _ => unreachable!(),
+///     }
+///     .unwrap();
+///     column.close().unwrap();
+///     index += 1;
+/// }
+/// group.close().unwrap();
+/// writer.close().unwrap();
+///
+/// // read as gym rows
+/// let captured = NaiveDate::from_ymd(2022, 1, 11).and_hms(1, 30, 0);
+/// let slots = vec![
+///     Timeslot::new(Utc.ymd(2022, 1, 11).and_hms(2, 0, 0), 25),
+///     Timeslot::new(Utc.ymd(2022, 1, 11).and_hms(3, 0, 0), 20),
+/// ];
+/// let old = parquet::rows_of(&GymSlotData::new(Gym::BISHAN, captured, slots.clone()));
+/// assert_eq!(parquet::read(&path).unwrap(), old);
+///
+/// // and rewritten with the activity when appended to
+/// let courts = GymSlotData::new(Gym::BISHAN, captured, slots).with_activity(Activity::BADMINTON);
+/// parquet::append_row_group(&path, &parquet::rows_of(&courts)).unwrap();
+/// let read = parquet::read(&path).unwrap();
+/// assert_eq!(read, [old, parquet::rows_of(&courts)].concat());
+///
+/// let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+/// let columns = reader.metadata().file_metadata().schema_descr().num_columns();
+/// assert_eq!((reader.metadata().num_row_groups(), columns), (2, 6));
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub fn read(path: &Path) -> DataMResult<Vec<ParquetRow>> {
+    let raw = fs::read(path)?;
+    read_row_groups(&raw)
+        .map(|groups| groups.concat())
+        .map_err(|e| errors::Error::Parquet(format!("{}: {}", path.display(), e)))
+}
+
+/// Rows of every row group of `raw`
+fn read_row_groups(raw: &[u8]) -> Result<Vec<Vec<ParquetRow>>, String> {
+    let (footer, _, columns) = read_footer(raw)?;
+    // where the columns of COLUMNS are in the file, the activity being missing from old files
+    let at = |name: &str| columns.iter().position(|(n, _, _)| *n == name);
+
+    let mut groups = vec![];
+    for group in footer.field(4)?.as_list()? {
+        let num_rows = group.field(3)?.as_i64()? as usize;
+        let chunks = group.field(1)?.as_list()?;
+        if chunks.len() != columns.len() {
+            return Err(format!("{} columns in a row group", chunks.len()));
+        }
+
+        let mut numbers = vec![vec![]; columns.len()];
+        let mut texts = vec![vec![]; columns.len()];
+        for (index, chunk) in chunks.iter().enumerate() {
+            let offset = chunk.field(3)?.field(9)?.as_i64()? as usize;
+            let mut decoder = Decoder::at(raw, offset);
+            let page_header = decoder.value(STRUCT)?;
+            let num_values = page_header.field(5)?.field(1)?.as_i64()? as usize;
+            if num_values != num_rows {
+                return Err(format!(
+                    "{} values of {} in a row group of {} rows",
+                    num_values, columns[index].0, num_rows
+                ));
+            }
+
+            for _ in 0..num_values {
+                match columns[index].1 {
+                    INT32 => numbers[index].push(decoder.i32_le()? as i64),
+                    INT64 => numbers[index].push(decoder.i64_le()?),
+                    _ => {
+                        let len = decoder.i32_le()? as usize;
+                        let text = String::from_utf8(decoder.bytes(len)?.to_vec())
+                            .map_err(|e| e.to_string())?;
+                        texts[index].push(text);
+                    }
+                }
+            }
+        }
+
+        let time = |ms: i64| from_millis(ms).ok_or_else(|| format!("invalid time {}ms", ms));
+        let number = |name| numbers[at(name).unwrap()].iter();
+        let (gym_ids, scraped_at, slot_times, slots_avail) = (
+            number("gym_id"),
+            number("scraped_at"),
+            number("slot_time"),
+            number("slots_avail"),
+        );
+        let gym_names = std::mem::take(&mut texts[at("gym_name").unwrap()]);
+        let mut activities = at("activity").map(|i| std::mem::take(&mut texts[i]).into_iter());
+
+        let mut rows = vec![];
+        let columns = gym_ids
+            .zip(gym_names)
+            .zip(scraped_at.zip(slot_times).zip(slots_avail));
+        for ((gym_id, gym_name), ((scraped_at, slot_time), slots_avail)) in columns {
+            let activity = match activities.as_mut().and_then(Iterator::next) {
+                Some(activity) => activity.parse().map_err(|e: errors::Error| e.to_string())?,
+                None => Activity::GYM,
+            };
+            rows.push(ParquetRow {
+                activity,
+                gym_id: *gym_id as u16,
+                gym_name,
+                scraped_at: time(*scraped_at)?,
+                slot_time: Utc.from_utc_datetime(&time(*slot_time)?),
+                slots_avail: *slots_avail as u16,
+            });
+        }
+        groups.push(rows);
+    }
+    Ok(groups)
+}
+
+/// Rows published since the last flush, by Singapore date of capture
+///
+/// ## Example
+/// ```
+/// use std::sync::Arc;
+/// use activesg_gym_datamine::{
+///     models::{Activity, Gym, GymSlotData, Timeslot},
+///     parquet::{self, RowGroupBuffer},
+/// };
+/// use chrono::{NaiveDate, TimeZone, Utc};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let dir = std::env::temp_dir().join(format!("row-groups-doctest-{}", std::process::id()));
+/// let slots = vec![Timeslot::new(Utc.ymd(2022, 1, 11).and_hms(13, 0, 0), 7); 15];
+/// // 23:30 UTC is already the next day in Singapore
+/// let late = NaiveDate::from_ymd(2022, 1, 10).and_hms(23, 30, 0);
+/// let early = NaiveDate::from_ymd(2022, 1, 10).and_hms(1, 30, 0);
+///
+/// let buffer = Arc::new(RowGroupBuffer::default());
+/// assert_eq!(buffer.push(&GymSlotData::new(Gym::BISHAN, early, slots.clone())), 15);
+/// assert_eq!(buffer.push(&GymSlotData::new(Gym::BISHAN, late, slots.clone())), 15);
+/// let courts = GymSlotData::new(Gym::BISHAN, early, slots.clone()).with_activity(Activity::BADMINTON);
+/// assert_eq!(buffer.push(&courts), 15);
+/// assert!(buffer.clone().flush(dir.clone()).await.unwrap() > 0);
+/// // nothing left to write
+/// assert_eq!(buffer.clone().flush(dir.clone()).await.unwrap(), 0);
+///
+/// let read = parquet::read(&dir.join("2022-01-10.parquet")).unwrap();
+/// let activities = read.iter().map(|r| r.activity).collect::<Vec<_>>();
+/// assert_eq!(activities, [[Activity::GYM; 15], [Activity::BADMINTON; 15]].concat());
+/// assert_eq!(parquet::read(&dir.join("2022-01-11.parquet")).unwrap().len(), 15);
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct RowGroupBuffer {
+    pending: Mutex<BTreeMap<NaiveDate, Vec<ParquetRow>>>,
+
+    /// held while appending, so that two flushes don't rewrite a file at once
+    writing: Mutex<()>,
+}
+
+impl RowGroupBuffer {
+    /// Buffers the rows of `data`, returning how many there were
+    pub fn push(&self, data: &GymSlotData) -> usize {
+        let date = DateTime::<FixedOffset>::from_utc(data.datetime(), *SGT)
+            .date()
+            .naive_local();
+        let rows = rows_of(data);
+        let len = rows.len();
+        self.pending
+            .lock()
+            .unwrap()
+            .entry(date)
+            .or_default()
+            .extend(rows);
+        len
+    }
+
+    /// Appends the buffered rows to `<output_dir>/<date>.parquet`, a row group per day,
+    /// and returns the bytes written
+    ///
+    /// The rows that couldn't be written are kept for the next flush
+    pub async fn flush(self: Arc<Self>, output_dir: PathBuf) -> DataMResult<u64> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        if pending.is_empty() {
+            return Ok(0);
+        }
+
+        let buffer = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let _writing = buffer.writing.lock().unwrap();
+            let mut written = 0;
+            let mut days = pending.into_iter();
+            while let Some((date, rows)) = days.next() {
+                let path = output_dir.join(format!("{}.parquet", date));
+                match append_row_group(&path, &rows) {
+                    Ok(n) => {
+                        info!("{}, {} rows appended", path.display(), rows.len());
+                        written += n;
+                    }
+                    Err(e) => {
+                        let mut pending = buffer.pending.lock().unwrap();
+                        for (date, rows) in std::iter::once((date, rows)).chain(days) {
+                            let newer = pending.remove(&date).unwrap_or_default();
+                            pending.insert(date, [rows, newer].concat());
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+            Ok(written)
+        })
+        .await
+        .map_err(|e| errors::Error::Parquet(format!("flush task panicked: {}", e)))?
+    }
+}
@@ -0,0 +1,192 @@
+//! Global cap on the requests sent to ActiveSG, whatever the concurrency and the retries
+//!
+//! Every request of a [crate::client::DataMiner] takes a token from the shared [RateLimiter]
+//! first, logins included, so the cap holds across the fetches in flight and their retries
+
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::pacer::WaitReason;
+
+/// Requests per second, written as a number in the config file, e.g. `rps = 0.5`
+///
+/// Always finite and positive, so it can be compared for equality
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "f64", into = "f64")]
+pub struct RequestRate(f64);
+
+impl Eq for RequestRate {}
+
+impl RequestRate {
+    /// [None] unless `rps` is finite and above 0
+    pub fn new(rps: f64) -> Option<Self> {
+        match rps.is_finite() && rps > 0.0 {
+            true => Some(Self(rps)),
+            false => None,
+        }
+    }
+
+    pub fn per_second(&self) -> f64 {
+        self.0
+    }
+
+    /// Time it takes to earn a token back
+    pub fn spacing(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.0)
+    }
+}
+
+impl TryFrom<f64> for RequestRate {
+    type Error = String;
+
+    fn try_from(rps: f64) -> Result<Self, Self::Error> {
+        Self::new(rps).ok_or_else(|| format!("invalid request rate {}, it must be above 0", rps))
+    }
+}
+
+impl From<RequestRate> for f64 {
+    fn from(rate: RequestRate) -> Self {
+        rate.0
+    }
+}
+
+impl FromStr for RequestRate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rps = s
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| format!("invalid request rate {:?}", s))?;
+        Self::try_from(rps)
+    }
+}
+
+impl fmt::Display for RequestRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/s", self.0)
+    }
+}
+
+/// Token bucket refilled at a [RequestRate], holding [RateLimiter::burst] tokens at most
+///
+/// A request waiting for a token reserves it, so that concurrent requests are spaced
+/// one after the other rather than all going out once a token is back
+///
+/// ## Example
+/// ```
+/// use std::time::Duration;
+/// use activesg_gym_datamine::ratelimit::{RateLimiter, RequestRate};
+/// use tokio::time::{self, Instant};
+///
+/// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+/// # async fn main() {
+/// let ms = Duration::from_millis;
+/// let limiter = RateLimiter::new("2".parse::<RequestRate>().unwrap());
+///
+/// let start = Instant::now();
+/// assert_eq!(limiter.acquire().await, Duration::ZERO);
+/// assert_eq!(limiter.acquire().await, ms(500));
+/// assert_eq!(limiter.acquire().await, ms(500));
+/// assert_eq!(start.elapsed(), ms(1000));
+///
+/// // idling doesn't bank more tokens than the bucket holds
+/// time::sleep(Duration::from_secs(10)).await;
+/// assert_eq!(limiter.acquire().await, Duration::ZERO);
+/// assert_eq!(limiter.acquire().await, ms(500));
+///
+/// // concurrent requests queue up
+/// time::sleep(Duration::from_secs(10)).await;
+/// let (a, b, c) = tokio::join!(limiter.acquire(), limiter.acquire(), limiter.acquire());
+/// assert_eq!([a, b, c], [Duration::ZERO, ms(500), ms(1000)]);
+/// assert_eq!(limiter.take_waited(), ms(3000));
+/// assert_eq!(limiter.take_waited(), Duration::ZERO);
+///
+/// // a bucket of 3 lets as many through at once after a pause
+/// let bursty = RateLimiter::new(RequestRate::new(1.0).unwrap()).with_burst(3);
+/// let mut waits = vec![];
+/// for _ in 0..5 {
+///     waits.push(bursty.acquire().await);
+/// }
+/// assert_eq!(waits, [Duration::ZERO, Duration::ZERO, Duration::ZERO, ms(1000), ms(1000)]);
+///
+/// assert!(RequestRate::new(0.0).is_none());
+/// assert!("-1".parse::<RequestRate>().is_err());
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: RequestRate,
+    burst: u32,
+
+    /// theoretical arrival time of the next request when the bucket is empty,
+    /// none before the first request
+    next: Mutex<Option<Instant>>,
+
+    /// time waited for tokens since the last [RateLimiter::take_waited]
+    waited_ms: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new(rate: RequestRate) -> Self {
+        Self {
+            rate,
+            burst: 1,
+            next: Mutex::default(),
+            waited_ms: AtomicU64::default(),
+        }
+    }
+
+    /// Requests that can go out back to back after a pause, 1 by default
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+
+    pub fn rate(&self) -> RequestRate {
+        self.rate
+    }
+
+    /// Waits for a token, returning the time waited
+    pub async fn acquire(&self) -> Duration {
+        let now = Instant::now();
+        let spacing = self.rate.spacing();
+        let wait = {
+            let mut next = self.next.lock().unwrap();
+            let tat = next.unwrap_or(now).max(now);
+            let tolerance = spacing * (self.burst - 1);
+            let start = tat.checked_sub(tolerance).unwrap_or(now).max(now);
+            *next = Some(tat + spacing);
+            start - now
+        };
+        if wait.is_zero() {
+            return wait;
+        }
+
+        debug!(
+            "waiting {}ms, {}",
+            wait.as_millis(),
+            WaitReason::RateLimiter.as_str()
+        );
+        self.waited_ms
+            .fetch_add(wait.as_millis() as u64, Ordering::Relaxed);
+        tokio::time::sleep(wait).await;
+        wait
+    }
+
+    /// Time waited for tokens since the last call, by every request
+    pub fn take_waited(&self) -> Duration {
+        Duration::from_millis(self.waited_ms.swap(0, Ordering::Relaxed))
+    }
+}
@@ -0,0 +1,368 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clock::SGT, fetch::FetchTask, metrics::IterationStats, models::Activity, sink, venue::Venue,
+    DataMResult,
+};
+
+/// Version of the [RunReport] layout, bumped on any breaking change
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
+
+/// Directory of the reports, relative to the dated output directory
+pub const REPORTS_DIR: &str = "runs";
+
+/// What happened to a single fetch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "lowercase")]
+pub enum TaskOutcome {
+    Ok {
+        slots: usize,
+        bytes_downloaded: u64,
+    },
+    Failed {
+        /// see [crate::errors::Error::class]
+        class: String,
+        message: String,
+    },
+    Skipped {
+        reason: String,
+    },
+}
+
+/// Outcome of a single fetch of an iteration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskReport {
+    pub activity: Activity,
+    pub gym: Venue,
+    pub date: NaiveDate,
+
+    #[serde(flatten)]
+    pub outcome: TaskOutcome,
+
+    /// time spent fetching, parsing and publishing
+    pub duration_ms: u64,
+}
+
+impl TaskReport {
+    pub fn new(task: FetchTask, outcome: TaskOutcome, duration_ms: u64) -> Self {
+        Self {
+            activity: task.activity,
+            gym: task.gym,
+            date: task.date,
+            outcome,
+            duration_ms,
+        }
+    }
+
+    pub fn skipped(task: FetchTask, reason: &str) -> Self {
+        let outcome = TaskOutcome::Skipped {
+            reason: reason.to_string(),
+        };
+        Self::new(task, outcome, 0)
+    }
+}
+
+/// Fetches of a single activity in an iteration
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActivityTotals {
+    pub fetched: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+impl ActivityTotals {
+    /// Totals of every activity fetched in `tasks`
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::{
+    ///     fetch::FetchTask,
+    ///     models::{Activity, Gym},
+    ///     report::{ActivityTotals, TaskOutcome, TaskReport},
+    /// };
+    /// use chrono::NaiveDate;
+    ///
+    /// let date = NaiveDate::from_ymd(2022, 1, 11);
+    /// let ok = TaskOutcome::Ok {
+    ///     slots: 15,
+    ///     bytes_downloaded: 2048,
+    /// };
+    /// let timeout = TaskOutcome::Failed {
+    ///     class: "timeout".into(),
+    ///     message: "timed out".into(),
+    /// };
+    /// let tasks = [
+    ///     TaskReport::new(FetchTask::new(Activity::GYM, Gym::BISHAN, date), ok.clone(), 120),
+    ///     TaskReport::new(FetchTask::new(Activity::GYM, Gym::CLEMENTI, date), ok.clone(), 90),
+    ///     TaskReport::skipped(FetchTask::new(Activity::GYM, Gym::TAMPINES, date), "quarantined"),
+    ///     TaskReport::new(FetchTask::new(Activity::BADMINTON, Gym::BISHAN, date), timeout, 10_000),
+    /// ];
+    ///
+    /// let totals = ActivityTotals::of(&tasks);
+    /// assert_eq!(
+    ///     serde_json::to_value(&totals).unwrap(),
+    ///     serde_json::json!({
+    ///         "BADMINTON": { "fetched": 0, "failed": 1, "skipped": 0 },
+    ///         "GYM": { "fetched": 2, "failed": 0, "skipped": 1 },
+    ///     })
+    /// );
+    /// assert!(ActivityTotals::of(&[]).is_empty());
+    /// ```
+    pub fn of(tasks: &[TaskReport]) -> BTreeMap<Activity, Self> {
+        let mut totals = BTreeMap::<Activity, Self>::new();
+        for task in tasks {
+            let entry = totals.entry(task.activity).or_default();
+            match task.outcome {
+                TaskOutcome::Ok { .. } => entry.fetched += 1,
+                TaskOutcome::Failed { .. } => entry.failed += 1,
+                TaskOutcome::Skipped { .. } => entry.skipped += 1,
+            }
+        }
+        totals
+    }
+}
+
+/// Machine readable summary of an iteration,
+/// written to `<output_dir>/<date>/runs/<datetime>.report.json`
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     errors::Error,
+///     fetch::FetchTask,
+///     metrics::IterationStats,
+///     models::{Activity, Gym},
+///     report::{RunReport, TaskOutcome, TaskReport},
+/// };
+/// use chrono::{NaiveDate, TimeZone, Utc};
+///
+/// let date = NaiveDate::from_ymd(2022, 1, 11);
+/// let bishan = FetchTask::new(Activity::GYM, Gym::BISHAN, date);
+/// let clementi = FetchTask::new(Activity::GYM, Gym::CLEMENTI, date);
+/// let courts = FetchTask::new(Activity::BADMINTON, Gym::BISHAN, date);
+///
+/// let mut stats = IterationStats {
+///     iteration: 42,
+///     fetched: 1,
+///     failed: 1,
+///     skipped: 1,
+///     bytes_downloaded: 2048,
+///     trace_id: Some("4bf92f3577b34da6a3ce929d0e0e4736".into()),
+///     ..Default::default()
+/// };
+/// stats.bytes_written.insert("file".into(), 512);
+/// stats.deliveries.insert("file".into(), 1);
+/// stats.filtered.insert("sqlite".into(), 1);
+/// stats.errors.record(clementi, &Error::SiteMaintenance);
+/// let ok = TaskOutcome::Ok { slots: 15, bytes_downloaded: 2048 };
+/// let failed = TaskOutcome::Failed {
+///     class: "site under maintenance".into(),
+///     message: "Site under maintenance!".into(),
+/// };
+/// stats.tasks = vec![
+///     TaskReport::new(bishan, ok, 250),
+///     TaskReport::new(clementi, failed, 80),
+///     TaskReport::skipped(courts, "download budget exhausted"),
+/// ];
+///
+/// // 09:30:00 to 09:30:01.5 SGT
+/// let started_at = Utc.ymd(2022, 1, 11).and_hms(1, 30, 0);
+/// let finished_at = Utc.ymd(2022, 1, 11).and_hms_milli(1, 30, 1, 500);
+/// let report = RunReport::new(started_at, finished_at, &stats);
+///
+/// let golden = r#"{
+///   "schema_version": 1,
+///   "iteration": 42,
+///   "started_at": "2022-01-11T01:30:00Z",
+///   "finished_at": "2022-01-11T01:30:01.500Z",
+///   "duration_ms": 1500,
+///   "fetched": 1,
+///   "failed": 1,
+///   "skipped": 1,
+///   "bytes_downloaded": 2048,
+///   "bytes_written": {
+///     "file": 512
+///   },
+///   "deliveries": {
+///     "file": 1
+///   },
+///   "filtered": {
+///     "sqlite": 1
+///   },
+///   "activities": {
+///     "BADMINTON": {
+///       "fetched": 0,
+///       "failed": 0,
+///       "skipped": 1
+///     },
+///     "GYM": {
+///       "fetched": 1,
+///       "failed": 1,
+///       "skipped": 0
+///     }
+///   },
+///   "error_classes": {
+///     "site under maintenance": 1
+///   },
+///   "tasks": [
+///     {
+///       "activity": "GYM",
+///       "gym": "BISHAN",
+///       "date": "2022-01-11",
+///       "outcome": "ok",
+///       "slots": 15,
+///       "bytes_downloaded": 2048,
+///       "duration_ms": 250
+///     },
+///     {
+///       "activity": "GYM",
+///       "gym": "CLEMENTI",
+///       "date": "2022-01-11",
+///       "outcome": "failed",
+///       "class": "site under maintenance",
+///       "message": "Site under maintenance!",
+///       "duration_ms": 80
+///     },
+///     {
+///       "activity": "BADMINTON",
+///       "gym": "BISHAN",
+///       "date": "2022-01-11",
+///       "outcome": "skipped",
+///       "reason": "download budget exhausted",
+///       "duration_ms": 0
+///     }
+///   ],
+///   "trace_id": "4bf92f3577b34da6a3ce929d0e0e4736"
+/// }"#;
+/// assert_eq!(serde_json::to_string_pretty(&report).unwrap(), golden);
+/// assert_eq!(serde_json::from_str::<RunReport>(golden).unwrap(), report);
+///
+/// let dir = std::env::temp_dir().join(format!("report-doctest-{}", std::process::id()));
+/// let path = report.write(&dir).unwrap();
+/// assert_eq!(
+///     path,
+///     dir.join("2022-01-11").join("runs").join("2022-01-11 09-30-01.42.report.json")
+/// );
+/// assert_eq!(std::fs::read_to_string(&path).unwrap(), golden);
+/// std::fs::remove_dir_all(&dir).unwrap();
+///
+/// // reports written before iterations were numbered
+/// let unnumbered = RunReport { iteration: 0, ..report };
+/// assert_eq!(
+///     unnumbered.path(&dir),
+///     dir.join("2022-01-11").join("runs").join("2022-01-11 09-30-01.report.json")
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunReport {
+    /// see [REPORT_SCHEMA_VERSION]
+    pub schema_version: u32,
+
+    /// see [crate::iteration::IterationCounter], 0 when not numbered
+    #[serde(default)]
+    pub iteration: u64,
+
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub duration_ms: u64,
+
+    pub fetched: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub bytes_downloaded: u64,
+
+    /// bytes written per sink
+    pub bytes_written: BTreeMap<String, u64>,
+
+    /// snapshots delivered per sink
+    #[serde(default)]
+    pub deliveries: BTreeMap<String, usize>,
+
+    /// snapshots kept from each sink by its filter, see [crate::filter]
+    #[serde(default)]
+    pub filtered: BTreeMap<String, usize>,
+
+    /// [RunReport::fetched], [RunReport::failed] and [RunReport::skipped] per activity
+    #[serde(default)]
+    pub activities: BTreeMap<Activity, ActivityTotals>,
+
+    /// number of failures per error class
+    pub error_classes: BTreeMap<String, usize>,
+
+    /// one entry per fetch, in the order they were attempted
+    pub tasks: Vec<TaskReport>,
+
+    /// trace of the iteration, see [crate::trace]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+}
+
+impl RunReport {
+    pub fn new(
+        started_at: DateTime<Utc>,
+        finished_at: DateTime<Utc>,
+        stats: &IterationStats,
+    ) -> Self {
+        Self {
+            schema_version: REPORT_SCHEMA_VERSION,
+            iteration: stats.iteration,
+            started_at,
+            finished_at,
+            duration_ms: (finished_at - started_at).num_milliseconds().max(0) as u64,
+            fetched: stats.fetched,
+            failed: stats.failed,
+            skipped: stats.skipped,
+            bytes_downloaded: stats.bytes_downloaded,
+            bytes_written: stats.bytes_written.clone(),
+            deliveries: stats.deliveries.clone(),
+            filtered: stats.filtered.clone(),
+            activities: ActivityTotals::of(&stats.tasks),
+            error_classes: stats
+                .errors
+                .counts()
+                .into_iter()
+                .map(|(class, n)| (class.to_string(), n))
+                .collect(),
+            tasks: stats.tasks.clone(),
+            trace_id: stats.trace_id.clone(),
+        }
+    }
+
+    /// Where the report is written, dated by [RunReport::finished_at] in Singapore time
+    /// and suffixed with the [RunReport::iteration] when numbered,
+    /// e.g. `2022-01-11/runs/2022-01-11 13-57-33.42.report.json`
+    pub fn path(&self, output_dir: &Path) -> PathBuf {
+        let finished_at = self.finished_at.with_timezone(&*SGT);
+        let name = match self.iteration {
+            0 => format!("{}.report.json", finished_at.format("%Y-%m-%d %H-%M-%S")),
+            n => format!(
+                "{}.{}.report.json",
+                finished_at.format("%Y-%m-%d %H-%M-%S"),
+                n
+            ),
+        };
+
+        output_dir
+            .join(finished_at.format("%Y-%m-%d").to_string())
+            .join(REPORTS_DIR)
+            .join(name)
+    }
+
+    /// Atomically writes the report under `output_dir`, returning its path
+    pub fn write(&self, output_dir: &Path) -> DataMResult<PathBuf> {
+        let path = self.path(output_dir);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let data = serde_json::to_vec_pretty(self).map_err(std::io::Error::from)?;
+        sink::write_atomic(&path, &data)?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,293 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use chrono::{Duration, NaiveDateTime};
+use tracing::info;
+
+use crate::{report::REPORTS_DIR, DataMResult};
+
+/// Which snapshots are kept when pruning the output directory
+///
+/// A snapshot survives if any configured rule keeps it,
+/// and everything is kept when no rule is configured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// keep snapshots younger than this
+    pub max_age: Option<Duration>,
+
+    /// keep the newest N snapshots of every gym regardless of age
+    pub keep_per_gym: Option<usize>,
+
+    /// only log what would be removed
+    pub dry_run: bool,
+}
+
+impl RetentionPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.max_age.is_some() || self.keep_per_gym.is_some()
+    }
+}
+
+/// Snapshot file found in the output directory
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SnapshotFile {
+    pub path: PathBuf,
+
+    /// `GYM` or `ACTIVITY-GYM` as found in the file name, suffixed with `.diff` for diffs,
+    /// [REPORTS_DIR] for run reports
+    pub series: String,
+
+    /// capture time in Singapore time, as found in the file name
+    pub captured_at: NaiveDateTime,
+}
+
+impl SnapshotFile {
+    const TIMESTAMP_FORMAT: &'static str = "%Y-%m-%d %H-%M-%S";
+    const TIMESTAMP_LEN: usize = "YYYY-MM-DD HH-MM-SS".len();
+
+    /// Parses names like `BISHAN-2022-01-11 13-57-33.json` and `2022-01-11 13-57-33.42.report.json`,
+    /// compressed or not, returning [None] for anything that isn't a snapshot or a run report
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::retention::SnapshotFile;
+    /// use std::path::Path;
+    ///
+    /// let series = |name: &str| SnapshotFile::parse(Path::new(name)).map(|f| f.series);
+    /// assert_eq!(series("BISHAN-2022-01-11 13-57-33.json").unwrap(), "BISHAN");
+    /// assert_eq!(series("BISHAN-2022-01-11 13-57-33.json.zst").unwrap(), "BISHAN");
+    /// assert_eq!(series("BADMINTON-BISHAN-2022-01-11 13-57-33.json.gz").unwrap(), "BADMINTON-BISHAN");
+    /// assert_eq!(series("BISHAN-2022-01-11 13-57-33.diff.json").unwrap(), "BISHAN.diff");
+    /// assert_eq!(series("2022-01-11 13-57-33.42.report.json").unwrap(), "runs");
+    /// assert_eq!(series("2022-01-11 13-57-33.report.json").unwrap(), "runs");
+    ///
+    /// let report = SnapshotFile::parse(Path::new("runs/2022-01-11 13-57-33.42.report.json")).unwrap();
+    /// assert_eq!(report.captured_at.to_string(), "2022-01-11 13:57:33");
+    ///
+    /// for other in [
+    ///     "2022-01-11.jsonl",
+    ///     "stats.json",
+    ///     "-2022-01-11 13-57-33.json",
+    ///     "BISHAN-2022-01-11 13-57-33.json.tmp",
+    ///     "BISHAN-2022-01-11 25-00-00.json",
+    ///     "BISHAN_2022-01-11 13-57-33.json",
+    /// ] {
+    ///     assert_eq!(series(other), None, "{}", other);
+    /// }
+    /// ```
+    pub fn parse(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let name = [".gz", ".zst"]
+            .iter()
+            .find_map(|ext| name.strip_suffix(ext))
+            .unwrap_or(name);
+        let stem = name.strip_suffix(".json")?;
+
+        if let Some(ts) = stem.strip_suffix(".report") {
+            // numbered reports end with `.<iteration>`
+            let ts = match ts.rsplit_once('.') {
+                Some((ts, n)) if n.bytes().all(|b| b.is_ascii_digit()) => ts,
+                _ => ts,
+            };
+            let captured_at = NaiveDateTime::parse_from_str(ts, Self::TIMESTAMP_FORMAT).ok()?;
+            return Some(Self {
+                path: path.to_path_buf(),
+                series: REPORTS_DIR.to_string(),
+                captured_at,
+            });
+        }
+        // diffs are retained separately from the snapshots of the same gym
+        let (stem, kind) = match stem.strip_suffix(".diff") {
+            Some(stem) => (stem, ".diff"),
+            None => (stem, ""),
+        };
+
+        let split = stem.len().checked_sub(Self::TIMESTAMP_LEN + 1)?;
+        let (series, ts) = (stem.get(..split)?, stem.get(split + 1..)?);
+
+        if series.is_empty() || !stem[split..].starts_with('-') {
+            return None;
+        }
+
+        let captured_at = NaiveDateTime::parse_from_str(ts, Self::TIMESTAMP_FORMAT).ok()?;
+        Some(Self {
+            path: path.to_path_buf(),
+            series: format!("{}{}", series, kind),
+            captured_at,
+        })
+    }
+}
+
+/// Returns the files to be removed under `policy` at `now`, oldest first
+///
+/// `now` is in Singapore time like the file names
+///
+/// ## Example
+/// ```
+/// use std::path::{Path, PathBuf};
+/// use activesg_gym_datamine::{
+///     clock::{Clock, FakeClock, SGT},
+///     retention::{self, RetentionPolicy, SnapshotFile},
+/// };
+/// use chrono::{Duration, TimeZone, Utc};
+///
+/// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+/// # async fn main() {
+/// let names = [
+///     "BISHAN-2022-01-09 12-00-00.json",
+///     "BISHAN-2022-01-10 13-00-00.json",
+///     "BISHAN-2022-01-11 11-00-00.json",
+/// ];
+/// let files = names.map(|n| SnapshotFile::parse(Path::new(n)).unwrap());
+/// let policy = RetentionPolicy {
+///     max_age: Some(Duration::days(1)),
+///     keep_per_gym: Some(1),
+///     dry_run: false,
+/// };
+///
+/// // 12:00 SGT
+/// let clock = FakeClock::new(Utc.ymd(2022, 1, 11).and_hms(4, 0, 0));
+/// let sgt_now = || clock.now().with_timezone(&*SGT).naive_local();
+/// assert_eq!(retention::plan(&files, &policy, sgt_now()), vec![PathBuf::from(names[0])]);
+///
+/// // the newest snapshot is kept however old
+/// clock.advance(Duration::days(1));
+/// assert_eq!(
+///     retention::plan(&files, &policy, sgt_now()),
+///     vec![PathBuf::from(names[0]), PathBuf::from(names[1])]
+/// );
+/// # }
+/// ```
+pub fn plan(files: &[SnapshotFile], policy: &RetentionPolicy, now: NaiveDateTime) -> Vec<PathBuf> {
+    if !policy.is_enabled() {
+        return vec![];
+    }
+
+    let mut by_series = HashMap::<&str, Vec<&SnapshotFile>>::new();
+    for f in files {
+        by_series.entry(&f.series).or_default().push(f);
+    }
+
+    let mut buf = vec![];
+    for series in by_series.values_mut() {
+        // newest first
+        series.sort_by_key(|f| std::cmp::Reverse(f.captured_at));
+
+        for (i, f) in series.iter().enumerate() {
+            let young = policy.max_age.map(|age| now - f.captured_at < age);
+            let recent = policy.keep_per_gym.map(|n| i < n);
+
+            if young != Some(true) && recent != Some(true) {
+                buf.push(*f);
+            }
+        }
+    }
+
+    buf.sort_by(|a, b| a.captured_at.cmp(&b.captured_at).then(a.path.cmp(&b.path)));
+    buf.into_iter().map(|f| f.path.clone()).collect()
+}
+
+/// Lists the snapshot files and run reports in the dated directories of `output_dir`
+pub fn scan(output_dir: &Path) -> DataMResult<Vec<SnapshotFile>> {
+    let mut buf = vec![];
+    if !output_dir.exists() {
+        return Ok(buf);
+    }
+
+    for day in std::fs::read_dir(output_dir)? {
+        let day = day?;
+        if !day.file_type()?.is_dir() {
+            continue;
+        }
+
+        let reports = day.path().join(REPORTS_DIR);
+        let reports = match reports.is_dir() {
+            true => Some(std::fs::read_dir(reports)?),
+            false => None,
+        };
+
+        for f in std::fs::read_dir(day.path())?.chain(reports.into_iter().flatten()) {
+            if let Some(snapshot) = SnapshotFile::parse(&f?.path()) {
+                buf.push(snapshot);
+            }
+        }
+    }
+
+    Ok(buf)
+}
+
+/// Prunes `output_dir` according to `policy`, returning the files removed (or to be removed)
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::retention::{self, RetentionPolicy};
+/// use chrono::{Duration, NaiveDate};
+///
+/// let dir = std::env::temp_dir().join(format!("retention-doctest-{}", std::process::id()));
+/// let files = [
+///     "2022-01-09/BISHAN-2022-01-09 12-00-00.json",
+///     "2022-01-09/runs/2022-01-09 12-00-00.1.report.json",
+///     "2022-01-11/BISHAN-2022-01-11 11-00-00.json",
+///     "2022-01-11/CLEMENTI-2022-01-11 11-00-00.json.zst",
+///     "2022-01-11/notes.txt",
+/// ];
+/// for f in files {
+///     std::fs::create_dir_all(dir.join(f).parent().unwrap()).unwrap();
+///     std::fs::write(dir.join(f), b"{}").unwrap();
+/// }
+/// let mut policy = RetentionPolicy {
+///     max_age: Some(Duration::days(1)),
+///     keep_per_gym: None,
+///     dry_run: true,
+/// };
+/// let now = NaiveDate::from_ymd(2022, 1, 11).and_hms(12, 0, 0);
+///
+/// let doomed = retention::apply(&dir, &policy, now).unwrap();
+/// assert_eq!(doomed, [dir.join(files[0]), dir.join(files[1])]);
+/// assert!(files.iter().all(|f| dir.join(f).exists()));
+///
+/// policy.dry_run = false;
+/// assert_eq!(retention::apply(&dir, &policy, now).unwrap(), doomed);
+/// // the emptied day is removed, the rest left alone
+/// assert!(!dir.join("2022-01-09").exists());
+/// assert!(files[2..].iter().all(|f| dir.join(f).exists()));
+/// assert!(retention::apply(&dir, &policy, now).unwrap().is_empty());
+///
+/// // no rule, nothing removed
+/// let keep_all = RetentionPolicy::default();
+/// assert!(retention::apply(&dir, &keep_all, now + Duration::days(365)).unwrap().is_empty());
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub fn apply(
+    output_dir: &Path,
+    policy: &RetentionPolicy,
+    now: NaiveDateTime,
+) -> DataMResult<Vec<PathBuf>> {
+    let files = scan(output_dir)?;
+    let doomed = plan(&files, policy, now);
+
+    for path in &doomed {
+        if policy.dry_run {
+            info!("retention dry run, would remove {}", path.display());
+            continue;
+        }
+
+        std::fs::remove_file(path)?;
+
+        // drop the reports and dated directories once they are empty
+        for dir in path.ancestors().skip(1) {
+            if dir == output_dir || std::fs::read_dir(dir)?.next().is_some() {
+                break;
+            }
+            std::fs::remove_dir(dir)?;
+        }
+    }
+
+    if !doomed.is_empty() && !policy.dry_run {
+        info!("retention removed {} snapshots", doomed.len());
+    }
+
+    Ok(doomed)
+}
@@ -0,0 +1,306 @@
+//! Retries of fetches failing for reasons that may not last
+//!
+//! Only what [crate::errors::Error::is_retryable] deems transient is retried: ActiveSG or the network
+//! failing, such as a timeout, a reset connection or a 5xx. Rejected credentials or a page that
+//! doesn't parse would fail the same way again. Every retry waits through the [Pacer] with an
+//! exponential backoff, see [RetryPolicy::backoff]
+//!
+//! A 429 is retried too, after the pause its `Retry-After` header asks for when longer than the
+//! backoff. A pause longer than [MAX_RETRY_AFTER] fails the fetch rather than holding up the
+//! iteration
+//!
+//! ## Example
+//! ActiveSG rate limiting the first two booking pages for a second
+//! ```
+//! use std::{
+//!     sync::atomic::{AtomicUsize, Ordering},
+//!     sync::Arc,
+//!     time::Duration,
+//! };
+//! use activesg_gym_datamine::{
+//!     client::DataMiner,
+//!     errors::Error,
+//!     fetch::{FetchTask, Fetcher},
+//!     models::{Activity, Gym, User},
+//!     pacer::Pacer,
+//!     retry::{self, RetryPolicy},
+//! };
+//! use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+//! let url = format!("http://{}", listener.local_addr().unwrap());
+//! let limited = Arc::new(AtomicUsize::new(0));
+//! tokio::spawn(async move {
+//!     loop {
+//!         let (mut socket, _) = listener.accept().await.unwrap();
+//!         let limited = limited.clone();
+//!         tokio::spawn(async move {
+//!             let mut buf = [0; 4096];
+//!             let n = socket.read(&mut buf).await.unwrap();
+//!             let booking = String::from_utf8_lossy(&buf[..n]).contains("/facilities/view/activity/");
+//!             let response = match booking && limited.fetch_add(1, Ordering::SeqCst) < 2 {
+//!                 true => "HTTP/1.1 429 Too Many Requests\r\nretry-after: 1\r\ncontent-length: 4\r\nconnection: close\r\n\r\nslow",
+//!                 false => "HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok",
+//!             };
+//!             socket.write_all(response.as_bytes()).await.unwrap();
+//!         });
+//!     }
+//! });
+//!
+//! let miner = DataMiner::new(User::new("", "")).with_anonymous(true).with_base_url(&url);
+//! let task = FetchTask::new(Activity::GYM, Gym::BISHAN, chrono::NaiveDate::from_ymd(2022, 1, 11));
+//!
+//! // the 429 page isn't taken for a day without slots
+//! let e = miner.fetch_booking_page(task).await.unwrap_err();
+//! assert!(matches!(e.root(), Error::RateLimited { retry_after: Some(d) } if *d == Duration::from_secs(1)));
+//!
+//! let policy = RetryPolicy::new(2, Duration::from_millis(10));
+//! let pacer = Pacer::new(Duration::ZERO);
+//! let retried = retry::retry(&policy, &pacer, task, || miner.fetch_booking_page(task)).await;
+//! assert_eq!(retried.res.unwrap().body, "ok");
+//! assert_eq!(retried.attempts, 2);
+//! // the pause asked for rather than the backoff
+//! assert!(retried.throttled >= Duration::from_secs(1));
+//! assert_eq!(retried.waited, retried.throttled);
+//! # }
+//! ```
+
+use std::{future::Future, time::Duration};
+
+use chrono::{DateTime, Utc};
+use tracing::debug;
+
+use crate::{
+    errors,
+    fetch::FetchTask,
+    pacer::{Pacer, WaitReason},
+    schedule, skew, DataMResult,
+};
+
+/// Retries of a fetch after its first attempt, 3 attempts in all
+pub const DEFAULT_RETRIES: usize = 2;
+
+/// Backoff before the first retry, doubled before every following one
+pub const DEFAULT_RETRY_BASE: Duration = Duration::from_millis(500);
+
+/// Longest backoff before a retry, however many attempts failed
+pub const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Longest pause asked for by a 429 that is waited out before retrying
+pub const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Pause asked for by a `Retry-After` header, either seconds or an HTTP date
+///
+/// ## Example
+/// ```
+/// use std::time::Duration;
+/// use activesg_gym_datamine::retry::parse_retry_after;
+/// use chrono::{TimeZone, Utc};
+///
+/// let now = Utc.ymd(2022, 1, 11).and_hms(5, 57, 3);
+/// assert_eq!(parse_retry_after("30", now), Some(Duration::from_secs(30)));
+/// assert_eq!(parse_retry_after(" 0 ", now), Some(Duration::ZERO));
+/// assert_eq!(
+///     parse_retry_after("Tue, 11 Jan 2022 05:57:33 GMT", now),
+///     Some(Duration::from_secs(30))
+/// );
+/// // already past
+/// assert_eq!(parse_retry_after("Tue, 11 Jan 2022 05:00:00 GMT", now), Some(Duration::ZERO));
+/// assert_eq!(parse_retry_after("-1", now), None);
+/// assert_eq!(parse_retry_after("soon", now), None);
+/// ```
+pub fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = skew::parse_http_date(value)?;
+    Some((at - now).to_std().unwrap_or(Duration::ZERO))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// attempts after the first one, none when 0
+    pub retries: usize,
+
+    /// see [DEFAULT_RETRY_BASE]
+    pub base: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: DEFAULT_RETRIES,
+            base: DEFAULT_RETRY_BASE,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(retries: usize, base: Duration) -> Self {
+        Self { retries, base }
+    }
+
+    /// Wait before retry number `retry` of `task`, counting from 1
+    ///
+    /// The backoff doubles from [RetryPolicy::base] up to [MAX_RETRY_BACKOFF], and the second
+    /// half of it is jitter, derived from the task and the retry so that the fetches failing
+    /// together during an outage don't all come back at once
+    ///
+    /// ## Example
+    /// ```
+    /// use std::time::Duration;
+    /// use activesg_gym_datamine::{
+    ///     fetch::FetchTask,
+    ///     models::{Activity, Gym},
+    ///     retry::{RetryPolicy, MAX_RETRY_BACKOFF},
+    /// };
+    /// use chrono::NaiveDate;
+    ///
+    /// let policy = RetryPolicy::new(3, Duration::from_secs(1));
+    /// let date = NaiveDate::from_ymd(2022, 1, 11);
+    /// let bishan = FetchTask::new(Activity::GYM, Gym::BISHAN, date);
+    /// let clementi = FetchTask::new(Activity::GYM, Gym::CLEMENTI, date);
+    ///
+    /// for (retry, exponential) in [(1, 1), (2, 2), (3, 4), (10, 512)] {
+    ///     let exponential = Duration::from_secs(exponential).min(MAX_RETRY_BACKOFF);
+    ///     let backoff = policy.backoff(bishan, retry);
+    ///     assert!(backoff >= exponential / 2 && backoff < exponential, "{:?}", backoff);
+    ///     assert_eq!(backoff, policy.backoff(bishan, retry));
+    /// }
+    /// assert_ne!(policy.backoff(bishan, 2), policy.backoff(clementi, 2));
+    /// ```
+    pub fn backoff(&self, task: FetchTask, retry: usize) -> Duration {
+        let doublings = retry.saturating_sub(1).min(16) as u32;
+        let exponential = self
+            .base
+            .saturating_mul(1 << doublings)
+            .min(MAX_RETRY_BACKOFF);
+
+        let half = exponential / 2;
+        let seed = format!("{:?}:{:?}:{}:{}", task.activity, task.gym, task.date, retry);
+        half + schedule::splay_offset(&seed, exponential - half)
+    }
+}
+
+/// Outcome of [retry], along with what it took
+#[derive(Debug)]
+pub struct Retried<T> {
+    pub res: DataMResult<T>,
+
+    /// attempts made, the first one included
+    pub attempts: usize,
+
+    /// time spent backing off, [Retried::throttled] included
+    pub waited: Duration,
+
+    /// time spent waiting for the pauses asked for by 429s
+    pub throttled: Duration,
+}
+
+/// Runs `attempt` for `task` until it succeeds, fails for good or runs out of retries
+///
+/// ## Example
+/// A server failing twice with a 502 before answering
+/// ```
+/// use std::time::Duration;
+/// use activesg_gym_datamine::{
+///     errors::Error,
+///     fetch::FetchTask,
+///     models::{Activity, Gym},
+///     pacer::Pacer,
+///     retry::{self, RetryPolicy},
+/// };
+/// use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+/// let url = format!("http://{}/", listener.local_addr().unwrap());
+/// tokio::spawn(async move {
+///     for status in ["502 Bad Gateway", "502 Bad Gateway", "200 OK", "403 Forbidden"] {
+///         let (mut socket, _) = listener.accept().await.unwrap();
+///         let mut buf = [0; 1024];
+///         socket.read(&mut buf).await.unwrap();
+///         let response = format!("HTTP/1.1 {}\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok", status);
+///         socket.write_all(response.as_bytes()).await.unwrap();
+///     }
+/// });
+///
+/// let get = || async {
+///     let res = reqwest::get(&url).await?.error_for_status()?;
+///     Ok::<_, Error>(res.text().await?)
+/// };
+/// let task = FetchTask::new(Activity::GYM, Gym::BISHAN, chrono::NaiveDate::from_ymd(2022, 1, 11));
+/// let policy = RetryPolicy::new(2, Duration::from_millis(10));
+/// let pacer = Pacer::new(Duration::ZERO);
+///
+/// let retried = retry::retry(&policy, &pacer, task, get).await;
+/// assert_eq!(retried.res.unwrap(), "ok");
+/// assert_eq!(retried.attempts, 3);
+/// assert!(retried.waited >= Duration::from_millis(15));
+///
+/// // a 403 isn't going away
+/// let retried = retry::retry(&policy, &pacer, task, get).await;
+/// assert!(retried.res.is_err());
+/// assert_eq!((retried.attempts, retried.waited), (1, Duration::ZERO));
+///
+/// // nor is a rejected login
+/// let retried = retry::retry(&policy, &pacer, task, || async {
+///     Err::<(), _>(Error::InvalidCredentialsSessionExpired)
+/// })
+/// .await;
+/// assert_eq!(retried.attempts, 1);
+/// # }
+/// ```
+pub async fn retry<T, A, Fut>(
+    policy: &RetryPolicy,
+    pacer: &Pacer,
+    task: FetchTask,
+    mut attempt: A,
+) -> Retried<T>
+where
+    A: FnMut() -> Fut,
+    Fut: Future<Output = DataMResult<T>>,
+{
+    let mut waited = Duration::ZERO;
+    let mut throttled = Duration::ZERO;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let res = attempt().await;
+        match &res {
+            Err(e) if e.is_retryable() && attempts <= policy.retries => {
+                debug!(
+                    "{:?} {:?} {}: attempt {} of {} failed, retrying: {}",
+                    task.activity,
+                    task.gym,
+                    task.date,
+                    attempts,
+                    policy.retries + 1,
+                    e
+                );
+                let backoff = policy.backoff(task, attempts);
+                match e.root() {
+                    errors::Error::RateLimited { retry_after } => {
+                        let pause = retry_after.unwrap_or_default().max(backoff);
+                        let paused = pacer.wait(task, pause, WaitReason::TooManyRequests).await;
+                        throttled += paused;
+                        waited += paused;
+                    }
+                    _ => waited += pacer.wait(task, backoff, WaitReason::RetryBackoff).await,
+                }
+            }
+            _ => {
+                return Retried {
+                    res,
+                    attempts,
+                    waited,
+                    throttled,
+                }
+            }
+        }
+    }
+}
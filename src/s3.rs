@@ -0,0 +1,284 @@
+//! Upload of the files written by [crate::sink::FileSink] to S3, only built with the `s3` feature
+//!
+//! Every snapshot and diff is queued once written, under a key mirroring its path in the
+//! output directory, e.g. `<prefix>/2022-01-11/BISHAN-2022-01-11 09-30-00.json`. A single
+//! background task uploads the queue in order, retrying transient failures, so a slow or
+//! failing upload never holds up the fetches. [Uploader::wait_idle] waits for the queue to
+//! drain, which the file sink does when flushed at the end of every iteration
+//!
+//! Credentials and the region are taken from the standard AWS environment, see [S3Store::from_env]
+//!
+//! ## Example
+//! ```
+//! use std::{
+//!     sync::{Arc, Mutex},
+//!     time::Duration,
+//! };
+//! use activesg_gym_datamine::{
+//!     models::{Gym, GymSlotData},
+//!     retry::RetryPolicy,
+//!     s3::{ObjectStore, PutError, Uploader},
+//!     sink::{FileSink, OutputFormat, Sink},
+//! };
+//! use chrono::NaiveDate;
+//!
+//! /// fails the first attempt of every key with a 503
+//! #[derive(Default, Clone)]
+//! struct FlakyStore {
+//!     attempts: Arc<Mutex<Vec<String>>>,
+//!     objects: Arc<Mutex<Vec<(String, Vec<u8>)>>>,
+//! }
+//!
+//! impl ObjectStore for FlakyStore {
+//!     async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), PutError> {
+//!         let mut attempts = self.attempts.lock().unwrap();
+//!         let first = !attempts.iter().any(|k| k == key);
+//!         attempts.push(key.to_string());
+//!         match (first, key.contains("CLEMENTI")) {
+//!             (_, true) => Err(PutError::Permanent("403 Forbidden".into())),
+//!             (true, _) => Err(PutError::Transient("503 Slow Down".into())),
+//!             (false, _) => Ok(self.objects.lock().unwrap().push((key.to_string(), body))),
+//!         }
+//!     }
+//! }
+//!
+//! # #[tokio::main(flavor = "current_thread", start_paused = true)]
+//! # async fn main() {
+//! let dir = std::env::temp_dir().join(format!("s3-doctest-{}", std::process::id()));
+//! let store = FlakyStore::default();
+//! let policy = RetryPolicy::new(2, Duration::from_secs(1));
+//! let uploader = Uploader::spawn(store.clone(), "miner/", policy, true);
+//! let sink = FileSink::new(&dir, OutputFormat::Json).with_uploader(uploader, true);
+//!
+//! let captured = NaiveDate::from_ymd(2022, 1, 11).and_hms(1, 30, 0);
+//! let bishan = GymSlotData::new(Gym::BISHAN, captured, vec![]);
+//! let clementi = GymSlotData::new(Gym::CLEMENTI, captured, vec![]);
+//! assert!(sink.publish(&bishan).await.unwrap() > 0);
+//! assert!(sink.publish(&clementi).await.unwrap() > 0);
+//! sink.flush().await.unwrap();
+//!
+//! let key = "miner/2022-01-11/BISHAN-2022-01-11 09-30-00.json";
+//! let objects = store.objects.lock().unwrap().clone();
+//! assert_eq!(objects.len(), 1);
+//! assert_eq!(objects[0].0, key);
+//! assert_eq!(objects[0].1, serde_json::to_string_pretty(&bishan).unwrap().as_bytes());
+//! // a rejected upload isn't retried
+//! assert_eq!(store.attempts.lock().unwrap().len(), 3);
+//!
+//! // the local copy is removed once uploaded, and kept when the upload failed for good
+//! let day = dir.join("2022-01-11");
+//! assert!(!day.join("BISHAN-2022-01-11 09-30-00.json").exists());
+//! assert!(day.join("CLEMENTI-2022-01-11 09-30-00.json").exists());
+//! std::fs::remove_dir_all(&dir).unwrap();
+//! # }
+//! ```
+
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use aws_sdk_s3::{error::SdkError, primitives::ByteStream, Client};
+use tokio::sync::{mpsc, watch};
+use tracing::{error, info, warn};
+
+use crate::retry::{RetryPolicy, MAX_RETRY_BACKOFF};
+
+/// Why an upload failed, telling the failures worth retrying apart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PutError {
+    /// the store couldn't be reached or answered with a 5xx or a 429
+    Transient(String),
+
+    /// anything else, e.g. missing credentials or a bucket that doesn't exist
+    Permanent(String),
+}
+
+/// Destination of the uploaded files
+pub trait ObjectStore {
+    /// Stores `body` under `key`, replacing any object of the same key
+    fn put(&self, key: &str, body: Vec<u8>) -> impl Future<Output = Result<(), PutError>> + Send;
+}
+
+/// A bucket of S3 or of a compatible store
+#[derive(Debug, Clone)]
+pub struct S3Store {
+    client: Client,
+    bucket: String,
+}
+
+impl S3Store {
+    pub fn new(client: Client, bucket: String) -> Self {
+        Self { client, bucket }
+    }
+
+    /// Client configured from the standard AWS environment: `AWS_ACCESS_KEY_ID`,
+    /// `AWS_SECRET_ACCESS_KEY`, `AWS_REGION`, `AWS_PROFILE`, instance roles and so on
+    pub async fn from_env(bucket: String) -> Self {
+        let config = aws_config::load_from_env().await;
+        Self::new(Client::new(&config), bucket)
+    }
+}
+
+impl ObjectStore for S3Store {
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), PutError> {
+        let res = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(ByteStream::from(body))
+            .send()
+            .await;
+
+        match res {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError(e)) => {
+                let status = e.raw().status().as_u16();
+                let message = format!("{} {}", status, e.err());
+                match status == 429 || status >= 500 {
+                    true => Err(PutError::Transient(message)),
+                    false => Err(PutError::Permanent(message)),
+                }
+            }
+            Err(e @ SdkError::ConstructionFailure(_)) => Err(PutError::Permanent(e.to_string())),
+            Err(e) => Err(PutError::Transient(e.to_string())),
+        }
+    }
+}
+
+/// File queued for upload
+#[derive(Debug)]
+struct Upload {
+    key: String,
+    body: Vec<u8>,
+
+    /// local copy removed once uploaded, none when kept or never written
+    remove: Option<PathBuf>,
+}
+
+/// Queue of the files to upload, see the [module docs](self)
+///
+/// Clones share the same queue
+#[derive(Debug, Clone)]
+pub struct Uploader {
+    queue: mpsc::UnboundedSender<Upload>,
+
+    /// uploads queued and not done yet
+    pending: Arc<watch::Sender<usize>>,
+
+    /// prepended to the path of the files, without a trailing `/`
+    prefix: String,
+
+    /// see [Uploader::spawn]
+    remove_local: bool,
+}
+
+impl Uploader {
+    /// Spawns the task uploading to `store` under `prefix`, retrying transient failures
+    /// as often as `policy` allows
+    ///
+    /// With `remove_local`, the local copy of a file is removed once it is uploaded
+    pub fn spawn<S>(store: S, prefix: &str, policy: RetryPolicy, remove_local: bool) -> Self
+    where
+        S: ObjectStore + Send + Sync + 'static,
+    {
+        let (queue, mut jobs) = mpsc::unbounded_channel::<Upload>();
+        let pending = Arc::new(watch::channel(0).0);
+
+        let done = pending.clone();
+        tokio::spawn(async move {
+            while let Some(upload) = jobs.recv().await {
+                if upload_with_retries(&store, &upload, policy).await {
+                    if let Some(path) = &upload.remove {
+                        if let Err(e) = tokio::fs::remove_file(path).await {
+                            warn!("{} uploaded but not removed: {}", path.display(), e);
+                        }
+                    }
+                }
+                done.send_modify(|n| *n -= 1);
+            }
+        });
+
+        Self {
+            queue,
+            pending,
+            prefix: prefix.trim_end_matches('/').to_string(),
+            remove_local,
+        }
+    }
+
+    /// Key of the file at `relative` in the output directory
+    pub fn key_of(&self, relative: &Path) -> String {
+        let path = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        match self.prefix.is_empty() {
+            true => path,
+            false => format!("{}/{}", self.prefix, path),
+        }
+    }
+
+    /// Queues `body` for upload as the file at `relative` in the output directory,
+    /// whose local copy, if written, is at `local`
+    pub fn queue(&self, relative: &Path, body: Vec<u8>, local: Option<PathBuf>) {
+        let upload = Upload {
+            key: self.key_of(relative),
+            body,
+            remove: local.filter(|_| self.remove_local),
+        };
+        self.pending.send_modify(|n| *n += 1);
+        if let Err(e) = self.queue.send(upload) {
+            // the task only stops with the runtime
+            error!("upload of {} not queued, the uploader stopped", e.0.key);
+            self.pending.send_modify(|n| *n -= 1);
+        }
+    }
+
+    /// Waits until every queued upload is done, uploaded or given up on
+    pub async fn wait_idle(&self) {
+        let mut pending = self.pending.subscribe();
+        // the sender lives as long as self
+        let _ = pending.wait_for(|n| *n == 0).await;
+    }
+}
+
+/// Uploads `upload`, backing off between attempts, returning whether it made it
+async fn upload_with_retries<S: ObjectStore>(
+    store: &S,
+    upload: &Upload,
+    policy: RetryPolicy,
+) -> bool {
+    let mut backoff = policy.base;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match store.put(&upload.key, upload.body.clone()).await {
+            Ok(()) => {
+                info!("{}, upload successful", upload.key);
+                return true;
+            }
+            Err(PutError::Transient(e)) if attempts <= policy.retries => {
+                warn!(
+                    "{}, upload attempt {} failed, retrying in {}ms: {}",
+                    upload.key,
+                    attempts,
+                    backoff.as_millis(),
+                    e
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+            }
+            Err(PutError::Transient(e) | PutError::Permanent(e)) => {
+                error!(
+                    "{}, upload failed after {} attempt(s): {}",
+                    upload.key, attempts, e
+                );
+                return false;
+            }
+        }
+    }
+}
@@ -0,0 +1,506 @@
+use std::{collections::HashMap, fmt, future::Future, str::FromStr, time::Duration};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use futures_util::FutureExt;
+use serde::{Deserialize, Serialize};
+use tokio::{task::JoinHandle, time::Interval};
+use tracing::{error, info, warn};
+
+use crate::{
+    clock::{Clock, SGT},
+    errors,
+    hours::HoursRange,
+    models::Gym,
+};
+
+/// Order in which `gyms` are fetched within an iteration
+///
+/// Gyms in `explicit` come first in the order given, the rest follow from the most stale
+/// to the freshest according to `last_success`, with never captured gyms first.
+/// Ties keep their position in `gyms`, so when iterations keep getting truncated
+/// the gyms skipped become the freshest and get fetched first next time
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{models::Gym, schedule};
+/// use chrono::NaiveDate;
+/// use std::collections::HashMap;
+///
+/// let gyms = [Gym::BISHAN, Gym::CLEMENTI, Gym::TAMPINES, Gym::JURONG_EAST, Gym::WOODLANDS];
+/// let at = |h| NaiveDate::from_ymd(2022, 1, 11).and_hms(h, 0, 0);
+/// let last_success = HashMap::from([
+///     (Gym::BISHAN, at(9)),
+///     (Gym::CLEMENTI, at(8)),
+///     (Gym::TAMPINES, at(9)),
+/// ]);
+///
+/// // never captured first, then the most stale, ties in configured order
+/// assert_eq!(
+///     schedule::prioritize_gyms(&gyms, &[], &last_success),
+///     [Gym::JURONG_EAST, Gym::WOODLANDS, Gym::CLEMENTI, Gym::BISHAN, Gym::TAMPINES]
+/// );
+///
+/// // explicit gyms first and once, unless they aren't queried at all
+/// let explicit = [Gym::TAMPINES, Gym::HOUGANG, Gym::TAMPINES, Gym::BISHAN];
+/// assert_eq!(
+///     schedule::prioritize_gyms(&gyms, &explicit, &last_success),
+///     [Gym::TAMPINES, Gym::BISHAN, Gym::JURONG_EAST, Gym::WOODLANDS, Gym::CLEMENTI]
+/// );
+///
+/// // nothing captured yet, the configured order
+/// assert_eq!(schedule::prioritize_gyms(&gyms, &[], &HashMap::new()), gyms);
+/// ```
+pub fn prioritize_gyms(
+    gyms: &[Gym],
+    explicit: &[Gym],
+    last_success: &HashMap<Gym, NaiveDateTime>,
+) -> Vec<Gym> {
+    let mut buf = Vec::<Gym>::with_capacity(gyms.len());
+    for gym in explicit {
+        if gyms.contains(gym) && !buf.contains(gym) {
+            buf.push(*gym);
+        }
+    }
+
+    let mut rest = gyms
+        .iter()
+        .filter(|g| !buf.contains(g))
+        .copied()
+        .collect::<Vec<_>>();
+
+    // stable sort, None < Some
+    rest.sort_by_key(|g| last_success.get(g).copied());
+
+    buf.extend(rest);
+    buf
+}
+
+/// Delay before the first iteration of an installation identified by `seed`
+///
+/// The seed is hashed with 64 bit FNV-1a and mapped onto `[0, max)` in whole milliseconds,
+/// so the same seed always gets the same splay and different installations started at the
+/// same time are spread out over `max`. Every later iteration is shifted by the same amount
+///
+/// ## Example
+/// ```
+/// use std::time::Duration;
+/// use activesg_gym_datamine::schedule::splay_offset;
+///
+/// let max = Duration::from_secs(300);
+/// let splay = splay_offset("host-a:output", max);
+///
+/// assert!(splay < max);
+/// assert_eq!(splay, splay_offset("host-a:output", max));
+/// assert_ne!(splay, splay_offset("host-b:output", max));
+/// assert_eq!(splay_offset("host-a:output", Duration::ZERO), Duration::ZERO);
+/// ```
+pub fn splay_offset(seed: &str, max: Duration) -> Duration {
+    const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+    let max_ms = max.as_millis() as u64;
+    if max_ms == 0 {
+        return Duration::ZERO;
+    }
+
+    let hash = seed.bytes().fold(FNV_OFFSET, |h, b| {
+        (h ^ u64::from(b)).wrapping_mul(FNV_PRIME)
+    });
+    Duration::from_millis(hash % max_ms)
+}
+
+/// Sleep between two consecutive fetches of an iteration
+///
+/// `inter_gym_delay` is politeness towards the venues while `limiter_spacing` is the minimum
+/// spacing a global rate limiter enforces between requests. When the limiter is at least as
+/// strict it already spaces the fetches, so sleeping on top of it would only slow the run down
+pub fn politeness_delay(inter_gym_delay: Duration, limiter_spacing: Option<Duration>) -> Duration {
+    match limiter_spacing {
+        Some(spacing) if spacing >= inter_gym_delay => Duration::ZERO,
+        _ => inter_gym_delay,
+    }
+}
+
+/// What the request rate of a configuration depends on, see [worst_case_request_rate]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateInputs {
+    /// fetches started every iteration, gyms times dates plus the other activities
+    pub fetches_per_iteration: usize,
+
+    /// http requests needed by a single fetch, logins included
+    pub requests_per_fetch: usize,
+
+    /// additional attempts of a failing fetch
+    pub retries: usize,
+
+    /// fetches in flight at once
+    pub concurrency: usize,
+
+    pub interval: Duration,
+    pub inter_gym_delay: Duration,
+
+    /// spacing of the global rate limiter, see [crate::ratelimit]
+    pub limiter_spacing: Option<Duration>,
+}
+
+/// Highest sustained number of requests per minute the configuration can send
+///
+/// Every fetch is assumed to fail and be retried, and responses are assumed to be instant,
+/// so the only things spacing requests are the interval and the delay between fetches.
+/// An iteration can't be shorter than the delay times the fetches of each concurrent worker,
+/// so a short interval is capped by the delay. The rate limiter caps the result
+///
+/// ## Example
+/// ```
+/// use std::time::Duration;
+/// use activesg_gym_datamine::schedule::{self, RateInputs};
+///
+/// // 10 fetches of 3 requests, each retried twice: 90 requests an iteration
+/// let inputs = RateInputs {
+///     fetches_per_iteration: 10,
+///     requests_per_fetch: 3,
+///     retries: 2,
+///     concurrency: 1,
+///     interval: Duration::from_secs(600),
+///     inter_gym_delay: Duration::from_secs(5),
+///     limiter_spacing: None,
+/// };
+/// let rate = |inputs| schedule::worst_case_request_rate(&inputs);
+/// assert_eq!(rate(inputs), 9.0);
+///
+/// // an iteration can't be shorter than 10 fetches 5s apart
+/// let short = RateInputs { interval: Duration::from_secs(30), ..inputs };
+/// assert_eq!(rate(short), 108.0);
+///
+/// // unless 4 workers fetch at once, 3 fetches each
+/// let concurrent = RateInputs { concurrency: 4, ..short };
+/// assert_eq!(rate(concurrent), 180.0);
+/// // a concurrency of 0 is a single worker
+/// assert_eq!(rate(RateInputs { concurrency: 0, ..short }), 108.0);
+///
+/// // at most one request a second
+/// let limited = RateInputs { limiter_spacing: Some(Duration::from_secs(1)), ..concurrent };
+/// assert_eq!(rate(limited), 60.0);
+///
+/// // nothing spacing the requests but the limiter
+/// let unspaced = RateInputs {
+///     interval: Duration::ZERO,
+///     inter_gym_delay: Duration::ZERO,
+///     ..inputs
+/// };
+/// assert_eq!(rate(unspaced), f64::INFINITY);
+/// assert_eq!(rate(RateInputs { limiter_spacing: Some(Duration::from_secs(2)), ..unspaced }), 30.0);
+/// assert_eq!(rate(RateInputs { limiter_spacing: Some(Duration::ZERO), ..unspaced }), f64::INFINITY);
+///
+/// assert_eq!(rate(RateInputs { fetches_per_iteration: 0, ..inputs }), 0.0);
+/// ```
+pub fn worst_case_request_rate(inputs: &RateInputs) -> f64 {
+    let requests = inputs.fetches_per_iteration * inputs.requests_per_fetch * (1 + inputs.retries);
+    let workers = inputs.concurrency.max(1);
+    let serial_fetches = inputs.fetches_per_iteration.div_ceil(workers);
+    let shortest_iteration = inputs.inter_gym_delay * serial_fetches as u32;
+
+    let period = inputs.interval.max(shortest_iteration).as_secs_f64();
+    let rate = match period > 0.0 {
+        true => requests as f64 * 60.0 / period,
+        false => f64::INFINITY,
+    };
+    match inputs.limiter_spacing {
+        Some(spacing) if !spacing.is_zero() => rate.min(60.0 / spacing.as_secs_f64()),
+        _ => rate,
+    }
+}
+
+/// Why an iteration runs, decided by the [Scheduler]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IterationKind {
+    /// on the interval
+    Regular,
+
+    /// first iteration after the blackout, ran as soon as it ends
+    CatchUp,
+}
+
+/// Time left in `blackout` at `now`, zero outside of it or without a blackout
+///
+/// `now` is compared against the blackout in Singapore time, the start of the blackout
+/// is within it and its end isn't
+///
+/// ## Example
+/// ```
+/// use std::time::Duration;
+/// use activesg_gym_datamine::schedule;
+/// use chrono::{TimeZone, Utc};
+///
+/// let blackout = Some("06:00-08:00".parse().unwrap());
+/// // times in Singapore, 8 hours ahead of UTC
+/// let left = |h, m, s| schedule::blackout_left(blackout, Utc.ymd(2022, 1, 10).and_hms(h, m, s));
+///
+/// // 05:59:59 SGT
+/// assert_eq!(left(21, 59, 59), Duration::ZERO);
+/// // 06:00:00 SGT
+/// assert_eq!(left(22, 0, 0), Duration::from_secs(2 * 60 * 60));
+/// // 07:59:59 SGT
+/// assert_eq!(left(23, 59, 59), Duration::from_secs(1));
+/// // 08:00:00 SGT
+/// assert_eq!(left(0, 0, 0), Duration::ZERO);
+/// // 06:00 UTC is 14:00 SGT
+/// assert_eq!(left(6, 30, 0), Duration::ZERO);
+///
+/// assert_eq!(schedule::blackout_left(None, Utc.ymd(2022, 1, 10).and_hms(22, 30, 0)), Duration::ZERO);
+/// ```
+pub fn blackout_left(blackout: Option<HoursRange>, now: DateTime<Utc>) -> Duration {
+    let time = now.with_timezone(&*SGT).time();
+    match blackout {
+        Some(b) if b.contains(time) => (b.close - time).to_std().unwrap_or_default(),
+        _ => Duration::ZERO,
+    }
+}
+
+/// Ticks every interval, skipping the ticks within the blackout, when ActiveSG doesn't answer
+///
+/// Once ticks were skipped, a [IterationKind::CatchUp] iteration runs as soon as the blackout
+/// ends, whatever the interval, and the regular ticks resume one interval after it.
+/// The time of `clock` is compared against the blackout in Singapore time
+///
+/// ## Example
+/// ```
+/// use std::time::Duration;
+/// use activesg_gym_datamine::{
+///     clock::FakeClock,
+///     schedule::{IterationKind, Scheduler},
+/// };
+/// use chrono::{TimeZone, Utc};
+/// use tokio::time::Instant;
+///
+/// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+/// # async fn main() {
+/// let interval = Duration::from_secs(20 * 60);
+/// let blackout = Some("06:00-08:00".parse().unwrap());
+///
+/// // 05:50 SGT, the blackout spans several ticks and ends between two of them
+/// let start = Instant::now();
+/// let clock = FakeClock::new(Utc.ymd(2022, 1, 10).and_hms(21, 50, 0));
+/// let mut scheduler = Scheduler::new(interval, blackout, clock);
+///
+/// let mins = |m: u64| Duration::from_secs(m * 60);
+/// assert_eq!(scheduler.next().await, IterationKind::Regular);
+/// assert_eq!(start.elapsed(), mins(0));
+/// assert_eq!(scheduler.next().await, IterationKind::CatchUp);
+/// assert_eq!(start.elapsed(), mins(130));
+/// assert_eq!(scheduler.next().await, IterationKind::Regular);
+/// assert_eq!(start.elapsed(), mins(150));
+///
+/// // 07:20 SGT, the blackout ends exactly on a tick
+/// let start = Instant::now();
+/// let clock = FakeClock::new(Utc.ymd(2022, 1, 10).and_hms(23, 20, 0));
+/// let mut scheduler = Scheduler::new(interval, blackout, clock.clone());
+///
+/// assert_eq!(scheduler.next().await, IterationKind::CatchUp);
+/// assert_eq!(start.elapsed(), mins(40));
+/// assert_eq!(scheduler.next().await, IterationKind::Regular);
+/// assert_eq!(start.elapsed(), mins(60));
+///
+/// // the wall clock jumps into the next blackout between two ticks
+/// clock.advance(chrono::Duration::hours(22) + chrono::Duration::minutes(30));
+/// assert_eq!(scheduler.next().await, IterationKind::CatchUp);
+/// assert_eq!(start.elapsed(), mins(60 + 20 + 50));
+/// # }
+/// ```
+pub struct Scheduler<C> {
+    timer: Interval,
+    blackout: Option<HoursRange>,
+    clock: C,
+
+    /// ticks were skipped in the blackout
+    catching_up: bool,
+}
+
+impl<C> Scheduler<C>
+where
+    C: Clock,
+{
+    /// Ticks immediately, then every `interval`
+    pub fn new(interval: Duration, blackout: Option<HoursRange>, clock: C) -> Self {
+        Self {
+            timer: tokio::time::interval(interval),
+            blackout,
+            clock,
+            catching_up: false,
+        }
+    }
+
+    /// Time left in the blackout at `now`, see [blackout_left]
+    fn blackout_left(&self, now: DateTime<Utc>) -> Duration {
+        blackout_left(self.blackout, now)
+    }
+
+    /// Waits for the next iteration
+    pub async fn next(&mut self) -> IterationKind {
+        loop {
+            if self.catching_up {
+                let left = self.blackout_left(self.clock.now());
+                tokio::select! {
+                    _ = tokio::time::sleep(left) => (),
+                    _ = self.timer.tick() => (),
+                }
+
+                if !self.blackout_left(self.clock.now()).is_zero() {
+                    continue;
+                }
+                self.catching_up = false;
+                self.timer.reset();
+                return IterationKind::CatchUp;
+            }
+
+            self.timer.tick().await;
+            if let (Some(blackout), false) = (
+                self.blackout,
+                self.blackout_left(self.clock.now()).is_zero(),
+            ) {
+                info!(
+                    "Skipping the iteration during the {} blackout, when ActiveSG doesn't answer, catching up once it ends",
+                    blackout
+                );
+                self.catching_up = true;
+                continue;
+            }
+            return IterationKind::Regular;
+        }
+    }
+}
+
+/// What to do when an iteration is due while the previous one is still running
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverlapPolicy {
+    /// skip the iteration, the next one is due an interval later
+    #[default]
+    Skip,
+
+    /// start the iteration as soon as the previous one is done
+    Queue,
+}
+
+impl FromStr for OverlapPolicy {
+    type Err = errors::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "skip" => Ok(Self::Skip),
+            "queue" => Ok(Self::Queue),
+            _ => Err(errors::Error::InvalidConfig(format!(
+                "unknown overlap policy {}, expected skip or queue",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for OverlapPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Skip => "skip",
+            Self::Queue => "queue",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Runs the iterations in the background one at a time, following an [OverlapPolicy]
+///
+/// An iteration outlasting the interval, on a slow site or with many retries, would otherwise
+/// run alongside the next one, doubling the load and racing on the output files
+///
+/// ## Example
+/// ```
+/// use std::{
+///     sync::{
+///         atomic::{AtomicUsize, Ordering},
+///         Arc,
+///     },
+///     time::Duration,
+/// };
+/// use activesg_gym_datamine::schedule::{Cycles, OverlapPolicy};
+/// use tokio::time::{self, Instant};
+///
+/// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+/// # async fn main() {
+/// let running = Arc::new(AtomicUsize::new(0));
+/// let most = Arc::new(AtomicUsize::new(0));
+/// // an iteration taking 25 minutes
+/// let slow_cycle = || {
+///     let (running, most) = (running.clone(), most.clone());
+///     async move {
+///         let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+///         most.fetch_max(now, Ordering::SeqCst);
+///         time::sleep(Duration::from_secs(25 * 60)).await;
+///         running.fetch_sub(1, Ordering::SeqCst);
+///     }
+/// };
+/// let mins = |m: u64| Duration::from_secs(m * 60);
+///
+/// // every 20 minutes, the ticks at 20 and 60 minutes find the previous iteration running
+/// let mut cycles = Cycles::new(OverlapPolicy::Skip);
+/// let mut started = vec![];
+/// for _ in 0..4 {
+///     started.push(cycles.start(slow_cycle()).await);
+///     time::sleep(mins(20)).await;
+/// }
+/// assert_eq!(started, [true, false, true, false]);
+/// assert_eq!(most.load(Ordering::SeqCst), 1);
+///
+/// // queued, each iteration starts once the previous one is done
+/// time::sleep(mins(60)).await;
+/// let start = Instant::now();
+/// let mut cycles = Cycles::new(OverlapPolicy::Queue);
+/// for _ in 0..3 {
+///     assert!(cycles.start(slow_cycle()).await);
+///     time::sleep(mins(20)).await;
+/// }
+/// assert_eq!(start.elapsed(), mins(25 + 25 + 20));
+/// assert_eq!(most.load(Ordering::SeqCst), 1);
+/// # }
+/// ```
+pub struct Cycles {
+    policy: OverlapPolicy,
+
+    /// iteration started last, none before the first one
+    current: Option<JoinHandle<()>>,
+}
+
+impl Cycles {
+    pub fn new(policy: OverlapPolicy) -> Self {
+        Self {
+            policy,
+            current: None,
+        }
+    }
+
+    /// Spawns `cycle` unless the previous iteration is still running and is to be skipped,
+    /// returning whether it was started
+    pub async fn start<F>(&mut self, cycle: F) -> bool
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        if let Some(previous) = &mut self.current {
+            if previous.now_or_never().is_none() {
+                match self.policy {
+                    OverlapPolicy::Skip => {
+                        warn!("Skipping the iteration, the previous one is still running");
+                        return false;
+                    }
+                    OverlapPolicy::Queue => {
+                        warn!("Previous iteration still running, starting once it is done");
+                        if let Err(e) = previous.await {
+                            error!("Previous iteration failed: {}", e);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.current = Some(tokio::spawn(cycle));
+        true
+    }
+}
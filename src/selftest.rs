@@ -0,0 +1,185 @@
+use std::fmt;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use scraper::Html;
+
+use crate::{
+    errors,
+    fetch::BookingPage,
+    models::{auth_parser, interstitial_parser, venue_parser, Gym, Timeslot},
+    skew, DataMResult,
+};
+
+/// Outcome of a single parser check of the `selftest` subcommand
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+
+    /// failing critical checks make the self-test fail
+    pub critical: bool,
+
+    /// sample of what was extracted on success, reason on failure
+    pub result: Result<String, String>,
+}
+
+impl Check {
+    pub fn new(name: &'static str, critical: bool, result: Result<String, String>) -> Self {
+        Self {
+            name,
+            critical,
+            result,
+        }
+    }
+
+    /// Whether this check makes the self-test fail
+    pub fn is_failure(&self) -> bool {
+        self.critical && self.result.is_err()
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.result, self.critical) {
+            (Ok(sample), _) => write!(f, "[PASS] {}: {}", self.name, sample),
+            (Err(e), true) => write!(f, "[FAIL] {}: {}", self.name, e),
+            (Err(e), false) => write!(f, "[WARN] {}: {}", self.name, e),
+        }
+    }
+}
+
+/// Checks the csrf token and rsa key of the login page, as fetched by
+/// [crate::client::DataMiner::fetch_login_page]
+pub fn check_login_page(res: DataMResult<String>) -> Vec<Check> {
+    let body = match res {
+        Ok(body) => body,
+        Err(e) => return vec![Check::new("login page", true, Err(e.to_string()))],
+    };
+
+    let html = Html::parse_document(&body);
+    let csrf = auth_parser::get_csrf_token(&html).map(|t| format!("{} chars", t.len()));
+    let rsa = auth_parser::get_rsa_key(&html).map(|k| format!("{} chars", k.len()));
+    vec![
+        Check::new("csrf token", true, csrf.map_err(|e| e.to_string())),
+        Check::new("rsa key", true, rsa.map_err(|e| e.to_string())),
+    ]
+}
+
+/// Runs the parsers of the booking page of `gym` on `date` fetched at `now`: the captcha
+/// and maintenance banners, the timeslots, the venue name and the `Date` header
+///
+/// A fetch failing on a captcha or maintenance page fails the banner check alone
+///
+/// ## Example
+/// A healthy booking page and one whose slots lost their grid
+/// ```
+/// use activesg_gym_datamine::{
+///     client::DataMiner,
+///     fetch::{FetchTask, Fetcher},
+///     models::{Activity, Gym, User},
+///     selftest,
+/// };
+/// use chrono::{NaiveDate, Utc};
+/// use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+///
+/// async fn serve(body: String) -> String {
+///     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+///     let url = format!("http://{}", listener.local_addr().unwrap());
+///     tokio::spawn(async move {
+///         loop {
+///             let (mut socket, _) = listener.accept().await.unwrap();
+///             let mut request = vec![];
+///             let mut buf = [0; 1024];
+///             while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+///                 let n = socket.read(&mut buf).await.unwrap();
+///                 request.extend_from_slice(&buf[..n]);
+///             }
+///             let date = Utc::now().format("%a, %d %b %Y %H:%M:%S GMT");
+///             let response = format!(
+///                 "HTTP/1.1 200 OK\r\ndate: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+///                 date,
+///                 body.len(),
+///                 body
+///             );
+///             socket.write_all(response.as_bytes()).await.unwrap();
+///         }
+///     });
+///     url
+/// }
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let fixture = std::fs::read_to_string(concat!(
+///     env!("CARGO_MANIFEST_DIR"),
+///     "/tests/fixtures/booking-GYM-BISHAN-2022-01-11.html"
+/// ))
+/// .unwrap();
+/// let date = NaiveDate::from_ymd(2022, 1, 11);
+/// let task = FetchTask::new(Activity::GYM, Gym::BISHAN, date);
+/// let checks = |url: String| async move {
+///     let miner = DataMiner::new(User::new("", "")).with_anonymous(true).with_base_url(&url);
+///     let page = miner.fetch_booking_page(task).await;
+///     selftest::check_booking_page(page, Gym::BISHAN, date, Utc::now())
+/// };
+///
+/// let healthy = checks(serve(fixture.clone()).await).await;
+/// let names: Vec<_> = healthy.iter().map(|c| c.name).collect();
+/// assert_eq!(names, ["banner", "timeslots", "venue name", "date header"]);
+/// assert!(healthy.iter().all(|c| c.result.is_ok()), "{:?}", healthy);
+/// assert!(healthy[1].result.as_ref().unwrap().ends_with("timeslots for BISHAN on 2022-01-11"));
+///
+/// let broken = checks(serve(fixture.replace("chkbox-grid", "slot-grid")).await).await;
+/// let failures: Vec<_> = broken.iter().filter(|c| c.is_failure()).collect();
+/// assert_eq!(failures.len(), 1);
+/// assert_eq!(failures[0].to_string(), "[FAIL] timeslots: no timeslots found");
+///
+/// let maintenance = "<html><head><title>Scheduled Maintenance</title></head></html>";
+/// let down = checks(serve(maintenance.to_string()).await).await;
+/// assert_eq!(down.len(), 1);
+/// assert!(down[0].is_failure());
+/// assert_eq!(down[0].name, "banner");
+/// # }
+/// ```
+pub fn check_booking_page(
+    res: DataMResult<BookingPage>,
+    gym: Gym,
+    date: NaiveDate,
+    now: DateTime<Utc>,
+) -> Vec<Check> {
+    let page = match res {
+        Ok(page) => page,
+        Err(e) => {
+            let name = match e.root() {
+                errors::Error::CaptchaRequired | errors::Error::SiteMaintenance => "banner",
+                _ => "booking page",
+            };
+            return vec![Check::new(name, true, Err(e.to_string()))];
+        }
+    };
+
+    let html = Html::parse_document(&page.body);
+    let banner = interstitial_parser::check(&html)
+        .map(|_| "no captcha or maintenance page".to_string())
+        .map_err(|e| e.to_string());
+
+    let slots = match Timeslot::parse_timeslots(&html, date).len() {
+        0 => Err("no timeslots found".to_string()),
+        n => Ok(format!("{} timeslots for {:?} on {}", n, gym, date)),
+    };
+
+    let venue = match venue_parser::get_display_name(&html) {
+        Some(name) if gym.matches_display_name(&name) => Ok(name),
+        Some(name) => Err(format!("{:?}, expected {:?}", name, gym.display())),
+        None => Err("no venue heading found".to_string()),
+    };
+
+    let skew = skew::measure_skew(&page.headers, now)
+        .map(|s| format!("clock skew {}s", s.num_seconds()))
+        .ok_or_else(|| "missing or malformed Date header".to_string());
+
+    vec![
+        Check::new("banner", true, banner),
+        Check::new("timeslots", true, slots),
+        Check::new("venue name", false, venue),
+        Check::new("date header", false, skew),
+    ]
+}
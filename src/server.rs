@@ -0,0 +1,572 @@
+//! HTTP API of the daemon, enabled with [crate::config::Config::http_listen]
+//!
+//! - `GET /api/gyms` id, name and official name of every known gym
+//! - `GET /api/gyms/<GYM>/slots?date=<YYYY-MM-DD>` latest snapshot of the gym with the time it
+//!   was scraped, of today in Singapore time without a date
+//! - `GET /api/slots/<GYM>/<YYYY-MM-DD>` latest snapshot of the gym and date, see [SnapshotCache]
+//! - `POST /api/refresh` with `{"gym": "BISHAN", "date": "2022-01-11"}` queues an immediate fetch
+//!   through the normal pipeline and answers `202` with the request id,
+//!   an optional `callback_url` receives the outcome once fetched
+//! - `GET /api/refresh/<id>` outcome of a queued refresh
+//! - `GET /api/sinks` state and latest p95 delivery latency of every sink, see [crate::sinkhealth]
+//!
+//! Refreshes cost requests to ActiveSG, so they need the bearer token of
+//! [crate::config::Config::api_token], at most [MAX_PENDING_REFRESHES] wait at a time
+//! and they are refused while the daily download budget is exhausted
+//!
+//! ## Example
+//! ```
+//! use activesg_gym_datamine::{
+//!     cache::{CachedSnapshot, SnapshotCache, SnapshotKey},
+//!     fetch::FetchTask,
+//!     models::{Activity, Gym, GymSlotData, Timeslot},
+//!     server::{Api, ApiState, RefreshQueue, RefreshStatus},
+//! };
+//! use chrono::{NaiveDate, Utc};
+//! use std::sync::atomic::{AtomicBool, Ordering};
+//!
+//! #[derive(Default)]
+//! struct State {
+//!     cache: SnapshotCache,
+//!     exhausted: AtomicBool,
+//! }
+//!
+//! impl ApiState for State {
+//!     fn budget_exhausted(&self) -> bool {
+//!         self.exhausted.load(Ordering::Relaxed)
+//!     }
+//!
+//!     fn snapshot(&self, key: &SnapshotKey) -> Option<CachedSnapshot> {
+//!         self.cache.latest(key, Utc::now())
+//!     }
+//! }
+//!
+//! let (queue, mut queued) = RefreshQueue::new(1);
+//! let api = Api::new(Some("s3cret".into()), queue);
+//! let state = State::default();
+//! let body = br#"{"gym": "BISHAN", "date": "2022-01-11"}"#;
+//!
+//! // queueing
+//! let res = api.handle(&state, "POST", "/api/refresh", Some("Bearer s3cret"), body);
+//! assert_eq!(res.status, 202);
+//! let id = res.body["request_id"].as_u64().unwrap();
+//! let refresh = queued.try_recv().unwrap();
+//! assert_eq!(refresh.id, id);
+//! assert_eq!(refresh.task, FetchTask::new(Activity::GYM, Gym::BISHAN, NaiveDate::from_ymd(2022, 1, 11)));
+//! let res = api.handle(&state, "GET", &format!("/api/refresh/{}", id), None, b"");
+//! assert_eq!(res.body["status"], "queued");
+//!
+//! api.queue().set_status(id, RefreshStatus::Done { slots: 1 });
+//! assert_eq!(api.handle(&state, "GET", &format!("/api/refresh/{}", id), None, b"").body["status"], "done");
+//!
+//! // token rejection
+//! assert_eq!(api.handle(&state, "POST", "/api/refresh", None, body).status, 401);
+//! assert_eq!(api.handle(&state, "POST", "/api/refresh", Some("Bearer guess"), body).status, 401);
+//! let open = Api::new(None, RefreshQueue::new(1).0);
+//! assert_eq!(open.handle(&state, "POST", "/api/refresh", None, body).status, 403);
+//!
+//! // concurrency limit, the previous refresh is still waiting
+//! api.handle(&state, "POST", "/api/refresh", Some("Bearer s3cret"), body);
+//! assert_eq!(api.handle(&state, "POST", "/api/refresh", Some("Bearer s3cret"), body).status, 429);
+//! queued.try_recv().unwrap();
+//!
+//! // budget exhausted
+//! state.exhausted.store(true, Ordering::Relaxed);
+//! let res = api.handle(&state, "POST", "/api/refresh", Some("Bearer s3cret"), body);
+//! assert_eq!(res.status, 503);
+//! assert_eq!(res.body["class"], "download budget exhausted");
+//! assert!(queued.try_recv().is_err());
+//!
+//! assert_eq!(api.handle(&state, "POST", "/api/refresh", Some("Bearer s3cret"), b"{}").status, 400);
+//!
+//! // polling the snapshot
+//! let date = NaiveDate::from_ymd(2022, 1, 11);
+//! assert_eq!(api.handle(&state, "GET", "/api/slots/BISHAN/2022-01-11", None, b"").status, 404);
+//! let slots = vec![Timeslot::new(Utc::now(), 25)];
+//! state.cache.insert(date, GymSlotData::new(Gym::BISHAN, Utc::now().naive_utc(), slots));
+//! let res = api.handle(&state, "GET", "/api/slots/BISHAN/2022-01-11", None, b"");
+//! assert_eq!(res.status, 200);
+//! assert_eq!(res.body["stale"], false);
+//! assert_eq!(api.handle(&state, "GET", "/api/slots/NOWHERE/2022-01-11", None, b"").status, 400);
+//! assert_eq!(api.handle(&state, "DELETE", "/api/slots/BISHAN/2022-01-11", None, b"").status, 405);
+//!
+//! // browsing by gym
+//! let gyms = api.handle(&state, "GET", "/api/gyms", None, b"").body;
+//! assert_eq!(gyms.as_array().unwrap().len(), Gym::gym_slice().len());
+//! assert_eq!(gyms[4], serde_json::json!({"id": 137, "gym": "BISHAN", "name": "Bishan ActiveSG Gym"}));
+//! let res = api.handle(&state, "GET", "/api/gyms/BISHAN/slots?date=2022-01-11", None, b"");
+//! assert_eq!(res.status, 200);
+//! assert_eq!(res.body["scraped_at"], res.body["data"]["datetime"].as_str().unwrap().to_owned() + "Z");
+//! assert_eq!(api.handle(&state, "GET", "/api/gyms/BISHAN/slots?date=tomorrow", None, b"").status, 400);
+//! // no snapshot of today
+//! assert_eq!(api.handle(&state, "GET", "/api/gyms/BISHAN/slots", None, b"").status, 404);
+//! assert_eq!(api.handle(&state, "POST", "/api/gyms", None, b"").status, 405);
+//! // also without the prefix
+//! assert_eq!(api.handle(&state, "GET", "/gyms", None, b"").body, gyms);
+//! assert_eq!(api.handle(&state, "GET", "/gyms/BISHAN/slots?date=2022-01-11", None, b"").body, res.body);
+//! assert_eq!(api.handle(&state, "POST", "/gyms/BISHAN/slots", None, b"").status, 405);
+//! assert_eq!(api.handle(&state, "GET", "/slots/BISHAN/2022-01-11", None, b"").status, 404);
+//!
+//! // sink health, none measured yet
+//! assert_eq!(api.handle(&state, "GET", "/api/sinks", None, b"").body, serde_json::json!([]));
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use hyper::{
+    body::HttpBody,
+    header,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::{
+    cache::{CachedSnapshot, SnapshotKey},
+    clock::SGT,
+    errors,
+    fetch::FetchTask,
+    models::{Activity, Gym},
+    sinkhealth::SinkStatus,
+    DataMResult,
+};
+
+/// Refreshes waiting to be fetched at most, further requests are refused with a `429`
+pub const MAX_PENDING_REFRESHES: usize = 4;
+
+/// Outcomes of this many refreshes are kept for polling, the oldest are forgotten
+pub const MAX_TRACKED_REFRESHES: usize = 256;
+
+/// Larger request bodies are refused
+pub const MAX_BODY_BYTES: u64 = 4096;
+
+/// What the API needs from the daemon
+pub trait ApiState: Send + Sync {
+    /// Whether the daily download budget is used up, see [crate::metrics::DownloadBudget]
+    fn budget_exhausted(&self) -> bool;
+
+    /// Latest snapshot of `key`
+    fn snapshot(&self, key: &SnapshotKey) -> Option<CachedSnapshot>;
+
+    /// Health of every sink, see [crate::sinkhealth]
+    fn sinks(&self) -> Vec<SinkStatus> {
+        vec![]
+    }
+
+    /// Date in Singapore, queried when a request leaves it out
+    fn today(&self) -> NaiveDate {
+        Utc::now().with_timezone(&*SGT).date().naive_local()
+    }
+}
+
+/// Body of `POST /api/refresh`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RefreshRequest {
+    pub gym: Gym,
+    pub date: NaiveDate,
+
+    /// POSTed the [RefreshStatus] once the refresh is over
+    #[serde(default)]
+    pub callback_url: Option<String>,
+}
+
+/// Refresh waiting for the daemon to fetch it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuedRefresh {
+    pub id: u64,
+    pub task: FetchTask,
+    pub callback_url: Option<String>,
+}
+
+/// Where a refresh is at, as answered by `GET /api/refresh/<id>`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RefreshStatus {
+    Queued,
+    Running,
+
+    /// fetched and published, the snapshot is served by `/api/slots`
+    Done {
+        slots: usize,
+    },
+    Failed {
+        class: String,
+        message: String,
+    },
+}
+
+/// Refreshes accepted by the API, handed to the daemon through a bounded channel
+#[derive(Debug)]
+pub struct RefreshQueue {
+    sender: mpsc::Sender<QueuedRefresh>,
+    next_id: AtomicU64,
+    statuses: Mutex<BTreeMap<u64, RefreshStatus>>,
+}
+
+impl RefreshQueue {
+    /// Queue of at most `capacity` pending refreshes, along with the receiving end for the daemon
+    pub fn new(capacity: usize) -> (Self, mpsc::Receiver<QueuedRefresh>) {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        let queue = Self {
+            sender,
+            next_id: AtomicU64::new(1),
+            statuses: Mutex::default(),
+        };
+        (queue, receiver)
+    }
+
+    /// Queues a refresh of `task`, none when the queue is full
+    pub fn enqueue(&self, task: FetchTask, callback_url: Option<String>) -> Option<u64> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let refresh = QueuedRefresh {
+            id,
+            task,
+            callback_url,
+        };
+
+        // the status goes first, the daemon may pick the refresh up right away
+        self.set_status(id, RefreshStatus::Queued);
+        match self.sender.try_send(refresh) {
+            Ok(_) => Some(id),
+            Err(_) => {
+                self.statuses.lock().unwrap().remove(&id);
+                None
+            }
+        }
+    }
+
+    pub fn set_status(&self, id: u64, status: RefreshStatus) {
+        let mut statuses = self.statuses.lock().unwrap();
+        statuses.insert(id, status);
+        while statuses.len() > MAX_TRACKED_REFRESHES {
+            statuses.pop_first();
+        }
+    }
+
+    pub fn status(&self, id: u64) -> Option<RefreshStatus> {
+        self.statuses.lock().unwrap().get(&id).cloned()
+    }
+}
+
+/// Answer of the API, always JSON
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiResponse {
+    pub status: u16,
+    pub body: serde_json::Value,
+}
+
+impl ApiResponse {
+    fn new(status: u16, body: serde_json::Value) -> Self {
+        Self { status, body }
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        Self::new(status, json!({ "error": message.into() }))
+    }
+}
+
+/// Routes of the API, independent of the HTTP server so they can be called directly
+#[derive(Debug)]
+pub struct Api {
+    token: Option<String>,
+    queue: RefreshQueue,
+}
+
+impl Api {
+    /// Refreshes are refused unless a `token` is given
+    pub fn new(token: Option<String>, queue: RefreshQueue) -> Self {
+        Self { token, queue }
+    }
+
+    pub fn queue(&self) -> &RefreshQueue {
+        &self.queue
+    }
+
+    /// Answers a request, `authorization` being the value of its `Authorization` header
+    /// and `path` possibly ending with a query string
+    pub fn handle<B: ApiState>(
+        &self,
+        state: &B,
+        method: &str,
+        path: &str,
+        authorization: Option<&str>,
+        body: &[u8],
+    ) -> ApiResponse {
+        let (path, query) = path.split_once('?').unwrap_or((path, ""));
+        let segments = path
+            .trim_matches('/')
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>();
+
+        match (method, segments.as_slice()) {
+            ("POST", ["api", "refresh"]) => self.refresh(state, authorization, body),
+            ("GET", ["api", "refresh", id]) => self.refresh_status(id),
+            ("GET", ["api", "slots", gym, date]) => slots(state, gym, date),
+            // also served without the prefix
+            ("GET", ["api", "gyms"] | ["gyms"]) => gyms(),
+            ("GET", ["api", "gyms", gym, "slots"] | ["gyms", gym, "slots"]) => {
+                let date = query_param(query, "date");
+                let today = state.today().to_string();
+                slots(state, gym, date.unwrap_or(&today))
+            }
+            ("GET", ["api", "sinks"]) => ApiResponse::new(200, json!(state.sinks())),
+            (
+                _,
+                ["api", "refresh"]
+                | ["api", "refresh", _]
+                | ["api", "slots", _, _]
+                | ["api", "gyms"]
+                | ["gyms"]
+                | ["api", "gyms", _, "slots"]
+                | ["gyms", _, "slots"]
+                | ["api", "sinks"],
+            ) => ApiResponse::error(405, format!("{} not allowed on {}", method, path)),
+            _ => ApiResponse::error(404, format!("no route {}", path)),
+        }
+    }
+
+    fn is_authorized(&self, authorization: Option<&str>) -> bool {
+        let given = authorization.and_then(|a| a.strip_prefix("Bearer "));
+        match (&self.token, given) {
+            (Some(token), Some(given)) => constant_time_eq(token.as_bytes(), given.as_bytes()),
+            _ => false,
+        }
+    }
+
+    fn refresh<B: ApiState>(
+        &self,
+        state: &B,
+        authorization: Option<&str>,
+        body: &[u8],
+    ) -> ApiResponse {
+        if self.token.is_none() {
+            return ApiResponse::error(403, "refreshes are disabled without an api token");
+        }
+        if !self.is_authorized(authorization) {
+            return ApiResponse::error(401, "missing or invalid bearer token");
+        }
+
+        let req = match serde_json::from_slice::<RefreshRequest>(body) {
+            Ok(req) => req,
+            Err(e) => return ApiResponse::error(400, format!("invalid refresh request: {}", e)),
+        };
+        if state.budget_exhausted() {
+            let e = errors::Error::BudgetExhausted;
+            return ApiResponse::new(503, json!({ "error": e.to_string(), "class": e.class() }));
+        }
+
+        let task = FetchTask::new(Activity::GYM, req.gym, req.date);
+        match self.queue.enqueue(task, req.callback_url) {
+            Some(id) => ApiResponse::new(
+                202,
+                json!({
+                    "request_id": id,
+                    "status_url": format!("/api/refresh/{}", id),
+                    "slots_url": format!("/api/slots/{:?}/{}", req.gym, req.date),
+                }),
+            ),
+            None => ApiResponse::error(429, "too many refreshes pending, try again later"),
+        }
+    }
+
+    fn refresh_status(&self, id: &str) -> ApiResponse {
+        let status = id.parse().ok().and_then(|id| self.queue.status(id));
+        match status {
+            Some(status) => ApiResponse::new(200, json!(status)),
+            None => ApiResponse::error(404, format!("no refresh {}", id)),
+        }
+    }
+}
+
+fn slots<B: ApiState>(state: &B, gym: &str, date: &str) -> ApiResponse {
+    let gym = match gym.parse::<Gym>() {
+        Ok(gym) => gym,
+        Err(_) => return ApiResponse::error(400, format!("unknown gym {}", gym)),
+    };
+    let date = match date.parse::<NaiveDate>() {
+        Ok(date) => date,
+        Err(_) => return ApiResponse::error(400, format!("invalid date {}", date)),
+    };
+
+    match state.snapshot(&(Activity::GYM, gym.into(), date)) {
+        Some(cached) => ApiResponse::new(
+            200,
+            json!({
+                "scraped_at": DateTime::<Utc>::from_utc(cached.data.datetime(), Utc),
+                "age_seconds": cached.age_seconds,
+                "stale": cached.stale,
+                "data": cached.data,
+            }),
+        ),
+        None => ApiResponse::error(404, format!("no snapshot of {:?} on {}", gym, date)),
+    }
+}
+
+fn gyms() -> ApiResponse {
+    let gyms = Gym::gym_slice()
+        .iter()
+        .map(|gym| json!({ "id": *gym as u16, "gym": gym, "name": gym.display() }))
+        .collect::<Vec<_>>();
+    ApiResponse::new(200, json!(gyms))
+}
+
+/// Value of `name` in a query string, taken as is
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Compares without returning early, so the time taken doesn't leak how much of the token matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Body of a request, none once it passes [MAX_BODY_BYTES], whether or not it said
+/// how long it is
+async fn read_body(mut body: Body) -> Result<Option<Vec<u8>>, hyper::Error> {
+    let mut buf = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if (buf.len() + chunk.len()) as u64 > MAX_BODY_BYTES {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(Some(buf))
+}
+
+async fn respond<B: ApiState>(api: &Api, state: &B, req: Request<Body>) -> Response<Body> {
+    let too_large = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse::<u64>().ok())
+        .is_some_and(|len| len > MAX_BODY_BYTES);
+
+    let (parts, body) = req.into_parts();
+    let body = match too_large {
+        true => Ok(None),
+        false => read_body(body).await,
+    };
+    let res = match body {
+        Ok(Some(body)) => {
+            let authorization = parts
+                .headers
+                .get(header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok());
+            api.handle(
+                state,
+                parts.method.as_str(),
+                parts
+                    .uri
+                    .path_and_query()
+                    .map_or(parts.uri.path(), |p| p.as_str()),
+                authorization,
+                &body,
+            )
+        }
+        Ok(None) => ApiResponse::error(413, "request body too large"),
+        Err(e) => ApiResponse::error(400, format!("reading the body failed: {}", e)),
+    };
+
+    Response::builder()
+        .status(res.status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(res.body.to_string()))
+        .unwrap_or_default()
+}
+
+/// Serves `api` on `addr` until the process exits
+///
+/// ## Example
+/// A chunked body, which doesn't say how long it is, is refused once too large
+/// ```
+/// use std::sync::Arc;
+/// use activesg_gym_datamine::{
+///     cache::{CachedSnapshot, SnapshotKey},
+///     server::{self, Api, ApiState, RefreshQueue, MAX_BODY_BYTES},
+/// };
+/// use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream};
+///
+/// struct State;
+///
+/// impl ApiState for State {
+///     fn budget_exhausted(&self) -> bool {
+///         false
+///     }
+///
+///     fn snapshot(&self, _: &SnapshotKey) -> Option<CachedSnapshot> {
+///         None
+///     }
+/// }
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+/// let api = Arc::new(Api::new(Some("s3cret".into()), RefreshQueue::new(1).0));
+/// tokio::spawn(server::serve(addr, api, Arc::new(State)));
+///
+/// let mut socket = loop {
+///     match TcpStream::connect(addr).await {
+///         Ok(socket) => break socket,
+///         Err(_) => tokio::task::yield_now().await,
+///     }
+/// };
+/// let head = "POST /api/refresh HTTP/1.1\r\nhost: localhost\r\nauthorization: Bearer s3cret\r\n\
+///     connection: close\r\ntransfer-encoding: chunked\r\n\r\n";
+/// socket.write_all(head.as_bytes()).await.unwrap();
+/// let chunk = format!("400\r\n{}\r\n", "x".repeat(0x400));
+/// for _ in 0..=MAX_BODY_BYTES / 0x400 {
+///     // the server may answer and close before the last chunks
+///     if socket.write_all(chunk.as_bytes()).await.is_err() {
+///         break;
+///     }
+/// }
+/// let _ = socket.write_all(b"0\r\n\r\n").await;
+///
+/// let mut response = vec![];
+/// let _ = socket.read_to_end(&mut response).await;
+/// let response = String::from_utf8_lossy(&response);
+/// assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+/// # }
+/// ```
+pub async fn serve<B>(addr: SocketAddr, api: Arc<Api>, state: Arc<B>) -> DataMResult<()>
+where
+    B: ApiState + 'static,
+{
+    let make_service = make_service_fn(move |_| {
+        let (api, state) = (api.clone(), state.clone());
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let (api, state) = (api.clone(), state.clone());
+                async move { Ok::<_, Infallible>(respond(&api, state.as_ref(), req).await) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&addr)
+        .map_err(|e| errors::Error::InvalidConfig(format!("can't listen on {}: {}", addr, e)))?
+        .serve(make_service);
+    info!("Serving the API on http://{}", addr);
+
+    if let Err(e) = server.await {
+        warn!("API server stopped: {}", e);
+    }
+    Ok(())
+}
@@ -0,0 +1,352 @@
+//! Prompts of the `setup` subcommand, which writes a config file and the password beside it
+
+use std::{
+    fs::OpenOptions,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::Config,
+    models::Gym,
+    sink::{FileSink, OutputFormat},
+};
+
+/// Answers collected by the [Wizard]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetupAnswers {
+    pub username: String,
+    pub password: String,
+    pub gyms: Vec<Gym>,
+    pub interval_secs: u64,
+    pub output_dir: PathBuf,
+    pub format: OutputFormat,
+}
+
+/// Prompt flow of the `setup` subcommand
+///
+/// Each step reads from `input` and writes its prompts to `output`,
+/// so the flow can be driven by anything implementing [BufRead] and [Write]
+///
+/// ## Example
+/// ```
+/// use std::io::Cursor;
+/// use activesg_gym_datamine::{models::Gym, setup::Wizard};
+///
+/// let mut output = vec![];
+/// let input = Cursor::new("user@example.com\nhunter2\n1\n60\n\njsonl\n");
+/// let answers = Wizard::new(input, &mut output, false).run().unwrap();
+/// assert_eq!(answers.username, "user@example.com");
+/// assert_eq!(answers.password, "hunter2");
+/// assert_eq!(answers.gyms, [Gym::gym_slice()[0]]);
+/// assert_eq!(answers.interval_secs, 60);
+/// assert_eq!(answers.output_dir.to_str(), Some("output"));
+///
+/// // input ending before the last answer
+/// let input = Cursor::new("user@example.com\nhunter2\n");
+/// assert!(Wizard::new(input, vec![], false).run().is_err());
+/// ```
+pub struct Wizard<R, W> {
+    input: R,
+    output: W,
+
+    /// read secrets with echo disabled instead of from `input`, with the `cli` feature
+    #[cfg_attr(not(feature = "cli"), allow(dead_code))]
+    hide_secrets: bool,
+}
+
+impl<R: BufRead, W: Write> Wizard<R, W> {
+    pub fn new(input: R, output: W, hide_secrets: bool) -> Self {
+        Self {
+            input,
+            output,
+            hide_secrets,
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut buf = String::new();
+        if self.input.read_line(&mut buf)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "input closed during setup",
+            ));
+        }
+        Ok(buf.trim().to_string())
+    }
+
+    /// Asks `question`, returning `default` when the answer is empty
+    fn ask(&mut self, question: &str, default: Option<&str>) -> io::Result<String> {
+        loop {
+            match default {
+                Some(d) => write!(self.output, "{} [{}]: ", question, d)?,
+                None => write!(self.output, "{}: ", question)?,
+            }
+            self.output.flush()?;
+
+            let answer = self.read_line()?;
+            match (answer.is_empty(), default) {
+                (true, Some(d)) => return Ok(d.to_string()),
+                (true, None) => continue,
+                (false, _) => return Ok(answer),
+            }
+        }
+    }
+
+    fn ask_secret(&mut self, question: &str) -> io::Result<String> {
+        #[cfg(feature = "cli")]
+        if self.hide_secrets {
+            return rpassword::prompt_password(format!("{}: ", question));
+        }
+
+        write!(self.output, "{}: ", question)?;
+        self.output.flush()?;
+        self.read_line()
+    }
+
+    /// Asks a yes or no `question` until answered, `default` being the empty answer
+    ///
+    /// ## Example
+    /// ```
+    /// use std::io::Cursor;
+    /// use activesg_gym_datamine::setup::Wizard;
+    ///
+    /// let mut output = vec![];
+    /// let mut wizard = Wizard::new(Cursor::new("maybe\nYES\n\n"), &mut output, false);
+    /// assert!(wizard.confirm("Overwrite?", false).unwrap());
+    /// assert!(!wizard.confirm("Overwrite?", false).unwrap());
+    /// assert!(wizard.confirm("Overwrite?", true).is_err());
+    /// drop(wizard);
+    ///
+    /// let output = String::from_utf8(output).unwrap();
+    /// assert!(output.starts_with("Overwrite? (y/N) []: Please answer y or n\n"));
+    /// ```
+    pub fn confirm(&mut self, question: &str, default: bool) -> io::Result<bool> {
+        let hint = if default { "Y/n" } else { "y/N" };
+        loop {
+            let answer = self.ask(&format!("{} ({})", question, hint), Some(""))?;
+            match answer.to_ascii_lowercase().as_str() {
+                "" => return Ok(default),
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => writeln!(self.output, "Please answer y or n")?,
+            }
+        }
+    }
+
+    pub fn credentials(&mut self) -> io::Result<(String, String)> {
+        let username = self.ask("ActiveSG email", None)?;
+        let password = loop {
+            let pwd = self.ask_secret("ActiveSG password")?;
+            if !pwd.is_empty() {
+                break pwd;
+            }
+        };
+        Ok((username, password))
+    }
+
+    /// Multi-select over [Gym::gym_slice], accepting `all` or comma separated numbers
+    ///
+    /// ## Example
+    /// ```
+    /// use std::io::Cursor;
+    /// use activesg_gym_datamine::{models::Gym, setup::Wizard};
+    ///
+    /// let gyms = Gym::gym_slice();
+    /// let mut output = vec![];
+    /// let mut wizard = Wizard::new(Cursor::new("0\n3, 1,3\n\n"), &mut output, false);
+    /// assert_eq!(wizard.gyms().unwrap(), [gyms[2], gyms[0]]);
+    /// assert_eq!(wizard.gyms().unwrap(), gyms);
+    /// drop(wizard);
+    ///
+    /// let output = String::from_utf8(output).unwrap();
+    /// assert!(output.starts_with(&format!("  1) {} ({:?})\n", gyms[0].display(), gyms[0])));
+    /// assert!(output.contains(&format!("Please pick numbers from 1 to {}", gyms.len())));
+    /// ```
+    pub fn gyms(&mut self) -> io::Result<Vec<Gym>> {
+        let gyms = Gym::gym_slice();
+        for (i, gym) in gyms.iter().enumerate() {
+            writeln!(self.output, "{:>3}) {} ({:?})", i + 1, gym.display(), gym)?;
+        }
+
+        loop {
+            let answer = self.ask("Gyms to mine, e.g. 1,5,9", Some("all"))?;
+            if answer.eq_ignore_ascii_case("all") {
+                return Ok(gyms.to_vec());
+            }
+
+            let selected = answer
+                .split(',')
+                .map(|n| n.trim().parse::<usize>().ok())
+                .map(|n| n.and_then(|n| n.checked_sub(1)).and_then(|n| gyms.get(n)))
+                .collect::<Option<Vec<_>>>();
+
+            match selected {
+                Some(s) if !s.is_empty() => {
+                    let mut buf = Vec::with_capacity(s.len());
+                    for gym in s {
+                        if !buf.contains(gym) {
+                            buf.push(*gym);
+                        }
+                    }
+                    return Ok(buf);
+                }
+                _ => writeln!(self.output, "Please pick numbers from 1 to {}", gyms.len())?,
+            }
+        }
+    }
+
+    /// Seconds between iterations, [Config::DEFAULT_INTERVAL] by default
+    ///
+    /// ## Example
+    /// ```
+    /// use std::io::Cursor;
+    /// use activesg_gym_datamine::{config::Config, setup::Wizard};
+    ///
+    /// let mut wizard = Wizard::new(Cursor::new("0\nsoon\n90\n\n"), vec![], false);
+    /// assert_eq!(wizard.interval().unwrap(), 90);
+    /// assert_eq!(wizard.interval().unwrap(), Config::DEFAULT_INTERVAL.as_secs());
+    /// ```
+    pub fn interval(&mut self) -> io::Result<u64> {
+        let default = Config::DEFAULT_INTERVAL.as_secs().to_string();
+        loop {
+            let answer = self.ask("Seconds between iterations", Some(&default))?;
+            match answer.parse::<u64>() {
+                Ok(secs) if secs > 0 => return Ok(secs),
+                _ => writeln!(self.output, "Please enter a positive number of seconds")?,
+            }
+        }
+    }
+
+    pub fn output_dir(&mut self) -> io::Result<PathBuf> {
+        self.ask("Output directory", Some(FileSink::DEFAULT_OUTPUT_DIR))
+            .map(PathBuf::from)
+    }
+
+    /// ## Example
+    /// ```
+    /// use std::io::Cursor;
+    /// use activesg_gym_datamine::{setup::Wizard, sink::OutputFormat};
+    ///
+    /// let mut wizard = Wizard::new(Cursor::new("csv\nSoA\n\n"), vec![], false);
+    /// assert_eq!(wizard.format().unwrap(), OutputFormat::Soa);
+    /// assert_eq!(wizard.format().unwrap(), OutputFormat::Json);
+    /// ```
+    pub fn format(&mut self) -> io::Result<OutputFormat> {
+        loop {
+            let answer = self.ask("Output format, json, soa or jsonl", Some("json"))?;
+            match answer.to_ascii_lowercase().as_str() {
+                "json" => return Ok(OutputFormat::Json),
+                "soa" => return Ok(OutputFormat::Soa),
+                "jsonl" => return Ok(OutputFormat::Jsonl),
+                _ => writeln!(self.output, "Please answer json, soa or jsonl")?,
+            }
+        }
+    }
+
+    /// Runs every step in order
+    pub fn run(&mut self) -> io::Result<SetupAnswers> {
+        let (username, password) = self.credentials()?;
+        Ok(SetupAnswers {
+            username,
+            password,
+            gyms: self.gyms()?,
+            interval_secs: self.interval()?,
+            output_dir: self.output_dir()?,
+            format: self.format()?,
+        })
+    }
+}
+
+fn toml_str(s: &str) -> String {
+    toml::Value::String(s.into()).to_string()
+}
+
+/// Renders `answers` as a commented TOML config that [ConfigFile::load] understands
+///
+/// The password itself is not included, it is referenced through `password_file`
+pub fn render_config(answers: &SetupAnswers, password_file: &Path) -> String {
+    let gyms = answers
+        .gyms
+        .iter()
+        .map(|g| toml_str(&format!("{:?}", g)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let format = match answers.format {
+        OutputFormat::Json => "json",
+        OutputFormat::Soa => "soa",
+        OutputFormat::Jsonl => "jsonl",
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => "parquet",
+    };
+
+    format!(
+        r#"# ActiveSG Slot Dataminer config, generated by `setup`
+
+# email address used to login
+username = {}
+
+# file containing only the password, relative to this file
+password_file = {}
+
+# gyms to query, remove this line to query every gym
+gyms = [{}]
+
+# seconds between iterations
+interval_secs = {}
+
+# directory the snapshots are written to
+output_dir = {}
+
+# "json" for array of struct, "soa" for struct of array or "jsonl" for a JSON Lines file per day
+format = {}
+"#,
+        toml_str(&answers.username),
+        toml_str(&password_file.to_string_lossy()),
+        gyms,
+        answers.interval_secs,
+        toml_str(&answers.output_dir.to_string_lossy()),
+        toml_str(format),
+    )
+}
+
+/// Writes the password readable only by the current user
+///
+/// A file already there is made so before the password is written, the mode given when
+/// creating a file doesn't change an existing one
+///
+/// ## Example
+/// ```
+/// # #[cfg(unix)] {
+/// use std::os::unix::fs::PermissionsExt;
+/// use activesg_gym_datamine::setup;
+///
+/// let path = std::env::temp_dir().join(format!("setup-doctest-{}.password", std::process::id()));
+/// std::fs::write(&path, "old").unwrap();
+/// std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+///
+/// setup::write_password_file(&path, "hunter2").unwrap();
+/// assert_eq!(std::fs::read_to_string(&path).unwrap(), "hunter2\n");
+/// let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+/// assert_eq!(mode & 0o777, 0o600);
+/// std::fs::remove_file(&path).unwrap();
+/// # }
+/// ```
+pub fn write_password_file(path: &Path, password: &str) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut f = options.open(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        f.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    writeln!(f, "{}", password)
+}
@@ -0,0 +1,945 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{fs::File, io::AsyncWriteExt};
+use tracing::{debug, info, warn, Instrument};
+
+use crate::{
+    clock::SGT,
+    compress::Compression,
+    diff::SnapshotDiff,
+    errors,
+    models::{Activity, GymSlotData, GymSlotDataSoA},
+    template::{FilenameFields, FilenameTemplate},
+    trace,
+    venue::Venue,
+    DataMResult,
+};
+
+/// Destination of parsed [GymSlotData]
+pub trait Sink {
+    /// Name used in logs and metrics
+    fn name(&self) -> &str;
+
+    /// Publishes a single snapshot, returning the number of bytes written
+    fn publish(&self, data: &GymSlotData) -> impl Future<Output = DataMResult<u64>> + Send;
+
+    /// Publishes the changes since the previous snapshot of the same gym and date,
+    /// ignored unless the sink supports diffs
+    fn publish_diff(&self, _diff: &SnapshotDiff) -> impl Future<Output = DataMResult<u64>> + Send {
+        async { Ok(0) }
+    }
+
+    /// Writes out what the sink buffered, at the end of every iteration,
+    /// returning the number of bytes written
+    fn flush(&self) -> impl Future<Output = DataMResult<u64>> + Send {
+        async { Ok(0) }
+    }
+
+    /// Publishes a snapshot that may have been published under the same `key` before,
+    /// sinks that can tell skip it rather than publishing it twice
+    fn publish_keyed(
+        &self,
+        _key: &PublishKey,
+        data: &GymSlotData,
+    ) -> impl Future<Output = DataMResult<u64>> + Send {
+        self.publish(data)
+    }
+
+    /// Publishes to the sinks that haven't got the snapshot yet according to `delivery`,
+    /// recording the ones that succeed and how long every attempt took
+    ///
+    /// Every attempt is within a `sink` span, see [trace::sink_span]
+    fn deliver(
+        &self,
+        key: &PublishKey,
+        data: &GymSlotData,
+        delivery: &mut Delivery,
+    ) -> impl Future<Output = DataMResult<()>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            if delivery.is_delivered(self.name()) || delivery.is_disabled(self.name()) {
+                return Ok(());
+            }
+
+            let span = trace::sink_span(self.name(), key);
+            let started = Instant::now();
+            let res = self.publish_keyed(key, data).instrument(span.clone()).await;
+            trace::record_outcome(&span, &res);
+            delivery.record_latency(self.name(), started.elapsed());
+            delivery.mark_delivered(self.name(), res?);
+            Ok(())
+        }
+    }
+}
+
+/// Deterministic key of a snapshot, the same across the retries of its delivery
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PublishKey {
+    pub activity: Activity,
+    pub gym: Venue,
+
+    /// date of the booking page
+    pub date: NaiveDate,
+
+    /// see [crate::iteration::IterationCounter]
+    pub iteration: u64,
+}
+
+impl PublishKey {
+    pub fn new<V: Into<Venue>>(
+        activity: Activity,
+        gym: V,
+        date: NaiveDate,
+        iteration: u64,
+    ) -> Self {
+        Self {
+            activity,
+            gym: gym.into(),
+            date,
+            iteration,
+        }
+    }
+}
+
+/// `<activity>-<gym>-<date>-<iteration>`, e.g. `GYM-BISHAN-2022-01-11-42`
+impl fmt::Display for PublishKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?}-{:?}-{}-{}",
+            self.activity, self.gym, self.date, self.iteration
+        )
+    }
+}
+
+/// Sinks a snapshot was delivered to, by [Sink::name], with the bytes written to each,
+/// and the sinks whose filter kept it from them, see [crate::filter]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Delivery {
+    delivered: BTreeMap<String, u64>,
+    filtered: BTreeSet<String>,
+    latencies: BTreeMap<String, Vec<Duration>>,
+
+    /// skipped, see [crate::sinkhealth]
+    disabled: BTreeSet<String>,
+}
+
+impl Delivery {
+    /// Delivery that skips the `disabled` sinks
+    pub fn without(disabled: BTreeSet<String>) -> Self {
+        Self {
+            disabled,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_disabled(&self, sink: &str) -> bool {
+        self.disabled.contains(sink)
+    }
+
+    pub fn is_delivered(&self, sink: &str) -> bool {
+        self.delivered.contains_key(sink)
+    }
+
+    pub fn mark_delivered(&mut self, sink: &str, written: u64) {
+        self.delivered.insert(sink.to_string(), written);
+    }
+
+    pub fn is_filtered(&self, sink: &str) -> bool {
+        self.filtered.contains(sink)
+    }
+
+    pub fn mark_filtered(&mut self, sink: &str) {
+        self.filtered.insert(sink.to_string());
+    }
+
+    /// Bytes written per sink
+    pub fn written(&self) -> &BTreeMap<String, u64> {
+        &self.delivered
+    }
+
+    /// Sinks the snapshot was filtered out of
+    pub fn filtered(&self) -> &BTreeSet<String> {
+        &self.filtered
+    }
+
+    pub fn record_latency(&mut self, sink: &str, latency: Duration) {
+        self.latencies
+            .entry(sink.to_string())
+            .or_default()
+            .push(latency);
+    }
+
+    /// Time taken by every attempt per sink, failed ones included
+    pub fn latencies(&self) -> &BTreeMap<String, Vec<Duration>> {
+        &self.latencies
+    }
+}
+
+/// Delivers `data` to `sink` in up to `attempts` attempts, each retry only going
+/// to the sinks that failed so far
+///
+/// The sinks it was delivered to are recorded in `delivery`, which is returned
+/// along with the last error once every attempt failed
+///
+/// ## Example
+/// ```
+/// use std::sync::atomic::{AtomicU32, Ordering};
+/// use activesg_gym_datamine::{
+///     models::{Activity, Gym, GymSlotData},
+///     sink::{self, Delivery, FanOut, PublishKey, Sink},
+///     DataMResult,
+/// };
+/// use chrono::NaiveDate;
+///
+/// /// fails the first `failures` publishes
+/// struct Flaky {
+///     name: &'static str,
+///     failures: u32,
+///     calls: AtomicU32,
+/// }
+///
+/// impl Sink for Flaky {
+///     fn name(&self) -> &str {
+///         self.name
+///     }
+///
+///     async fn publish(&self, _data: &GymSlotData) -> DataMResult<u64> {
+///         match self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+///             true => Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into()),
+///             false => Ok(10),
+///         }
+///     }
+/// }
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let flaky = |name, failures| Flaky { name, failures, calls: AtomicU32::new(0) };
+/// let date = NaiveDate::from_ymd(2022, 1, 11);
+/// let data = GymSlotData::new(Gym::BISHAN, date.and_hms(1, 0, 0), vec![]);
+/// let key = PublishKey::new(Activity::GYM, Gym::BISHAN, date, 42);
+/// assert_eq!(key.to_string(), "GYM-BISHAN-2022-01-11-42");
+///
+/// let sinks = FanOut::new(flaky("steady", 0), flaky("webhook", 2));
+/// let mut delivery = Delivery::default();
+/// sink::deliver(&sinks, &key, &data, 3, &mut delivery).await.unwrap();
+/// assert_eq!(delivery.written().values().sum::<u64>(), 20);
+/// assert_eq!(delivery.latencies()["webhook"].len(), 3);
+///
+/// // the steady sink got the snapshot once, the retries only went to the webhook
+/// assert_eq!(sinks.first().calls.load(Ordering::SeqCst), 1);
+/// assert_eq!(sinks.second().calls.load(Ordering::SeqCst), 3);
+///
+/// let sinks = FanOut::new(flaky("steady", 0), flaky("webhook", 5));
+/// assert!(sink::deliver(&sinks, &key, &data, 3, &mut Delivery::default()).await.is_err());
+/// assert_eq!(sinks.first().calls.load(Ordering::SeqCst), 1);
+/// assert_eq!(sinks.second().calls.load(Ordering::SeqCst), 3);
+/// # }
+/// ```
+pub async fn deliver<S>(
+    sink: &S,
+    key: &PublishKey,
+    data: &GymSlotData,
+    attempts: u32,
+    delivery: &mut Delivery,
+) -> DataMResult<()>
+where
+    S: Sink + Sync,
+{
+    let mut attempt = 1;
+
+    loop {
+        match sink.deliver(key, data, delivery).await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt >= attempts => return Err(e),
+            Err(e) => {
+                warn!(
+                    "{}, delivering {} failed on attempt {} of {}, retrying: {}",
+                    sink.name(),
+                    key,
+                    attempt,
+                    attempts,
+                    e
+                );
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Publishes every snapshot to two sinks, nest them for more
+///
+/// Deliveries are tracked per sink, so the sinks need distinct names
+#[derive(Debug, Clone)]
+pub struct FanOut<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> FanOut<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+
+    pub fn first(&self) -> &A {
+        &self.first
+    }
+
+    pub fn second(&self) -> &B {
+        &self.second
+    }
+}
+
+impl<A, B> Sink for FanOut<A, B>
+where
+    A: Sink + Send + Sync,
+    B: Sink + Send + Sync,
+{
+    fn name(&self) -> &str {
+        "fanout"
+    }
+
+    /// Publishes to both sinks, failing if either failed
+    async fn publish(&self, data: &GymSlotData) -> DataMResult<u64> {
+        let first = self.first.publish(data).await;
+        let second = self.second.publish(data).await;
+        Ok(first? + second?)
+    }
+
+    async fn publish_diff(&self, diff: &SnapshotDiff) -> DataMResult<u64> {
+        let first = self.first.publish_diff(diff).await;
+        let second = self.second.publish_diff(diff).await;
+        Ok(first? + second?)
+    }
+
+    async fn publish_keyed(&self, key: &PublishKey, data: &GymSlotData) -> DataMResult<u64> {
+        let first = self.first.publish_keyed(key, data).await;
+        let second = self.second.publish_keyed(key, data).await;
+        Ok(first? + second?)
+    }
+
+    async fn flush(&self) -> DataMResult<u64> {
+        let first = self.first.flush().await;
+        let second = self.second.flush().await;
+        Ok(first? + second?)
+    }
+
+    /// Delivers to both sinks, each tracked on its own
+    async fn deliver(
+        &self,
+        key: &PublishKey,
+        data: &GymSlotData,
+        delivery: &mut Delivery,
+    ) -> DataMResult<()> {
+        let first = self.first.deliver(key, data, delivery).await;
+        let second = self.second.deliver(key, data, delivery).await;
+        first.and(second)
+    }
+}
+
+/// `<path>.tmp`, next to `path` so that renaming it over `path` is atomic
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    PathBuf::from(tmp)
+}
+
+/// Writes `data` next to `path` then renames it over `path`,
+/// so that readers never see a partially written file
+///
+/// The temporary file is synced before the rename and removed when anything fails
+///
+/// ## Example
+/// A week of slots a minute apart, a few hundred KiB of JSON, reads back whole
+/// ```
+/// use activesg_gym_datamine::{
+///     models::{Gym, GymSlotData, GymSlotDataSoA, Timeslot},
+///     sink,
+/// };
+/// use chrono::{Duration, NaiveDate, TimeZone, Utc};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let dir = std::env::temp_dir().join(format!("short-write-doctest-{}", std::process::id()));
+/// std::fs::create_dir_all(&dir).unwrap();
+///
+/// let first = Utc.ymd(2022, 1, 11).and_hms(0, 0, 0);
+/// let slots = (0..7 * 24 * 60)
+///     .map(|i| Timeslot::new(first + Duration::minutes(i), (i % 50) as u16))
+///     .collect();
+/// let captured = NaiveDate::from_ymd(2022, 1, 11).and_hms(1, 30, 0);
+/// let soa = GymSlotDataSoA::from(GymSlotData::new(Gym::BISHAN, captured, slots));
+/// let data = serde_json::to_string_pretty(&soa).unwrap();
+/// assert!(data.len() > 256 * 1024);
+///
+/// let sync_path = dir.join("sync.json");
+/// let async_path = dir.join("async.json");
+/// sink::write_atomic(&sync_path, data.as_bytes()).unwrap();
+/// sink::write_atomic_async(&async_path, data.as_bytes()).await.unwrap();
+/// for path in [sync_path, async_path] {
+///     let raw = std::fs::read_to_string(&path).unwrap();
+///     assert_eq!(raw.len(), data.len());
+///     assert_eq!(serde_json::from_str::<GymSlotDataSoA>(&raw).unwrap(), soa);
+/// }
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// # }
+/// ```
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = tmp_path(path);
+    let res = (|| {
+        let mut f = std::fs::File::create(&tmp)?;
+        io::Write::write_all(&mut f, data)?;
+        f.sync_all()?;
+        std::fs::rename(&tmp, path)
+    })();
+    if res.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    res
+}
+
+/// Same as [write_atomic] without blocking, how [FileSink] writes its snapshots
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     models::{Gym, GymSlotData},
+///     sink::{self, FileSink, OutputFormat, Sink},
+/// };
+/// use chrono::NaiveDate;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let dir = std::env::temp_dir().join(format!("atomic-doctest-{}", std::process::id()));
+/// let day = dir.join("2022-01-11");
+/// let files = || {
+///     let mut names = std::fs::read_dir(&day)
+///         .unwrap()
+///         .map(|e| e.unwrap().file_name().into_string().unwrap())
+///         .collect::<Vec<_>>();
+///     names.sort();
+///     names
+/// };
+///
+/// let path = day.join("BISHAN-2022-01-11 09-30-00.json");
+/// std::fs::create_dir_all(&day).unwrap();
+/// sink::write_atomic_async(&path, b"{}").await.unwrap();
+/// assert_eq!(files(), ["BISHAN-2022-01-11 09-30-00.json"]);
+///
+/// // the rename fails when a directory is in the way, leaving no temporary file behind
+/// let captured = NaiveDate::from_ymd(2022, 1, 11).and_hms(2, 0, 0);
+/// std::fs::create_dir(day.join("CLEMENTI-2022-01-11 10-00-00.json")).unwrap();
+/// let sink = FileSink::new(&dir, OutputFormat::Json);
+/// assert!(sink.publish(&GymSlotData::new(Gym::CLEMENTI, captured, vec![])).await.is_err());
+/// assert_eq!(
+///     files(),
+///     ["BISHAN-2022-01-11 09-30-00.json", "CLEMENTI-2022-01-11 10-00-00.json"]
+/// );
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// # }
+/// ```
+pub async fn write_atomic_async(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = tmp_path(path);
+    let res = async {
+        let mut f = File::create(&tmp).await?;
+        f.write_all(data).await?;
+        // tokio finishes writing in the background unless flushed
+        f.flush().await?;
+        f.sync_all().await?;
+        tokio::fs::rename(&tmp, path).await
+    }
+    .await;
+    if res.is_err() {
+        let _ = tokio::fs::remove_file(&tmp).await;
+    }
+    res
+}
+
+/// Layout of the written snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// [GymSlotData], array of struct
+    #[default]
+    Json,
+
+    /// [GymSlotDataSoA], struct of array
+    Soa,
+
+    /// [GymSlotData] on a single line, appended to one file per day
+    Jsonl,
+
+    /// a row per timeslot, appended to one file per day when flushed, see [crate::parquet]
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl OutputFormat {
+    /// Whether snapshots are appended to a file per day rather than written to a file each
+    pub fn is_appended(&self) -> bool {
+        match self {
+            Self::Json | Self::Soa => false,
+            Self::Jsonl => true,
+            #[cfg(feature = "parquet")]
+            Self::Parquet => true,
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "soa" => Ok(Self::Soa),
+            "jsonl" => Ok(Self::Jsonl),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(Self::Parquet),
+            #[cfg(not(feature = "parquet"))]
+            "parquet" => Err("built without the parquet feature".into()),
+            _ => Err(format!("unknown output format {}", s)),
+        }
+    }
+}
+
+/// Writes each snapshot to `<output_dir>/<date>/<gym>-<datetime>.json`, or under the name of
+/// [FileSink::with_filename_template],
+/// or appends it to `<output_dir>/<date>.jsonl` with [OutputFormat::Jsonl]
+/// and to `<output_dir>/<date>.parquet` when flushed with `OutputFormat::Parquet`
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     models::{Gym, GymSlotData, Timeslot},
+///     sink::{FileSink, OutputFormat, Sink},
+/// };
+/// use chrono::{NaiveDate, TimeZone, Utc};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let dir = std::env::temp_dir().join(format!("jsonl-doctest-{}", std::process::id()));
+/// let sink = FileSink::new(&dir, OutputFormat::Jsonl);
+///
+/// // 09:30 SGT, fetched together
+/// let captured = NaiveDate::from_ymd(2022, 1, 11).and_hms(1, 30, 0);
+/// let snapshots = [Gym::BISHAN, Gym::CLEMENTI, Gym::TAMPINES].map(|gym| {
+///     let slot = Timeslot::new(Utc.ymd(2022, 1, 11).and_hms(13, 0, 0), 7);
+///     GymSlotData::new(gym, captured, vec![slot; 15])
+/// });
+/// let [a, b, c] = &snapshots;
+/// // clones append under the same lock
+/// let copy = sink.clone();
+/// let (a, b, c) = tokio::join!(sink.publish(a), copy.publish(b), sink.publish(c));
+/// assert!(a.is_ok() && b.is_ok() && c.is_ok());
+///
+/// let raw = std::fs::read_to_string(dir.join("2022-01-11.jsonl")).unwrap();
+/// let lines = raw.lines().collect::<Vec<_>>();
+/// assert_eq!(lines.len(), 3);
+/// for line in lines {
+///     let data = serde_json::from_str::<GymSlotData>(line).unwrap();
+///     assert!(snapshots.contains(&data));
+/// }
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FileSink {
+    output_dir: PathBuf,
+    format: OutputFormat,
+
+    /// see [FileSink::with_compression]
+    compression: Compression,
+    compression_level: Option<u32>,
+
+    /// see [FileSink::with_filename_template]
+    filename_template: FilenameTemplate,
+
+    /// held while appending, shared by the clones, so that lines never interleave
+    appending: Arc<tokio::sync::Mutex<()>>,
+
+    /// rows waiting for the next flush, shared by the clones
+    #[cfg(feature = "parquet")]
+    row_groups: Arc<crate::parquet::RowGroupBuffer>,
+
+    /// see [FileSink::with_uploader]
+    #[cfg(feature = "s3")]
+    uploader: Option<crate::s3::Uploader>,
+    #[cfg(feature = "s3")]
+    local: bool,
+}
+
+impl FileSink {
+    pub const DEFAULT_OUTPUT_DIR: &'static str = "output";
+
+    /// see [Sink::name]
+    pub const NAME: &'static str = "file";
+
+    pub fn new<P: Into<PathBuf>>(output_dir: P, format: OutputFormat) -> Self {
+        Self {
+            output_dir: output_dir.into(),
+            format,
+            compression: Compression::None,
+            compression_level: None,
+            filename_template: FilenameTemplate::default(),
+            appending: Arc::default(),
+            #[cfg(feature = "parquet")]
+            row_groups: Arc::default(),
+            #[cfg(feature = "s3")]
+            uploader: None,
+            #[cfg(feature = "s3")]
+            local: true,
+        }
+    }
+
+    /// Queues every snapshot and diff written for upload, see [crate::s3]
+    ///
+    /// Without `local` the files are only uploaded, never written to the output directory.
+    /// Appended formats aren't uploaded
+    #[cfg(feature = "s3")]
+    pub fn with_uploader(mut self, uploader: crate::s3::Uploader, local: bool) -> Self {
+        self.uploader = Some(uploader);
+        self.local = local;
+        self
+    }
+
+    /// Compresses the snapshots and diffs written, at `level` or the default level of
+    /// `compression`, see [crate::compress]
+    ///
+    /// Every line appended to a JSON Lines file is a gzip member of its own, which `zcat`
+    /// reads as a single file. Parquet files are never compressed this way
+    pub fn with_compression(mut self, compression: Compression, level: Option<u32>) -> Self {
+        self.compression = compression;
+        self.compression_level = level;
+        self
+    }
+
+    /// Names the snapshots and diffs written after `template`, see [crate::template]
+    ///
+    /// Files are dated in Singapore time, whatever the template
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::{
+    ///     models::{Activity, Gym, GymSlotData, Timeslot},
+    ///     sink::{FileSink, OutputFormat, Sink},
+    /// };
+    /// use chrono::{NaiveDate, TimeZone, Utc};
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let dir = std::env::temp_dir().join(format!("template-doctest-{}", std::process::id()));
+    /// // 07:30 SGT on the 11th, still the 10th in UTC
+    /// let captured = NaiveDate::from_ymd(2022, 1, 10).and_hms(23, 30, 0);
+    /// let slot = Timeslot::new(Utc.ymd(2022, 1, 12).and_hms(1, 0, 0), 7);
+    /// let data = GymSlotData::new(Gym::BISHAN, captured, vec![slot]);
+    ///
+    /// FileSink::new(&dir, OutputFormat::Json).publish(&data).await.unwrap();
+    /// assert!(dir.join("2022-01-11").join("BISHAN-2022-01-11 07-30-00.json").exists());
+    ///
+    /// // the courts of the same venue, captured at the same time, don't overwrite the gym
+    /// let courts = data.clone().with_activity(Activity::BADMINTON);
+    /// FileSink::new(&dir, OutputFormat::Json).publish(&courts).await.unwrap();
+    /// let written = std::fs::read_to_string(
+    ///     dir.join("2022-01-11").join("BADMINTON-BISHAN-2022-01-11 07-30-00.json"),
+    /// )
+    /// .unwrap();
+    /// assert_eq!(serde_json::from_str::<GymSlotData>(&written).unwrap(), courts);
+    /// assert!(dir.join("2022-01-11").join("BISHAN-2022-01-11 07-30-00.json").exists());
+    ///
+    /// let template = "{queried_date}/{gym_id}_{time}.{ext}".parse().unwrap();
+    /// let sink = FileSink::new(&dir, OutputFormat::Json).with_filename_template(template);
+    /// sink.publish(&data).await.unwrap();
+    /// assert!(dir.join("2022-01-11").join("2022-01-12").join("137_07-30-00.json").exists());
+    /// std::fs::remove_dir_all(&dir).unwrap();
+    /// # }
+    /// ```
+    pub fn with_filename_template(mut self, template: FilenameTemplate) -> Self {
+        self.filename_template = template;
+        self
+    }
+
+    /// Appends the snapshot as a single line to the file of its day in Singapore time
+    async fn append_line(&self, data: &GymSlotData) -> DataMResult<u64> {
+        let with_tz = DateTime::<FixedOffset>::from_utc(data.datetime(), *SGT);
+        let path = self.output_dir.join(format!(
+            "{}.jsonl{}",
+            with_tz.format("%Y-%m-%d"),
+            self.compression.extension()
+        ));
+
+        let mut line = serde_json::to_string(data).map_err(errors::Error::Serialize)?;
+        line.push('\n');
+        let line = self
+            .compression
+            .encode(line.as_bytes(), self.compression_level)?;
+
+        let _appending = self.appending.lock().await;
+        tokio::fs::create_dir_all(&self.output_dir).await?;
+        let mut f = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        // a single write, so that a line is never split
+        f.write_all(&line).await?;
+        f.flush().await?;
+
+        info!("{}, append successful", path.display());
+        Ok(line.len() as u64)
+    }
+
+    /// Writes the snapshot named after its capture time, so that the same snapshot
+    /// always lands in the same file
+    async fn write_to_file<T>(&self, buf: T, data: &GymSlotData) -> DataMResult<u64>
+    where
+        T: Serialize,
+    {
+        let path = self.snapshot_path(data);
+        self.write_json(buf, path).await
+    }
+
+    /// Path in the directory of the day of `datetime` in Singapore time, named after
+    /// [FileSink::with_filename_template], followed by the extension of the compression
+    ///
+    /// `first_slot` is the earliest slot of the file, see [crate::template]
+    fn path_of(
+        &self,
+        activity: Activity,
+        gym: Venue,
+        datetime: NaiveDateTime,
+        first_slot: Option<DateTime<Utc>>,
+        ext: &'static str,
+    ) -> PathBuf {
+        let with_tz = DateTime::<FixedOffset>::from_utc(datetime, *SGT);
+        let dt_no_time = with_tz.format("%Y-%m-%d").to_string();
+
+        let fields = FilenameFields {
+            activity,
+            gym,
+            captured_at: with_tz.naive_local(),
+            queried_date: first_slot
+                .map_or(with_tz, |t| t.with_timezone(&*SGT))
+                .naive_local()
+                .date(),
+            ext,
+        };
+        let filename = format!(
+            "{}{}",
+            self.filename_template.expand(&fields),
+            self.compression.extension()
+        );
+        self.output_dir.join(dt_no_time).join(filename)
+    }
+
+    fn snapshot_path(&self, data: &GymSlotData) -> PathBuf {
+        let first_slot = data.data().iter().map(|s| s.time()).min();
+        self.path_of(
+            data.activity(),
+            data.gym(),
+            data.datetime(),
+            first_slot,
+            "json",
+        )
+    }
+
+    /// Writes `buf` to `filename`, see [write_atomic_async]
+    async fn write_json<T>(&self, buf: T, filename: PathBuf) -> DataMResult<u64>
+    where
+        T: Serialize,
+    {
+        let data = serde_json::to_string_pretty(&buf).map_err(errors::Error::Serialize)?;
+        let data = self
+            .compression
+            .encode(data.as_bytes(), self.compression_level)?;
+        let len = data.len() as u64;
+
+        #[cfg(feature = "s3")]
+        if let Some(uploader) = &self.uploader {
+            let relative = filename.strip_prefix(&self.output_dir).unwrap_or(&filename);
+            let local = match self.local {
+                true => {
+                    self.write_local(&filename, &data).await?;
+                    Some(filename.clone())
+                }
+                false => None,
+            };
+            uploader.queue(relative, data, local);
+            return Ok(len);
+        }
+
+        self.write_local(&filename, &data).await?;
+        Ok(len)
+    }
+
+    /// Writes `data` to `filename`, creating its directory first
+    async fn write_local(&self, filename: &Path, data: &[u8]) -> DataMResult<()> {
+        if let Some(dir) = filename.parent() {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .map_err(errors::Error::Io)?;
+        }
+        write_atomic_async(filename, data).await?;
+
+        info!("{}, write successful", filename.display());
+        Ok(())
+    }
+}
+
+impl Sink for FileSink {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    async fn publish(&self, data: &GymSlotData) -> DataMResult<u64> {
+        match self.format {
+            OutputFormat::Json => self.write_to_file(data, data).await,
+            OutputFormat::Soa => {
+                let soa = GymSlotDataSoA::from(data.clone());
+                self.write_to_file(soa, data).await
+            }
+            OutputFormat::Jsonl => self.append_line(data).await,
+            // written when flushed
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => {
+                self.row_groups.push(data);
+                Ok(0)
+            }
+        }
+    }
+
+    async fn flush(&self) -> DataMResult<u64> {
+        #[cfg(feature = "s3")]
+        if let Some(uploader) = &self.uploader {
+            uploader.wait_idle().await;
+        }
+
+        #[cfg(feature = "parquet")]
+        if self.format == OutputFormat::Parquet {
+            return self.row_groups.clone().flush(self.output_dir.clone()).await;
+        }
+        Ok(0)
+    }
+
+    /// Skips the snapshot when its file already exists, appended snapshots aren't looked for
+    async fn publish_keyed(&self, key: &PublishKey, data: &GymSlotData) -> DataMResult<u64> {
+        if self.format.is_appended() {
+            return self.publish(data).await;
+        }
+
+        let path = self.snapshot_path(data);
+
+        if tokio::fs::metadata(&path).await.is_ok() {
+            info!("{}, {} already written, skipping", path.display(), key);
+            return Ok(0);
+        }
+        self.publish(data).await
+    }
+
+    async fn publish_diff(&self, diff: &SnapshotDiff) -> DataMResult<u64> {
+        let first_slot = diff.changes.iter().map(|c| c.time()).min();
+        let path = self.path_of(diff.activity, diff.gym, diff.to, first_slot, "diff.json");
+        self.write_json(diff, path).await
+    }
+}
+
+/// Logs each snapshot instead of storing it, for `--dry-run`
+///
+/// A line per snapshot at info level, see [DryRunSink::summary], and the whole snapshot
+/// as JSON at debug level
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     models::{Gym, GymSlotData, Timeslot},
+///     sink::{DryRunSink, Sink},
+/// };
+/// use chrono::{NaiveDate, TimeZone, Utc};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let captured = NaiveDate::from_ymd(2022, 1, 11).and_hms(1, 30, 0);
+/// let slots = [(13, 7), (14, 0), (15, 25)]
+///     .map(|(h, n)| Timeslot::new(Utc.ymd(2022, 1, 11).and_hms(h, 0, 0), n));
+/// let data = GymSlotData::new(Gym::BISHAN, captured, slots.to_vec());
+///
+/// assert_eq!(
+///     DryRunSink::summary(&data),
+///     "BISHAN on 2022-01-11, 3 timeslots, 0 to 25 slots available, not written"
+/// );
+/// // nothing written
+/// assert_eq!(DryRunSink.publish(&data).await.unwrap(), 0);
+///
+/// let empty = GymSlotData::new(Gym::CLEMENTI, captured, vec![]);
+/// assert_eq!(DryRunSink::summary(&empty), "CLEMENTI, no timeslots, not written");
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DryRunSink;
+
+impl DryRunSink {
+    /// see [Sink::name]
+    pub const NAME: &'static str = "dry-run";
+
+    /// Gym, day of the slots in Singapore, number of timeslots and range of availability
+    pub fn summary(data: &GymSlotData) -> String {
+        let slots = data.data();
+        let series = match data.activity() {
+            Activity::GYM => format!("{:?}", data.gym()),
+            activity => format!("{:?} {:?}", activity, data.gym()),
+        };
+        let (first, min, max) = match (
+            slots.iter().map(|s| s.time()).min(),
+            slots.iter().map(|s| s.slots_avail()).min(),
+            slots.iter().map(|s| s.slots_avail()).max(),
+        ) {
+            (Some(first), Some(min), Some(max)) => (first, min, max),
+            _ => return format!("{}, no timeslots, not written", series),
+        };
+
+        format!(
+            "{} on {}, {} timeslots, {} to {} slots available, not written",
+            series,
+            first.with_timezone(&*SGT).format("%Y-%m-%d"),
+            slots.len(),
+            min,
+            max
+        )
+    }
+}
+
+impl Sink for DryRunSink {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    async fn publish(&self, data: &GymSlotData) -> DataMResult<u64> {
+        info!("{}", Self::summary(data));
+        match serde_json::to_string(data) {
+            Ok(json) => debug!("{}", json),
+            Err(e) => warn!("{:?} can't be serialized: {}", data.gym(), e),
+        }
+        Ok(0)
+    }
+
+    async fn publish_diff(&self, diff: &SnapshotDiff) -> DataMResult<u64> {
+        info!(
+            "{:?}, {} slots changed, diff not written",
+            diff.gym,
+            diff.changes.len()
+        );
+        Ok(0)
+    }
+}
@@ -0,0 +1,367 @@
+//! Detection of sinks slowing every iteration down
+//!
+//! The latency of every delivery is recorded per sink, see [crate::sink::Delivery::latencies],
+//! and judged once per iteration by its 95th percentile. A sink is reported slow when it
+//! stays above [SlowSinkPolicy::p95_threshold] for [SlowSinkPolicy::persistence] iterations
+//! in a row, and recovered once it stays under [RECOVERY_SHARE] of the threshold as long,
+//! so that a sink hovering around the threshold doesn't alert every iteration
+//!
+//! The deliveries in flight are counted too, see [SinkMonitor::start_delivery], the most
+//! at once during an iteration telling how far the sinks fall behind the fetches
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use serde::Serialize;
+
+/// Iterations in a row a sink must be slow, or fast again, before its state changes
+pub const DEFAULT_SLOW_SINK_PERSISTENCE: usize = 3;
+
+/// Share of the threshold a slow sink's p95 must be under to count towards recovering
+pub const RECOVERY_SHARE: f64 = 0.8;
+
+/// When a sink is too slow, and what is done about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlowSinkPolicy {
+    /// 95th percentile of the delivery latency of an iteration above which a sink is slow
+    pub p95_threshold: Duration,
+
+    /// see [DEFAULT_SLOW_SINK_PERSISTENCE]
+    pub persistence: usize,
+
+    /// stop delivering to a slow sink until restart instead of only alerting
+    pub auto_disable: bool,
+}
+
+impl SlowSinkPolicy {
+    pub fn new(p95_threshold: Duration) -> Self {
+        Self {
+            p95_threshold,
+            persistence: DEFAULT_SLOW_SINK_PERSISTENCE,
+            auto_disable: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkState {
+    #[default]
+    Healthy,
+    Slow,
+
+    /// nothing is delivered to the sink until restart
+    Disabled,
+}
+
+impl SinkState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Healthy => "healthy",
+            Self::Slow => "slow",
+            Self::Disabled => "disabled",
+        }
+    }
+}
+
+/// A sink changed state at the end of an iteration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SinkEvent {
+    pub sink: String,
+    pub state: SinkState,
+
+    /// of the iteration that confirmed the change
+    pub p95: Duration,
+}
+
+impl fmt::Display for SinkEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let p95 = self.p95.as_millis();
+        match self.state {
+            SinkState::Healthy => write!(f, "sink {} recovered, p95 latency {}ms", self.sink, p95),
+            SinkState::Slow => write!(f, "sink {} is slow, p95 latency {}ms", self.sink, p95),
+            SinkState::Disabled => write!(
+                f,
+                "sink {} disabled until restart, p95 latency {}ms",
+                self.sink, p95
+            ),
+        }
+    }
+}
+
+/// Health of a sink, as exposed by the metrics and the API
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SinkStatus {
+    pub sink: String,
+    pub state: SinkState,
+
+    /// of the last iteration with a delivery, none before the first one
+    pub p95_ms: Option<u64>,
+}
+
+/// 95th percentile of `latencies` by the nearest rank, none when empty
+///
+/// ## Example
+/// ```
+/// use std::time::Duration;
+/// use activesg_gym_datamine::sinkhealth;
+///
+/// let ms = |v: &[u64]| v.iter().copied().map(Duration::from_millis).collect::<Vec<_>>();
+///
+/// assert_eq!(sinkhealth::p95(&ms(&[])), None);
+/// assert_eq!(sinkhealth::p95(&ms(&[40])), Some(Duration::from_millis(40)));
+///
+/// // a single outlier in 20 deliveries is the 5% left out
+/// let mut latencies = ms(&[10; 19]);
+/// latencies.push(Duration::from_secs(30));
+/// assert_eq!(sinkhealth::p95(&latencies), Some(Duration::from_millis(10)));
+///
+/// latencies.push(Duration::from_secs(30));
+/// assert_eq!(sinkhealth::p95(&latencies), Some(Duration::from_secs(30)));
+/// ```
+pub fn p95(latencies: &[Duration]) -> Option<Duration> {
+    let mut sorted = latencies.to_vec();
+    sorted.sort_unstable();
+    let rank = (sorted.len() as f64 * 0.95).ceil() as usize;
+    sorted.get(rank.checked_sub(1)?).copied()
+}
+
+/// State of a sink, with the iterations in a row counting towards its next state
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Track {
+    state: SinkState,
+    streak: usize,
+    latencies: Vec<Duration>,
+    last_p95: Option<Duration>,
+}
+
+impl Track {
+    /// Judges the p95 of an iteration, returning the new state when it changed
+    fn step(&mut self, p95: Duration, policy: &SlowSinkPolicy) -> Option<SinkState> {
+        let towards_next = match self.state {
+            SinkState::Healthy => p95 > policy.p95_threshold,
+            SinkState::Slow => {
+                p95.as_secs_f64() < policy.p95_threshold.as_secs_f64() * RECOVERY_SHARE
+            }
+            SinkState::Disabled => false,
+        };
+        self.streak = match towards_next {
+            true => self.streak + 1,
+            false => 0,
+        };
+        if self.streak < policy.persistence.max(1) {
+            return None;
+        }
+
+        self.streak = 0;
+        self.state = match (self.state, policy.auto_disable) {
+            (SinkState::Healthy, true) => SinkState::Disabled,
+            (SinkState::Healthy, false) => SinkState::Slow,
+            _ => SinkState::Healthy,
+        };
+        Some(self.state)
+    }
+}
+
+/// Delivery latencies and state of every sink
+///
+/// ## Example
+/// ```
+/// use std::time::Duration;
+/// use activesg_gym_datamine::sinkhealth::{SinkMonitor, SinkState, SlowSinkPolicy};
+///
+/// let ms = Duration::from_millis;
+/// let mut policy = SlowSinkPolicy::new(ms(500));
+/// policy.persistence = 2;
+/// let monitor = SinkMonitor::new(Some(policy));
+///
+/// // one iteration per entry, the p95 of each => state changed to
+/// let iterations = [
+///     (900, None),
+///     (100, None),
+///     // a single slow iteration is forgiven
+///     (900, None),
+///     (900, Some(SinkState::Slow)),
+///     // under the threshold but not under 80% of it, still slow
+///     (450, None),
+///     (300, None),
+///     (450, None),
+///     (300, None),
+///     (300, Some(SinkState::Healthy)),
+/// ];
+/// for (p95, state) in iterations {
+///     for _ in 0..10 {
+///         monitor.record("sqlite", ms(p95));
+///     }
+///     let events = monitor.end_iteration();
+///     assert_eq!(events.first().map(|e| e.state), state, "{}ms", p95);
+/// }
+///
+/// // iterations without deliveries don't count either way
+/// monitor.record("sqlite", ms(900));
+/// monitor.end_iteration();
+/// monitor.end_iteration();
+/// monitor.record("sqlite", ms(100));
+/// assert!(monitor.end_iteration().is_empty());
+///
+/// // disabled for good once slow with auto-disable
+/// policy.auto_disable = true;
+/// let monitor = SinkMonitor::new(Some(policy));
+/// for _ in 0..2 {
+///     monitor.record("sqlite", ms(900));
+///     monitor.record("file", ms(5));
+///     monitor.end_iteration();
+/// }
+/// assert_eq!(monitor.disabled().into_iter().collect::<Vec<_>>(), ["sqlite"]);
+/// for _ in 0..5 {
+///     monitor.record("sqlite", ms(5));
+///     assert!(monitor.end_iteration().is_empty());
+/// }
+/// assert_eq!(monitor.statuses()[1].state, SinkState::Disabled);
+///
+/// // without a policy latencies are only measured
+/// let monitor = SinkMonitor::new(None);
+/// monitor.record("sqlite", ms(900));
+/// assert!(monitor.end_iteration().is_empty());
+/// assert_eq!(monitor.statuses()[0].p95_ms, Some(900));
+/// ```
+#[derive(Debug, Default)]
+pub struct SinkMonitor {
+    policy: Option<SlowSinkPolicy>,
+    tracks: Mutex<BTreeMap<String, Track>>,
+
+    /// deliveries started and not finished yet
+    in_flight: AtomicUsize,
+
+    /// most of [SinkMonitor::in_flight] at once since the last [SinkMonitor::end_iteration]
+    peak_in_flight: AtomicUsize,
+
+    /// [SinkMonitor::peak_in_flight] of the last iteration
+    last_peak_in_flight: AtomicUsize,
+}
+
+/// Counts a delivery as in flight until dropped, see [SinkMonitor::start_delivery]
+#[derive(Debug)]
+pub struct InFlight<'a>(&'a SinkMonitor);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl SinkMonitor {
+    pub fn new(policy: Option<SlowSinkPolicy>) -> Self {
+        Self {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    /// Counts a delivery to the sinks as in flight until the returned guard is dropped
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::sinkhealth::SinkMonitor;
+    ///
+    /// let monitor = SinkMonitor::new(None);
+    /// let first = monitor.start_delivery();
+    /// let second = monitor.start_delivery();
+    /// drop(first);
+    /// let third = monitor.start_delivery();
+    /// assert_eq!(monitor.in_flight(), 2);
+    /// monitor.end_iteration();
+    /// assert_eq!(monitor.last_peak_in_flight(), 2);
+    ///
+    /// // still in flight when the next iteration starts
+    /// drop((second, third));
+    /// monitor.end_iteration();
+    /// assert_eq!((monitor.in_flight(), monitor.last_peak_in_flight()), (0, 2));
+    /// monitor.end_iteration();
+    /// assert_eq!(monitor.last_peak_in_flight(), 0);
+    /// ```
+    pub fn start_delivery(&self) -> InFlight<'_> {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        InFlight(self)
+    }
+
+    /// Deliveries to the sinks in flight right now
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Most deliveries in flight at once during the last iteration
+    pub fn last_peak_in_flight(&self) -> usize {
+        self.last_peak_in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Records the latency of a delivery to `sink` in the current iteration
+    pub fn record(&self, sink: &str, latency: Duration) {
+        self.tracks
+            .lock()
+            .unwrap()
+            .entry(sink.to_string())
+            .or_default()
+            .latencies
+            .push(latency);
+    }
+
+    /// Judges the latencies recorded since the last call, returning the sinks that changed state
+    pub fn end_iteration(&self) -> Vec<SinkEvent> {
+        let peak = self.peak_in_flight.swap(self.in_flight(), Ordering::SeqCst);
+        self.last_peak_in_flight.store(peak, Ordering::SeqCst);
+
+        let mut events = vec![];
+        for (sink, track) in self.tracks.lock().unwrap().iter_mut() {
+            let latencies = std::mem::take(&mut track.latencies);
+            let p95 = match p95(&latencies) {
+                Some(p95) => p95,
+                None => continue,
+            };
+            track.last_p95 = Some(p95);
+
+            let state = self.policy.as_ref().and_then(|p| track.step(p95, p));
+            if let Some(state) = state {
+                events.push(SinkEvent {
+                    sink: sink.clone(),
+                    state,
+                    p95,
+                });
+            }
+        }
+        events
+    }
+
+    /// Sinks nothing is delivered to anymore
+    pub fn disabled(&self) -> BTreeSet<String> {
+        self.tracks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, t)| t.state == SinkState::Disabled)
+            .map(|(sink, _)| sink.clone())
+            .collect()
+    }
+
+    /// Every sink delivered to so far, by name
+    pub fn statuses(&self) -> Vec<SinkStatus> {
+        self.tracks
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(sink, t)| SinkStatus {
+                sink: sink.clone(),
+                state: t.state,
+                p95_ms: t.last_p95.map(|d| d.as_millis() as u64),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+use reqwest::header::{HeaderMap, DATE};
+use tracing::warn;
+
+/// Skew in seconds above which it is considered significant and will be logged
+pub const SKEW_WARN_THRESHOLD_SECS: i64 = 60;
+
+/// Parses an HTTP `Date` header value
+///
+/// ## Example of header values
+/// - Tue, 11 Jan 2022 05:57:33 GMT
+pub fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+/// Measures how far ahead the server clock is compared to `local_now`
+///
+/// Negative values mean the local clock is ahead of the server.
+/// Returns [None] when the `Date` header is missing or malformed
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::skew;
+/// use chrono::{Duration, TimeZone, Utc};
+/// use reqwest::header::{HeaderMap, HeaderValue, DATE};
+///
+/// let local_now = Utc.ymd(2022, 1, 11).and_hms(5, 57, 33);
+/// let with_date = |value: &'static str| {
+///     let mut headers = HeaderMap::new();
+///     headers.insert(DATE, HeaderValue::from_static(value));
+///     headers
+/// };
+///
+/// // the server is ahead, the local clock runs late
+/// let ahead = with_date("Tue, 11 Jan 2022 06:02:33 GMT");
+/// assert_eq!(skew::measure_skew(&ahead, local_now), Some(Duration::minutes(5)));
+///
+/// // the server is behind, the local clock runs early
+/// let behind = with_date("Tue, 11 Jan 2022 05:57:03 GMT");
+/// assert_eq!(skew::measure_skew(&behind, local_now), Some(Duration::seconds(-30)));
+///
+/// let same = with_date("Tue, 11 Jan 2022 05:57:33 GMT");
+/// assert_eq!(skew::measure_skew(&same, local_now), Some(Duration::zero()));
+///
+/// for malformed in ["yesterday", "2022-01-11T05:57:33Z", "Tue, 32 Jan 2022 05:57:33 GMT", ""] {
+///     assert_eq!(skew::measure_skew(&with_date(malformed), local_now), None, "{:?}", malformed);
+/// }
+/// assert_eq!(skew::measure_skew(&HeaderMap::new(), local_now), None);
+///
+/// assert!(skew::is_significant(Duration::minutes(5)));
+/// assert!(skew::is_significant(Duration::minutes(-5)));
+/// assert!(!skew::is_significant(Duration::seconds(-30)));
+/// assert!(!skew::is_significant(Duration::seconds(60)));
+/// ```
+pub fn measure_skew(headers: &HeaderMap, local_now: DateTime<Utc>) -> Option<Duration> {
+    headers
+        .get(DATE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_http_date)
+        .map(|server_now| server_now - local_now)
+}
+
+/// Whether `skew` is large enough to be warned about
+pub fn is_significant(skew: Duration) -> bool {
+    skew.num_seconds().abs() > SKEW_WARN_THRESHOLD_SECS
+}
+
+/// Applies a previously measured `skew` to `local_now`, giving the server's idea of now
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::skew;
+/// use chrono::{Duration, TimeZone, Utc};
+///
+/// // 23:55 in Singapore by the local clock, already the next day for ActiveSG
+/// let local_now = Utc.ymd(2022, 1, 11).and_hms(15, 55, 0);
+/// let corrected = skew::corrected_now(local_now, Some(Duration::minutes(10)));
+/// assert_eq!(corrected, Utc.ymd(2022, 1, 11).and_hms(16, 5, 0));
+///
+/// let corrected = skew::corrected_now(local_now, Some(Duration::seconds(-90)));
+/// assert_eq!(corrected, Utc.ymd(2022, 1, 11).and_hms(15, 53, 30));
+///
+/// // nothing measured yet, or the header was malformed
+/// assert_eq!(skew::corrected_now(local_now, None), local_now);
+/// ```
+pub fn corrected_now(local_now: DateTime<Utc>, skew: Option<Duration>) -> DateTime<Utc> {
+    match skew {
+        Some(skew) => local_now + skew,
+        None => local_now,
+    }
+}
+
+/// Last clock skew measured from ActiveSG responses, shared across iterations
+#[derive(Debug, Default)]
+pub struct SkewTracker {
+    last: Mutex<Option<Duration>>,
+}
+
+impl SkewTracker {
+    /// Records a new measurement, warning when it exceeds [SKEW_WARN_THRESHOLD_SECS]
+    pub fn record(&self, skew: Duration) {
+        if is_significant(skew) {
+            warn!(
+                "System clock is skewed by {}s relative to ActiveSG, query windows may be wrong",
+                skew.num_seconds()
+            );
+        }
+
+        *self.last.lock().unwrap() = Some(skew);
+    }
+
+    pub fn last(&self) -> Option<Duration> {
+        *self.last.lock().unwrap()
+    }
+}
@@ -0,0 +1,227 @@
+use std::{collections::BTreeMap, path::Path, sync::atomic::Ordering, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    metrics::Metrics,
+    models::{Gym, GymSlotData, SlotState},
+    pacer::WaitReason,
+    sink, DataMResult,
+};
+
+/// How often [UsageStats] are written
+pub const STATS_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// File name of [UsageStats], relative to the output directory
+pub const STATS_FILENAME: &str = "stats.json";
+
+/// Fetch counts by outcome
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FetchOutcomes {
+    pub ok: u64,
+    pub failed: u64,
+}
+
+/// Notifier delivery counts by outcome
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeliveryOutcomes {
+    pub ok: u64,
+    pub failed: u64,
+}
+
+/// How many of the slots captured were fully booked
+///
+/// Slots past the booking cut-off show no availability whether full or not, see
+/// [crate::models::Timeslot::is_past_cutoff], so they are left out unless included,
+/// and closed slots are always left out
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::{
+///     models::{Gym, GymSlotData, SlotState, Timeslot},
+///     stats::FillRate,
+/// };
+/// use chrono::{NaiveDate, TimeZone, Utc};
+///
+/// let slot = |h, n, past_cutoff| {
+///     let mut slot = Timeslot::new(Utc.ymd(2022, 1, 11).and_hms(h, 0, 0), n);
+///     slot.mut_past_cutoff(past_cutoff);
+///     slot
+/// };
+/// let mut closed = slot(5, 0, false);
+/// closed.mut_state(SlotState::Closed);
+/// // captured at 09:45 SGT, the 10:00 slot is past the cut-off and shows 0 left
+/// let captured = NaiveDate::from_ymd(2022, 1, 11).and_hms(1, 45, 0);
+/// let slots = vec![slot(2, 0, true), slot(3, 0, false), slot(4, 12, false), closed];
+/// let data = GymSlotData::new(Gym::BISHAN, captured, slots);
+///
+/// let mut fill_rate = FillRate::default();
+/// assert_eq!(fill_rate.ratio(), None);
+/// fill_rate.add(&data, false);
+/// assert_eq!(fill_rate, FillRate { slots: 2, full: 1, past_cutoff: 1 });
+/// assert_eq!(fill_rate.ratio(), Some(0.5));
+///
+/// let mut with_past = FillRate::default();
+/// with_past.add(&data, true);
+/// assert_eq!(with_past, FillRate { slots: 3, full: 2, past_cutoff: 0 });
+///
+/// // only slots past the cut-off
+/// let mut late = FillRate::default();
+/// late.add(&GymSlotData::new(Gym::BISHAN, captured, vec![slot(2, 0, true)]), false);
+/// assert_eq!(late.ratio(), None);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FillRate {
+    /// slots counted
+    pub slots: u64,
+
+    /// slots counted with none left
+    pub full: u64,
+
+    /// slots past the booking cut-off left out
+    pub past_cutoff: u64,
+}
+
+impl FillRate {
+    /// Counts the slots of `data`, those past the booking cut-off only if `include_past_cutoff`
+    pub fn add(&mut self, data: &GymSlotData, include_past_cutoff: bool) {
+        for slot in data.data() {
+            if slot.state() == SlotState::Closed {
+                continue;
+            }
+            if slot.past_cutoff() && !include_past_cutoff {
+                self.past_cutoff += 1;
+                continue;
+            }
+
+            self.slots += 1;
+            if slot.slots_avail() == 0 {
+                self.full += 1;
+            }
+        }
+    }
+
+    /// Share of the slots counted that were full, none without any
+    pub fn ratio(&self) -> Option<f64> {
+        match self.slots {
+            0 => None,
+            n => Some(self.full as f64 / n as f64),
+        }
+    }
+}
+
+/// Local summary of how the miner is doing, never sent anywhere
+///
+/// Built from the [Metrics] counters and written to [STATS_FILENAME] every [STATS_INTERVAL]
+///
+/// ## Example
+/// ```
+/// use std::{sync::atomic::Ordering, time::Duration};
+/// use activesg_gym_datamine::{
+///     metrics::Metrics,
+///     models::Gym,
+///     pacer::WaitReason,
+///     stats::UsageStats,
+/// };
+/// use chrono::{TimeZone, Utc};
+///
+/// let metrics = Metrics::default();
+/// metrics.reset(Utc.ymd(2022, 1, 11).and_hms(0, 0, 0));
+/// metrics.iterations.fetch_add(3, Ordering::Relaxed);
+/// metrics.fetches_ok.fetch_add(40, Ordering::Relaxed);
+/// metrics.fetches_failed.fetch_add(2, Ordering::Relaxed);
+/// metrics.bytes_downloaded.fetch_add(2_000_000, Ordering::Relaxed);
+/// metrics.parse_warnings.fetch_add(1, Ordering::Relaxed);
+/// metrics.add_bytes_written("file", 50_000);
+/// metrics.add_waited(WaitReason::InterGymDelay, Duration::from_secs(90));
+/// metrics.record_delivery("evenings/webhook", true);
+/// metrics.record_delivery("evenings/webhook", false);
+///
+/// let mut stats = UsageStats::from_metrics(&metrics, Utc.ymd(2022, 1, 11).and_hms(1, 0, 0));
+/// stats.quarantined = vec![Gym::CLEMENTI];
+///
+/// assert_eq!(
+///     serde_json::to_value(&stats).unwrap(),
+///     serde_json::json!({
+///         "since": "2022-01-11T00:00:00Z",
+///         "generated_at": "2022-01-11T01:00:00Z",
+///         "iterations": 3,
+///         "fetches": { "ok": 40, "failed": 2 },
+///         "bytes_downloaded": 2000000,
+///         "parse_warnings": 1,
+///         "bytes_written": { "file": 50000 },
+///         "waited_ms": { "inter_gym_delay": 90000 },
+///         "notifier_deliveries": { "evenings/webhook": { "ok": 1, "failed": 1 } },
+///         "quarantined": ["CLEMENTI"],
+///     })
+/// );
+///
+/// // written before these fields existed
+/// let old = r#"{"since": "2022-01-11T00:00:00Z", "generated_at": "2022-01-11T01:00:00Z",
+///     "iterations": 3, "fetches": {"ok": 40, "failed": 2}, "bytes_downloaded": 0, "bytes_written": {}}"#;
+/// let old: UsageStats = serde_json::from_str(old).unwrap();
+/// assert_eq!(old.parse_warnings, 0);
+/// assert!(old.notifier_deliveries.is_empty() && old.quarantined.is_empty());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageStats {
+    /// start of the period covered, process start or the last reset
+    pub since: DateTime<Utc>,
+
+    /// when these stats were taken
+    pub generated_at: DateTime<Utc>,
+
+    pub iterations: u64,
+    pub fetches: FetchOutcomes,
+    pub bytes_downloaded: u64,
+
+    /// slot counts skipped and venue names not matching, see
+    /// [crate::models::SnapshotMetadata::parse_warnings]
+    #[serde(default)]
+    pub parse_warnings: u64,
+
+    /// bytes written per sink
+    pub bytes_written: BTreeMap<String, u64>,
+
+    /// milliseconds waited between requests per reason
+    #[serde(default)]
+    pub waited_ms: BTreeMap<WaitReason, u64>,
+
+    /// alert deliveries per `<profile>/<notifier>`
+    #[serde(default)]
+    pub notifier_deliveries: BTreeMap<String, DeliveryOutcomes>,
+
+    /// gyms not fetched because their booking page refers to another venue
+    #[serde(default)]
+    pub quarantined: Vec<Gym>,
+}
+
+impl UsageStats {
+    pub fn from_metrics(metrics: &Metrics, now: DateTime<Utc>) -> Self {
+        Self {
+            since: metrics.since(),
+            generated_at: now,
+            iterations: metrics.iterations.load(Ordering::Relaxed),
+            fetches: FetchOutcomes {
+                ok: metrics.fetches_ok.load(Ordering::Relaxed),
+                failed: metrics.fetches_failed.load(Ordering::Relaxed),
+            },
+            bytes_downloaded: metrics.bytes_downloaded.load(Ordering::Relaxed),
+            parse_warnings: metrics.parse_warnings.load(Ordering::Relaxed),
+            bytes_written: metrics.bytes_written(),
+            waited_ms: metrics.waited_ms(),
+            notifier_deliveries: metrics.notifier_deliveries(),
+            quarantined: vec![],
+        }
+    }
+}
+
+/// Atomically writes `stats` to `output_dir`/[STATS_FILENAME]
+pub fn write(output_dir: &Path, stats: &UsageStats) -> DataMResult<()> {
+    std::fs::create_dir_all(output_dir)?;
+
+    let data = serde_json::to_vec_pretty(stats).map_err(std::io::Error::from)?;
+    sink::write_atomic(&output_dir.join(STATS_FILENAME), &data)?;
+    Ok(())
+}
@@ -0,0 +1,528 @@
+//! SQLite storage of the snapshots, one row per timeslot, only built with the `sqlite` feature
+//!
+//! Links against the system `libsqlite3`. Rows land in a `slots` table created on first open:
+//!
+//! ```sql
+//! CREATE TABLE slots (activity TEXT, gym_id INTEGER, gym_name TEXT, scraped_at TEXT, slot_time TEXT,
+//!     slots_avail INTEGER, slot_id TEXT);
+//! CREATE UNIQUE INDEX slots_unique ON slots (activity, gym_id, scraped_at, slot_time);
+//! CREATE INDEX slots_slot_id ON slots (slot_id);
+//! ```
+//!
+//! Activities are named like [Activity], e.g. `GYM`, and `slot_id` is `NULL` unless slot ids are
+//! enabled, see [crate::models::Timeslot::slot_id_of]. Times are UTC to the second, formatted as
+//! `2022-01-11T02:00:00Z` so that they sort as text. The unique index makes publishing the same
+//! snapshot twice, e.g. on a retry, a no-op. A table created before the activity was stored
+//! gets the new columns on open, its rows being gym ones
+//!
+//! ## Example
+//! ```
+//! use std::sync::Arc;
+//! use activesg_gym_datamine::{
+//!     clock::FakeClock,
+//!     config::Config,
+//!     fetch::{self, FakeFetcher, FetchTask},
+//!     models::{Activity, Gym, GymSlotData, Timeslot, User},
+//!     storage::SqliteStore,
+//! };
+//! use chrono::{NaiveDate, TimeZone, Utc};
+//!
+//! # #[tokio::main(flavor = "current_thread", start_paused = true)]
+//! # async fn main() {
+//! let page = r#"<div class="chkbox-grid">
+//!     <label>07:00 AM</label><label>25 Left</label>
+//!     <label>07:00 PM</label><label>12 Left</label>
+//! </div>"#;
+//! let mut config = Config::new(User::new("user@example.com", "password"));
+//! config.keep_out_of_hours = true;
+//! config.slot_ids = true;
+//! let task = FetchTask::new(Activity::GYM, Gym::BISHAN, NaiveDate::from_ymd(2022, 1, 11));
+//! let clock = FakeClock::new(Utc.ymd(2022, 1, 10).and_hms(13, 57, 33));
+//! let fetcher = FakeFetcher::new(|_| Ok(page.to_string())).with_clock(Arc::new(clock));
+//! let data = fetch::fetch_slots(&fetcher, &config, task).await.unwrap();
+//!
+//! let path = std::env::temp_dir().join(format!("activesg-doc-{}.db", std::process::id()));
+//! let store = SqliteStore::open(&path).unwrap();
+//! assert_eq!(store.insert(&data).unwrap(), 2);
+//! // retried
+//! assert_eq!(store.insert(&data).unwrap(), 0);
+//!
+//! let rows = store.slots(Gym::BISHAN).unwrap();
+//! assert_eq!(rows.len(), 2);
+//! assert_eq!(rows[0].activity, Activity::GYM);
+//! assert_eq!(rows[0].gym_name, "Bishan ActiveSG Gym");
+//! assert_eq!(rows[0].scraped_at, data.datetime());
+//! assert_eq!(rows[0].slot_time, data.data()[0].time());
+//! assert_eq!(rows[0].slot_id.as_deref(), data.data()[0].slot_id());
+//! let avail = rows.iter().map(|r| r.slots_avail).collect::<Vec<_>>();
+//! assert_eq!(avail, vec![25, 12]);
+//!
+//! // another activity of the same venue at the same time is kept apart
+//! let courts = GymSlotData::new(Gym::BISHAN, data.datetime(), vec![Timeslot::new(data.data()[0].time(), 3)])
+//!     .with_activity(Activity::BADMINTON);
+//! assert_eq!(store.insert(&courts).unwrap(), 1);
+//! let rows = store.slots(Gym::BISHAN).unwrap();
+//! let activities = rows.iter().map(|r| r.activity).collect::<Vec<_>>();
+//! assert_eq!(activities, [Activity::BADMINTON, Activity::GYM, Activity::GYM]);
+//! assert_eq!((rows[0].slots_avail, rows[0].slot_id.as_deref()), (3, None));
+//!
+//! // a slot across captures, by its id
+//! let slot_id = data.data()[1].slot_id().unwrap();
+//! let captures = store.by_slot_id(slot_id).unwrap();
+//! assert_eq!(captures.len(), 1);
+//! assert_eq!((captures[0].activity, captures[0].slots_avail), (Activity::GYM, 12));
+//! assert!(store.by_slot_id("v1-nowhere").unwrap().is_empty());
+//!
+//! // the schema is only created once
+//! drop(store);
+//! assert_eq!(SqliteStore::open(&path).unwrap().slots(Gym::BISHAN).unwrap(), rows);
+//! assert!(SqliteStore::open(&path).unwrap().slots(Gym::CLEMENTI).unwrap().is_empty());
+//! # std::fs::remove_file(&path).unwrap();
+//! # }
+//! ```
+
+use std::{
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int},
+    path::{Path, PathBuf},
+    ptr,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use tracing::info;
+
+use crate::{
+    errors,
+    models::{Activity, GymSlotData},
+    sink::Sink,
+    venue::Venue,
+    DataMResult,
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS slots (
+    activity TEXT NOT NULL DEFAULT 'GYM',
+    gym_id INTEGER NOT NULL,
+    gym_name TEXT NOT NULL,
+    scraped_at TEXT NOT NULL,
+    slot_time TEXT NOT NULL,
+    slots_avail INTEGER NOT NULL,
+    slot_id TEXT
+);
+";
+
+/// Columns added to a `slots` table created before they were, the unique index being replaced
+const MIGRATION: &str = "
+ALTER TABLE slots ADD COLUMN activity TEXT NOT NULL DEFAULT 'GYM';
+ALTER TABLE slots ADD COLUMN slot_id TEXT;
+DROP INDEX IF EXISTS slots_unique;
+";
+
+const INDEXES: &str = "
+CREATE UNIQUE INDEX IF NOT EXISTS slots_unique ON slots (activity, gym_id, scraped_at, slot_time);
+CREATE INDEX IF NOT EXISTS slots_slot_id ON slots (slot_id);
+";
+
+const COLUMNS: &str = "activity, gym_id, gym_name, scraped_at, slot_time, slots_avail, slot_id";
+
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+#[allow(non_camel_case_types)]
+mod ffi {
+    use std::os::raw::{c_char, c_int, c_uchar, c_void};
+
+    pub enum sqlite3 {}
+    pub enum sqlite3_stmt {}
+
+    pub const SQLITE_OK: c_int = 0;
+    pub const SQLITE_ROW: c_int = 100;
+    pub const SQLITE_DONE: c_int = 101;
+    pub const SQLITE_OPEN_READWRITE: c_int = 0x2;
+    pub const SQLITE_OPEN_CREATE: c_int = 0x4;
+
+    /// makes SQLite copy bound text before the call returns
+    pub const SQLITE_TRANSIENT: isize = -1;
+
+    #[link(name = "sqlite3")]
+    extern "C" {
+        pub fn sqlite3_open_v2(
+            filename: *const c_char,
+            db: *mut *mut sqlite3,
+            flags: c_int,
+            vfs: *const c_char,
+        ) -> c_int;
+        pub fn sqlite3_close(db: *mut sqlite3) -> c_int;
+        pub fn sqlite3_errmsg(db: *mut sqlite3) -> *const c_char;
+        pub fn sqlite3_exec(
+            db: *mut sqlite3,
+            sql: *const c_char,
+            callback: *const c_void,
+            arg: *mut c_void,
+            errmsg: *mut *mut c_char,
+        ) -> c_int;
+        pub fn sqlite3_changes(db: *mut sqlite3) -> c_int;
+        pub fn sqlite3_prepare_v2(
+            db: *mut sqlite3,
+            sql: *const c_char,
+            len: c_int,
+            stmt: *mut *mut sqlite3_stmt,
+            tail: *mut *const c_char,
+        ) -> c_int;
+        pub fn sqlite3_bind_int64(stmt: *mut sqlite3_stmt, index: c_int, value: i64) -> c_int;
+        pub fn sqlite3_bind_text(
+            stmt: *mut sqlite3_stmt,
+            index: c_int,
+            value: *const c_char,
+            len: c_int,
+            destructor: isize,
+        ) -> c_int;
+        pub fn sqlite3_step(stmt: *mut sqlite3_stmt) -> c_int;
+        pub fn sqlite3_reset(stmt: *mut sqlite3_stmt) -> c_int;
+        pub fn sqlite3_finalize(stmt: *mut sqlite3_stmt) -> c_int;
+        pub fn sqlite3_column_int64(stmt: *mut sqlite3_stmt, column: c_int) -> i64;
+        pub fn sqlite3_column_text(stmt: *mut sqlite3_stmt, column: c_int) -> *const c_uchar;
+        pub fn sqlite3_bind_null(stmt: *mut sqlite3_stmt, index: c_int) -> c_int;
+    }
+}
+
+/// Open database, only used behind the mutex of [SqliteStore]
+struct Connection(*mut ffi::sqlite3);
+
+// the handle is never used by two threads at once
+unsafe impl Send for Connection {}
+
+impl Connection {
+    fn open(path: &Path) -> DataMResult<Self> {
+        let filename = CString::new(path.to_string_lossy().as_bytes())
+            .map_err(|_| errors::Error::Sqlite(format!("invalid path {}", path.display())))?;
+
+        let mut db = ptr::null_mut();
+        let flags = ffi::SQLITE_OPEN_READWRITE | ffi::SQLITE_OPEN_CREATE;
+        let rc = unsafe { ffi::sqlite3_open_v2(filename.as_ptr(), &mut db, flags, ptr::null()) };
+        // a handle is returned even on failure, and must be closed
+        let conn = Self(db);
+        match (rc, db.is_null()) {
+            (ffi::SQLITE_OK, false) => Ok(conn),
+            (_, true) => Err(errors::Error::Sqlite("out of memory".into())),
+            _ => Err(conn.error()),
+        }
+    }
+
+    /// Last error of the connection
+    fn error(&self) -> errors::Error {
+        let message = unsafe { CStr::from_ptr(ffi::sqlite3_errmsg(self.0)) };
+        errors::Error::Sqlite(message.to_string_lossy().into_owned())
+    }
+
+    fn check(&self, rc: c_int) -> DataMResult<()> {
+        match rc {
+            ffi::SQLITE_OK => Ok(()),
+            _ => Err(self.error()),
+        }
+    }
+
+    /// Runs `sql`, which may hold several statements without parameters
+    fn execute_batch(&self, sql: &str) -> DataMResult<()> {
+        let sql = CString::new(sql).map_err(|_| errors::Error::Sqlite("nul in sql".into()))?;
+        let rc = unsafe {
+            ffi::sqlite3_exec(
+                self.0,
+                sql.as_ptr(),
+                ptr::null(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        self.check(rc)
+    }
+
+    fn prepare(&self, sql: &str) -> DataMResult<Statement<'_>> {
+        let mut raw = ptr::null_mut();
+        let rc = unsafe {
+            ffi::sqlite3_prepare_v2(
+                self.0,
+                sql.as_ptr() as *const c_char,
+                sql.len() as c_int,
+                &mut raw,
+                ptr::null_mut(),
+            )
+        };
+        let statement = Statement { conn: self, raw };
+        self.check(rc)?;
+        Ok(statement)
+    }
+
+    /// Rows changed by the last statement
+    fn changes(&self) -> usize {
+        unsafe { ffi::sqlite3_changes(self.0) as usize }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::sqlite3_close(self.0);
+        }
+    }
+}
+
+struct Statement<'a> {
+    conn: &'a Connection,
+    raw: *mut ffi::sqlite3_stmt,
+}
+
+impl Statement<'_> {
+    /// Binds `value` to the parameter at `index`, counted from 1
+    fn bind_i64(&self, index: c_int, value: i64) -> DataMResult<()> {
+        self.conn
+            .check(unsafe { ffi::sqlite3_bind_int64(self.raw, index, value) })
+    }
+
+    fn bind_text(&self, index: c_int, value: &str) -> DataMResult<()> {
+        let rc = unsafe {
+            ffi::sqlite3_bind_text(
+                self.raw,
+                index,
+                value.as_ptr() as *const c_char,
+                value.len() as c_int,
+                ffi::SQLITE_TRANSIENT,
+            )
+        };
+        self.conn.check(rc)
+    }
+
+    fn bind_optional_text(&self, index: c_int, value: Option<&str>) -> DataMResult<()> {
+        match value {
+            Some(value) => self.bind_text(index, value),
+            None => self
+                .conn
+                .check(unsafe { ffi::sqlite3_bind_null(self.raw, index) }),
+        }
+    }
+
+    /// Steps once, true when a row is available
+    fn step(&self) -> DataMResult<bool> {
+        match unsafe { ffi::sqlite3_step(self.raw) } {
+            ffi::SQLITE_ROW => Ok(true),
+            ffi::SQLITE_DONE => Ok(false),
+            _ => Err(self.conn.error()),
+        }
+    }
+
+    /// Makes the statement ready to be bound and stepped again
+    fn reset(&self) {
+        unsafe {
+            ffi::sqlite3_reset(self.raw);
+        }
+    }
+
+    fn column_i64(&self, column: c_int) -> i64 {
+        unsafe { ffi::sqlite3_column_int64(self.raw, column) }
+    }
+
+    fn column_text(&self, column: c_int) -> String {
+        self.column_optional_text(column).unwrap_or_default()
+    }
+
+    /// Text of `column`, none when `NULL`
+    fn column_optional_text(&self, column: c_int) -> Option<String> {
+        let text = unsafe { ffi::sqlite3_column_text(self.raw, column) };
+        match text.is_null() {
+            true => None,
+            false => Some(
+                unsafe { CStr::from_ptr(text as *const c_char) }
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+        }
+    }
+}
+
+impl Drop for Statement<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::sqlite3_finalize(self.raw);
+        }
+    }
+}
+
+/// A row of the `slots` table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotRow {
+    pub activity: Activity,
+    pub gym_id: u16,
+    pub gym_name: String,
+    pub scraped_at: NaiveDateTime,
+    pub slot_time: DateTime<Utc>,
+    pub slots_avail: u16,
+    pub slot_id: Option<String>,
+}
+
+/// Snapshots stored in a SQLite database, see the [module docs](self)
+pub struct SqliteStore {
+    path: PathBuf,
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Opens the database at `path`, creating it and the `slots` table when missing
+    pub fn open<P: Into<PathBuf>>(path: P) -> DataMResult<Self> {
+        let path = path.into();
+        let conn = Connection::open(&path)?;
+        conn.execute_batch(SCHEMA)?;
+        let migrated = conn
+            .prepare("SELECT 1 FROM pragma_table_info('slots') WHERE name = 'activity'")?
+            .step()?;
+        if !migrated {
+            info!(
+                "{}, adding the activity and slot_id columns",
+                path.display()
+            );
+            conn.execute_batch(MIGRATION)?;
+        }
+        conn.execute_batch(INDEXES)?;
+
+        Ok(Self {
+            path,
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Inserts every timeslot of `data` in a single transaction,
+    /// returning the number of rows that weren't already there
+    pub fn insert(&self, data: &GymSlotData) -> DataMResult<usize> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute_batch("BEGIN")?;
+        match Self::insert_rows(&conn, data) {
+            Ok(inserted) => {
+                conn.execute_batch("COMMIT")?;
+                Ok(inserted)
+            }
+            Err(e) => {
+                let _ = conn.execute_batch("ROLLBACK");
+                Err(e)
+            }
+        }
+    }
+
+    fn insert_rows(conn: &Connection, data: &GymSlotData) -> DataMResult<usize> {
+        let statement = conn.prepare(&format!(
+            "INSERT OR IGNORE INTO slots ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            COLUMNS
+        ))?;
+        let activity = format!("{:?}", data.activity());
+        let gym = data.gym();
+        let scraped_at = data.datetime().format(TIME_FORMAT).to_string();
+
+        let mut inserted = 0;
+        for slot in data.data() {
+            statement.bind_text(1, &activity)?;
+            statement.bind_i64(2, gym.id().into())?;
+            statement.bind_text(3, gym.display())?;
+            statement.bind_text(4, &scraped_at)?;
+            statement.bind_text(5, &slot.time().format(TIME_FORMAT).to_string())?;
+            statement.bind_i64(6, slot.slots_avail().into())?;
+            statement.bind_optional_text(7, slot.slot_id())?;
+            statement.step()?;
+            statement.reset();
+            inserted += conn.changes();
+        }
+        Ok(inserted)
+    }
+
+    /// Every row of `venue`, by activity, capture time then slot time
+    pub fn slots<V: Into<Venue>>(&self, venue: V) -> DataMResult<Vec<SlotRow>> {
+        let conn = self.conn.lock().unwrap();
+        let statement = conn.prepare(&format!(
+            "SELECT {} FROM slots WHERE gym_id = ?1 ORDER BY activity, scraped_at, slot_time",
+            COLUMNS
+        ))?;
+        statement.bind_i64(1, venue.into().id().into())?;
+        Self::read_rows(&statement)
+    }
+
+    /// Every capture of the slot `slot_id`, by capture time
+    pub fn by_slot_id(&self, slot_id: &str) -> DataMResult<Vec<SlotRow>> {
+        let conn = self.conn.lock().unwrap();
+        let statement = conn.prepare(&format!(
+            "SELECT {} FROM slots WHERE slot_id = ?1 ORDER BY scraped_at",
+            COLUMNS
+        ))?;
+        statement.bind_text(1, slot_id)?;
+        Self::read_rows(&statement)
+    }
+
+    /// Rows of `statement` selecting [COLUMNS]
+    fn read_rows(statement: &Statement) -> DataMResult<Vec<SlotRow>> {
+        let time = |text: String| {
+            NaiveDateTime::parse_from_str(&text, TIME_FORMAT)
+                .map_err(|e| errors::Error::Sqlite(format!("invalid time {:?}: {}", text, e)))
+        };
+        let mut buf = vec![];
+        while statement.step()? {
+            buf.push(SlotRow {
+                activity: statement.column_text(0).parse()?,
+                gym_id: statement.column_i64(1) as u16,
+                gym_name: statement.column_text(2),
+                scraped_at: time(statement.column_text(3))?,
+                slot_time: Utc.from_utc_datetime(&time(statement.column_text(4))?),
+                slots_avail: statement.column_i64(5) as u16,
+                slot_id: statement.column_optional_text(6),
+            });
+        }
+        Ok(buf)
+    }
+}
+
+/// Inserts each snapshot into a [SqliteStore] instead of writing a file
+#[derive(Clone)]
+pub struct SqliteSink {
+    store: Arc<SqliteStore>,
+}
+
+impl SqliteSink {
+    /// see [Sink::name]
+    pub const NAME: &'static str = "sqlite";
+
+    pub fn open<P: Into<PathBuf>>(path: P) -> DataMResult<Self> {
+        let store = SqliteStore::open(path)?;
+        info!("{}, storing snapshots", store.path().display());
+        Ok(Self {
+            store: Arc::new(store),
+        })
+    }
+}
+
+impl Sink for SqliteSink {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    /// Returns the bytes of the values inserted, not counting the overhead of SQLite
+    async fn publish(&self, data: &GymSlotData) -> DataMResult<u64> {
+        let store = self.store.clone();
+        let data = data.clone();
+        let fixed_bytes = format!("{:?}", data.activity()).len()
+            + 8
+            + data.gym().display().len()
+            + 2 * "YYYY-MM-DDTHH:MM:SSZ".len()
+            + 8;
+        let bytes = data
+            .data()
+            .iter()
+            .map(|slot| (fixed_bytes + slot.slot_id().map_or(0, str::len)) as u64)
+            .collect::<Vec<_>>();
+        let inserted = tokio::task::spawn_blocking(move || store.insert(&data))
+            .await
+            .map_err(|e| errors::Error::Sqlite(format!("insert task panicked: {}", e)))??;
+
+        // the rows of a snapshot are the same size but for their slot ids
+        let average = bytes.iter().sum::<u64>() / bytes.len().max(1) as u64;
+        Ok(inserted as u64 * average)
+    }
+}
@@ -0,0 +1,325 @@
+//! Names of the files written by [crate::sink::FileSink], see [FilenameTemplate]
+//!
+//! A template is the path of a file relative to the directory of its day, with placeholders:
+//!
+//! - `{gym}`, the name of the gym, e.g. `BISHAN`
+//! - `{gym_id}`, the venue id of the gym, e.g. `137`
+//! - `{date}` and `{time}`, the capture time in Singapore, e.g. `2022-01-11` and `09-30-00`
+//! - `{queried_date}`, the day in Singapore of the first slot, the capture date when there is none
+//! - `{ext}`, `json` for snapshots and `diff.json` for diffs
+//!
+//! The default, [FilenameTemplate::DEFAULT], is the original `BISHAN-2022-01-11 09-30-00.json`.
+//! Activities other than the gym prefix `{gym}` and `{gym_id}` with their name, e.g.
+//! `BADMINTON-BISHAN`, so that they never collide with the gym of the same venue
+//!
+//! ## Example
+//! ```
+//! use activesg_gym_datamine::{
+//!     models::{Activity, Gym},
+//!     template::{FilenameFields, FilenameTemplate},
+//! };
+//! use chrono::NaiveDate;
+//!
+//! let fields = FilenameFields {
+//!     activity: Activity::GYM,
+//!     gym: Gym::BISHAN.into(),
+//!     captured_at: NaiveDate::from_ymd(2022, 1, 11).and_hms(9, 30, 0),
+//!     queried_date: NaiveDate::from_ymd(2022, 1, 13),
+//!     ext: "json",
+//! };
+//! let expand = |template: &str| template.parse::<FilenameTemplate>().unwrap().expand(&fields);
+//!
+//! assert_eq!(
+//!     FilenameTemplate::default().expand(&fields),
+//!     "BISHAN-2022-01-11 09-30-00.json"
+//! );
+//! assert_eq!(expand("{gym_id}_{date}T{time}.{ext}"), "137_2022-01-11T09-30-00.json");
+//! assert_eq!(expand("{queried_date}/{gym}/{time}.{ext}"), "2022-01-13/BISHAN/09-30-00.json");
+//! assert_eq!(expand("{gym}.{ext}"), "BISHAN.json");
+//!
+//! let badminton = FilenameFields {
+//!     activity: Activity::BADMINTON,
+//!     ext: "diff.json",
+//!     ..fields
+//! };
+//! assert_eq!(
+//!     FilenameTemplate::default().expand(&badminton),
+//!     "BADMINTON-BISHAN-2022-01-11 09-30-00.diff.json"
+//! );
+//! assert_eq!(
+//!     "{gym_id}-{time}.{ext}".parse::<FilenameTemplate>().unwrap().expand(&badminton),
+//!     "BADMINTON-137-09-30-00.diff.json"
+//! );
+//! ```
+//!
+//! Templates are checked when parsed, so that a typo fails at startup
+//! ```
+//! use activesg_gym_datamine::template::FilenameTemplate;
+//!
+//! let parse = |template: &str| template.parse::<FilenameTemplate>();
+//! assert_eq!(parse(FilenameTemplate::DEFAULT).unwrap().to_string(), FilenameTemplate::DEFAULT);
+//! assert!(parse("{gym}/{date}/{time}.{ext}").is_ok());
+//!
+//! let rejected = [
+//!     // unknown placeholders and stray braces
+//!     "{gmy}-{time}.{ext}",
+//!     "{Gym}-{time}.{ext}",
+//!     "{}{gym}.{ext}",
+//!     "{gym-{time}.{ext}",
+//!     "gym}-{time}.{ext}",
+//!     "{{gym}}.{ext}",
+//!     // outside of the directory of the day
+//!     "../{gym}.{ext}",
+//!     "{gym}/../../{time}.{ext}",
+//!     "./{gym}.{ext}",
+//!     "/etc/{gym}.{ext}",
+//!     "{gym}//{time}.{ext}",
+//!     "..\\{gym}.{ext}",
+//!     "C:{gym}.{ext}",
+//!     // characters that aren't allowed in a file name on Windows
+//!     "{gym}|{time}.{ext}",
+//!     "{gym}?.{ext}",
+//!     "{gym}\n.{ext}",
+//!     // the archive commands look for the extension
+//!     "{gym}-{time}.json",
+//!     "{gym}-{time}{ext}",
+//!     "{gym}-{time}.{ext}.bak",
+//!     // the gyms of an iteration would overwrite each other
+//!     "{date} {time}.{ext}",
+//!     "",
+//! ];
+//! for template in rejected {
+//!     assert!(parse(template).is_err(), "{:?} was accepted", template);
+//! }
+//! ```
+
+use std::{fmt, str::FromStr};
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{errors, models::Activity, venue::Venue, DataMResult};
+
+/// Placeholder of a [FilenameTemplate]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Gym,
+    GymId,
+    Date,
+    Time,
+    QueriedDate,
+    Ext,
+}
+
+impl Field {
+    const ALL: [Self; 6] = [
+        Self::Gym,
+        Self::GymId,
+        Self::Date,
+        Self::Time,
+        Self::QueriedDate,
+        Self::Ext,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Gym => "gym",
+            Self::GymId => "gym_id",
+            Self::Date => "date",
+            Self::Time => "time",
+            Self::QueriedDate => "queried_date",
+            Self::Ext => "ext",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Field(Field),
+}
+
+/// What a [FilenameTemplate] is expanded from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilenameFields {
+    pub activity: Activity,
+    pub gym: Venue,
+
+    /// capture time in Singapore time
+    pub captured_at: NaiveDateTime,
+
+    /// see the [module docs](self)
+    pub queried_date: NaiveDate,
+
+    /// without the leading `.`
+    pub ext: &'static str,
+}
+
+/// Path of the files relative to the directory of their day, see the [module docs](self)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct FilenameTemplate {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl FilenameTemplate {
+    /// `BISHAN-2022-01-11 09-30-00.json`
+    pub const DEFAULT: &'static str = "{gym}-{date} {time}.{ext}";
+
+    /// Characters refused in the literal parts, those Windows doesn't allow in file names
+    const FORBIDDEN: [char; 8] = ['\\', ':', '<', '>', '"', '|', '?', '*'];
+
+    pub fn parse(template: &str) -> DataMResult<Self> {
+        let invalid = |why: String| {
+            errors::Error::InvalidConfig(format!(
+                "invalid filename template {:?}, {}",
+                template, why
+            ))
+        };
+
+        let mut segments = vec![];
+        let mut rest = template;
+        while let Some(start) = rest.find(['{', '}']) {
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+            if rest[start..].starts_with('}') {
+                return Err(invalid("unmatched }".into()));
+            }
+            let len = rest[start..]
+                .find('}')
+                .ok_or_else(|| invalid("unclosed {".into()))?;
+            let name = &rest[start + 1..start + len];
+            let field = Field::ALL
+                .into_iter()
+                .find(|f| f.name() == name)
+                .ok_or_else(|| {
+                    let names = Field::ALL.map(|f| format!("{{{}}}", f.name()));
+                    invalid(format!(
+                        "unknown placeholder {{{}}}, expected one of {}",
+                        name,
+                        names.join(", ")
+                    ))
+                })?;
+            segments.push(Segment::Field(field));
+            rest = &rest[start + len + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+
+        let literals = segments.iter().filter_map(|s| match s {
+            Segment::Literal(literal) => Some(literal),
+            Segment::Field(_) => None,
+        });
+        for literal in literals {
+            if let Some(c) = literal
+                .chars()
+                .find(|c| c.is_control() || Self::FORBIDDEN.contains(c))
+            {
+                return Err(invalid(format!("{:?} isn't allowed in a file name", c)));
+            }
+        }
+
+        // placeholders never expand to `/` or to a lone `.`
+        let shape = segments
+            .iter()
+            .map(|s| match s {
+                Segment::Literal(literal) => literal.as_str(),
+                Segment::Field(_) => "x",
+            })
+            .collect::<String>();
+        if shape
+            .split('/')
+            .any(|part| part.is_empty() || part == "." || part == "..")
+        {
+            return Err(invalid(
+                "it must stay within the directory of the day".into(),
+            ));
+        }
+
+        let has = |field| segments.contains(&Segment::Field(field));
+        if !has(Field::Gym) && !has(Field::GymId) {
+            return Err(invalid("it needs {gym} or {gym_id}".into()));
+        }
+        let ends_with_ext = match &segments[..] {
+            [.., Segment::Literal(literal), Segment::Field(Field::Ext)] => literal.ends_with('.'),
+            _ => false,
+        };
+        if !ends_with_ext {
+            return Err(invalid("it must end with .{ext}".into()));
+        }
+
+        Ok(Self {
+            source: template.to_string(),
+            segments,
+        })
+    }
+
+    /// The file name of `fields`, `/` separating the directories if any
+    pub fn expand(&self, fields: &FilenameFields) -> String {
+        let prefix = match fields.activity {
+            Activity::GYM => String::new(),
+            activity => format!("{:?}-", activity),
+        };
+
+        let mut buf = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => buf.push_str(literal),
+                Segment::Field(Field::Gym) => {
+                    buf.push_str(&format!("{}{:?}", prefix, fields.gym));
+                }
+                Segment::Field(Field::GymId) => {
+                    buf.push_str(&format!("{}{}", prefix, fields.gym.id()));
+                }
+                Segment::Field(Field::Date) => {
+                    buf.push_str(&fields.captured_at.format("%Y-%m-%d").to_string());
+                }
+                Segment::Field(Field::Time) => {
+                    buf.push_str(&fields.captured_at.format("%H-%M-%S").to_string());
+                }
+                Segment::Field(Field::QueriedDate) => {
+                    buf.push_str(&fields.queried_date.format("%Y-%m-%d").to_string());
+                }
+                Segment::Field(Field::Ext) => buf.push_str(fields.ext),
+            }
+        }
+        buf
+    }
+}
+
+impl Default for FilenameTemplate {
+    fn default() -> Self {
+        Self::parse(Self::DEFAULT).expect("the default template is valid")
+    }
+}
+
+impl FromStr for FilenameTemplate {
+    type Err = errors::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl TryFrom<String> for FilenameTemplate {
+    type Error = errors::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::parse(&s)
+    }
+}
+
+impl From<FilenameTemplate> for String {
+    fn from(template: FilenameTemplate) -> Self {
+        template.source
+    }
+}
+
+impl fmt::Display for FilenameTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
@@ -0,0 +1,291 @@
+//! W3C trace context of each iteration, so that the alerts it sends can be correlated
+//! with what the receivers do with them
+//!
+//! The context of the running iteration is task local: [scope] sets it around an iteration
+//! and [current] reads it wherever an outgoing request is made
+//!
+//! ## Spans
+//! The spans are built here, so that their names and fields are the same in the logs
+//! and wherever they're exported:
+//! - `cycle`, an iteration: `iteration` and `trace`, see [iteration_span]
+//! - `fetch`, or `refresh` when queued through the API, a booking page: `activity`, `gym`
+//!   and `date`, see [fetch_span]
+//! - `sink`, a delivery to a single sink: `sink`, `activity`, `gym` and `date`, see [sink_span]
+//! - `notify`, a delivery by a notifier: `profile` and `notifier`, see [notifier_span]
+//!
+//! Each of them ends with an `outcome`, `ok`, `skipped` or the class of the error
+//! (see [crate::errors::Error::class]), a failure also setting `otel.status_code` to `error`.
+//! With the `otel` feature they can be exported to an OpenTelemetry collector with
+//! [otlp_provider], the exported trace being the one of the [current] context
+
+use std::{fmt, future::Future};
+
+use reqwest::{header::HeaderValue, RequestBuilder};
+use tracing::{field, info_span, Span};
+
+use crate::{fetch::FetchTask, sink::PublishKey, DataMResult};
+
+/// Header carrying the context on outgoing requests, see <https://www.w3.org/TR/trace-context/>
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+tokio::task_local! {
+    static CURRENT: TraceContext;
+}
+
+/// Identifies a trace and the span within it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+}
+
+impl TraceContext {
+    /// Starts a new trace with random ids
+    pub fn new_root() -> Self {
+        let mut trace_id = [0; 16];
+        let mut span_id = [0; 8];
+        // all zero ids are invalid, which a failing generator would produce
+        if getrandom::getrandom(&mut trace_id).is_err()
+            || getrandom::getrandom(&mut span_id).is_err()
+        {
+            trace_id[15] = 1;
+            span_id[7] = 1;
+        }
+        Self { trace_id, span_id }
+    }
+
+    /// New span within the same trace
+    pub fn child(&self) -> Self {
+        Self {
+            span_id: Self::new_root().span_id,
+            ..*self
+        }
+    }
+
+    /// Trace id as 32 lowercase hex digits
+    pub fn trace_id_hex(&self) -> String {
+        hex(&self.trace_id)
+    }
+
+    /// Value of the [TRACEPARENT_HEADER], always sampled
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::trace::TraceContext;
+    ///
+    /// let context = TraceContext {
+    ///     trace_id: [0x4b; 16],
+    ///     span_id: [0x0a, 0, 0, 0, 0, 0, 0, 0xff],
+    /// };
+    /// assert_eq!(
+    ///     context.traceparent(),
+    ///     "00-4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b-0a000000000000ff-01"
+    /// );
+    ///
+    /// let root = TraceContext::new_root();
+    /// let child = root.child();
+    /// assert_eq!(child.trace_id, root.trace_id);
+    /// assert_ne!(child.span_id, root.span_id);
+    /// assert_ne!(TraceContext::new_root().trace_id, root.trace_id);
+    /// ```
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-01", hex(&self.trace_id), hex(&self.span_id))
+    }
+}
+
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.trace_id_hex())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Runs `f` with `context` as the [current] context
+pub async fn scope<F: Future>(context: TraceContext, f: F) -> F::Output {
+    CURRENT.scope(context, f).await
+}
+
+/// Context of the running iteration, none outside of [scope]
+///
+/// ## Example
+/// ```
+/// use activesg_gym_datamine::trace::{self, TraceContext};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// assert_eq!(trace::current(), None);
+///
+/// let context = TraceContext::new_root();
+/// let seen = trace::scope(context, async { trace::current() }).await;
+/// assert_eq!(seen, Some(context));
+/// # }
+/// ```
+pub fn current() -> Option<TraceContext> {
+    CURRENT.try_with(|c| *c).ok()
+}
+
+/// Adds the [TRACEPARENT_HEADER] of a new span of the [current] trace to `request`,
+/// leaving it as is outside of an iteration
+///
+/// The span is the exported one the request is made within, if any
+pub fn propagate(request: RequestBuilder) -> RequestBuilder {
+    let context = exported().or_else(|| current().map(|c| c.child()));
+    let header = context.and_then(|c| HeaderValue::from_str(&c.traceparent()).ok());
+    match header {
+        Some(value) => request.header(TRACEPARENT_HEADER, value),
+        None => request,
+    }
+}
+
+/// Span of an iteration, exported within the [current] trace
+pub fn iteration_span() -> Span {
+    let span = info_span!(
+        "cycle",
+        iteration = field::Empty,
+        trace = field::Empty,
+        outcome = field::Empty,
+        otel.status_code = field::Empty,
+    );
+    if let Some(context) = current() {
+        span.record("trace", field::display(context));
+        set_parent(&span, context);
+    }
+    span
+}
+
+/// Span of the fetch of `task` by an iteration
+pub fn fetch_span(task: FetchTask) -> Span {
+    info_span!(
+        "fetch",
+        activity = ?task.activity,
+        gym = ?task.gym,
+        date = %task.date,
+        outcome = field::Empty,
+        otel.status_code = field::Empty,
+    )
+}
+
+/// Span of a refresh of `task` queued through the API, exported within the [current] trace
+pub fn refresh_span(task: FetchTask) -> Span {
+    let span = info_span!(
+        "refresh",
+        activity = ?task.activity,
+        gym = ?task.gym,
+        date = %task.date,
+        outcome = field::Empty,
+        otel.status_code = field::Empty,
+    );
+    if let Some(context) = current() {
+        set_parent(&span, context);
+    }
+    span
+}
+
+/// Span of a delivery of the snapshot of `key` to `sink`
+pub fn sink_span(sink: &str, key: &PublishKey) -> Span {
+    info_span!(
+        "sink",
+        sink,
+        activity = ?key.activity,
+        gym = ?key.gym,
+        date = %key.date,
+        outcome = field::Empty,
+        otel.status_code = field::Empty,
+    )
+}
+
+/// Span of a delivery by `notifier` of `profile`
+pub fn notifier_span(profile: &str, notifier: &str) -> Span {
+    info_span!(
+        "notify",
+        profile,
+        notifier,
+        outcome = field::Empty,
+        otel.status_code = field::Empty,
+    )
+}
+
+/// Records the outcome of `res` on `span`, `ok` or the class of the error
+pub fn record_outcome<T>(span: &Span, res: &DataMResult<T>) {
+    match res {
+        Ok(_) => span.record("outcome", "ok"),
+        Err(e) => span
+            .record("outcome", e.class())
+            .record("otel.status_code", "error"),
+    };
+}
+
+/// Records on `span` that it was skipped
+pub fn record_skipped(span: &Span) {
+    span.record("outcome", "skipped");
+}
+
+/// Exports `span` as a child of `context`, so that the exported trace is the one of the
+/// run report and of the [TRACEPARENT_HEADER]
+#[cfg(feature = "otel")]
+fn set_parent(span: &Span, context: TraceContext) {
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+    };
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let parent = SpanContext::new(
+        TraceId::from_bytes(context.trace_id),
+        SpanId::from_bytes(context.span_id),
+        TraceFlags::SAMPLED,
+        true,
+        TraceState::default(),
+    );
+    span.set_parent(opentelemetry::Context::new().with_remote_span_context(parent));
+}
+
+#[cfg(not(feature = "otel"))]
+fn set_parent(_: &Span, _: TraceContext) {}
+
+/// Context of the exported span the caller is within, none when the spans aren't exported
+#[cfg(feature = "otel")]
+fn exported() -> Option<TraceContext> {
+    use opentelemetry::trace::TraceContextExt;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let context = Span::current().context();
+    let span = context.span();
+    let span = span.span_context();
+    span.is_valid().then(|| TraceContext {
+        trace_id: span.trace_id().to_bytes(),
+        span_id: span.span_id().to_bytes(),
+    })
+}
+
+#[cfg(not(feature = "otel"))]
+fn exported() -> Option<TraceContext> {
+    None
+}
+
+/// Provider of tracers exporting their spans in batches over OTLP/HTTP to the collector
+/// at `endpoint`, such as `http://localhost:4318`
+///
+/// The spans are exported on the tokio runtime, which has to be running. See
+/// `tests/e2e.rs` for the spans of an iteration as exported
+#[cfg(feature = "otel")]
+pub fn otlp_provider(endpoint: &str) -> DataMResult<opentelemetry_sdk::trace::TracerProvider> {
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::{runtime, trace, Resource};
+
+    use crate::errors;
+
+    let exporter = opentelemetry_otlp::new_exporter()
+        .http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build_span_exporter()
+        .map_err(|e| errors::Error::InvalidConfig(format!("OTLP endpoint {}: {}", endpoint, e)))?;
+    let resource = Resource::new([KeyValue::new("service.name", env!("CARGO_PKG_NAME"))]);
+    Ok(trace::TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_config(trace::Config::default().with_resource(resource))
+        .build())
+}
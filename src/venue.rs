@@ -0,0 +1,200 @@
+//! Venues to be queried, a known [Gym] or a raw venue id given with `--venue-id`
+//!
+//! A custom venue is written `ID:NAME`, such as `1234:MY_GYM`, which lets a newly opened gym
+//! be tracked before it is added to [Gym]. Snapshots of a custom venue are written with the
+//! same `ID:NAME` as their gym so that they can be read back without the flag.
+//!
+//! Custom names are interned, a venue is `Copy` like [Gym] and only the few names given
+//! at start are ever kept
+//!
+//! ## Example
+//! ```
+//! use activesg_gym_datamine::{models::Gym, venue::Venue};
+//! use std::convert::TryFrom;
+//!
+//! assert_eq!(Gym::try_from(1048).unwrap(), Gym::FERNVALE_SQ);
+//! assert!(Gym::try_from(1234).is_err());
+//!
+//! let custom = "1234:MY_GYM".parse::<Venue>().unwrap();
+//! assert_eq!(custom.id(), 1234);
+//! assert_eq!(custom.name(), "MY_GYM");
+//! assert_eq!(custom.gym(), None);
+//! assert_eq!(format!("{:?}", custom), "MY_GYM");
+//! assert_eq!(serde_json::to_string(&custom).unwrap(), r#""1234:MY_GYM""#);
+//! assert_eq!(serde_json::from_str::<Venue>(r#""1234:MY_GYM""#).unwrap(), custom);
+//!
+//! let known = "bishan".parse::<Venue>().unwrap();
+//! assert_eq!(known, Gym::BISHAN);
+//! assert_eq!((known.id(), known.name()), (137, "BISHAN"));
+//! assert_eq!(serde_json::to_string(&known).unwrap(), r#""BISHAN""#);
+//!
+//! // known ids and names are not custom venues
+//! assert!("137:MY_GYM".parse::<Venue>().is_err());
+//! assert!("1234:BISHAN".parse::<Venue>().is_err());
+//! assert!("1234:my gym".parse::<Venue>().is_err());
+//! assert!("MY_GYM".parse::<Venue>().is_err());
+//! ```
+
+use std::{collections::BTreeSet, fmt, str::FromStr, sync::Mutex};
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::{errors, models::Gym, DataMResult};
+
+lazy_static! {
+    static ref NAMES: Mutex<BTreeSet<&'static str>> = Mutex::default();
+}
+
+/// Venue to be queried, see the [module docs](self)
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Venue {
+    Known(Gym),
+    Custom { id: u16, name: &'static str },
+}
+
+impl Venue {
+    /// Custom venue `id` named `name`, refusing ids and names of known gyms
+    ///
+    /// Names are made of uppercase letters, digits and underscores like the names of [Gym]
+    pub fn custom(id: u16, name: &str) -> DataMResult<Self> {
+        if let Ok(gym) = Gym::try_from(id) {
+            return Err(errors::Error::InvalidVenue(format!(
+                "{} is already the id of {:?}",
+                id, gym
+            )));
+        }
+        let valid = name
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+        if name.is_empty() || !valid {
+            return Err(errors::Error::InvalidVenue(format!(
+                "{:?}, names are made of A-Z, 0-9 and _",
+                name
+            )));
+        }
+        if Gym::from_canonical(name).is_some() {
+            return Err(errors::Error::InvalidVenue(format!(
+                "{} is already the name of a gym",
+                name
+            )));
+        }
+
+        let mut names = NAMES.lock().unwrap();
+        let name = match names.get(name) {
+            Some(name) => *name,
+            None => {
+                let name: &'static str = Box::leak(name.to_owned().into_boxed_str());
+                names.insert(name);
+                name
+            }
+        };
+        Ok(Self::Custom { id, name })
+    }
+
+    /// Venue id in the booking urls of ActiveSG
+    pub fn id(&self) -> u16 {
+        match self {
+            Venue::Known(gym) => *gym as u16,
+            Venue::Custom { id, .. } => *id,
+        }
+    }
+
+    /// Name as written in configs, flags and snapshots, see [Gym::name]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Venue::Known(gym) => gym.name(),
+            Venue::Custom { name, .. } => name,
+        }
+    }
+
+    /// Official name of a known gym, the name of a custom venue
+    pub fn display(&self) -> &'static str {
+        match self {
+            Venue::Known(gym) => gym.display(),
+            Venue::Custom { name, .. } => name,
+        }
+    }
+
+    /// The known gym, none for a custom venue
+    pub fn gym(&self) -> Option<Gym> {
+        match self {
+            Venue::Known(gym) => Some(*gym),
+            Venue::Custom { .. } => None,
+        }
+    }
+
+    /// Whether the venue title of a booking page refers to this venue,
+    /// always for a custom venue whose official name isn't known
+    pub fn matches_display_name(&self, title: &str) -> bool {
+        match self {
+            Venue::Known(gym) => gym.matches_display_name(title),
+            Venue::Custom { .. } => true,
+        }
+    }
+}
+
+impl From<Gym> for Venue {
+    fn from(gym: Gym) -> Self {
+        Venue::Known(gym)
+    }
+}
+
+impl PartialEq<Gym> for Venue {
+    fn eq(&self, other: &Gym) -> bool {
+        *self == Venue::Known(*other)
+    }
+}
+
+/// Same as the [Debug] of [Gym], only the name
+impl fmt::Debug for Venue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl fmt::Display for Venue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.display())
+    }
+}
+
+/// `ID:NAME` for a custom venue, anything [Gym] parses otherwise
+impl FromStr for Venue {
+    type Err = errors::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((id, name)) => {
+                let id = id
+                    .trim()
+                    .parse::<u16>()
+                    .map_err(|_| errors::Error::InvalidVenue(format!("invalid id in {}", s)))?;
+                Self::custom(id, name.trim())
+            }
+            None => s.parse::<Gym>().map(Venue::Known),
+        }
+    }
+}
+
+impl Serialize for Venue {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Venue::Known(gym) => gym.serialize(serializer),
+            Venue::Custom { id, name } => serializer.collect_str(&format_args!("{}:{}", id, name)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Venue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
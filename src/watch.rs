@@ -0,0 +1,274 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt,
+    str::FromStr,
+    sync::Mutex,
+};
+
+use chrono::{DateTime, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clock::SGT,
+    distance::{self, LatLng},
+    errors,
+    gymset::{GymGroups, GymSelection},
+    hours::HoursRange,
+    models::{Activity, Gym, GymSlotData},
+    notify::NotifierConfig,
+    venue::Venue,
+    DataMResult,
+};
+
+fn default_min_slots() -> u16 {
+    1
+}
+
+/// Slots someone is interested in
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WatchRule {
+    #[serde(default)]
+    pub activity: Activity,
+
+    /// venues watched as a gym set, see [crate::gymset], every venue when empty
+    #[serde(default, skip_serializing_if = "GymSelection::is_empty")]
+    pub gyms: GymSelection,
+
+    /// minimum number of available slots
+    #[serde(default = "default_min_slots")]
+    pub min_slots: u16,
+
+    /// only slots starting within this range, in Singapore time
+    #[serde(default)]
+    pub hours: Option<HoursRange>,
+
+    /// [WatchRule::gyms] resolved by [WatchRule::resolve_gyms]
+    #[serde(skip)]
+    venues: Option<BTreeSet<Gym>>,
+}
+
+impl WatchRule {
+    /// Resolves [WatchRule::gyms] once, so that groups can be referred to
+    pub fn resolve_gyms(&mut self, groups: &GymGroups) -> DataMResult<()> {
+        self.venues = match self.gyms.is_empty() {
+            true => None,
+            false => Some(self.gyms.resolve(groups)?),
+        };
+        Ok(())
+    }
+
+    /// Custom venues are only watched when no gyms are selected
+    pub fn watches<V: Into<Venue>>(&self, activity: Activity, gym: V) -> bool {
+        if self.activity != activity || self.gyms.is_empty() {
+            return self.activity == activity;
+        }
+        let gym = match gym.into().gym() {
+            Some(gym) => gym,
+            None => return false,
+        };
+
+        match &self.venues {
+            Some(venues) => venues.contains(&gym),
+            // not resolved, without groups
+            None => self
+                .gyms
+                .resolve(&GymGroups::new())
+                .is_ok_and(|v| v.contains(&gym)),
+        }
+    }
+
+    pub fn matches<V: Into<Venue>>(
+        &self,
+        activity: Activity,
+        gym: V,
+        time: DateTime<Utc>,
+        slots: u16,
+    ) -> bool {
+        self.watches(activity, gym)
+            && slots >= self.min_slots
+            && self
+                .hours
+                .is_none_or(|h| h.contains(time.with_timezone(&*SGT).time()))
+    }
+}
+
+impl FromStr for WatchRule {
+    type Err = errors::Error;
+
+    /// Parses `GYM=HH:MM`, the slot of a gym starting at a time in Singapore, or
+    /// `GYM=HH:MM-HH:MM` for the slots starting within a range
+    ///
+    /// ## Example
+    /// ```
+    /// use activesg_gym_datamine::{models::{Activity, Gym}, watch::WatchRule};
+    /// use chrono::{TimeZone, Utc};
+    ///
+    /// let rule = "BISHAN=19:00".parse::<WatchRule>().unwrap();
+    /// // 7 PM and 8 PM in Singapore
+    /// let seven = Utc.ymd(2022, 1, 11).and_hms(11, 0, 0);
+    /// let eight = Utc.ymd(2022, 1, 11).and_hms(12, 0, 0);
+    /// assert!(rule.matches(Activity::GYM, Gym::BISHAN, seven, 1));
+    /// assert!(!rule.matches(Activity::GYM, Gym::BISHAN, seven, 0));
+    /// assert!(!rule.matches(Activity::GYM, Gym::BISHAN, eight, 3));
+    /// assert!(!rule.matches(Activity::GYM, Gym::CLEMENTI, seven, 3));
+    ///
+    /// let evening = "BISHAN=18:00-21:00".parse::<WatchRule>().unwrap();
+    /// assert!(evening.matches(Activity::GYM, Gym::BISHAN, eight, 1));
+    ///
+    /// assert!("BISHAN=23:59".parse::<WatchRule>().is_ok());
+    /// assert!("BISHAN".parse::<WatchRule>().is_err());
+    /// assert!("BISHAN=7pm".parse::<WatchRule>().is_err());
+    /// assert!("NOWHERE=19:00".parse::<WatchRule>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || errors::Error::InvalidConfig(format!("invalid watch {:?}, expected GYM=HH:MM", s));
+        let (gym, time) = s.split_once('=').ok_or_else(invalid)?;
+        let gym = gym.trim().parse::<Gym>()?;
+
+        let hours = match time.contains('-') {
+            true => time.parse::<HoursRange>()?,
+            false => {
+                let open =
+                    NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| invalid())?;
+                // 23:59 would wrap around to midnight
+                let close = match open + chrono::Duration::minutes(1) {
+                    close if close > open => close,
+                    _ => NaiveTime::from_hms_milli(23, 59, 59, 999),
+                };
+                HoursRange::new(open, close)
+            }
+        };
+
+        Ok(Self {
+            activity: Activity::GYM,
+            gyms: format!("{:?}", gym).as_str().into(),
+            min_slots: default_min_slots(),
+            hours: Some(hours),
+            venues: Some(BTreeSet::from([gym])),
+        })
+    }
+}
+
+/// Named set of watches with their own notifiers
+///
+/// Every profile is evaluated against the same snapshots,
+/// so a household can share a single miner
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub name: String,
+
+    #[serde(default)]
+    pub watches: Vec<WatchRule>,
+
+    #[serde(default)]
+    pub notifiers: Vec<NotifierConfig>,
+}
+
+impl Profile {
+    /// Name of the profile of the watches given on the command line
+    pub const CLI: &'static str = "cli";
+
+    /// Slots of `data` matching any of the watches, once per slot
+    ///
+    /// Slots past the booking cut-off can't be booked anymore and never match
+    pub fn evaluate(&self, data: &GymSlotData) -> Vec<Alert> {
+        data.data()
+            .iter()
+            .filter(|s| !s.past_cutoff())
+            .filter(|s| {
+                self.watches
+                    .iter()
+                    .any(|w| w.matches(data.activity(), data.gym(), s.time(), s.slots_avail()))
+            })
+            .map(|s| Alert {
+                profile: self.name.clone(),
+                activity: data.activity(),
+                gym: data.gym(),
+                time: s.time(),
+                slots_avail: s.slots_avail(),
+                distance_m: None,
+            })
+            .collect()
+    }
+}
+
+/// A watched slot that became available
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Alert {
+    pub profile: String,
+    pub activity: Activity,
+    pub gym: Venue,
+    pub time: DateTime<Utc>,
+    pub slots_avail: u16,
+
+    /// distance of the gym from [crate::config::Config::home_location], in metres
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distance_m: Option<u32>,
+}
+
+impl Alert {
+    /// Sets [Alert::distance_m] and sorts `alerts` nearest first,
+    /// see [distance::sort_by_distance]
+    pub fn sort_by_distance(alerts: &mut [Alert], home: Option<LatLng>) {
+        for alert in alerts.iter_mut() {
+            alert.distance_m =
+                distance::distance_km(home, alert.gym).map(|km| (km * 1000.0) as u32);
+        }
+        distance::sort_by_distance(alerts, |a| a.gym, home);
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} {:?} at {}: {} slots available",
+            self.gym,
+            self.activity,
+            self.time.with_timezone(&*SGT).format("%a %Y-%m-%d %H:%M"),
+            self.slots_avail
+        )?;
+        match self.distance_m {
+            Some(m) => write!(f, ", {:.1} km away", f64::from(m) / 1000.0),
+            None => Ok(()),
+        }
+    }
+}
+
+type AlertKey = (String, Activity, Venue, DateTime<Utc>);
+
+/// Remembers the slots already alerted so that a slot is only alerted
+/// when it starts matching, not on every snapshot it keeps matching in
+#[derive(Debug, Default)]
+pub struct AlertTracker {
+    alerted: Mutex<HashSet<AlertKey>>,
+}
+
+impl AlertTracker {
+    /// Returns the alerts of `profile` for `data` that were not alerted before
+    ///
+    /// Slots of `data` that stopped matching are forgotten so that they alert again
+    /// once they match again, slots that already started are forgotten at `now`
+    pub fn fresh(&self, profile: &Profile, data: &GymSlotData, now: DateTime<Utc>) -> Vec<Alert> {
+        let alerts = profile.evaluate(data);
+        let mut alerted = self.alerted.lock().unwrap();
+
+        alerted.retain(|(name, activity, gym, time)| {
+            let in_snapshot = *name == profile.name
+                && *activity == data.activity()
+                && *gym == data.gym()
+                && data.data().iter().any(|s| s.time() == *time);
+            let still_matching = alerts.iter().any(|a| a.time == *time);
+
+            *time > now && (!in_snapshot || still_matching)
+        });
+
+        alerts
+            .into_iter()
+            .filter(|a| alerted.insert((a.profile.clone(), a.activity, a.gym, a.time)))
+            .collect()
+    }
+}
@@ -0,0 +1,346 @@
+//! Mines two gyms from a local server answering with the booking pages of `tests/fixtures`,
+//! through the same client, login included, as the miner, then writes the snapshots in both
+//! formats and reads them back
+//!
+//! ```text
+//! cargo test --test e2e
+//! ```
+//!
+//! Needs no network and takes a few seconds, run it after any change to how logging in,
+//! fetching, parsing and writing fit together. With the `otel` feature, the spans of an
+//! iteration of the daemon over the same server are checked as exported
+#![cfg(any(feature = "rsa", feature = "openssl"))]
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use activesg_gym_datamine::{
+    archive::{SnapshotFilter, SnapshotReader},
+    client::DataMiner,
+    clock::FakeClock,
+    config::Config,
+    models::{Gym, User},
+    sink::{FileSink, OutputFormat, Sink},
+};
+use chrono::{TimeZone, Utc};
+use jsonschema::JSONSchema;
+use serde_json::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+const LOGIN: &str = r#"<html><body><form id="formSignin" action="/auth/signin" method="post">
+    <input type="hidden" name="_csrf" value="WkRfc1BjQ2w0Lmc">
+    <input type="hidden" name="rsapublickey" value="-----BEGIN PUBLIC KEY-----
+MFwwDQYJKoZIhvcNAQEBBQADSwAwSAJBANtFLKv5ebeCtNwkO9mbMeZCzZS9/YaD
+M9vIk9fm74YcqGbYlfqIbn7yfg9ZzKGzIIpU9U35b0jPPwxHQ+CPUYECAwEAAQ==
+-----END PUBLIC KEY-----">
+    <input type="email" name="email"><input type="password" name="password">
+</form></body></html>"#;
+
+/// Slot counts of the booking pages of the fixtures, in order
+const BISHAN_SLOTS: [u16; 15] = [0, 3, 0, 12, 25, 25, 18, 7, 0, 22, 25, 25, 9, 4, 0];
+const CLEMENTI_SLOTS: [u16; 6] = [14, 0, 2, 20, 0, 6];
+
+fn fixture(name: &str) -> String {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+/// Removes the directory when dropped, whether the test passed or not
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        Self(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Answers a single request of ActiveSG with its fixture, or of the webhook at `/hook`,
+/// recording the request line and headers
+async fn serve(mut socket: TcpStream, requests: Arc<Mutex<Vec<String>>>) {
+    let mut request = vec![];
+    let mut buf = [0; 4096];
+    let head = loop {
+        let n = socket.read(&mut buf).await.unwrap();
+        if n == 0 {
+            return;
+        }
+        request.extend_from_slice(&buf[..n]);
+        let text = String::from_utf8_lossy(&request).to_string();
+        if let Some(end) = text.find("\r\n\r\n") {
+            let len = text[..end]
+                .lines()
+                .find_map(|l| {
+                    let l = l.to_ascii_lowercase();
+                    l.strip_prefix("content-length: ")
+                        .map(|v| v.trim().parse().unwrap())
+                })
+                .unwrap_or(0);
+            if request.len() >= end + 4 + len {
+                break text[..end].to_string();
+            }
+        }
+    };
+
+    requests.lock().unwrap().push(head.clone());
+    let line = head.lines().next().unwrap();
+    let parts = line.split(' ').take(2).collect::<Vec<_>>();
+    let path = parts[1].split('?').next().unwrap();
+    let segments = path.split('/').skip(1).collect::<Vec<_>>();
+    let (status, page) = match (parts[0], &segments[..]) {
+        ("GET", ["auth"]) => ("200 OK", LOGIN.to_string()),
+        ("POST", ["auth", "signin"]) => ("302 Found\r\nlocation: /profile", String::new()),
+        ("GET", ["profile"]) => ("200 OK", "profile".to_string()),
+        ("POST", ["hook"]) => ("200 OK", String::new()),
+        ("GET", ["facilities", "view", "activity", _, "venue", venue]) => match *venue {
+            "137" => ("200 OK", fixture("booking-GYM-BISHAN-2022-01-11.html")),
+            "160" => ("200 OK", fixture("booking-GYM-CLEMENTI-2022-01-11.html")),
+            _ => ("404 Not Found", String::new()),
+        },
+        _ => ("404 Not Found", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        status,
+        page.len(),
+        page
+    );
+    socket.write_all(response.as_bytes()).await.unwrap();
+}
+
+/// Validator of the snapshots written as `format`
+fn schema(format: OutputFormat) -> JSONSchema {
+    let read = |name: &str| serde_json::from_str::<Value>(&fixture(name)).unwrap();
+    let snapshot = read("snapshot.schema.json");
+    let schema = match format {
+        OutputFormat::Soa => read("snapshot-soa.schema.json"),
+        _ => snapshot.clone(),
+    };
+    JSONSchema::options()
+        .with_document("urn:activesg-gym-datamine:snapshot".into(), snapshot)
+        .compile(&schema)
+        .unwrap()
+}
+
+/// Starts the server, returning its url and the requests it's answered so far
+async fn start_server() -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(vec![]));
+    let served = requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(serve(socket, served.clone()));
+        }
+    });
+    (url, requests)
+}
+
+/// Mining both gyms of the fixtures, for the day of the fixtures
+fn config() -> Config {
+    let mut config = Config::new(User::new("user@example.com", "password"));
+    config.gyms = vec![Gym::BISHAN, Gym::CLEMENTI];
+    config.day_offsets = vec![1];
+    config.inter_gym_delay = Duration::ZERO;
+    config.keep_out_of_hours = true;
+    config.slot_ids = true;
+    config
+}
+
+/// The day before the fixtures, in Singapore
+fn clock() -> Arc<FakeClock> {
+    Arc::new(FakeClock::new(Utc.ymd(2022, 1, 10).and_hms(2, 0, 0)))
+}
+
+#[tokio::test]
+async fn mines_two_gyms_in_both_formats() {
+    let (url, requests) = start_server().await;
+    let output = TempDir::new("activesg-e2e");
+
+    let config = config();
+    let miner = DataMiner::from_config(&config)
+        .with_base_url(&url)
+        .with_clock(clock());
+
+    let data = activesg_gym_datamine::mine_once_with(&miner, &config, None)
+        .await
+        .unwrap();
+    let gyms = data.iter().map(|d| d.gym()).collect::<Vec<_>>();
+    assert_eq!(gyms, [Gym::BISHAN, Gym::CLEMENTI]);
+
+    // logged in once, then a booking page per gym
+    let requests = requests.lock().unwrap().clone();
+    let count = |prefix: &str| requests.iter().filter(|r| r.starts_with(prefix)).count();
+    assert_eq!(count("POST /auth/signin "), 1, "{:#?}", requests);
+    assert_eq!(
+        count("GET /facilities/view/activity/"),
+        2,
+        "{:#?}",
+        requests
+    );
+
+    for (format, dir) in [(OutputFormat::Json, "json"), (OutputFormat::Soa, "soa")] {
+        let sink = FileSink::new(output.0.join(dir), format);
+        for snapshot in &data {
+            sink.publish(snapshot).await.unwrap();
+        }
+
+        let read = SnapshotReader::new(output.0.join(dir), SnapshotFilter::default())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(read.len(), data.len(), "{:?}", format);
+
+        let validator = schema(format);
+        for snapshot in read {
+            let raw = std::fs::read_to_string(&snapshot.path).unwrap();
+            let json = serde_json::from_str::<Value>(&raw).unwrap();
+            if let Err(errors) = validator.validate(&json) {
+                let errors = errors.map(|e| e.to_string()).collect::<Vec<_>>();
+                panic!("{}: {:#?}", snapshot.path.display(), errors);
+            }
+
+            let (gym, expected) = match snapshot.data.gym() == Gym::BISHAN {
+                true => (Gym::BISHAN, &BISHAN_SLOTS[..]),
+                false => (Gym::CLEMENTI, &CLEMENTI_SLOTS[..]),
+            };
+            let slots = snapshot
+                .data
+                .data()
+                .iter()
+                .map(|s| s.slots_avail())
+                .collect::<Vec<_>>();
+            assert_eq!(snapshot.format, format);
+            assert_eq!(slots, expected, "{}", snapshot.path.display());
+            assert_eq!(snapshot.data.venue_display_name(), Some(gym.display()));
+            assert_eq!(snapshot.data.metadata().parse_warnings, None);
+            assert!(snapshot.data.metadata().is_authenticated());
+            assert!(!snapshot.data.metadata().synthetic);
+        }
+    }
+}
+
+/// An iteration of the daemon exports its spans in a tree within the trace of its run report,
+/// the trace going on with the webhook
+#[cfg(all(feature = "otel", feature = "cli"))]
+#[tokio::test]
+async fn exports_the_spans_of_an_iteration() {
+    use activesg_gym_datamine::{daemon::Daemon, notify::NotifierConfig, watch::Profile};
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::{
+        export::trace::SpanData, testing::trace::InMemorySpanExporter, trace::TracerProvider,
+    };
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let (url, requests) = start_server().await;
+    let output = TempDir::new("activesg-e2e-spans");
+
+    let exporter = InMemorySpanExporter::default();
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("e2e"));
+    let _subscriber = tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+
+    let mut config = config();
+    config.output_dir = output.0.clone();
+    config.profiles = vec![Profile {
+        name: "evenings".into(),
+        watches: vec!["BISHAN=18:00-21:00".parse().unwrap()],
+        notifiers: vec![NotifierConfig::Webhook {
+            url: format!("{}/hook", url),
+        }],
+    }];
+    let sink = FileSink::new(output.0.join("json"), OutputFormat::Json);
+    let daemon = Daemon::new(config, sink)
+        .with_clock(clock())
+        .with_base_url(&url);
+    let stats = Arc::new(daemon).run_once().await.unwrap();
+    assert_eq!((stats.fetched, stats.failed), (2, 0));
+
+    let spans = exporter.get_finished_spans().unwrap();
+    let named = |name: &str| spans.iter().filter(|s| s.name == name).collect::<Vec<_>>();
+    let attribute = |span: &SpanData, key: &str| {
+        let kv = span.attributes.iter().find(|kv| kv.key.as_str() == key);
+        kv.map(|kv| kv.value.to_string())
+            .unwrap_or_else(|| panic!("{} without {}: {:#?}", span.name, key, span.attributes))
+    };
+    let child_of = |span: &SpanData, parent: &SpanData| {
+        span.parent_span_id == parent.span_context.span_id()
+            && span.span_context.trace_id() == parent.span_context.trace_id()
+    };
+
+    // within the trace of the run report, the iteration being the child of its context
+    let cycle = named("cycle");
+    assert_eq!(cycle.len(), 1, "{:#?}", spans);
+    let cycle = cycle[0];
+    let runs = std::fs::read_dir(output.0.join("2022-01-10").join("runs")).unwrap();
+    let report = runs.map(|e| e.unwrap().path()).next().unwrap();
+    let report = serde_json::from_str::<Value>(&std::fs::read_to_string(report).unwrap()).unwrap();
+    let trace_id = cycle.span_context.trace_id().to_string();
+    assert_eq!(report["trace_id"], trace_id.as_str());
+    assert_eq!(attribute(cycle, "trace"), trace_id);
+    assert_eq!(attribute(cycle, "iteration"), stats.iteration.to_string());
+    assert_eq!(attribute(cycle, "outcome"), "ok");
+
+    let fetches = named("fetch");
+    assert_eq!(fetches.len(), 2, "{:#?}", spans);
+    let mut gyms = vec![];
+    for fetch in &fetches {
+        assert!(child_of(fetch, cycle), "{:#?}", fetch);
+        assert_eq!(attribute(fetch, "activity"), "GYM");
+        assert_eq!(attribute(fetch, "date"), "2022-01-11");
+        assert_eq!(attribute(fetch, "outcome"), "ok");
+        gyms.push(attribute(fetch, "gym"));
+    }
+    gyms.sort();
+    assert_eq!(gyms, ["BISHAN", "CLEMENTI"]);
+
+    // a delivery per fetch, within it
+    let sinks = named("sink");
+    assert_eq!(sinks.len(), 2, "{:#?}", spans);
+    for sink in sinks {
+        let fetch = fetches.iter().find(|f| child_of(sink, f)).unwrap();
+        assert_eq!(attribute(sink, "sink"), FileSink::NAME);
+        assert_eq!(attribute(sink, "gym"), attribute(fetch, "gym"));
+        assert_eq!(attribute(sink, "date"), "2022-01-11");
+        assert_eq!(attribute(sink, "outcome"), "ok");
+    }
+
+    // the alerts of Bishan, the webhook getting the span of their delivery
+    let notify = named("notify");
+    assert_eq!(notify.len(), 1, "{:#?}", spans);
+    let notify = notify[0];
+    let fetch = fetches.iter().find(|f| child_of(notify, f)).unwrap();
+    assert_eq!(attribute(fetch, "gym"), "BISHAN");
+    assert_eq!(attribute(notify, "profile"), "evenings");
+    assert_eq!(attribute(notify, "notifier"), "webhook");
+    assert_eq!(attribute(notify, "outcome"), "ok");
+
+    let requests = requests.lock().unwrap().clone();
+    let hook = requests
+        .iter()
+        .find(|r| r.starts_with("POST /hook "))
+        .unwrap();
+    let traceparent = format!(
+        "traceparent: 00-{}-{}-01",
+        trace_id,
+        notify.span_context.span_id()
+    );
+    assert!(hook.to_ascii_lowercase().contains(&traceparent), "{}", hook);
+}